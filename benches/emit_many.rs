@@ -0,0 +1,55 @@
+//! Emitting 100k items collected in a Lua array, with an `emit` loop and
+//! with a single `emit_many` call, for numbers, where the calls themselves
+//! are most of the cost, and for tables. Run with
+//! `cargo bench --bench emit_many`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use mlua_play::Runner;
+
+const ITEMS: usize = 100_000;
+const ROUNDS: u32 = 10;
+
+const EMIT_LOOP: &str = r#"
+local items = {}
+for i = 1, ITEMS do items[i] = ITEM end
+for i = 1, #items do emit(items[i]) end
+"#;
+
+const EMIT_MANY: &str = r#"
+local items = {}
+for i = 1, ITEMS do items[i] = ITEM end
+emit_many(items)
+"#;
+
+/// The fastest of `ROUNDS` runs of `script`, each a batch of its own, with
+/// `item` making up every element.
+fn fastest(script: &str, item: &str) -> Duration {
+    let script = script
+        .replace("ITEMS", &ITEMS.to_string())
+        .replace("ITEM", item);
+    let mut runner = Runner::new(&script).unwrap();
+    (0..ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+            let outputs = runner.run_batch([]).unwrap();
+            let elapsed = start.elapsed();
+            assert_eq!(black_box(outputs).len(), ITEMS);
+            elapsed
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    for (kind, item) in [("numbers", "i"), ("tables", "{ n = i }")] {
+        let looped = fastest(EMIT_LOOP, item);
+        let many = fastest(EMIT_MANY, item);
+        println!("{ITEMS} {kind}: emit loop {looped:?}, emit_many {many:?}");
+        println!(
+            "  emit_many takes {:.2}x the time of the loop",
+            many.as_secs_f64() / looped.as_secs_f64()
+        );
+    }
+}
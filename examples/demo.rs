@@ -0,0 +1,56 @@
+use mlua_play::{Result, RunOptions, TraceEvent, run_with_options};
+use serde_json::json;
+
+fn main() -> Result<()> {
+    let input = vec![
+        json!({
+            "foo": 1,
+            "nested": { "bar": "baz" },
+            "arr": [10, 20, 30]
+        }),
+        json!({
+            "foo": 2,
+            "nested": { "bar": "BAZ" },
+            "arr": [100, 200, 300]
+        }),
+    ];
+    for x in &input {
+        println!("{x}");
+    }
+
+    let options = RunOptions {
+        trace: Some(Box::new(|event: TraceEvent<'_>| match event {
+            TraceEvent::ScriptStarting { source, .. } => {
+                eprintln!("\n--------\nRunning\n--------\n{source}");
+            }
+            TraceEvent::DocumentRead { index } => eprintln!("read document {index}"),
+            TraceEvent::Emitted { index } => eprintln!("emitted document {index}"),
+        })),
+        ..RunOptions::default()
+    };
+    let out = run_with_options(
+        r#"
+            sum = 0
+            while has_next() do
+                local doc = get_next()
+
+                doc.foo = 42
+                doc.nested.bar = "changed"
+                doc.arr[2] = 99
+
+                sum = sum + doc.arr[3]
+
+                emit(doc)
+            end
+
+            emit({sum=sum})
+        "#,
+        input,
+        options,
+    )?;
+
+    for x in out {
+        println!("{x}");
+    }
+    Ok(())
+}
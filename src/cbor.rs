@@ -0,0 +1,162 @@
+use std::io::{self, BufRead, Write};
+
+use ciborium::Value as CborValue;
+use serde_json::{Map, Number, Value};
+
+use crate::error::{Error, Result};
+use crate::sink::OutputSink;
+use crate::source::InputSource;
+
+/// CBOR data items one after the other, every item becoming a document.
+///
+/// CBOR holds more than JSON, which is mapped as follows:
+///
+/// - Tags are dropped, leaving the item they tag, so a date is its string or
+///   epoch number and a bignum its bytes.
+/// - Byte strings must be UTF-8, becoming strings, as for MessagePack; other
+///   bytes are an error.
+/// - `undefined` and simple values become null, and NaN and infinite floats
+///   are an error.
+/// - Map keys that are integers, floats or booleans become their decimal or
+///   `true`/`false` spelling, and byte strings are taken as for values,
+///   unless [`CborSource::reject_non_string_keys`] is set; keys that are
+///   arrays or maps are always an error.
+pub struct CborSource<R: BufRead> {
+    reader: R,
+    string_keys_only: bool,
+    /// Documents read so far.
+    read: usize,
+}
+
+impl<R: BufRead> CborSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            string_keys_only: false,
+            read: 0,
+        }
+    }
+
+    /// Fails on map keys that are not text strings instead of spelling them
+    /// out.
+    pub fn reject_non_string_keys(mut self) -> Self {
+        self.string_keys_only = true;
+        self
+    }
+
+    fn to_json(&self, value: CborValue) -> std::result::Result<Value, String> {
+        Ok(match value {
+            CborValue::Null => Value::Null,
+            CborValue::Bool(b) => Value::Bool(b),
+            CborValue::Integer(n) => {
+                let n = i128::from(n);
+                if let Ok(n) = i64::try_from(n) {
+                    Value::from(n)
+                } else {
+                    u64::try_from(n)
+                        .map(Value::from)
+                        .map_err(|_| format!("integer {n} has no JSON representation"))?
+                }
+            }
+            CborValue::Float(f) => Value::Number(
+                Number::from_f64(f).ok_or_else(|| format!("{f} has no JSON representation"))?,
+            ),
+            CborValue::Text(s) => Value::String(s),
+            CborValue::Bytes(bytes) => Value::String(utf8(bytes)?),
+            CborValue::Tag(_, value) => self.to_json(*value)?,
+            CborValue::Array(items) => Value::Array(
+                items
+                    .into_iter()
+                    .map(|item| self.to_json(item))
+                    .collect::<std::result::Result<_, _>>()?,
+            ),
+            CborValue::Map(entries) => {
+                let mut object = Map::new();
+                for (key, value) in entries {
+                    object.insert(self.key(key)?, self.to_json(value)?);
+                }
+                Value::Object(object)
+            }
+            _ => Value::Null,
+        })
+    }
+
+    fn key(&self, key: CborValue) -> std::result::Result<String, String> {
+        match key {
+            CborValue::Text(s) => return Ok(s),
+            CborValue::Tag(_, key) => return self.key(*key),
+            _ if self.string_keys_only => {
+                return Err("map key is not a string".to_string());
+            }
+            _ => {}
+        }
+        match key {
+            CborValue::Bytes(bytes) => utf8(bytes),
+            CborValue::Array(_) | CborValue::Map(_) => {
+                Err("map keys must be scalars to become JSON".to_string())
+            }
+            key => match self.to_json(key)? {
+                Value::String(s) => Ok(s),
+                key => Ok(key.to_string()),
+            },
+        }
+    }
+}
+
+fn utf8(bytes: Vec<u8>) -> std::result::Result<String, String> {
+    String::from_utf8(bytes)
+        .map_err(|_| "byte string is not UTF-8, which JSON strings cannot hold".to_string())
+}
+
+impl<R: BufRead> InputSource for CborSource<R> {
+    fn next_doc(&mut self) -> Result<Option<Value>> {
+        if self.reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        self.read += 1;
+        let invalid = |message: String| Error::InvalidCbor {
+            document: self.read,
+            message,
+        };
+        let value = ciborium::from_reader::<CborValue, _>(&mut self.reader)
+            .map_err(|err| invalid(err.to_string()))?;
+        self.to_json(value).map(Some).map_err(invalid)
+    }
+}
+
+/// Writes every document as a CBOR data item, one after the other, the way
+/// [`CborSource`] reads them. Documents emitted with `emit_kv` are written as
+/// `{key, value}` maps.
+pub struct CborSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> CborSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> OutputSink for CborSink<W> {
+    fn emit(&mut self, _channel: &str, value: Value) -> Result<()> {
+        ciborium::into_writer(&value, &mut self.writer).map_err(|err| match err {
+            ciborium::ser::Error::Io(err) => Error::Io(err),
+            ciborium::ser::Error::Value(message) => {
+                Error::Io(io::Error::new(io::ErrorKind::InvalidData, message))
+            }
+        })
+    }
+
+    fn emit_kv(&mut self, channel: &str, key: Value, value: Value) -> Result<()> {
+        self.emit(channel, serde_json::json!({ "key": key, "value": value }))
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
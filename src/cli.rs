@@ -0,0 +1,891 @@
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use mlua_play::{
+    CsvColumns, CsvSink, Error, ErrorPolicy, InputSource, JsonLinesSink, JsonSeqSink, Mode,
+    MsgpackSink, OutputOrder, OutputSink, ParallelOptions, RawOutput, RunOptions, Runner, Sandbox,
+    SlicedSource, SlurpedSource, TomlSink, XmlOptions, YamlSink, run_parallel_into,
+};
+use serde_json::{Map, Value};
+
+mod check;
+mod error;
+mod input;
+mod limits;
+mod output;
+mod progress;
+mod repl;
+mod stats;
+mod watch;
+
+pub use error::{CliError, CliResult};
+use error::{EXIT_CODES, file_error};
+use input::{CsvInput, InputFiles, expand_inputs};
+pub use input::{Decompress, InputFormat, OnInvalidJson};
+pub use limits::{LimitArgs, OnLimit};
+use output::{ChannelFiles, Committing, Staged};
+use progress::Progress;
+
+/// Runs a Lua script over newline-delimited JSON documents, or ones in any of
+/// the other formats of `--input-format`, writing what it emits as
+/// newline-delimited JSON or any of the formats of `--output-format`.
+#[derive(Clone, Debug, Parser)]
+#[command(version, subcommand_negates_reqs = true, after_help = EXIT_CODES)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// The Lua script to run, or `-` to read it from stdin.
+    #[arg(required_unless_present = "eval", conflicts_with = "eval")]
+    pub script: Option<PathBuf>,
+    /// Lua code to run instead of a script file. Given more than once, the
+    /// pieces are joined by newlines. Under `--mode map` the code is the body
+    /// of `transform(doc)`.
+    #[arg(short, long = "eval", value_name = "CODE")]
+    pub eval: Vec<String>,
+    /// How the script is driven.
+    #[arg(long, value_enum, default_value_t = ModeArg::FreeForm)]
+    pub mode: ModeArg,
+    /// What a document the script fails on does to the run; `continue`
+    /// needs `--mode map` or `aggregate`, and counts it among the failures
+    /// `--stats` reports.
+    #[arg(long, value_enum, default_value_t = OnError::Abort)]
+    pub on_error: OnError,
+    /// Set `args[NAME]` to the string VALUE for the script. Given more than
+    /// once, names accumulate and a later value replaces an earlier one.
+    #[arg(long, num_args = 2, value_names = ["NAME", "VALUE"], global = true)]
+    pub arg: Vec<String>,
+    /// Like `--arg`, with VALUE parsed as JSON; set after every `--arg`, so
+    /// it wins over one of the same name.
+    #[arg(long, num_args = 2, value_names = ["NAME", "JSON"], global = true)]
+    pub argjson: Vec<String>,
+    /// Run the script on this many threads, each with a Lua state of its
+    /// own, handing every document to whichever is free. Needs `--mode map`,
+    /// as globals are not shared between them.
+    #[arg(short, long, default_value_t = 1, value_name = "N")]
+    pub jobs: usize,
+    #[command(flatten)]
+    pub limits: LimitArgs,
+    /// Which parts of the Lua standard library the script sees. `standard`
+    /// when left out for a script file owned by another user, or with
+    /// `--untrusted`, and `full` otherwise.
+    #[arg(long, value_enum)]
+    pub sandbox: Option<SandboxArg>,
+    /// Treat the script as untrusted, running it under the `standard`
+    /// sandbox unless `--sandbox` says otherwise.
+    #[arg(long)]
+    pub untrusted: bool,
+    /// With `--jobs`, write outputs in the order of the documents they came
+    /// from; the default.
+    #[arg(long, conflicts_with = "unordered")]
+    pub ordered: bool,
+    /// With `--jobs`, write outputs as soon as they are ready, whatever the
+    /// order of the documents they came from.
+    #[arg(long)]
+    pub unordered: bool,
+    /// Newline-delimited JSON to run the script over, read in the order
+    /// given as one stream; stdin when left out. Glob patterns read every
+    /// file they match and directories every `.ndjson` and `.json` file
+    /// directly inside, compressed or not, both in sorted order.
+    #[arg(short, long, value_name = "PATH", global = true)]
+    pub input: Vec<String>,
+    /// How the input is read.
+    #[arg(long, value_enum, default_value_t = InputFormat::Ndjson, global = true)]
+    pub input_format: InputFormat,
+    /// Carry on when a pattern or directory given as input has no files.
+    #[arg(long, global = true)]
+    pub allow_empty: bool,
+    /// Pass over this many documents at the start of the input, counting
+    /// across all of its files, without parsing them where the format
+    /// allows. `doc_index()` still counts them.
+    #[arg(long, default_value_t = 0, value_name = "M", global = true)]
+    pub skip: usize,
+    /// Stop reading after this many documents; with 0, the script runs
+    /// without any.
+    #[arg(long, value_name = "N", global = true)]
+    pub limit: Option<usize>,
+    /// Read every document of the input, across all of its files, into a
+    /// single array, which the script gets as its only document. `--skip`
+    /// and `--limit` pick the documents that go in; it all has to fit in
+    /// memory.
+    #[arg(long, global = true)]
+    pub slurp: bool,
+    /// How to decompress the input. Without it, files ending in `.gz` are
+    /// read as gzip, ones ending in `.zst` as zstd, and stdin as it is.
+    #[arg(long, value_enum, global = true)]
+    pub decompress: Option<Decompress>,
+    /// Where to write emitted documents; stdout when left out. The file is
+    /// written under a temporary name and only replaces what was there once
+    /// the run succeeds.
+    #[arg(short, long, conflicts_with = "output_per_channel")]
+    pub output: Option<PathBuf>,
+    /// Add to the end of the output file, creating it if need be, rather
+    /// than replacing it. What a failed run wrote is kept.
+    #[arg(long)]
+    pub append: bool,
+    /// Write every channel to a file of its own in this directory, named
+    /// after the channel, e.g. `out.ndjson`, written and moved into place
+    /// like `--output`.
+    #[arg(long, value_name = "DIR")]
+    pub output_per_channel: Option<PathBuf>,
+    /// How emitted documents are written.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Ndjson)]
+    pub output_format: OutputFormat,
+    /// What to do with input lines, or JSON text sequence records, that are
+    /// not valid JSON.
+    #[arg(long, value_enum, default_value_t = OnInvalidJson::Fail, global = true)]
+    pub on_invalid_json: OnInvalidJson,
+    /// With `--on-invalid-json skip`, still read the whole input, but exit
+    /// with status 4 if any line or record was skipped.
+    #[arg(long)]
+    pub fail_on_input_errors: bool,
+    /// Pretty-print every document over several lines, for JSON output.
+    #[arg(long, group = "format")]
+    pub pretty: bool,
+    /// Write every document on a single line; the default.
+    #[arg(long, group = "format")]
+    pub compact: bool,
+    /// Pretty-print when writing to a terminal, and write single lines
+    /// otherwise.
+    #[arg(long, group = "format")]
+    pub auto: bool,
+    /// What ends every newline-delimited JSON record read: `newline`, `nul`,
+    /// or any text, with `\n`, `\t`, `\0`, `\xHH` and `\\` escapes.
+    /// Records may then span lines.
+    #[arg(
+        long,
+        default_value = "newline",
+        value_parser = parse_separator,
+        value_name = "SEP",
+        global = true
+    )]
+    pub input_separator: Separator,
+    /// What ends every newline-delimited JSON document written, like
+    /// `--input-separator`; with `--pretty`, this is what keeps documents
+    /// spanning lines apart.
+    #[arg(
+        long,
+        default_value = "newline",
+        value_parser = parse_separator,
+        value_name = "SEP"
+    )]
+    pub output_separator: Separator,
+    /// What to write between pretty-printed documents; a blank line by
+    /// default. Left out with an --output-separator other than newline.
+    #[arg(
+        long,
+        default_value = "\n",
+        hide_default_value = true,
+        value_name = "TEXT"
+    )]
+    pub separator: String,
+    /// Write emitted strings as their text rather than as JSON, and anything
+    /// else as JSON, or with `--raw-output=strict`, fail on anything else.
+    #[arg(
+        short,
+        long,
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "lenient",
+        value_name = "MODE"
+    )]
+    pub raw_output: Option<RawArg>,
+    /// Like `--raw-output`, without a separator after every document.
+    #[arg(long)]
+    pub join_output: bool,
+    /// Flush the output after every document rather than when the buffer
+    /// fills, for following the output as it is produced.
+    #[arg(long)]
+    pub line_buffered: bool,
+    /// Run the script again every time it changes, until interrupted. The
+    /// input must come from files, read anew on every run.
+    #[arg(long)]
+    pub watch: bool,
+    /// With `--watch`, also run again when an input file changes.
+    #[arg(long, requires = "watch")]
+    pub watch_inputs: bool,
+    /// Once the run is over, write to stderr how many documents were read
+    /// and emitted to every channel, failures, how long it took, peak Lua
+    /// memory and the script's metrics.
+    #[arg(long, conflicts_with = "stats_json")]
+    pub stats: bool,
+    /// Like `--stats`, as a single JSON object.
+    #[arg(long)]
+    pub stats_json: bool,
+    /// Show on stderr how many documents were read, how fast and for how
+    /// long, and with input files, how much of them is done and how long the
+    /// rest should take. Only when stderr is a terminal, unless
+    /// `--progress=force`.
+    #[arg(
+        long,
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "auto",
+        value_name = "WHEN"
+    )]
+    pub progress: Option<ProgressArg>,
+    /// CSV has no header record: input columns are named `column1`,
+    /// `column2` and so on, and output is written without one.
+    #[arg(long, global = true)]
+    pub no_header: bool,
+    /// Read CSV fields that look like numbers or booleans as such, and empty
+    /// ones as null, rather than all as strings.
+    #[arg(long, global = true)]
+    pub csv_types: bool,
+    /// The columns of CSV output: `first` for the fields of the first
+    /// document, `union` for those of every document, or a comma-separated
+    /// list of fields.
+    #[arg(long, default_value = "first", value_name = "COLUMNS")]
+    pub csv_columns: String,
+    /// Flatten nested values into a CSV column per leaf, named by the path to
+    /// it joined by dots, rather than failing on them.
+    #[arg(long)]
+    pub csv_flatten: bool,
+    /// The character separating CSV fields.
+    #[arg(long, default_value_t = ',', value_name = "CHAR", global = true)]
+    pub csv_delimiter: char,
+    /// The character quoting CSV fields.
+    #[arg(long, default_value_t = '"', value_name = "CHAR", global = true)]
+    pub csv_quote: char,
+    /// Quote every field of CSV output, rather than only the ones that need
+    /// it.
+    #[arg(long)]
+    pub csv_quote_all: bool,
+    /// Read JSONC and JSON5 input a document per line, as with
+    /// newline-delimited JSON, rather than a document per file.
+    #[arg(long, global = true)]
+    pub json_lines: bool,
+    /// Precede every MessagePack value, read or written, with its length as
+    /// a big-endian 32-bit integer, rather than putting values one after
+    /// the other.
+    #[arg(long, global = true)]
+    pub msgpack_length_prefixed: bool,
+    /// What to do with CBOR map keys that are not strings.
+    #[cfg(feature = "cbor")]
+    #[arg(long, value_enum, default_value_t = CborKeys::Stringify, global = true)]
+    pub cbor_keys: CborKeys,
+    /// The Parquet columns to read, comma-separated, leaving the others
+    /// undecoded; every column when left out.
+    #[cfg(feature = "parquet")]
+    #[arg(long, value_delimiter = ',', value_name = "COLUMNS", global = true)]
+    pub columns: Option<Vec<String>>,
+    /// How Parquet timestamps are written.
+    #[cfg(feature = "parquet")]
+    #[arg(long, value_enum, default_value_t = TimestampArg::Rfc3339, global = true)]
+    pub parquet_timestamps: TimestampArg,
+    /// The XML element every one of which is a document, wherever it is,
+    /// rather than the root element being the one document.
+    #[arg(long, value_name = "TAG", global = true)]
+    pub xml_record: Option<String>,
+    /// Keep namespace prefixes on XML names, and `xmlns` attributes, rather
+    /// than dropping them.
+    #[arg(long, global = true)]
+    pub xml_keep_namespaces: bool,
+}
+
+/// What to do instead of running a script.
+#[derive(Clone, Debug, PartialEq, Eq, Subcommand)]
+pub enum Command {
+    /// Prompt for Lua to run against the input a chunk at a time, with
+    /// `get_next` and `emit` at hand and variables kept from one chunk to
+    /// the next.
+    Repl,
+    /// Check that scripts compile, without running them, printing a line
+    /// for every one; fails if any does not.
+    Check {
+        /// The scripts to check.
+        #[arg(required = true, value_name = "SCRIPT")]
+        scripts: Vec<PathBuf>,
+        /// Also warn about globals the scripts read that neither they nor
+        /// the runner define, as found by a best-effort scan of the source.
+        #[arg(long)]
+        undefined_globals: bool,
+    },
+}
+
+/// [`mlua_play::TimestampFormat`], as spelled on the command line.
+#[cfg(feature = "parquet")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TimestampArg {
+    /// RFC 3339 strings, taking timestamps without a time zone as UTC.
+    Rfc3339,
+    /// Milliseconds since the Unix epoch.
+    EpochMillis,
+}
+
+#[cfg(feature = "parquet")]
+impl From<TimestampArg> for mlua_play::TimestampFormat {
+    fn from(timestamps: TimestampArg) -> Self {
+        match timestamps {
+            TimestampArg::Rfc3339 => mlua_play::TimestampFormat::Rfc3339,
+            TimestampArg::EpochMillis => mlua_play::TimestampFormat::EpochMillis,
+        }
+    }
+}
+
+/// What to do with CBOR map keys that are not strings.
+#[cfg(feature = "cbor")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CborKeys {
+    /// Spell numbers and booleans out as strings.
+    Stringify,
+    /// Fail on the document.
+    Reject,
+}
+
+/// [`Sandbox`], as spelled on the command line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SandboxArg {
+    /// The whole standard library, `io`, `os`, `debug` and `require`
+    /// included.
+    Full,
+    /// Removes `io`, `debug`, `package`, `require`, `dofile`, `loadfile` and
+    /// `jit`, and every `os` function but `time`, `clock` and `date`.
+    Standard,
+    /// Removes what `standard` does and the rest of `os`, leaving the base
+    /// library, `string`, `table`, `math` and `bit`.
+    Pure,
+}
+
+impl From<SandboxArg> for Sandbox {
+    fn from(sandbox: SandboxArg) -> Self {
+        match sandbox {
+            SandboxArg::Full => Sandbox::Full,
+            SandboxArg::Standard => Sandbox::Standard,
+            SandboxArg::Pure => Sandbox::Pure,
+        }
+    }
+}
+
+/// When `--progress` is shown.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ProgressArg {
+    /// When stderr is a terminal.
+    Auto,
+    /// Always.
+    Force,
+}
+
+/// [`RawOutput`], as spelled on the command line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RawArg {
+    /// Write anything but strings as JSON.
+    Lenient,
+    /// Fail on anything but strings.
+    Strict,
+}
+
+impl From<RawArg> for RawOutput {
+    fn from(raw: RawArg) -> Self {
+        match raw {
+            RawArg::Lenient => RawOutput::Strings,
+            RawArg::Strict => RawOutput::StringsOnly,
+        }
+    }
+}
+
+/// [`Mode`], as spelled on the command line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ModeArg {
+    /// The script reads with `get_next` and writes with `emit`.
+    FreeForm,
+    /// The script defines `transform(doc)`.
+    Map,
+    /// The script defines `init`, `accumulate` and `finalize`.
+    Aggregate,
+}
+
+/// What a document the script fails on does to the run, as spelled on the
+/// command line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OnError {
+    /// Fail the run, naming the document.
+    Abort,
+    /// Drop the document and carry on with the next one.
+    Continue,
+}
+
+/// How emitted documents are written, as spelled on the command line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Newline-delimited JSON, a document per line unless pretty-printed.
+    Ndjson,
+    /// A JSON text sequence (RFC 7464), a document per RS-framed record,
+    /// pretty-printed with `--pretty`.
+    JsonSeq,
+    /// A YAML stream, with `---` between documents.
+    Yaml,
+    /// A TOML file, for runs emitting a single object.
+    Toml,
+    /// CSV, a record per document after a header naming the columns.
+    Csv,
+    /// MessagePack, a value per document.
+    Msgpack,
+    /// CBOR, a data item per document.
+    #[cfg(feature = "cbor")]
+    Cbor,
+}
+
+impl OutputFormat {
+    /// The extension of files in this format, for `--output-per-channel`.
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::JsonSeq => "json-seq",
+            OutputFormat::Yaml => "yaml",
+            OutputFormat::Toml => "toml",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Msgpack => "msgpack",
+            #[cfg(feature = "cbor")]
+            OutputFormat::Cbor => "cbor",
+        }
+    }
+}
+
+impl From<ModeArg> for Mode {
+    fn from(mode: ModeArg) -> Self {
+        match mode {
+            ModeArg::FreeForm => Mode::FreeForm,
+            ModeArg::Map => Mode::Map,
+            ModeArg::Aggregate => Mode::Aggregate,
+        }
+    }
+}
+
+impl From<OnError> for ErrorPolicy {
+    fn from(on_error: OnError) -> Self {
+        match on_error {
+            OnError::Abort => ErrorPolicy::Abort,
+            OnError::Continue => ErrorPolicy::Collect,
+        }
+    }
+}
+
+fn is_stdin(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// The sandbox `--sandbox` names, or the one the script deserves by default.
+fn sandbox(args: &Args) -> Sandbox {
+    if let Some(sandbox) = args.sandbox {
+        return sandbox.into();
+    }
+    let foreign = args
+        .script
+        .as_deref()
+        .is_some_and(|script| !is_stdin(script) && owned_by_another_user(script));
+    if args.untrusted || foreign {
+        Sandbox::Standard
+    } else {
+        Sandbox::Full
+    }
+}
+
+#[cfg(unix)]
+fn owned_by_another_user(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    std::fs::metadata(path)
+        .is_ok_and(|metadata| metadata.uid() != rustix::process::geteuid().as_raw())
+}
+
+#[cfg(not(unix))]
+fn owned_by_another_user(_path: &Path) -> bool {
+    false
+}
+
+/// The script's source and the name its errors are reported under, from
+/// `--eval` or the script file.
+fn script_source(args: &Args) -> CliResult<(String, String)> {
+    let Some(path) = &args.script else {
+        let code = args.eval.join("\n");
+        let script = match args.mode {
+            // Kept on the first line so that line numbers stay the same.
+            ModeArg::Map => format!("function transform(doc) {code}\nend"),
+            _ => code,
+        };
+        return Ok((script, "(eval)".to_string()));
+    };
+    read_script(path)
+}
+
+fn read_script(path: &Path) -> CliResult<(String, String)> {
+    let mut source = String::new();
+    if is_stdin(path) {
+        io::stdin()
+            .read_to_string(&mut source)
+            .map_err(file_error("read the script from", None))?;
+        return Ok((source, "(stdin)".to_string()));
+    }
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut source))
+        .map_err(file_error("read script", Some(path)))?;
+    Ok((source, path.display().to_string()))
+}
+
+/// What ends every record, as given to `--input-separator` and
+/// `--output-separator`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Separator(pub Vec<u8>);
+
+fn parse_separator(s: &str) -> Result<Separator, String> {
+    match s {
+        "newline" => return Ok(Separator(b"\n".to_vec())),
+        "nul" => return Ok(Separator(b"\0".to_vec())),
+        "" => return Err("the separator cannot be empty".to_string()),
+        _ => {}
+    }
+    let mut bytes = Vec::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut utf8 = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('r') => bytes.push(b'\r'),
+            Some('t') => bytes.push(b'\t'),
+            Some('0') => bytes.push(0),
+            Some('\\') => bytes.push(b'\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 2)
+                    .ok_or_else(|| format!("'\\x{hex}' is not two hex digits"))?;
+                bytes.push(byte);
+            }
+            Some(c) => return Err(format!("unknown escape '\\{c}'")),
+            None => return Err("the separator ends in a lone '\\'".to_string()),
+        }
+    }
+    Ok(Separator(bytes))
+}
+
+/// A CSV field delimiter or quote, which must be a single byte.
+fn csv_byte(c: char, flag: &str) -> CliResult<u8> {
+    u8::try_from(c)
+        .ok()
+        .filter(|b| b.is_ascii() && !matches!(*b, b'\r' | b'\n'))
+        .ok_or_else(|| CliError::Usage(format!("--{flag} must be an ASCII character, not '{c}'")))
+}
+
+fn csv_input(args: &Args) -> CliResult<CsvInput> {
+    Ok(CsvInput {
+        delimiter: csv_byte(args.csv_delimiter, "csv-delimiter")?,
+        quote: csv_byte(args.csv_quote, "csv-quote")?,
+        header: !args.no_header,
+        infer_types: args.csv_types,
+    })
+}
+
+fn csv_sink(args: &Args, writer: Box<dyn Write>) -> CliResult<CsvSink<Box<dyn Write>>> {
+    let columns = match args.csv_columns.as_str() {
+        "first" => CsvColumns::First,
+        "union" => CsvColumns::Union,
+        names => CsvColumns::Named(names.split(',').map(str::to_string).collect()),
+    };
+    let mut sink = CsvSink::new(writer)
+        .delimiter(csv_byte(args.csv_delimiter, "csv-delimiter")?)
+        .quote(csv_byte(args.csv_quote, "csv-quote")?)
+        .columns(columns);
+    if args.no_header {
+        sink = sink.no_header();
+    }
+    if args.csv_quote_all {
+        sink = sink.quote_all();
+    }
+    if args.csv_flatten {
+        sink = sink.flatten(".");
+    }
+    Ok(sink)
+}
+
+fn open_sink(args: &Args) -> CliResult<Box<dyn OutputSink>> {
+    if args.append && args.output.is_none() && args.output_per_channel.is_none() {
+        return Err(CliError::Usage(
+            "--append needs a file to add to; pass --output or --output-per-channel".to_string(),
+        ));
+    }
+    if let Some(dir) = &args.output_per_channel {
+        fs::create_dir_all(dir).map_err(file_error("create output directory", Some(dir)))?;
+        let format_args = args.clone();
+        let make = Box::new(move |writer: Box<dyn Write>| format_sink(&format_args, writer, false));
+        return Ok(Box::new(ChannelFiles::new(
+            dir.clone(),
+            args.output_format.extension(),
+            args.append,
+            make,
+        )));
+    }
+    match &args.output {
+        Some(path) if args.append => format_sink(args, output::append(path)?, false),
+        Some(path) => {
+            let mut staged = Staged::default();
+            let file = staged
+                .create(path)
+                .map_err(file_error("create output", Some(path)))?;
+            let sink = format_sink(args, Box::new(BufWriter::new(file)), false)?;
+            Ok(Box::new(Committing::new(sink, staged)))
+        }
+        None => {
+            let writer = Box::new(BufWriter::new(io::stdout().lock()));
+            format_sink(args, writer, io::stdout().is_terminal())
+        }
+    }
+}
+
+/// A sink writing `--output-format` to `writer`, which goes to a terminal
+/// when `to_terminal` is set.
+fn format_sink(
+    args: &Args,
+    writer: Box<dyn Write>,
+    to_terminal: bool,
+) -> CliResult<Box<dyn OutputSink>> {
+    match args.output_format {
+        OutputFormat::Ndjson => {}
+        OutputFormat::JsonSeq => {
+            let mut sink = JsonSeqSink::new(writer);
+            if args.pretty || (args.auto && to_terminal) {
+                sink = sink.pretty();
+            }
+            return Ok(Box::new(sink));
+        }
+        OutputFormat::Yaml => return Ok(Box::new(YamlSink::new(writer))),
+        OutputFormat::Toml => return Ok(Box::new(TomlSink::new(writer))),
+        OutputFormat::Csv => return Ok(Box::new(csv_sink(args, writer)?)),
+        OutputFormat::Msgpack => {
+            let mut sink = MsgpackSink::new(writer);
+            if args.msgpack_length_prefixed {
+                sink = sink.length_prefixed();
+            }
+            return Ok(Box::new(sink));
+        }
+        #[cfg(feature = "cbor")]
+        OutputFormat::Cbor => return Ok(Box::new(mlua_play::CborSink::new(writer))),
+    }
+    let mut sink = JsonLinesSink::new(writer);
+    if args.pretty || (args.auto && to_terminal) {
+        sink = sink.pretty(args.separator.clone());
+    }
+    if args.line_buffered {
+        sink = sink.line_buffered();
+    }
+    match (args.raw_output, args.join_output) {
+        (Some(raw), _) => sink = sink.raw(raw.into()),
+        (None, true) => sink = sink.raw(RawOutput::Strings),
+        (None, false) => {}
+    }
+    let separator = if args.join_output {
+        Vec::new()
+    } else {
+        args.output_separator.0.clone()
+    };
+    Ok(Box::new(sink.record_separator(separator)))
+}
+
+/// The documents of the input `--skip` and `--limit` leave, slurped into
+/// one with `--slurp`, reporting progress with `--progress`.
+fn slice_input(args: &Args, input: InputFiles) -> Box<dyn InputSource> {
+    let total = input.total_bytes();
+    let mut sliced = SlicedSource::new(input).skip(args.skip);
+    if let Some(limit) = args.limit {
+        sliced = sliced.limit(limit);
+    }
+    let mut input: Box<dyn InputSource> = Box::new(sliced);
+    let show_progress = match args.progress {
+        Some(ProgressArg::Auto) => io::stderr().is_terminal(),
+        Some(ProgressArg::Force) => true,
+        None => false,
+    };
+    if show_progress {
+        input = Box::new(Progress::new(input, total));
+    }
+    if args.slurp {
+        input = Box::new(SlurpedSource::new(input));
+    }
+    input
+}
+
+fn open_input(args: &Args) -> CliResult<InputFiles> {
+    let paths = expand_inputs(&args.input, args.input_format, args.allow_empty)?;
+    let input = InputFiles::new(
+        paths,
+        args.input_format,
+        csv_input(args)?,
+        args.msgpack_length_prefixed,
+        args.json_lines,
+        args.on_invalid_json,
+        args.decompress,
+    );
+    #[cfg(feature = "cbor")]
+    let input = match args.cbor_keys {
+        CborKeys::Stringify => input,
+        CborKeys::Reject => input.reject_non_string_keys(),
+    };
+    #[cfg(feature = "parquet")]
+    let input = input.parquet(mlua_play::ParquetOptions {
+        columns: args.columns.clone(),
+        timestamps: args.parquet_timestamps.into(),
+        ..mlua_play::ParquetOptions::default()
+    });
+    Ok(input
+        .xml(XmlOptions {
+            record: args.xml_record.clone(),
+            keep_namespaces: args.xml_keep_namespaces,
+        })
+        .record_separator(args.input_separator.0.clone()))
+}
+
+pub fn run(args: Args) -> CliResult<()> {
+    match &args.command {
+        Some(Command::Repl) => return repl::run(&args),
+        Some(Command::Check {
+            scripts,
+            undefined_globals,
+        }) => return check::run(scripts, *undefined_globals),
+        None => {}
+    }
+    if args.watch {
+        return watch::run(&args);
+    }
+    run_script(&args)
+}
+
+/// Runs the script once over the input.
+fn run_script(args: &Args) -> CliResult<()> {
+    if args.script.as_deref().is_some_and(is_stdin) && args.input.is_empty() {
+        return Err(CliError::Usage(
+            "the script and the input cannot both come from stdin; pass --input".to_string(),
+        ));
+    }
+    if args.jobs > 1 && args.mode != ModeArg::Map {
+        return Err(CliError::Usage(
+            "--jobs runs the script on separate Lua states, which share no globals; pass \
+             --mode map"
+                .to_string(),
+        ));
+    }
+    if args.limits.on_limit == OnLimit::SkipDoc && args.mode == ModeArg::FreeForm {
+        return Err(CliError::Usage(
+            "--on-limit skip-doc needs --mode map or aggregate, which can carry on after a \
+             document"
+                .to_string(),
+        ));
+    }
+    if args.on_error == OnError::Continue && args.mode == ModeArg::FreeForm {
+        return Err(CliError::Usage(
+            "--on-error continue needs --mode map or aggregate, which can carry on after a \
+             document"
+                .to_string(),
+        ));
+    }
+    let script_args = script_args(args)?;
+    let (script, script_name) = script_source(args)?;
+
+    let input = open_input(args)?;
+    let reading = input.reading();
+    let invalid = input.invalid_inputs();
+    let invalid_unit = input.invalid_unit();
+    let input = slice_input(args, input);
+    let mut sink = open_sink(args)?;
+
+    let limits = args.limits;
+    let sandbox = sandbox(args);
+    let index_offset = args.skip;
+    let on_error = args.on_error.into();
+    if args.jobs > 1 {
+        let options = ParallelOptions {
+            workers: args.jobs,
+            order: if args.unordered {
+                OutputOrder::Arrival
+            } else {
+                OutputOrder::Input
+            },
+            run_options: Arc::new(move || RunOptions {
+                script_name: Some(script_name.clone()),
+                mode: Mode::Map,
+                index_offset,
+                on_error,
+                sandbox,
+                print_to: Some(Box::new(io::stderr())),
+                args: script_args.clone(),
+                ..limits.options()
+            }),
+        };
+        let mut stats = run_parallel_into(&script, input, options, &mut *sink)
+            .map_err(|err| run_error(err, &reading))?;
+        if args.stats || args.stats_json {
+            stats.failures += invalid.get();
+            stats::report(&stats, args.stats_json);
+        }
+        return check_invalid(args, invalid.get(), invalid_unit);
+    }
+    let options = RunOptions {
+        script_name: Some(script_name),
+        mode: args.mode.into(),
+        on_error,
+        sink: Some(sink),
+        print_to: Some(Box::new(io::stderr())),
+        index_offset,
+        sandbox,
+        args: script_args,
+        ..limits.options()
+    };
+    let mut runner = Runner::with_options(&script, options)?;
+    let result = runner.run_source(input);
+    // Also when the run failed, to show how far it got.
+    if args.stats || args.stats_json {
+        let mut stats = runner.stats();
+        // Skipped invalid input is a failure too, if not the script's.
+        stats.failures += invalid.get();
+        stats::report(&stats, args.stats_json);
+    }
+    result.map_err(|err| run_error(err, &reading))?;
+    check_invalid(args, invalid.get(), invalid_unit)
+}
+
+/// The `args` global `--arg` and `--argjson` make up, if any were given.
+fn script_args(args: &Args) -> CliResult<Option<Value>> {
+    if args.arg.is_empty() && args.argjson.is_empty() {
+        return Ok(None);
+    }
+    let mut script_args = Map::new();
+    for pair in args.arg.chunks(2) {
+        script_args.insert(pair[0].clone(), Value::String(pair[1].clone()));
+    }
+    for pair in args.argjson.chunks(2) {
+        let value = serde_json::from_str(&pair[1]).map_err(|err| {
+            CliError::Usage(format!("--argjson {}: invalid JSON: {err}", pair[0]))
+        })?;
+        script_args.insert(pair[0].clone(), value);
+    }
+    Ok(Some(Value::Object(script_args)))
+}
+
+/// Fails a run that went through with `--fail-on-input-errors` when
+/// `skipped` invalid input lines, or other `unit`s, were passed over.
+fn check_invalid(args: &Args, skipped: usize, unit: &'static str) -> CliResult<()> {
+    if args.fail_on_input_errors && skipped > 0 {
+        return Err(CliError::InputErrors { skipped, unit });
+    }
+    Ok(())
+}
+
+/// `err`, naming the input being read when reading it failed.
+fn run_error(err: Error, reading: &RefCell<Option<PathBuf>>) -> CliError {
+    match err {
+        err @ Error::InputError { .. } => CliError::Input {
+            path: reading.borrow().clone(),
+            source: Box::new(err),
+        },
+        err => err.into(),
+    }
+}
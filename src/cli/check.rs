@@ -0,0 +1,49 @@
+use std::fs;
+use std::path::PathBuf;
+
+use mlua_play::{undefined_globals, validate};
+
+use super::error::{CliError, CliResult};
+
+/// Checks every script in turn, printing `path: OK` for one that compiles
+/// and `path:line: message` for one that does not, and with `warn_globals`,
+/// `path:line: warning: ...` for every global it reads that nothing defines.
+/// Fails once all are checked if any did not compile or could not be read.
+pub(super) fn run(scripts: &[PathBuf], warn_globals: bool) -> CliResult<()> {
+    let mut failed = 0;
+    for path in scripts {
+        let name = path.display();
+        let script = match fs::read_to_string(path) {
+            Ok(script) => script,
+            Err(err) => {
+                println!("{name}: cannot read: {err}");
+                failed += 1;
+                continue;
+            }
+        };
+        if let Err(err) = validate(&script) {
+            match err.line {
+                Some(_) => println!("{name}:{err}"),
+                None => println!("{name}: {err}"),
+            }
+            failed += 1;
+            continue;
+        }
+        println!("{name}: OK");
+        if warn_globals {
+            for global in undefined_globals(&script)? {
+                println!(
+                    "{name}:{}: warning: undefined global '{}'",
+                    global.line, global.name
+                );
+            }
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        failed => Err(CliError::Check {
+            failed,
+            checked: scripts.len(),
+        }),
+    }
+}
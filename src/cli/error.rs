@@ -0,0 +1,150 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What went wrong, with the paths involved.
+#[derive(Debug)]
+pub enum CliError {
+    /// Failed to `action` the file at `path`, or stdin/stdout when `None`.
+    File {
+        action: &'static str,
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    /// Reading the input at `path`, or stdin when `None`, failed.
+    Input {
+        path: Option<PathBuf>,
+        source: Box<mlua_play::Error>,
+    },
+    Usage(String),
+    Run(Box<mlua_play::Error>),
+    /// Watching files for changes failed.
+    Watch(notify::Error),
+    /// The run went through, but with `--fail-on-input-errors`, `skipped`
+    /// invalid input lines, or whatever `unit` the input format reads, were
+    /// passed over.
+    InputErrors {
+        skipped: usize,
+        unit: &'static str,
+    },
+    /// `failed` of the `checked` scripts given to `check` did not compile or
+    /// could not be read.
+    Check {
+        failed: usize,
+        checked: usize,
+    },
+}
+
+/// The exit statuses of the command, as listed by `--help`.
+pub(super) const EXIT_CODES: &str = "\
+Exit status:
+  0  success
+  1  the script failed while running, or writing the output did
+  2  usage error: bad arguments or options
+  3  the script has a syntax error, or one given to check does
+  4  the input failed to parse, or with --fail-on-input-errors, invalid input
+     was skipped
+  5  a resource limit, such as --timeout or --max-memory, was hit";
+
+impl CliError {
+    /// The status to exit with, one of [`EXIT_CODES`].
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            CliError::File { .. } | CliError::Watch(_) => 1,
+            CliError::Usage(_) => 2,
+            CliError::Check { .. } => 3,
+            CliError::Input { .. } | CliError::InputErrors { .. } => 4,
+            CliError::Run(err) => run_exit_code(err),
+        }
+    }
+
+    /// Whether writing to a pipe failed because whatever reads it, like
+    /// `head`, stopped reading, which is no failure of the run.
+    pub fn is_broken_pipe(&self) -> bool {
+        match self {
+            CliError::File { source, .. } => source.kind() == io::ErrorKind::BrokenPipe,
+            CliError::Run(err) => run_broken_pipe(err),
+            _ => false,
+        }
+    }
+}
+
+fn run_broken_pipe(err: &mlua_play::Error) -> bool {
+    use mlua_play::Error;
+    match err {
+        Error::Io(err) => err.kind() == io::ErrorKind::BrokenPipe,
+        Error::Sink { source, .. }
+        | Error::Document { source, .. }
+        | Error::Stage { source, .. }
+        | Error::Script { source, .. } => run_broken_pipe(source),
+        _ => false,
+    }
+}
+
+fn run_exit_code(err: &mlua_play::Error) -> u8 {
+    use mlua_play::Error;
+    match err {
+        Error::ScriptSyntax { .. } => 3,
+        Error::InvalidOption { .. } => 2,
+        Error::InputError { .. } => 4,
+        Error::LimitExceeded { .. } | Error::OutputLimitExceeded { .. } => 5,
+        Error::Document { source, .. }
+        | Error::Stage { source, .. }
+        | Error::Script { source, .. } => run_exit_code(source),
+        _ => 1,
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::File {
+                action,
+                path: Some(path),
+                source,
+            } => write!(f, "cannot {action} '{}': {source}", path.display()),
+            CliError::File {
+                action,
+                path: None,
+                source,
+            } => write!(f, "cannot {action} the standard stream: {source}"),
+            CliError::Input {
+                path: Some(path),
+                source,
+            } => write!(f, "in '{}': {source}", path.display()),
+            CliError::Input { path: None, source } => write!(f, "in stdin: {source}"),
+            CliError::Usage(message) => write!(f, "{message}"),
+            CliError::Run(err) => write!(f, "{err}"),
+            CliError::Watch(err) => write!(f, "cannot watch for changes: {err}"),
+            CliError::InputErrors { skipped: 1, unit } => {
+                write!(f, "skipped 1 invalid input {unit}")
+            }
+            CliError::Check { failed, checked } => {
+                write!(f, "{failed} of {checked} scripts failed the check")
+            }
+            CliError::InputErrors { skipped, unit } => {
+                write!(f, "skipped {skipped} invalid input {unit}s")
+            }
+        }
+    }
+}
+
+impl From<mlua_play::Error> for CliError {
+    fn from(err: mlua_play::Error) -> Self {
+        CliError::Run(Box::new(err))
+    }
+}
+
+pub type CliResult<T> = Result<T, CliError>;
+
+pub(super) fn file_error(
+    action: &'static str,
+    path: Option<&Path>,
+) -> impl FnOnce(io::Error) -> CliError {
+    let path = path.map(Path::to_path_buf);
+    move |source| CliError::File {
+        action,
+        path,
+        source,
+    }
+}
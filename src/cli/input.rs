@@ -0,0 +1,502 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use clap::ValueEnum;
+use mlua_play::{
+    Compression, CsvSource, Error, InputSource, JsonDialect, JsonDocumentSource, JsonLinesSource,
+    JsonSeqSource, MsgpackSource, SourcePosition, TomlSource, XmlOptions, XmlSource, YamlSource,
+};
+use serde_json::Value;
+
+use super::error::{CliError, CliResult, file_error};
+use super::is_stdin;
+
+/// How inputs are read, as spelled on the command line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum InputFormat {
+    /// Newline-delimited JSON, a document per line.
+    Ndjson,
+    /// JSON with comments and trailing commas, a document per file, or per
+    /// line with `--json-lines`.
+    Jsonc,
+    /// JSON5, a document per file, or per line with `--json-lines`.
+    Json5,
+    /// A JSON text sequence (RFC 7464), a document per RS-framed record;
+    /// `--on-invalid-json skip` passes over malformed records.
+    JsonSeq,
+    /// A YAML stream, a document per `---`-separated YAML document.
+    Yaml,
+    /// TOML, a document per file.
+    Toml,
+    /// CSV, a document per record after the header.
+    Csv,
+    /// MessagePack, a document per value.
+    Msgpack,
+    /// CBOR, a document per data item.
+    #[cfg(feature = "cbor")]
+    Cbor,
+    /// Parquet, a document per row; files only, not stdin.
+    #[cfg(feature = "parquet")]
+    Parquet,
+    /// XML, a document per file, or per `--xml-record` element.
+    Xml,
+}
+
+impl InputFormat {
+    /// Extensions of the files a directory given as input stands for.
+    fn extensions(self) -> &'static [&'static str] {
+        match self {
+            InputFormat::Ndjson => &["ndjson", "json"],
+            InputFormat::Jsonc => &["jsonc", "json"],
+            InputFormat::Json5 => &["json5", "json"],
+            InputFormat::JsonSeq => &["json-seq"],
+            InputFormat::Yaml => &["yaml", "yml"],
+            InputFormat::Toml => &["toml"],
+            InputFormat::Csv => &["csv"],
+            InputFormat::Msgpack => &["msgpack", "mpk"],
+            #[cfg(feature = "cbor")]
+            InputFormat::Cbor => &["cbor"],
+            #[cfg(feature = "parquet")]
+            InputFormat::Parquet => &["parquet"],
+            InputFormat::Xml => &["xml"],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OnInvalidJson {
+    /// Stop with an error naming the line.
+    Fail,
+    /// Report the line on stderr and carry on with the next one.
+    Skip,
+}
+
+/// How inputs are decompressed, as spelled on the command line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Decompress {
+    /// Go by the magic bytes every input, stdin included, starts with.
+    Auto,
+    /// Every input is gzip.
+    Gzip,
+    /// Every input is zstd.
+    Zstd,
+    /// No input is compressed, whatever its extension.
+    Plain,
+}
+
+impl Decompress {
+    /// The compression of the input at `path`, or stdin when `None`, which
+    /// without `--decompress` goes by the extension and takes stdin as
+    /// uncompressed.
+    fn compression(
+        setting: Option<Self>,
+        path: Option<&Path>,
+        reader: &mut impl BufRead,
+    ) -> io::Result<Option<Compression>> {
+        Ok(match setting {
+            None => path.and_then(Compression::from_extension),
+            Some(Decompress::Auto) => Compression::sniff(reader)?,
+            Some(Decompress::Gzip) => Some(Compression::Gzip),
+            Some(Decompress::Zstd) => Some(Compression::Zstd),
+            Some(Decompress::Plain) => None,
+        })
+    }
+}
+
+/// Turns the `--input` arguments into the files to read, in order, `None`
+/// standing for stdin:
+///
+/// - Without any, the input is stdin, as it is for `-`.
+/// - Glob patterns stand for the files they match, in sorted order.
+/// - Directories stand for the files of `format` directly inside them,
+///   e.g. the `.ndjson` and `.json` ones, compressed or not, in sorted
+///   order, leaving subdirectories alone.
+///
+/// Patterns and directories matching nothing are an error, unless
+/// `allow_empty` is set.
+pub(super) fn expand_inputs(
+    inputs: &[String],
+    format: InputFormat,
+    allow_empty: bool,
+) -> CliResult<Vec<Option<PathBuf>>> {
+    if inputs.is_empty() {
+        return Ok(vec![None]);
+    }
+    let mut paths = Vec::new();
+    for input in inputs {
+        let path = Path::new(input);
+        if is_stdin(path) {
+            paths.push(None);
+            continue;
+        }
+        let mut matched = if input.contains(['*', '?', '[']) {
+            glob_matches(input)?
+        } else if path.is_dir() {
+            directory_files(path, format)?
+        } else {
+            paths.push(Some(path.to_path_buf()));
+            continue;
+        };
+        if matched.is_empty() && !allow_empty {
+            return Err(CliError::Usage(format!(
+                "no input files match '{input}'; pass --allow-empty to carry on anyway"
+            )));
+        }
+        matched.sort();
+        paths.extend(matched.into_iter().map(Some));
+    }
+    Ok(paths)
+}
+
+fn glob_matches(pattern: &str) -> CliResult<Vec<PathBuf>> {
+    let matches = glob::glob(pattern)
+        .map_err(|err| CliError::Usage(format!("invalid input pattern '{pattern}': {err}")))?;
+    matches
+        .filter(|entry| !entry.as_ref().is_ok_and(|path| path.is_dir()))
+        .map(|entry| {
+            entry.map_err(|err| {
+                let path = err.path().to_path_buf();
+                file_error("read", Some(&path))(err.into())
+            })
+        })
+        .collect()
+}
+
+fn directory_files(dir: &Path, format: InputFormat) -> CliResult<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).map_err(file_error("read directory", Some(dir)))? {
+        let path = entry
+            .map_err(file_error("read directory", Some(dir)))?
+            .path();
+        // Compressed files count by the extension under the compression one.
+        let inner = match Compression::from_extension(&path) {
+            Some(_) => path.with_extension(""),
+            None => path.clone(),
+        };
+        let wanted = inner
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| format.extensions().contains(&ext));
+        if wanted && path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// The documents of every input in turn, as one stream, with the
+/// `--on-invalid-json` policy applied to newline-delimited JSON.
+pub(super) struct InputFiles {
+    pending: VecDeque<Option<PathBuf>>,
+    current: Option<Box<dyn InputSource>>,
+    /// The input being read, kept where [`super::run`] can see it to name
+    /// the input that failed.
+    reading: Rc<RefCell<Option<PathBuf>>>,
+    /// How many invalid lines or records `--on-invalid-json skip` passed
+    /// over.
+    invalid: Rc<Cell<usize>>,
+    /// Bytes read from input files so far, before decompression.
+    consumed: Rc<Cell<u64>>,
+    format: InputFormat,
+    csv: CsvInput,
+    length_prefixed: bool,
+    /// Whether JSONC and JSON5 hold a document per line rather than per
+    /// file.
+    json_lines: bool,
+    /// Whether CBOR map keys that are not strings are an error.
+    #[cfg(feature = "cbor")]
+    string_keys_only: bool,
+    #[cfg(feature = "parquet")]
+    parquet: mlua_play::ParquetOptions,
+    xml: XmlOptions,
+    /// What ends every newline-delimited JSON record.
+    separator: Vec<u8>,
+    policy: OnInvalidJson,
+    decompress: Option<Decompress>,
+}
+
+/// How CSV input is read.
+pub(super) struct CsvInput {
+    pub(super) delimiter: u8,
+    pub(super) quote: u8,
+    pub(super) header: bool,
+    pub(super) infer_types: bool,
+}
+
+impl InputFiles {
+    pub(super) fn new(
+        paths: Vec<Option<PathBuf>>,
+        format: InputFormat,
+        csv: CsvInput,
+        length_prefixed: bool,
+        json_lines: bool,
+        policy: OnInvalidJson,
+        decompress: Option<Decompress>,
+    ) -> Self {
+        Self {
+            pending: paths.into(),
+            current: None,
+            reading: Rc::default(),
+            invalid: Rc::default(),
+            consumed: Rc::default(),
+            format,
+            csv,
+            length_prefixed,
+            json_lines,
+            #[cfg(feature = "cbor")]
+            string_keys_only: false,
+            #[cfg(feature = "parquet")]
+            parquet: mlua_play::ParquetOptions::default(),
+            xml: XmlOptions::default(),
+            separator: b"\n".to_vec(),
+            policy,
+            decompress,
+        }
+    }
+
+    /// Fails on CBOR map keys that are not strings instead of spelling them
+    /// out.
+    #[cfg(feature = "cbor")]
+    pub(super) fn reject_non_string_keys(mut self) -> Self {
+        self.string_keys_only = true;
+        self
+    }
+
+    #[cfg(feature = "parquet")]
+    pub(super) fn parquet(mut self, options: mlua_play::ParquetOptions) -> Self {
+        self.parquet = options;
+        self
+    }
+
+    pub(super) fn xml(mut self, options: XmlOptions) -> Self {
+        self.xml = options;
+        self
+    }
+
+    pub(super) fn record_separator(mut self, separator: Vec<u8>) -> Self {
+        self.separator = separator;
+        self
+    }
+
+    pub(super) fn reading(&self) -> Rc<RefCell<Option<PathBuf>>> {
+        self.reading.clone()
+    }
+
+    /// The count of invalid lines or records skipped so far, kept up to
+    /// date as the input is read.
+    pub(super) fn invalid_inputs(&self) -> Rc<Cell<usize>> {
+        self.invalid.clone()
+    }
+
+    /// What `--on-invalid-json skip` passes over in this input's format.
+    pub(super) fn invalid_unit(&self) -> &'static str {
+        match self.format {
+            InputFormat::JsonSeq => "record",
+            InputFormat::Jsonc | InputFormat::Json5 if !self.json_lines => "document",
+            _ => "line",
+        }
+    }
+
+    /// The size of all of the input together, as it is on disk, when it is
+    /// all in files that can be read as they go.
+    pub(super) fn total_bytes(&self) -> Option<u64> {
+        #[cfg(feature = "parquet")]
+        if self.format == InputFormat::Parquet {
+            return None;
+        }
+        self.pending
+            .iter()
+            .map(|path| Some(fs::metadata(path.as_ref()?).ok()?.len()))
+            .sum()
+    }
+
+    /// Moves on to the next input, returning false once there is none.
+    fn open_next(&mut self) -> mlua_play::Result<bool> {
+        let Some(path) = self.pending.pop_front() else {
+            self.current = None;
+            return Ok(false);
+        };
+        #[cfg(feature = "parquet")]
+        if self.format == InputFormat::Parquet {
+            let Some(file) = &path else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Parquet input cannot be read from stdin",
+                )
+                .into());
+            };
+            *self.reading.borrow_mut() = path.clone();
+            let file = open_file(file)?;
+            self.current = Some(Box::new(mlua_play::ParquetSource::new(
+                file,
+                &self.parquet,
+            )?));
+            return Ok(true);
+        }
+        // Set first, so that failing to open the input names it.
+        *self.reading.borrow_mut() = path.clone();
+        let mut reader: Box<dyn BufRead> = match &path {
+            Some(path) => Box::new(BufReader::new(Counted {
+                inner: open_file(path)?,
+                count: self.consumed.clone(),
+            })),
+            None => Box::new(io::stdin().lock()),
+        };
+        if let Some(compression) =
+            Decompress::compression(self.decompress, path.as_deref(), &mut reader)?
+        {
+            reader = compression.decoder(reader)?;
+        }
+        self.current = Some(match self.format {
+            InputFormat::Ndjson => Box::new(json_lines(
+                reader,
+                JsonDialect::Strict,
+                &self.separator,
+                path,
+            )),
+            InputFormat::Jsonc | InputFormat::Json5 => {
+                let dialect = if self.format == InputFormat::Jsonc {
+                    JsonDialect::Jsonc
+                } else {
+                    JsonDialect::Json5
+                };
+                if self.json_lines {
+                    Box::new(json_lines(reader, dialect, &self.separator, path))
+                } else {
+                    Box::new(JsonDocumentSource::new(reader, dialect))
+                }
+            }
+            InputFormat::JsonSeq => Box::new(JsonSeqSource::new(reader)),
+            InputFormat::Yaml => Box::new(YamlSource::new(reader)),
+            InputFormat::Toml => Box::new(TomlSource::new(reader)),
+            InputFormat::Csv => {
+                let mut records = CsvSource::new(reader)
+                    .delimiter(self.csv.delimiter)
+                    .quote(self.csv.quote);
+                if !self.csv.header {
+                    records = records.no_header();
+                }
+                if self.csv.infer_types {
+                    records = records.infer_types();
+                }
+                if let Some(path) = &path {
+                    records = records.with_source(path.display().to_string());
+                }
+                Box::new(records)
+            }
+            InputFormat::Msgpack if self.length_prefixed => {
+                Box::new(MsgpackSource::new(reader).length_prefixed())
+            }
+            InputFormat::Msgpack => Box::new(MsgpackSource::new(reader)),
+            #[cfg(feature = "cbor")]
+            InputFormat::Cbor if self.string_keys_only => {
+                Box::new(mlua_play::CborSource::new(reader).reject_non_string_keys())
+            }
+            #[cfg(feature = "cbor")]
+            InputFormat::Cbor => Box::new(mlua_play::CborSource::new(reader)),
+            InputFormat::Xml => Box::new(XmlSource::new(reader, self.xml.clone())),
+            #[cfg(feature = "parquet")]
+            InputFormat::Parquet => unreachable!("Parquet files are opened above"),
+        });
+        Ok(true)
+    }
+}
+
+/// A reader adding up the bytes read through it.
+struct Counted<R> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+}
+
+fn open_file(path: &Path) -> io::Result<File> {
+    File::open(path).map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("cannot open '{}': {err}", path.display()),
+        )
+    })
+}
+
+fn json_lines(
+    reader: Box<dyn BufRead>,
+    dialect: JsonDialect,
+    separator: &[u8],
+    path: Option<PathBuf>,
+) -> JsonLinesSource<Box<dyn BufRead>> {
+    let lines = JsonLinesSource::new(reader)
+        .dialect(dialect)
+        .record_separator(separator);
+    match path {
+        Some(path) => lines.with_source(path.display().to_string()),
+        None => lines,
+    }
+}
+
+impl InputSource for InputFiles {
+    fn next_doc(&mut self) -> mlua_play::Result<Option<Value>> {
+        loop {
+            let Some(current) = &mut self.current else {
+                if !self.open_next()? {
+                    return Ok(None);
+                }
+                continue;
+            };
+            match current.next_doc() {
+                Ok(None) => self.current = None,
+                Err(
+                    err @ (Error::InvalidJson { .. }
+                    | Error::InvalidJson5 { .. }
+                    | Error::InvalidJsonSeq { .. }),
+                ) if self.policy == OnInvalidJson::Skip => {
+                    self.invalid.set(self.invalid.get() + 1);
+                    match &*self.reading.borrow() {
+                        Some(path) => eprintln!("mlua_play: skipping '{}': {err}", path.display()),
+                        None => eprintln!("mlua_play: skipping stdin: {err}"),
+                    }
+                }
+                next => return next,
+            }
+        }
+    }
+
+    fn position(&self) -> Option<SourcePosition> {
+        self.current.as_ref()?.position()
+    }
+
+    /// Counts the bytes of input files as they are on disk, compressed or
+    /// not, leaving stdin out.
+    fn bytes_read(&self) -> Option<u64> {
+        Some(self.consumed.get())
+    }
+
+    fn skip(&mut self, n: usize) -> mlua_play::Result<usize> {
+        let mut skipped = 0;
+        while skipped < n {
+            let Some(current) = &mut self.current else {
+                if !self.open_next()? {
+                    break;
+                }
+                continue;
+            };
+            let wanted = n - skipped;
+            let passed = current.skip(wanted)?;
+            skipped += passed;
+            if passed < wanted {
+                self.current = None;
+            }
+        }
+        Ok(skipped)
+    }
+}
@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+use clap::ValueEnum;
+use mlua_play::{DocumentLimitPolicy, RunOptions};
+
+/// Limits on what the script may take, as given on the command line.
+#[derive(Clone, Copy, Debug, clap::Args)]
+pub struct LimitArgs {
+    /// Stop the run once it has taken this long, e.g. `30s` or `1m 30s`.
+    #[arg(long, value_parser = parse_duration, value_name = "DURATION")]
+    pub timeout: Option<Duration>,
+    /// Stop the run once the script has executed about this many Lua
+    /// instructions, e.g. `1e9`.
+    #[arg(long, value_parser = parse_count, value_name = "COUNT")]
+    pub max_instructions: Option<u64>,
+    /// Stop the run once Lua has allocated this much memory, e.g. `256MB`.
+    #[arg(long, value_parser = parse_size, value_name = "SIZE")]
+    pub max_memory: Option<usize>,
+    /// Like `--timeout`, but for every document on its own, counted from when
+    /// the script gets it.
+    #[arg(long, value_parser = parse_duration, value_name = "DURATION")]
+    pub doc_timeout: Option<Duration>,
+    /// Like `--max-instructions`, but for every document on its own.
+    #[arg(long, value_parser = parse_count, value_name = "COUNT")]
+    pub doc_max_instructions: Option<u64>,
+    /// What a document running past `--doc-timeout` or
+    /// `--doc-max-instructions` does; `skip-doc` needs `--mode map` or
+    /// `aggregate`.
+    #[arg(long, value_enum, default_value_t = OnLimit::Abort)]
+    pub on_limit: OnLimit,
+}
+
+impl LimitArgs {
+    /// Run options with these limits, and defaults otherwise.
+    pub(super) fn options(self) -> RunOptions {
+        RunOptions {
+            timeout: self.timeout,
+            max_instructions: self.max_instructions,
+            max_lua_memory: self.max_memory,
+            per_document_timeout: self.doc_timeout,
+            per_document_max_instructions: self.doc_max_instructions,
+            on_document_limit: self.on_limit.into(),
+            ..RunOptions::default()
+        }
+    }
+}
+
+/// What a document running past `--doc-timeout` or `--doc-max-instructions`
+/// does to the run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OnLimit {
+    /// Fail the run, naming the document.
+    Abort,
+    /// Drop the document and carry on with the next one.
+    SkipDoc,
+}
+
+impl From<OnLimit> for DocumentLimitPolicy {
+    fn from(on_limit: OnLimit) -> Self {
+        match on_limit {
+            OnLimit::Abort => DocumentLimitPolicy::Abort,
+            OnLimit::SkipDoc => DocumentLimitPolicy::Skip,
+        }
+    }
+}
+
+/// A duration such as `30s`, `1m 30s` or `500ms`.
+pub(super) fn parse_duration(s: &str) -> Result<Duration, String> {
+    humantime::parse_duration(s).map_err(|err| err.to_string())
+}
+
+/// A number of bytes such as `256MB`, with units `K`, `M` and `G`, with or
+/// without a trailing `B` or `iB`, all counted in powers of 1024.
+pub(super) fn parse_size(s: &str) -> Result<usize, String> {
+    let s = s.trim();
+    let digits = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(digits);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("'{s}' does not start with a number"))?;
+    let shift = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        unit => return Err(format!("unknown unit '{unit}'; use K, M or G")),
+    };
+    let bytes = number * (1u64 << shift) as f64;
+    if bytes < 1.0 || bytes > usize::MAX as f64 {
+        return Err(format!("'{s}' is out of range"));
+    }
+    Ok(bytes as usize)
+}
+
+/// A count that may be written in scientific notation, such as `1e9`.
+pub(super) fn parse_count(s: &str) -> Result<u64, String> {
+    if let Ok(count) = s.parse() {
+        return Ok(count);
+    }
+    match s.parse::<f64>() {
+        Ok(count) if count.fract() == 0.0 && (0.0..=u64::MAX as f64).contains(&count) => {
+            Ok(count as u64)
+        }
+        _ => Err(format!("'{s}' is not a whole number")),
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use mlua_play::{Error, OutputSink};
+use serde_json::Value;
+
+use super::error::{CliResult, file_error};
+
+/// Files written under a temporary name next to where they belong, moved into
+/// place together once the run succeeds and removed if it does not, so that a
+/// failed run leaves whatever was there before untouched.
+#[derive(Default)]
+pub(super) struct Staged {
+    /// Every file as the temporary path written to and the one it belongs
+    /// at.
+    files: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Staged {
+    /// Creates the temporary file standing in for `path` until
+    /// [`Staged::commit`].
+    pub(super) fn create(&mut self, path: &Path) -> io::Result<File> {
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        let mut temp_name = std::ffi::OsString::from(".");
+        temp_name.push(name);
+        temp_name.push(format!(".{}.tmp", std::process::id()));
+        let temp = path.with_file_name(temp_name);
+        let file = File::create(&temp)?;
+        self.files.push((temp, path.to_path_buf()));
+        Ok(file)
+    }
+
+    /// Moves every file written into place.
+    fn commit(&mut self) -> io::Result<()> {
+        for (temp, path) in self.files.drain(..) {
+            fs::rename(&temp, &path).inspect_err(|_| {
+                let _ = fs::remove_file(&temp);
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        for (temp, _) in &self.files {
+            let _ = fs::remove_file(temp);
+        }
+    }
+}
+
+/// Opens `path` to add to the end of, creating it if need be.
+pub(super) fn append(path: &Path) -> CliResult<Box<dyn Write>> {
+    let file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .map_err(file_error("open output", Some(path)))?;
+    Ok(Box::new(BufWriter::new(file)))
+}
+
+/// A sink writing to [`Staged`] files, which it moves into place once it
+/// finishes.
+pub(super) struct Committing {
+    sink: Box<dyn OutputSink>,
+    staged: Staged,
+}
+
+impl Committing {
+    pub(super) fn new(sink: Box<dyn OutputSink>, staged: Staged) -> Self {
+        Self { sink, staged }
+    }
+}
+
+impl OutputSink for Committing {
+    fn emit(&mut self, channel: &str, value: Value) -> mlua_play::Result<()> {
+        self.sink.emit(channel, value)
+    }
+
+    fn emit_kv(&mut self, channel: &str, key: Value, value: Value) -> mlua_play::Result<()> {
+        self.sink.emit_kv(channel, key, value)
+    }
+
+    fn finish(&mut self) -> mlua_play::Result<()> {
+        self.sink.finish()?;
+        self.staged.commit()?;
+        Ok(())
+    }
+}
+
+/// Makes the sink writing one channel's documents to a file.
+pub(super) type MakeSink = Box<dyn Fn(Box<dyn Write>) -> CliResult<Box<dyn OutputSink>>>;
+
+/// Writes every channel to a file of its own in a directory, named after the
+/// channel, created as soon as something is emitted to it. Files are staged
+/// unless appended to.
+pub(super) struct ChannelFiles {
+    dir: PathBuf,
+    extension: &'static str,
+    append: bool,
+    make: MakeSink,
+    sinks: BTreeMap<String, Box<dyn OutputSink>>,
+    staged: Staged,
+}
+
+impl ChannelFiles {
+    pub(super) fn new(dir: PathBuf, extension: &'static str, append: bool, make: MakeSink) -> Self {
+        Self {
+            dir,
+            extension,
+            append,
+            make,
+            sinks: BTreeMap::new(),
+            staged: Staged::default(),
+        }
+    }
+
+    fn sink(&mut self, channel: &str) -> mlua_play::Result<&mut Box<dyn OutputSink>> {
+        if !self.sinks.contains_key(channel) {
+            // The name becomes a file name, which must not lead out of the
+            // directory.
+            if channel.is_empty() || channel.starts_with('.') || channel.contains(['/', '\\']) {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("channel '{channel}' cannot be used as a file name"),
+                )));
+            }
+            let path = self.dir.join(format!("{channel}.{}", self.extension));
+            let writer: Box<dyn Write> = if self.append {
+                append(&path).map_err(other)?
+            } else {
+                Box::new(BufWriter::new(self.staged.create(&path)?))
+            };
+            let sink = (self.make)(writer).map_err(other)?;
+            self.sinks.insert(channel.to_string(), sink);
+        }
+        Ok(self.sinks.get_mut(channel).expect("inserted above"))
+    }
+}
+
+impl OutputSink for ChannelFiles {
+    fn emit(&mut self, channel: &str, value: Value) -> mlua_play::Result<()> {
+        self.sink(channel)?.emit(channel, value)
+    }
+
+    fn emit_kv(&mut self, channel: &str, key: Value, value: Value) -> mlua_play::Result<()> {
+        self.sink(channel)?.emit_kv(channel, key, value)
+    }
+
+    fn finish(&mut self) -> mlua_play::Result<()> {
+        for sink in self.sinks.values_mut() {
+            sink.finish()?;
+        }
+        self.sinks.clear();
+        self.staged.commit()?;
+        Ok(())
+    }
+}
+
+fn other(err: super::CliError) -> Error {
+    Error::Io(io::Error::other(err.to_string()))
+}
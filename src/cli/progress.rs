@@ -0,0 +1,118 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use mlua_play::{InputSource, SourcePosition};
+use serde_json::Value;
+
+/// How often the progress line is redrawn at most.
+const REFRESH: Duration = Duration::from_millis(250);
+
+/// Reports on stderr how far reading `inner` got, on a line redrawn as
+/// documents are read: how many, how fast and for how long, and when the
+/// size of the input is known, how much of it is done and how long the rest
+/// should take.
+pub(super) struct Progress<S> {
+    inner: S,
+    /// The size of the input, which [`InputSource::bytes_read`] counts up to.
+    total: Option<u64>,
+    documents: usize,
+    started: Instant,
+    drawn: Option<Instant>,
+    /// Whether the input ran out and the line was ended.
+    done: bool,
+}
+
+impl<S: InputSource> Progress<S> {
+    pub(super) fn new(inner: S, total: Option<u64>) -> Self {
+        Self {
+            inner,
+            total,
+            documents: 0,
+            started: Instant::now(),
+            drawn: None,
+            done: false,
+        }
+    }
+
+    fn draw(&mut self) {
+        let elapsed = self.started.elapsed();
+        let secs = elapsed.as_secs_f64();
+        let rate = if secs > 0.0 {
+            self.documents as f64 / secs
+        } else {
+            0.0
+        };
+        let mut line = format!(
+            "{} documents, {rate:.0}/s, {}",
+            self.documents,
+            clock(elapsed)
+        );
+        if let (Some(total), Some(read)) = (self.total, self.inner.bytes_read())
+            && total > 0
+        {
+            let done = (read as f64 / total as f64).min(1.0);
+            line.push_str(&format!(", {:.1}%", done * 100.0));
+            if done > 0.0 {
+                let left = elapsed.as_secs_f64() * (1.0 - done) / done;
+                line.push_str(&format!(", ETA {}", clock(Duration::from_secs_f64(left))));
+            }
+        }
+        // Clears what is left of a longer line drawn before.
+        eprint!("\r{line}\x1b[K");
+        let _ = io::stderr().flush();
+        self.drawn = Some(Instant::now());
+    }
+}
+
+impl<S: InputSource> InputSource for Progress<S> {
+    fn next_doc(&mut self) -> mlua_play::Result<Option<Value>> {
+        let next = self.inner.next_doc()?;
+        match next {
+            Some(_) => {
+                self.documents += 1;
+                if self.drawn.is_none_or(|drawn| drawn.elapsed() >= REFRESH) {
+                    self.draw();
+                }
+            }
+            None if !self.done => {
+                self.draw();
+                eprintln!();
+                self.done = true;
+            }
+            None => {}
+        }
+        Ok(next)
+    }
+
+    fn position(&self) -> Option<SourcePosition> {
+        self.inner.position()
+    }
+
+    fn skip(&mut self, n: usize) -> mlua_play::Result<usize> {
+        self.inner.skip(n)
+    }
+
+    fn bytes_read(&self) -> Option<u64> {
+        self.inner.bytes_read()
+    }
+}
+
+impl<S> Drop for Progress<S> {
+    /// Ends the progress line, so that nothing written after runs into it.
+    fn drop(&mut self) {
+        if self.drawn.is_some() && !self.done {
+            eprintln!();
+        }
+    }
+}
+
+/// `duration` as `m:ss`, or `h:mm:ss` past an hour.
+fn clock(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, minutes, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{secs:02}")
+    } else {
+        format!("{minutes}:{secs:02}")
+    }
+}
@@ -0,0 +1,131 @@
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+
+use mlua_play::{Evaluated, JsonLinesSink, RunOptions, Session};
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+
+use super::error::{CliError, CliResult, file_error};
+use super::{Args, open_input, run_error, sandbox, script_args, slice_input};
+
+const HELP: &str = "\
+:doc          pretty-print the document get_next last returned
+:load <file>  run a Lua file in the session
+:reset        forget every variable set so far
+:help         show this
+:quit         leave, as does Ctrl-D";
+
+/// Prompts for Lua, running every chunk as soon as it is complete, until
+/// `:quit` or end of input. Failures are reported and the session carries
+/// on.
+pub(super) fn run(args: &Args) -> CliResult<()> {
+    if args.input.is_empty() {
+        return Err(CliError::Usage(
+            "the REPL reads Lua from stdin, so the input cannot come from it; pass --input"
+                .to_string(),
+        ));
+    }
+    let script_args = script_args(args)?;
+    let input = open_input(args)?;
+    let reading = input.reading();
+    let input = slice_input(args, input);
+    let mut sink = JsonLinesSink::new(io::stdout()).line_buffered();
+    if io::stdout().is_terminal() {
+        sink = sink.pretty("\n");
+    }
+    let options = RunOptions {
+        script_name: Some("repl".to_string()),
+        print_to: Some(Box::new(io::stdout())),
+        sink: Some(Box::new(sink)),
+        index_offset: args.skip,
+        sandbox: sandbox(args),
+        args: script_args,
+        ..RunOptions::default()
+    };
+    let mut session = Session::new(options, input)?;
+    let mut editor = DefaultEditor::new().map_err(prompt_error)?;
+
+    let mut chunk = String::new();
+    loop {
+        let prompt = if chunk.is_empty() { "> " } else { ">> " };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            // Ctrl-C drops the chunk being typed, as in a shell.
+            Err(ReadlineError::Interrupted) => {
+                chunk.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => return Ok(()),
+            Err(err) => return Err(prompt_error(err)),
+        };
+        if !line.trim().is_empty() {
+            // Only fails when the history is disabled.
+            let _ = editor.add_history_entry(line.as_str());
+        }
+
+        let result = match line.trim().strip_prefix(':') {
+            Some(command) if chunk.is_empty() => match meta_command(&mut session, command) {
+                Ok(Some(values)) => Ok(values),
+                Ok(None) => return Ok(()),
+                Err(err) => Err(err),
+            },
+            _ => {
+                chunk.push_str(&line);
+                chunk.push('\n');
+                match session.eval(&chunk) {
+                    Ok(Evaluated::Incomplete) => continue,
+                    Ok(Evaluated::Done(values)) => Ok(values),
+                    Err(err) => Err(run_error(err, &reading)),
+                }
+            }
+        };
+        chunk.clear();
+        match result {
+            Ok(values) => {
+                for value in values {
+                    println!("{value}");
+                }
+            }
+            Err(err) => eprintln!("error: {err}"),
+        }
+    }
+}
+
+/// Runs `:command`, returning what to show, or `None` to quit.
+fn meta_command(session: &mut Session, command: &str) -> CliResult<Option<Vec<String>>> {
+    let (name, arg) = command
+        .split_once(char::is_whitespace)
+        .map_or((command, ""), |(name, arg)| (name, arg.trim()));
+    Ok(Some(match (name, arg) {
+        ("doc", "") => match session.last_document() {
+            Some(doc) => vec![serde_json::to_string_pretty(&doc).expect("JSON values serialize")],
+            None => vec!["no document read yet".to_string()],
+        },
+        ("load", "") => return Err(CliError::Usage(":load needs a file".to_string())),
+        ("load", path) => {
+            let code =
+                fs::read_to_string(path).map_err(file_error("read", Some(Path::new(path))))?;
+            session.load(&code, path)?
+        }
+        ("reset", "") => {
+            session.reset()?;
+            Vec::new()
+        }
+        ("help", "") => vec![HELP.to_string()],
+        ("quit" | "q", "") => return Ok(None),
+        _ => {
+            return Err(CliError::Usage(format!(
+                "unknown command ':{command}'; :help lists them"
+            )));
+        }
+    }))
+}
+
+fn prompt_error(err: ReadlineError) -> CliError {
+    let err = match err {
+        ReadlineError::Io(err) => err,
+        err => io::Error::other(err),
+    };
+    file_error("prompt on", None)(err)
+}
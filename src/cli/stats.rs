@@ -0,0 +1,110 @@
+use mlua_play::{Metric, RunStats};
+use serde_json::{Map, Value, json};
+
+/// Writes a summary of a run to stderr, for `--stats`, or as a single JSON
+/// object with `json`, for `--stats-json`.
+pub(super) fn report(stats: &RunStats, json: bool) {
+    if json {
+        eprintln!("{}", to_json(stats));
+        return;
+    }
+    let mut lines = vec![(
+        "documents read".to_string(),
+        stats.documents_read.to_string(),
+    )];
+    for (channel, count) in &stats.emitted_by_channel {
+        lines.push((format!("emitted to {channel}"), count.to_string()));
+    }
+    if stats.emitted_by_channel.is_empty() {
+        lines.push(("emitted".to_string(), "0".to_string()));
+    }
+    lines.push(("failures".to_string(), stats.failures.to_string()));
+    lines.push((
+        "elapsed".to_string(),
+        format!("{:.3}s", stats.elapsed.as_secs_f64()),
+    ));
+    lines.push((
+        "documents/second".to_string(),
+        format!("{:.1}", per_second(stats)),
+    ));
+    lines.push(("peak Lua memory".to_string(), size(stats.peak_memory)));
+    if let Some(instructions) = stats.instructions {
+        lines.push(("instructions".to_string(), instructions.to_string()));
+    }
+    for (name, metric) in &stats.metrics {
+        let value = match metric {
+            Metric::Counter(total) => total.to_string(),
+            Metric::Gauge(value) => value.to_string(),
+            Metric::Summary(summary) => format!(
+                "count {}, sum {}, min {}, max {}",
+                summary.count, summary.sum, summary.min, summary.max
+            ),
+        };
+        lines.push((format!("metric {name}"), value));
+    }
+
+    let width = lines
+        .iter()
+        .map(|(label, _)| label.len())
+        .max()
+        .unwrap_or(0);
+    for (label, value) in lines {
+        eprintln!("{label:<width$}  {value}");
+    }
+}
+
+fn to_json(stats: &RunStats) -> Value {
+    let metrics = stats
+        .metrics
+        .iter()
+        .map(|(name, metric)| {
+            let metric = match metric {
+                Metric::Counter(total) => json!({ "type": "counter", "value": total }),
+                Metric::Gauge(value) => json!({ "type": "gauge", "value": value }),
+                Metric::Summary(summary) => json!({
+                    "type": "summary",
+                    "count": summary.count,
+                    "sum": summary.sum,
+                    "min": summary.min,
+                    "max": summary.max,
+                }),
+            };
+            (name.clone(), metric)
+        })
+        .collect::<Map<_, _>>();
+    json!({
+        "documents_read": stats.documents_read,
+        "emitted": stats.emitted_by_channel,
+        "failures": stats.failures,
+        "elapsed_seconds": stats.elapsed.as_secs_f64(),
+        "documents_per_second": per_second(stats),
+        "peak_memory_bytes": stats.peak_memory,
+        "instructions": stats.instructions,
+        "metrics": metrics,
+    })
+}
+
+fn per_second(stats: &RunStats) -> f64 {
+    let seconds = stats.elapsed.as_secs_f64();
+    if seconds > 0.0 {
+        stats.documents_read as f64 / seconds
+    } else {
+        0.0
+    }
+}
+
+/// `bytes` in the largest unit of 1024 it makes at least one of.
+fn size(bytes: usize) -> String {
+    let units = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < units.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", units[unit])
+    }
+}
@@ -0,0 +1,146 @@
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use super::error::{CliError, CliResult, file_error};
+use super::input::expand_inputs;
+use super::{Args, is_stdin, run_script};
+
+/// How long files must go unchanged before a burst of writes to them counts
+/// as done, so that an editor saving in several steps triggers a single run.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Runs the script, and again every time it changes, or with
+/// `--watch-inputs` one of the input files does, until interrupted. Failures
+/// are reported and watching carries on.
+pub(super) fn run(args: &Args) -> CliResult<()> {
+    let script = match &args.script {
+        Some(script) if !is_stdin(script) => script.clone(),
+        _ => {
+            return Err(CliError::Usage(
+                "--watch needs a script file to watch".to_string(),
+            ));
+        }
+    };
+    let inputs = expand_inputs(&args.input, args.input_format, args.allow_empty)?;
+    let Some(inputs) = inputs.into_iter().collect::<Option<Vec<_>>>() else {
+        return Err(CliError::Usage(
+            "--watch reads the input again on every run, which stdin cannot be; pass --input \
+             with files"
+                .to_string(),
+        ));
+    };
+    let mut files = vec![script];
+    if args.watch_inputs {
+        files.extend(inputs);
+    }
+    let changes = Changes::watch(&files)?;
+
+    // Clearing the screen only makes sense when that is where output goes.
+    let clear =
+        args.output.is_none() && args.output_per_channel.is_none() && io::stdout().is_terminal();
+    let mut runs = 1;
+    loop {
+        if let Err(err) = run_script(args) {
+            eprintln!("mlua_play: {err}");
+        }
+        eprintln!("[watch] run {runs} done; waiting for changes");
+        let changed = changes.wait()?;
+        runs += 1;
+        if clear {
+            print!("\x1b[2J\x1b[H");
+            io::stdout().flush().map_err(file_error("write to", None))?;
+        }
+        let names = changed
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>();
+        eprintln!("[watch] run {runs}: {} changed", names.join(", "));
+    }
+}
+
+/// Changes to a set of files, a burst of writes at a time.
+struct Changes {
+    // Stops watching once dropped.
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    /// The files watched, made absolute to compare with the paths of
+    /// events.
+    files: Vec<PathBuf>,
+}
+
+impl Changes {
+    /// Watches the directories holding `files` rather than the files
+    /// themselves, which editors saving by renaming a new file over the old
+    /// one would otherwise leave unwatched after the first save.
+    fn watch(files: &[PathBuf]) -> CliResult<Self> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender).map_err(CliError::Watch)?;
+        let mut dirs: Vec<PathBuf> = Vec::new();
+        let mut absolute = Vec::new();
+        for file in files {
+            let file = file
+                .canonicalize()
+                .map_err(file_error("watch", Some(file)))?;
+            if let Some(dir) = file.parent()
+                && !dirs.iter().any(|watched| watched == dir)
+            {
+                watcher
+                    .watch(dir, RecursiveMode::NonRecursive)
+                    .map_err(CliError::Watch)?;
+                dirs.push(dir.to_path_buf());
+            }
+            absolute.push(file);
+        }
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            files: absolute,
+        })
+    }
+
+    /// Blocks until a watched file changes and then stays unchanged for
+    /// [`DEBOUNCE`], returning the files that changed.
+    fn wait(&self) -> CliResult<Vec<PathBuf>> {
+        let mut changed = Vec::new();
+        loop {
+            let event = if changed.is_empty() {
+                self.events
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected)
+            } else {
+                self.events.recv_timeout(DEBOUNCE)
+            };
+            match event {
+                Ok(event) => {
+                    for path in self.relevant(event.map_err(CliError::Watch)?) {
+                        if !changed.contains(&path) {
+                            changed.push(path);
+                        }
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => return Ok(changed),
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(CliError::Watch(notify::Error::generic(
+                        "the watcher stopped",
+                    )));
+                }
+            }
+        }
+    }
+
+    /// The watched files `event` changed.
+    fn relevant(&self, event: Event) -> Vec<PathBuf> {
+        if matches!(event.kind, EventKind::Access(_)) {
+            return Vec::new();
+        }
+        event
+            .paths
+            .into_iter()
+            .filter(|path| self.files.contains(path))
+            .collect()
+    }
+}
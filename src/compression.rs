@@ -0,0 +1,78 @@
+use std::fmt;
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+use std::io::BufReader;
+use std::io::{self, BufRead};
+use std::path::Path;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// A compression format inputs can be read through, decompressing as they
+/// are read rather than all at once.
+///
+/// Gzip needs the `gzip` feature and zstd the `zstd` feature, both on by
+/// default; without them, [`Compression::decoder`] fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    /// Gzip, including several gzip members one after the other.
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// The compression of the file at `path`, going by its extension: `.gz`
+    /// for gzip and `.zst` for zstd.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gz" => Some(Compression::Gzip),
+            "zst" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// The compression `reader` starts with, going by its magic bytes,
+    /// without consuming them. Only looks at what the reader has buffered, or
+    /// reads once to fill its buffer, so a pipe handing over fewer than four
+    /// bytes at first reads as uncompressed.
+    pub fn sniff(reader: &mut impl BufRead) -> io::Result<Option<Self>> {
+        let start = reader.fill_buf()?;
+        Ok(if start.starts_with(GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if start.starts_with(ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        })
+    }
+
+    /// `reader`, decompressed as it is read.
+    pub fn decoder<'a>(self, reader: impl BufRead + 'a) -> io::Result<Box<dyn BufRead + 'a>> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Ok(Box::new(BufReader::new(
+                flate2::bufread::MultiGzDecoder::new(reader),
+            ))),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(Box::new(BufReader::new(
+                zstd::stream::read::Decoder::with_buffer(reader)?,
+            ))),
+            #[allow(unreachable_patterns)]
+            other => {
+                drop(reader);
+                Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("{other} input needs the '{other}' feature"),
+                ))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        })
+    }
+}
@@ -0,0 +1,383 @@
+use std::collections::BTreeSet;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+use serde_json::{Map, Number, Value};
+
+use crate::error::{Error, Result};
+use crate::runner::flatten_into;
+use crate::sink::OutputSink;
+use crate::source::{InputSource, SourcePosition};
+
+/// How CSV is split into fields, shared by [`CsvSource`] and [`CsvSink`].
+#[derive(Clone, Copy, Debug)]
+struct Dialect {
+    delimiter: u8,
+    quote: u8,
+    header: bool,
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            quote: b'"',
+            header: true,
+        }
+    }
+}
+
+/// CSV, with every record after the header becoming an object keyed by the
+/// header's names. Values are strings unless [`CsvSource::infer_types`] is
+/// set. Records with fewer fields than the header leave the rest out, and
+/// ones with more are an error.
+///
+/// Fields are quoted with `"`, or [`CsvSource::quote`], doubled to stand for
+/// itself, and may span lines when quoted.
+pub struct CsvSource<R: Read> {
+    dialect: Dialect,
+    infer_types: bool,
+    /// The reader until the first document is asked for, which settles the
+    /// dialect.
+    reader: Option<R>,
+    records: Option<csv::Reader<R>>,
+    names: Vec<String>,
+    record: csv::StringRecord,
+    line: usize,
+    source: Option<Rc<str>>,
+}
+
+impl<R: Read> CsvSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            dialect: Dialect::default(),
+            infer_types: false,
+            reader: Some(reader),
+            records: None,
+            names: Vec::new(),
+            record: csv::StringRecord::new(),
+            line: 0,
+            source: None,
+        }
+    }
+
+    /// Splits fields on `delimiter` rather than `,`.
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.dialect.delimiter = delimiter;
+        self
+    }
+
+    /// Quotes fields with `quote` rather than `"`.
+    pub fn quote(mut self, quote: u8) -> Self {
+        self.dialect.quote = quote;
+        self
+    }
+
+    /// Reads the first record as a document like the others, naming fields
+    /// `column1`, `column2` and so on.
+    pub fn no_header(mut self) -> Self {
+        self.dialect.header = false;
+        self
+    }
+
+    /// Turns fields spelling an integer or a finite float into numbers,
+    /// `true` and `false` into booleans, and empty fields into null.
+    pub fn infer_types(mut self) -> Self {
+        self.infer_types = true;
+        self
+    }
+
+    /// Names the input, e.g. with the path of the file being read, for
+    /// [`SourcePosition::source`].
+    pub fn with_source(mut self, source: impl Into<Rc<str>>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    fn invalid(&self, message: impl Into<String>) -> Error {
+        Error::InvalidCsv {
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn field_value(&self, field: &str) -> Value {
+        if !self.infer_types {
+            return Value::String(field.to_string());
+        }
+        match field {
+            "" => Value::Null,
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            _ => {
+                if let Ok(n) = field.parse::<i64>() {
+                    Value::from(n)
+                } else if let Some(n) = field.parse::<f64>().ok().and_then(Number::from_f64) {
+                    Value::Number(n)
+                } else {
+                    Value::String(field.to_string())
+                }
+            }
+        }
+    }
+}
+
+impl<R: Read> InputSource for CsvSource<R> {
+    fn next_doc(&mut self) -> Result<Option<Value>> {
+        if let Some(reader) = self.reader.take() {
+            let mut records = csv::ReaderBuilder::new()
+                .delimiter(self.dialect.delimiter)
+                .quote(self.dialect.quote)
+                .has_headers(self.dialect.header)
+                .flexible(true)
+                .from_reader(reader);
+            if self.dialect.header {
+                let names = records
+                    .headers()
+                    .map_err(|err| self.invalid(err.to_string()))?;
+                self.names = names.iter().map(str::to_string).collect();
+            }
+            self.records = Some(records);
+        }
+        let Some(records) = &mut self.records else {
+            return Ok(None);
+        };
+        let read = records.read_record(&mut self.record);
+        if let Some(position) = self.record.position() {
+            self.line = position.line() as usize;
+        }
+        if !read.map_err(|err| self.invalid(err.to_string()))? {
+            return Ok(None);
+        }
+
+        if !self.dialect.header {
+            while self.names.len() < self.record.len() {
+                self.names.push(format!("column{}", self.names.len() + 1));
+            }
+        } else if self.record.len() > self.names.len() {
+            return Err(self.invalid(format!(
+                "record has {} fields, but the header only names {}",
+                self.record.len(),
+                self.names.len()
+            )));
+        }
+        let mut doc = Map::new();
+        for (name, field) in self.names.iter().zip(&self.record) {
+            doc.insert(name.clone(), self.field_value(field));
+        }
+        Ok(Some(Value::Object(doc)))
+    }
+
+    fn position(&self) -> Option<SourcePosition> {
+        Some(SourcePosition {
+            line: self.line,
+            source: self.source.clone(),
+        })
+    }
+}
+
+/// Which fields of the documents become the columns of [`CsvSink`].
+#[derive(Clone, Debug, Default)]
+pub enum CsvColumns {
+    /// The fields of the first document, sorted by name like the fields of
+    /// every document.
+    #[default]
+    First,
+    /// The fields of every document, sorted by name. Nothing is written until
+    /// the run finishes, for the columns to be known.
+    Union,
+    /// The given fields, in order.
+    Named(Vec<String>),
+}
+
+/// Writes every document, which must be an object, as a CSV record, with a
+/// header naming the columns first. Fields a document lacks are written
+/// empty, as are nulls, and numbers and booleans are written as in JSON.
+/// Fields outside the columns are left out.
+///
+/// Arrays and objects are an error, unless [`CsvSink::flatten`] is set,
+/// except empty ones, written as `[]` and `{}`.
+/// Documents emitted with `emit_kv` are written as `{key, value}`.
+pub struct CsvSink<W: Write> {
+    dialect: Dialect,
+    quote_all: bool,
+    columns: CsvColumns,
+    /// The flattening separator, when flattening.
+    flatten: Option<String>,
+    /// The writer until the first record, which settles the dialect.
+    writer: Option<W>,
+    records: Option<csv::Writer<W>>,
+    /// The columns, once known.
+    names: Option<Vec<String>>,
+    /// The documents held back until the columns are known.
+    held: Vec<Map<String, Value>>,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            dialect: Dialect::default(),
+            quote_all: false,
+            columns: CsvColumns::default(),
+            flatten: None,
+            writer: Some(writer),
+            records: None,
+            names: None,
+            held: Vec::new(),
+        }
+    }
+
+    /// Separates fields with `delimiter` rather than `,`.
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.dialect.delimiter = delimiter;
+        self
+    }
+
+    /// Quotes fields with `quote` rather than `"`.
+    pub fn quote(mut self, quote: u8) -> Self {
+        self.dialect.quote = quote;
+        self
+    }
+
+    /// Quotes every field, rather than only the ones that need it.
+    pub fn quote_all(mut self) -> Self {
+        self.quote_all = true;
+        self
+    }
+
+    /// Leaves out the header record.
+    pub fn no_header(mut self) -> Self {
+        self.dialect.header = false;
+        self
+    }
+
+    pub fn columns(mut self, columns: CsvColumns) -> Self {
+        self.columns = columns;
+        self
+    }
+
+    /// Flattens arrays and objects into a column per leaf, named by the path
+    /// to it joined by `separator`, as the `flatten` global does.
+    pub fn flatten(mut self, separator: impl Into<String>) -> Self {
+        self.flatten = Some(separator.into());
+        self
+    }
+
+    fn record_of(&self, value: Value) -> Result<Map<String, Value>> {
+        let Value::Object(doc) = value else {
+            return Err(invalid_output(
+                "CSV output needs every document to be an object",
+            ));
+        };
+        let Some(separator) = &self.flatten else {
+            return Ok(doc);
+        };
+        let mut flat = Map::new();
+        for (key, value) in doc {
+            flatten_into(&mut flat, key, value, separator).map_err(|key| {
+                invalid_output(format!(
+                    "flattened column '{key}' collides with another one"
+                ))
+            })?;
+        }
+        Ok(flat)
+    }
+
+    fn write(&mut self, doc: &Map<String, Value>) -> Result<()> {
+        let names = self
+            .names
+            .as_ref()
+            .expect("columns are known before writing");
+        let starting = self.records.is_none();
+        let records = self.records.get_or_insert_with(|| {
+            let writer = self.writer.take().expect("the writer is only taken once");
+            csv::WriterBuilder::new()
+                .delimiter(self.dialect.delimiter)
+                .quote(self.dialect.quote)
+                .quote_style(if self.quote_all {
+                    csv::QuoteStyle::Always
+                } else {
+                    csv::QuoteStyle::Necessary
+                })
+                .from_writer(writer)
+        });
+        if starting && self.dialect.header {
+            records.write_record(names).map_err(csv_io)?;
+        }
+        let fields = names
+            .iter()
+            .map(|name| field_text(name, doc.get(name)))
+            .collect::<Result<Vec<_>>>()?;
+        records.write_record(&fields).map_err(csv_io)
+    }
+}
+
+impl<W: Write> OutputSink for CsvSink<W> {
+    fn emit(&mut self, _channel: &str, value: Value) -> Result<()> {
+        let doc = self.record_of(value)?;
+        match &self.columns {
+            CsvColumns::Union => {
+                self.held.push(doc);
+                return Ok(());
+            }
+            CsvColumns::First if self.names.is_none() => {
+                self.names = Some(doc.keys().cloned().collect());
+            }
+            CsvColumns::Named(names) if self.names.is_none() => {
+                self.names = Some(names.clone());
+            }
+            _ => {}
+        }
+        self.write(&doc)
+    }
+
+    fn emit_kv(&mut self, channel: &str, key: Value, value: Value) -> Result<()> {
+        self.emit(channel, serde_json::json!({ "key": key, "value": value }))
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if matches!(self.columns, CsvColumns::Union) {
+            let held = std::mem::take(&mut self.held);
+            let names = held
+                .iter()
+                .flat_map(|doc| doc.keys())
+                .collect::<BTreeSet<_>>();
+            self.names = Some(names.into_iter().cloned().collect());
+            for doc in &held {
+                self.write(doc)?;
+            }
+        }
+        match &mut self.records {
+            Some(records) => records.flush()?,
+            None => {
+                if let Some(writer) = &mut self.writer {
+                    writer.flush()?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn field_text(name: &str, value: Option<&Value>) -> Result<String> {
+    Ok(match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Array(items)) if items.is_empty() => "[]".to_string(),
+        Some(Value::Object(fields)) if fields.is_empty() => "{}".to_string(),
+        Some(Value::Array(_) | Value::Object(_)) => {
+            return Err(invalid_output(format!(
+                "column '{name}' holds a nested value, which needs flattening to be written as CSV"
+            )));
+        }
+        Some(value) => value.to_string(),
+    })
+}
+
+fn invalid_output(message: impl Into<String>) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::InvalidData, message.into()))
+}
+
+fn csv_io(err: csv::Error) -> Error {
+    Error::Io(err.into())
+}
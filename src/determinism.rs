@@ -0,0 +1,301 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use mlua::{
+    Error as LuaError, Function as LuaFunction, Lua, Result, String as LuaString,
+    Table as LuaTable, UserData, UserDataMethods, Value as LuaValue,
+};
+use serde_json::Value;
+use uuid::{Builder, Uuid};
+
+use crate::value::{SharedValue, json_to_table, lua_to_json};
+
+/// Where `os.time`, `os.clock` and `os.date` get the current time from.
+pub enum ClockSource {
+    /// Always the given Unix timestamp, in seconds.
+    Fixed(i64),
+    /// Whatever the closure returns, in seconds since the Unix epoch.
+    Custom(Box<dyn Fn() -> f64>),
+}
+
+impl ClockSource {
+    pub(crate) fn now(&self) -> f64 {
+        match self {
+            ClockSource::Fixed(timestamp) => *timestamp as f64,
+            ClockSource::Custom(now) => now(),
+        }
+    }
+}
+
+/// Points the time functions of `os` at `now()`. `os.time` and `os.date`
+/// keep their behavior when given an explicit date or time, and `os.clock`
+/// counts seconds since the clock was installed.
+const CLOCK: &str = r#"
+local now = ...
+if not os then
+    return
+end
+local time, date, floor = os.time, os.date, math.floor
+local start = now()
+os.time = function(t)
+    if t ~= nil then
+        return time(t)
+    end
+    return floor(now())
+end
+os.clock = function()
+    return now() - start
+end
+os.date = function(format, t)
+    return date(format, t or floor(now()))
+end
+"#;
+
+/// SplitMix64, which is small, fast and plenty for scripts that only need
+/// reproducible randomness.
+struct SplitMix64(Cell<u64>);
+
+impl SplitMix64 {
+    fn next_u64(&self) -> u64 {
+        let state = self.0.get().wrapping_add(0x9E37_79B9_7F4A_7C15);
+        self.0.set(state);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    fn next_f64(&self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform in `[low, high]`.
+    fn between(&self, low: i64, high: i64) -> i64 {
+        let span = high.wrapping_sub(low) as u64;
+        match span.checked_add(1) {
+            Some(count) => low.wrapping_add((self.next_u64() % count) as i64),
+            None => self.next_u64() as i64,
+        }
+    }
+}
+
+/// Replaces `math.random` and `math.randomseed` with a generator seeded from
+/// `seed`, so that runs with the same seed see the same numbers.
+pub(crate) fn install_random(lua: &Lua, seed: u64) -> Result<()> {
+    let rng = Rc::new(SplitMix64(Cell::new(seed)));
+    let math: LuaTable = lua.globals().get("math")?;
+
+    let state = rng.clone();
+    math.set(
+        "random",
+        lua.create_function(move |_, (m, n): (Option<i64>, Option<i64>)| {
+            let (low, high) = match (m, n) {
+                (None, _) => return Ok(LuaValue::Number(state.next_f64())),
+                (Some(m), None) => (1, m),
+                (Some(m), Some(n)) => (m, n),
+            };
+            if low > high {
+                return Err(LuaError::runtime(
+                    "bad argument to 'random' (interval is empty)",
+                ));
+            }
+            Ok(LuaValue::Integer(state.between(low, high)))
+        })?,
+    )?;
+
+    math.set(
+        "randomseed",
+        lua.create_function(move |_, seed: f64| {
+            rng.0.set(seed.to_bits());
+            Ok(())
+        })?,
+    )
+}
+
+pub(crate) fn install_clock(lua: &Lua, clock: Rc<ClockSource>) -> Result<()> {
+    let now = lua.create_function(move |_, ()| Ok(clock.now()))?;
+    let setup: LuaFunction = lua
+        .load(CLOCK)
+        .set_name("=[mlua_play clock]")
+        .into_function()?;
+    setup.call(now)
+}
+
+/// Crockford's base32 alphabet, which ULIDs are written in.
+const ULID_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Where `uuid` and `ulid` get their randomness from.
+enum IdSource {
+    Seeded(SplitMix64),
+    System,
+}
+
+impl IdSource {
+    fn random_bytes(&self) -> Result<[u8; 16]> {
+        let mut bytes = [0; 16];
+        match self {
+            IdSource::Seeded(rng) => {
+                bytes[..8].copy_from_slice(&rng.next_u64().to_be_bytes());
+                bytes[8..].copy_from_slice(&rng.next_u64().to_be_bytes());
+            }
+            IdSource::System => getrandom::fill(&mut bytes).map_err(LuaError::external)?,
+        }
+        Ok(bytes)
+    }
+}
+
+/// Installs `uuid()`, `ulid()` and `uuid5(namespace, name)`. With a `seed`
+/// the random ones come from a generator seeded with it, and `ulid` takes its
+/// timestamp from `clock` when there is one, so replayed runs get the same
+/// identifiers.
+///
+/// `namespace` is a UUID or one of `dns`, `url`, `oid` and `x500`.
+pub(crate) fn install_ids(
+    lua: &Lua,
+    seed: Option<u64>,
+    clock: Option<Rc<ClockSource>>,
+) -> Result<()> {
+    let source = Rc::new(match seed {
+        Some(seed) => IdSource::Seeded(SplitMix64(Cell::new(seed))),
+        None => IdSource::System,
+    });
+    let globals = lua.globals();
+
+    let random = source.clone();
+    globals.set(
+        "uuid",
+        lua.create_function(move |_, ()| {
+            let uuid = Builder::from_random_bytes(random.random_bytes()?).into_uuid();
+            Ok(uuid.hyphenated().to_string())
+        })?,
+    )?;
+
+    globals.set(
+        "ulid",
+        lua.create_function(move |_, ()| {
+            let millis = match &clock {
+                Some(clock) => (clock.now() * 1000.0) as u64,
+                None => std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(0, |elapsed| elapsed.as_millis() as u64),
+            };
+            let random = u128::from_be_bytes(source.random_bytes()?);
+            let ulid = (u128::from(millis & 0xFFFF_FFFF_FFFF) << 80) | (random >> 48);
+            let text = (0..26)
+                .map(|i| ULID_ALPHABET[((ulid >> (125 - 5 * i)) & 0x1F) as usize] as char)
+                .collect::<String>();
+            Ok(text)
+        })?,
+    )?;
+
+    globals.set(
+        "uuid5",
+        lua.create_function(|_, (namespace, name): (LuaString, LuaString)| {
+            let namespace = match &*namespace.to_str()? {
+                "dns" => Uuid::NAMESPACE_DNS,
+                "url" => Uuid::NAMESPACE_URL,
+                "oid" => Uuid::NAMESPACE_OID,
+                "x500" => Uuid::NAMESPACE_X500,
+                other => Uuid::parse_str(other).map_err(|err| {
+                    LuaError::runtime(format!("invalid namespace '{other}': {err}"))
+                })?,
+            };
+            let uuid = Uuid::new_v5(&namespace, &name.as_bytes());
+            Ok(uuid.hyphenated().to_string())
+        })?,
+    )
+}
+
+/// Installs `sample(rate)`, which is true with probability `rate`, and
+/// `reservoir(k)`, which keeps a uniform random sample of at most `k` of the
+/// values added to it with `r:add(v)`, returned as an array by `r:items()`.
+/// Values are copied when kept, handles included, so changing them after
+/// adding leaves the sample alone.
+///
+/// Both draw from a generator seeded with `seed` when there is one, making
+/// the sample the same on every run with the same input.
+pub(crate) fn install_sampling(lua: &Lua, seed: Option<u64>) -> Result<()> {
+    let seed = match seed {
+        Some(seed) => seed,
+        None => getrandom::u64().map_err(LuaError::external)?,
+    };
+    let rng = Rc::new(SplitMix64(Cell::new(seed)));
+    let globals = lua.globals();
+
+    let state = rng.clone();
+    globals.set(
+        "sample",
+        lua.create_function(move |_, rate: f64| {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(LuaError::runtime(format!(
+                    "sample rate must be between 0 and 1, got {rate}"
+                )));
+            }
+            Ok(state.next_f64() < rate)
+        })?,
+    )?;
+
+    globals.set(
+        "reservoir",
+        lua.create_function(move |_, capacity: i64| {
+            if capacity <= 0 {
+                return Err(LuaError::runtime(format!(
+                    "reservoir size must be positive, got {capacity}"
+                )));
+            }
+            Ok(Reservoir {
+                capacity: capacity as usize,
+                seen: 0,
+                // Grows as values are kept, as `capacity` may be far more
+                // than are ever added.
+                items: Vec::new(),
+                rng: rng.clone(),
+            })
+        })?,
+    )
+}
+
+/// Algorithm R over copies of the values added.
+struct Reservoir {
+    capacity: usize,
+    seen: u64,
+    items: Vec<Value>,
+    rng: Rc<SplitMix64>,
+}
+
+impl UserData for Reservoir {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method_mut("add", |_, this, val: LuaValue| {
+            this.seen += 1;
+            let slot = if this.items.len() < this.capacity {
+                this.items.len()
+            } else {
+                let pick = this.rng.between(0, this.seen as i64 - 1) as usize;
+                if pick >= this.capacity {
+                    return Ok(());
+                }
+                pick
+            };
+            let val = match val {
+                LuaValue::UserData(data) if data.is::<SharedValue>() => {
+                    data.borrow::<SharedValue>()?.to_value()?
+                }
+                val => lua_to_json(val)?,
+            };
+            if slot == this.items.len() {
+                this.items.push(val);
+            } else {
+                this.items[slot] = val;
+            }
+            Ok(())
+        });
+        methods.add_method("items", |lua, this, ()| {
+            let items = lua.create_table_with_capacity(this.items.len(), 0)?;
+            for (i, item) in this.items.iter().enumerate() {
+                items.raw_set(i + 1, json_to_table(lua, item)?)?;
+            }
+            Ok(items)
+        });
+    }
+}
@@ -0,0 +1,495 @@
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use mlua::Error as LuaError;
+use serde_json::Value;
+
+use crate::limits::Interrupt;
+use crate::value::ConversionError;
+
+/// Indices of documents, pipeline stages and fan-out scripts count from 0 in
+/// the variants, and from 1 in the messages shown for them, as `doc_index()`
+/// does in scripts.
+#[derive(Debug)]
+pub enum Error {
+    /// The script failed to compile.
+    ScriptSyntax {
+        script: String,
+        line: Option<u32>,
+        message: String,
+    },
+    /// The script raised an error, or called one of our globals in a way that
+    /// failed, while running.
+    ScriptRuntime {
+        script: String,
+        line: Option<u32>,
+        message: String,
+        traceback: Option<String>,
+        document: Option<usize>,
+    },
+    /// A Lua value has no JSON representation; `path` is a JSON pointer to
+    /// the offending value within what was being converted.
+    Conversion {
+        path: String,
+        message: String,
+        document: Option<usize>,
+    },
+    /// An input document does not match
+    /// [`RunOptions::input_schema`](crate::RunOptions::input_schema); every
+    /// error names the offending value by JSON pointer.
+    SchemaViolation {
+        errors: Vec<String>,
+    },
+    /// Reading the input document at `index` failed.
+    InputError {
+        index: usize,
+        source: Box<Error>,
+    },
+    /// One of the execution limits in [`RunOptions`](crate::RunOptions) was hit.
+    LimitExceeded {
+        which: Limit,
+        script: String,
+        document: Option<usize>,
+    },
+    /// [`RunOptions::max_emits`](crate::RunOptions::max_emits) or
+    /// [`RunOptions::max_output_bytes`](crate::RunOptions::max_output_bytes)
+    /// was hit. `outputs` holds what the run emitted to `out` until then,
+    /// unless it went to a sink.
+    OutputLimitExceeded {
+        which: Limit,
+        outputs: Vec<Value>,
+    },
+    Cancelled,
+    /// A [`RunOptions`](crate::RunOptions) field holds something unusable.
+    InvalidOption {
+        option: &'static str,
+        message: String,
+    },
+    /// One of the [`Hooks`](crate::Hooks) vetoed an emit or panicked.
+    Hook {
+        hook: &'static str,
+        message: String,
+    },
+    /// A worker thread of [`run_parallel`](crate::run_parallel) panicked.
+    WorkerPanicked {
+        message: String,
+    },
+    /// The [`OutputSink`](crate::OutputSink) failed on a document emitted to
+    /// `channel`, or while finishing when there is none.
+    Sink {
+        channel: Option<String>,
+        source: Box<Error>,
+    },
+    Io(io::Error),
+    /// Line `line` of newline-delimited JSON input failed to parse.
+    InvalidJson {
+        line: usize,
+        source: serde_json::Error,
+    },
+    /// JSON5 input failed to parse at `line` and `column`.
+    InvalidJson5 {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    /// Document `document`, 1-based, of a YAML stream failed to parse or has
+    /// no JSON representation.
+    InvalidYaml {
+        document: usize,
+        source: serde_yaml::Error,
+    },
+    /// The CSV record on line `line` failed to parse.
+    InvalidCsv {
+        line: usize,
+        message: String,
+    },
+    /// Document `document`, 1-based, of CBOR input failed to parse or has no
+    /// JSON representation.
+    InvalidCbor {
+        document: usize,
+        message: String,
+    },
+    /// Document `document`, 1-based, of MessagePack input failed to parse or
+    /// has no JSON representation.
+    InvalidMsgpack {
+        document: usize,
+        message: String,
+    },
+    /// A Parquet file failed to read or decode.
+    InvalidParquet {
+        message: String,
+    },
+    /// A TOML file failed to parse.
+    InvalidToml {
+        source: toml::de::Error,
+    },
+    /// Record `record`, 1-based, of a JSON text sequence failed to parse.
+    InvalidJsonSeq {
+        record: usize,
+        message: String,
+    },
+    /// XML input failed to parse, `offset` bytes into it.
+    InvalidXml {
+        offset: u64,
+        message: String,
+    },
+    /// The runner failed to set up the Lua state.
+    Lua(LuaError),
+    /// Failure while processing the input document at `index`.
+    Document {
+        index: usize,
+        source: Box<Error>,
+    },
+    /// Failure in the pipeline stage at `index`.
+    Stage {
+        index: usize,
+        source: Box<Error>,
+    },
+    /// Failure in the script at `index` of a fan-out.
+    Script {
+        index: usize,
+        source: Box<Error>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Limit {
+    Instructions { executed: u64 },
+    Timeout { after: Duration },
+    DocumentInstructions { executed: u64 },
+    DocumentTimeout { after: Duration },
+    Memory { limit: usize },
+    Emits { limit: u64 },
+    OutputBytes { limit: u64 },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Instructions { executed } => {
+                write!(
+                    f,
+                    "instruction budget exceeded after {executed} instructions"
+                )
+            }
+            Limit::Timeout { after } => {
+                write!(f, "script timed out after {}s", after.as_secs_f64())
+            }
+            Limit::DocumentInstructions { executed } => write!(
+                f,
+                "per-document instruction budget exceeded after {executed} instructions"
+            ),
+            Limit::DocumentTimeout { after } => {
+                write!(f, "document timed out after {}s", after.as_secs_f64())
+            }
+            Limit::Memory { limit } => write!(f, "Lua memory limit of {limit} bytes exceeded"),
+            Limit::Emits { limit } => write!(f, "limit of {limit} emitted documents exceeded"),
+            Limit::OutputBytes { limit } => {
+                write!(f, "output limit of about {limit} bytes exceeded")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ScriptSyntax {
+                script,
+                line,
+                message,
+            } => {
+                write!(f, "syntax error in {script}")?;
+                write_line(f, *line)?;
+                write!(f, ":\n  {message}")
+            }
+            Error::ScriptRuntime {
+                script,
+                line,
+                message,
+                traceback,
+                document,
+            } => {
+                write!(f, "runtime error in {script}")?;
+                write_line(f, *line)?;
+                write_document(f, *document)?;
+                write!(f, ":\n  {message}")?;
+                if let Some(traceback) = traceback {
+                    write!(f, "\n{traceback}")?;
+                }
+                Ok(())
+            }
+            Error::Conversion {
+                path,
+                message,
+                document,
+            } => {
+                write!(f, "cannot convert value at '{path}' to JSON")?;
+                write_document(f, *document)?;
+                write!(f, ":\n  {message}")
+            }
+            Error::SchemaViolation { errors } => {
+                write!(f, "document does not match the input schema:")?;
+                for error in errors {
+                    write!(f, "\n  {error}")?;
+                }
+                Ok(())
+            }
+            Error::InputError { index, source } => {
+                write!(
+                    f,
+                    "failed to read input document {}:\n  {source}",
+                    index + 1
+                )
+            }
+            Error::LimitExceeded {
+                which,
+                script,
+                document,
+            } => {
+                write!(f, "{which} in {script}")?;
+                write_document(f, *document)
+            }
+            Error::OutputLimitExceeded { which, .. } => write!(f, "{which}"),
+            Error::Cancelled => write!(f, "script was cancelled"),
+            Error::InvalidOption { option, message } => {
+                write!(f, "invalid option '{option}': {message}")
+            }
+            Error::Hook { hook, message } => write!(f, "{hook} hook failed: {message}"),
+            Error::WorkerPanicked { message } => write!(f, "worker thread panicked: {message}"),
+            Error::Sink {
+                channel: Some(channel),
+                source,
+            } => write!(f, "output sink failed on channel '{channel}': {source}"),
+            Error::Sink {
+                channel: None,
+                source,
+            } => write!(f, "output sink failed to finish: {source}"),
+            Error::Io(e) => write!(f, "{e}"),
+            Error::InvalidJson { line, source } => {
+                write!(f, "invalid JSON on line {line}: {source}")
+            }
+            Error::InvalidJson5 {
+                line,
+                column,
+                message,
+            } => write!(
+                f,
+                "invalid JSON5 on line {line}, column {column}: {message}"
+            ),
+            Error::InvalidYaml { document, source } => {
+                write!(f, "invalid YAML in document {document}: {source}")
+            }
+            Error::InvalidCsv { line, message } => {
+                write!(f, "invalid CSV on line {line}: {message}")
+            }
+            Error::InvalidCbor { document, message } => {
+                write!(f, "invalid CBOR in document {document}: {message}")
+            }
+            Error::InvalidMsgpack { document, message } => {
+                write!(f, "invalid MessagePack in document {document}: {message}")
+            }
+            Error::InvalidParquet { message } => write!(f, "invalid Parquet: {message}"),
+            Error::InvalidToml { source } => write!(f, "invalid TOML: {source}"),
+            Error::InvalidJsonSeq { record, message } => {
+                write!(f, "invalid JSON text sequence record {record}: {message}")
+            }
+            Error::InvalidXml { offset, message } => {
+                write!(f, "invalid XML at byte {offset}: {message}")
+            }
+            Error::Lua(e) => write!(f, "{e}"),
+            Error::Document { index, source } => write!(f, "document {}: {source}", index + 1),
+            Error::Stage { index, source } => {
+                write!(f, "pipeline stage {}: {source}", index + 1)
+            }
+            Error::Script { index, source } => write!(f, "script {}: {source}", index + 1),
+        }
+    }
+}
+
+fn write_line(f: &mut fmt::Formatter<'_>, line: Option<u32>) -> fmt::Result {
+    match line {
+        Some(line) => write!(f, " at line {line}"),
+        None => Ok(()),
+    }
+}
+
+fn write_document(f: &mut fmt::Formatter<'_>, document: Option<usize>) -> fmt::Result {
+    match document {
+        Some(index) => write!(f, " while processing document {}", index + 1),
+        None => Ok(()),
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Lua(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::InvalidJson { source, .. } => Some(source),
+            Error::InvalidYaml { source, .. } => Some(source),
+            Error::InvalidToml { source } => Some(source),
+            Error::InputError { source, .. }
+            | Error::Document { source, .. }
+            | Error::Stage { source, .. }
+            | Error::Script { source, .. }
+            | Error::Sink { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<LuaError> for Error {
+    fn from(err: LuaError) -> Self {
+        Error::Lua(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+/// What the runner knows about the script when one of its errors reaches us.
+pub(crate) struct ErrorContext<'a> {
+    pub(crate) script: &'a str,
+    pub(crate) document: Option<usize>,
+    pub(crate) memory_limit: Option<usize>,
+}
+
+impl Error {
+    /// Hands the outputs of a run that hit an output limit over to its
+    /// error, which is raised before they are known.
+    pub(crate) fn with_outputs(self, kept: Vec<Value>) -> Error {
+        match self {
+            Error::OutputLimitExceeded { which, .. } => Error::OutputLimitExceeded {
+                which,
+                outputs: kept,
+            },
+            err => err,
+        }
+    }
+
+    /// Classifies an error raised while compiling or running a script.
+    pub(crate) fn from_lua(err: LuaError, ctx: &ErrorContext<'_>) -> Error {
+        let script = ctx.script.to_string();
+        let document = ctx.document;
+
+        if let Some(interrupt) = find_external::<Interrupt>(&err) {
+            let (which, document) = match *interrupt {
+                Interrupt::Cancelled => return Error::Cancelled,
+                Interrupt::InstructionBudget { executed } => {
+                    (Limit::Instructions { executed }, document)
+                }
+                Interrupt::Timeout { after } => (Limit::Timeout { after }, document),
+                Interrupt::DocumentInstructionBudget { index, executed } => {
+                    (Limit::DocumentInstructions { executed }, Some(index))
+                }
+                Interrupt::DocumentTimeout { index, after } => {
+                    (Limit::DocumentTimeout { after }, Some(index))
+                }
+            };
+            return Error::LimitExceeded {
+                which,
+                script,
+                document,
+            };
+        }
+        if let Some(conversion) = find_external::<ConversionError>(&err) {
+            return Error::Conversion {
+                path: conversion.path.clone(),
+                message: conversion.message.clone(),
+                document,
+            };
+        }
+        if let Some(limit) = ctx.memory_limit
+            && is_memory_error(&err)
+        {
+            return Error::LimitExceeded {
+                which: Limit::Memory { limit },
+                script,
+                document,
+            };
+        }
+
+        match err {
+            LuaError::SyntaxError { message, .. } => Error::ScriptSyntax {
+                line: find_line(&message, ctx.script),
+                script,
+                message,
+            },
+            LuaError::CallbackError { traceback, cause } => Error::ScriptRuntime {
+                line: find_line(&traceback, ctx.script),
+                message: root_message(&cause),
+                traceback: Some(traceback),
+                script,
+                document,
+            },
+            err => {
+                let message = root_message(&err);
+                let (message, traceback) = match message.split_once("\nstack traceback:") {
+                    Some((message, traceback)) => (
+                        message.to_string(),
+                        Some(format!("stack traceback:{traceback}")),
+                    ),
+                    None => (message, None),
+                };
+                Error::ScriptRuntime {
+                    line: find_line(&message, ctx.script),
+                    message,
+                    traceback,
+                    script,
+                    document,
+                }
+            }
+        }
+    }
+}
+
+/// Errors our Rust code raises inside Lua reach us wrapped in however many
+/// callback and context layers the script had on the stack at the time.
+fn find_external<T: std::error::Error + 'static>(err: &LuaError) -> Option<&T> {
+    match err {
+        LuaError::ExternalError(e) => e.downcast_ref(),
+        LuaError::CallbackError { cause, .. } | LuaError::WithContext { cause, .. } => {
+            find_external(cause)
+        }
+        _ => None,
+    }
+}
+
+fn is_memory_error(err: &LuaError) -> bool {
+    match err {
+        LuaError::MemoryError(_) => true,
+        LuaError::CallbackError { cause, .. } | LuaError::WithContext { cause, .. } => {
+            is_memory_error(cause)
+        }
+        _ => false,
+    }
+}
+
+/// The innermost error message, without mlua's "runtime error: " style
+/// prefixes.
+fn root_message(err: &LuaError) -> String {
+    match err {
+        LuaError::RuntimeError(message) => message.clone(),
+        LuaError::CallbackError { cause, .. } | LuaError::WithContext { cause, .. } => {
+            root_message(cause)
+        }
+        err => err.to_string(),
+    }
+}
+
+/// Finds the first `<script>:<line>:` location Lua put in `text`.
+pub(crate) fn find_line(text: &str, script: &str) -> Option<u32> {
+    let prefix = format!("{script}:");
+    text.match_indices(&prefix).find_map(|(start, _)| {
+        let rest = &text[start + prefix.len()..];
+        let (line, _) = rest.split_once(':')?;
+        line.parse().ok()
+    })
+}
@@ -0,0 +1,140 @@
+use std::rc::Rc;
+
+use serde_json::Value;
+
+use crate::error::{Error, Result};
+use crate::runner::InputIter;
+
+/// Splits every input document into one document per element of an array
+/// inside it, set as [`RunOptions::explode`](crate::RunOptions::explode).
+#[derive(Clone, Debug)]
+pub struct Explode {
+    /// Where the array is: a JSON pointer like `/items`, or a JSONPath like
+    /// `$.items[*]` made of plain field names and indices.
+    pub path: String,
+    /// Adds what is left of the document, minus the array, to every element
+    /// that is an object under this key.
+    pub parent_key: Option<String>,
+    /// Passes documents without the array through as they are, instead of
+    /// dropping them.
+    pub keep_unmatched: bool,
+}
+
+impl Explode {
+    pub fn new(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            parent_key: None,
+            keep_unmatched: false,
+        }
+    }
+
+    pub(crate) fn compile(self) -> Result<Exploder> {
+        let pointer = to_pointer(&self.path).map_err(|message| Error::InvalidOption {
+            option: "explode",
+            message: format!("invalid path '{}': {message}", self.path),
+        })?;
+        Ok(Exploder {
+            pointer,
+            parent_key: self.parent_key,
+            keep_unmatched: self.keep_unmatched,
+        })
+    }
+}
+
+/// An [`Explode`] with its path turned into a JSON pointer.
+pub(crate) struct Exploder {
+    pointer: String,
+    parent_key: Option<String>,
+    keep_unmatched: bool,
+}
+
+impl Exploder {
+    pub(crate) fn wrap(self: Rc<Self>, input: InputIter) -> InputIter {
+        Box::new(input.flat_map(move |next| match next {
+            Ok(doc) => self.split(doc).into_iter().map(Ok).collect(),
+            Err(err) => vec![Err(err)],
+        }))
+    }
+
+    fn split(&self, mut doc: Value) -> Vec<Value> {
+        let elements = match doc.pointer_mut(&self.pointer) {
+            Some(Value::Array(elements)) => std::mem::take(elements),
+            _ if self.keep_unmatched => return vec![doc],
+            _ => return Vec::new(),
+        };
+        let Some(parent_key) = &self.parent_key else {
+            return elements;
+        };
+        remove(&mut doc, &self.pointer);
+        elements
+            .into_iter()
+            .map(|mut elem| {
+                if let Value::Object(map) = &mut elem {
+                    map.insert(parent_key.clone(), doc.clone());
+                }
+                elem
+            })
+            .collect()
+    }
+}
+
+/// Removes whatever `pointer` points at from `doc`.
+fn remove(doc: &mut Value, pointer: &str) {
+    let Some((parent, last)) = pointer.rsplit_once('/') else {
+        return;
+    };
+    let last = last.replace("~1", "/").replace("~0", "~");
+    match doc.pointer_mut(parent) {
+        Some(Value::Object(map)) => {
+            map.remove(&last);
+        }
+        Some(Value::Array(arr)) => {
+            if let Ok(index) = last.parse::<usize>()
+                && index < arr.len()
+            {
+                arr.remove(index);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Turns the JSONPath subset [`Explode::path`] accepts into a JSON pointer,
+/// leaving JSON pointers as they are.
+fn to_pointer(path: &str) -> std::result::Result<String, String> {
+    if path.starts_with('/') || path.is_empty() {
+        return Ok(path.to_string());
+    }
+    let Some(mut rest) = path.strip_prefix('$') else {
+        return Err("expected a JSON pointer or a JSONPath starting with '$'".to_string());
+    };
+    rest = rest.strip_suffix("[*]").unwrap_or(rest);
+
+    let mut pointer = String::new();
+    while !rest.is_empty() {
+        let segment;
+        if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            (segment, rest) = (after[..end].to_string(), &after[end..]);
+        } else if let Some(after) = rest.strip_prefix("['") {
+            let end = after.find("']").ok_or("unterminated ['...']")?;
+            (segment, rest) = (after[..end].to_string(), &after[end + 2..]);
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').ok_or("unterminated [...]")?;
+            let index = &after[..end];
+            if index.parse::<usize>().is_err() {
+                return Err(format!("unsupported selector [{index}]"));
+            }
+            (segment, rest) = (index.to_string(), &after[end + 1..]);
+        } else {
+            return Err(format!("unexpected '{rest}'"));
+        }
+        if segment.is_empty() {
+            return Err("empty field name".to_string());
+        }
+        pointer.push('/');
+        pointer.push_str(&segment.replace('~', "~0").replace('/', "~1"));
+    }
+    Ok(pointer)
+}
@@ -0,0 +1,83 @@
+use serde_json::Value;
+
+use crate::error::{Error, Result};
+use crate::runner::{Feeder, Runner};
+
+/// Runs every script over a single pass of `input`, returning the outputs of
+/// each script in the order the scripts were given.
+///
+/// The scripts run in separate Lua states and take turns: each input document
+/// is handed, as a clone, to every script before the next one is read, so
+/// `get_next` may be called from a script's own coroutines but not from
+/// inside a C function such as a `table.sort` comparator. The first failing
+/// script aborts the whole run with [`Error::Script`]; see
+/// [`run_fanout_isolated`] to let the others carry on.
+pub fn run_fanout<I>(scripts: &[&str], input: I) -> Result<Vec<Vec<Value>>>
+where
+    I: IntoIterator<Item = Value>,
+{
+    let mut feeders = scripts
+        .iter()
+        .enumerate()
+        .map(|(index, script)| {
+            Runner::new(script)
+                .map(Feeder::new)
+                .map_err(|err| script_error(index, err))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    for doc in input {
+        for feeder in feeders.iter_mut().filter(|feeder| feeder.is_running()) {
+            feeder.push(doc.clone());
+        }
+        if feeders.iter().any(Feeder::has_failed) {
+            break;
+        }
+    }
+
+    if let Some((index, err)) = feeders
+        .iter_mut()
+        .enumerate()
+        .find_map(|(index, feeder)| feeder.take_error().map(|err| (index, err)))
+    {
+        return Err(script_error(index, err));
+    }
+
+    feeders
+        .into_iter()
+        .enumerate()
+        .map(|(index, feeder)| feeder.finish().map_err(|err| script_error(index, err)))
+        .collect()
+}
+
+/// Like [`run_fanout`], but a failing script only loses its own output; the
+/// others still see the whole input.
+pub fn run_fanout_isolated<I>(scripts: &[&str], input: I) -> Vec<Result<Vec<Value>>>
+where
+    I: IntoIterator<Item = Value>,
+{
+    let mut feeders: Vec<Result<Feeder>> = scripts
+        .iter()
+        .map(|script| Runner::new(script).map(Feeder::new))
+        .collect();
+
+    for doc in input {
+        for feeder in feeders.iter_mut().flatten() {
+            if feeder.is_running() {
+                feeder.push(doc.clone());
+            }
+        }
+    }
+
+    feeders
+        .into_iter()
+        .map(|feeder| feeder.and_then(Feeder::finish))
+        .collect()
+}
+
+fn script_error(index: usize, err: Error) -> Error {
+    Error::Script {
+        index,
+        source: Box::new(err),
+    }
+}
@@ -0,0 +1,137 @@
+use std::borrow::Cow;
+use std::io::Read;
+
+use serde_json::Value;
+
+use crate::error::{Error, Result};
+use crate::source::InputSource;
+
+/// How forgiving JSON parsing is.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JsonDialect {
+    #[default]
+    Strict,
+    /// JSON with `//` and `/* */` comments and trailing commas, which are
+    /// blanked out before parsing as JSON, so errors point at the line and
+    /// column of the original text.
+    Jsonc,
+    /// [JSON5](https://json5.org): comments, trailing commas, unquoted keys,
+    /// single-quoted strings, hexadecimal numbers and more.
+    Json5,
+}
+
+impl JsonDialect {
+    /// Parses `text`, reporting errors on `line` onwards, the line `text`
+    /// starts on in its input. Returns `None` when there is nothing but
+    /// whitespace and, outside strict JSON, comments.
+    pub(crate) fn parse(self, text: &str, line: usize) -> Result<Option<Value>> {
+        let text = match self {
+            JsonDialect::Strict => Cow::Borrowed(text),
+            JsonDialect::Jsonc => Cow::Owned(blank_jsonc(text)),
+            JsonDialect::Json5 => return parse_json5(text, line),
+        };
+        if text.trim().is_empty() {
+            return Ok(None);
+        }
+        serde_json::from_str(&text)
+            .map(Some)
+            .map_err(|source| Error::InvalidJson {
+                line: line + source.line().saturating_sub(1),
+                source,
+            })
+    }
+}
+
+fn parse_json5(text: &str, line: usize) -> Result<Option<Value>> {
+    if blank_jsonc(text).trim().is_empty() {
+        return Ok(None);
+    }
+    json5::from_str(text).map(Some).map_err(|err| {
+        let json5::Error::Message { msg, location } = err;
+        let (at_line, column) = location.map_or((1, 0), |at| (at.line, at.column));
+        Error::InvalidJson5 {
+            line: line + at_line.saturating_sub(1),
+            column,
+            message: msg,
+        }
+    })
+}
+
+/// `text` with its comments and trailing commas replaced by spaces, byte for
+/// byte, and newlines kept, so every other byte stays where it was.
+fn blank_jsonc(text: &str) -> String {
+    let mut out = text.as_bytes().to_vec();
+    // Where the last comma outside a string is, while only whitespace and
+    // comments follow it.
+    let mut comma = None;
+    let mut i = 0;
+    while i < out.len() {
+        match out[i] {
+            b'"' => {
+                comma = None;
+                i += 1;
+                while i < out.len() && out[i] != b'"' {
+                    i += if out[i] == b'\\' { 2 } else { 1 };
+                }
+            }
+            b'/' if out.get(i + 1) == Some(&b'/') => {
+                while i < out.len() && out[i] != b'\n' {
+                    out[i] = b' ';
+                    i += 1;
+                }
+                continue;
+            }
+            b'/' if out.get(i + 1) == Some(&b'*') => {
+                let end = text[i + 2..]
+                    .find("*/")
+                    .map_or(out.len(), |end| i + 2 + end + 2);
+                for byte in &mut out[i..end] {
+                    if !matches!(*byte, b'\n' | b'\r') {
+                        *byte = b' ';
+                    }
+                }
+                i = end;
+                continue;
+            }
+            b',' => comma = Some(i),
+            b'}' | b']' => {
+                if let Some(comma) = comma.take() {
+                    out[comma] = b' ';
+                }
+            }
+            byte if byte.is_ascii_whitespace() => {}
+            _ => comma = None,
+        }
+        i += 1;
+    }
+    // Whole characters were replaced, every byte of them by a space.
+    String::from_utf8(out).expect("blanking keeps UTF-8 intact")
+}
+
+/// A whole input as a single JSON document, parsed by `dialect`; for config
+/// files rather than streams of documents. An input holding nothing but
+/// whitespace and comments has no document.
+pub struct JsonDocumentSource<R: Read> {
+    reader: Option<R>,
+    dialect: JsonDialect,
+}
+
+impl<R: Read> JsonDocumentSource<R> {
+    pub fn new(reader: R, dialect: JsonDialect) -> Self {
+        Self {
+            reader: Some(reader),
+            dialect,
+        }
+    }
+}
+
+impl<R: Read> InputSource for JsonDocumentSource<R> {
+    fn next_doc(&mut self) -> Result<Option<Value>> {
+        let Some(mut reader) = self.reader.take() else {
+            return Ok(None);
+        };
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        self.dialect.parse(&text, 1)
+    }
+}
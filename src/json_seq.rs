@@ -0,0 +1,127 @@
+use std::io::{BufRead, Write};
+
+use serde_json::Value;
+
+use crate::error::{Error, Result};
+use crate::sink::OutputSink;
+use crate::source::InputSource;
+
+/// The record separator, ASCII RS, that starts every record.
+const RS: u8 = 0x1e;
+
+/// A JSON text sequence (RFC 7464): records each starting with an RS byte
+/// and ending with a newline, every one a document, pretty-printed or not.
+///
+/// A record that fails to parse is an [`Error::InvalidJsonSeq`], after which
+/// reading carries on with the next record, so a reader passing over errors
+/// loses only the malformed one. Empty records are skipped. As the RFC asks,
+/// a record holding a number, `true`, `false` or `null` but missing its final
+/// newline is taken as truncated and fails too.
+pub struct JsonSeqSource<R: BufRead> {
+    reader: R,
+    buf: Vec<u8>,
+    /// Records read so far.
+    read: usize,
+    /// Whether the first RS has been read.
+    started: bool,
+}
+
+impl<R: BufRead> JsonSeqSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buf: Vec::new(),
+            read: 0,
+            started: false,
+        }
+    }
+
+    fn invalid(&self, message: impl ToString) -> Error {
+        Error::InvalidJsonSeq {
+            record: self.read,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl<R: BufRead> InputSource for JsonSeqSource<R> {
+    fn next_doc(&mut self) -> Result<Option<Value>> {
+        loop {
+            self.buf.clear();
+            if self.reader.read_until(RS, &mut self.buf)? == 0 {
+                return Ok(None);
+            }
+            if self.buf.last() == Some(&RS) {
+                self.buf.pop();
+            }
+            let leading = !std::mem::replace(&mut self.started, true);
+            if self.buf.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            self.read += 1;
+            if leading {
+                return Err(self.invalid("record does not start with RS"));
+            }
+            let text = std::str::from_utf8(&self.buf).map_err(|err| self.invalid(err))?;
+            let value: Value = serde_json::from_str(text).map_err(|err| self.invalid(err))?;
+            let truncated = !text.ends_with('\n')
+                && matches!(value, Value::Number(_) | Value::Bool(_) | Value::Null);
+            if truncated {
+                return Err(self.invalid("record is missing its final newline"));
+            }
+            return Ok(Some(value));
+        }
+    }
+}
+
+/// Writes every document as a JSON text sequence record, an RS byte, the
+/// document as compact JSON, or pretty-printed when asked to, and a newline.
+/// Documents emitted with `emit_kv` are written as
+/// `{"key": ..., "value": ...}`.
+pub struct JsonSeqSink<W: Write> {
+    writer: W,
+    pretty: bool,
+}
+
+impl<W: Write> JsonSeqSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            pretty: false,
+        }
+    }
+
+    /// Pretty-prints every document over as many lines as it takes, which
+    /// the framing keeps apart without anything more.
+    pub fn pretty(mut self) -> Self {
+        self.pretty = true;
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> OutputSink for JsonSeqSink<W> {
+    fn emit(&mut self, _channel: &str, value: Value) -> Result<()> {
+        self.writer.write_all(&[RS])?;
+        if self.pretty {
+            serde_json::to_writer_pretty(&mut self.writer, &value)
+        } else {
+            serde_json::to_writer(&mut self.writer, &value)
+        }
+        .map_err(std::io::Error::from)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn emit_kv(&mut self, channel: &str, key: Value, value: Value) -> Result<()> {
+        self.emit(channel, serde_json::json!({ "key": key, "value": value }))
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
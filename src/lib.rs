@@ -0,0 +1,64 @@
+#[cfg(feature = "cbor")]
+mod cbor;
+mod compression;
+mod csv_format;
+mod determinism;
+mod error;
+mod explode;
+mod fanout;
+mod json_dialect;
+mod json_seq;
+mod limits;
+mod msgpack;
+mod parallel;
+#[cfg(feature = "parquet")]
+mod parquet_format;
+mod pipeline;
+mod runner;
+mod sandbox;
+mod sink;
+mod source;
+mod toml_format;
+mod trace;
+mod validate;
+mod value;
+mod xml;
+mod yaml;
+
+#[cfg(feature = "cbor")]
+pub use cbor::{CborSink, CborSource};
+pub use compression::Compression;
+pub use csv_format::{CsvColumns, CsvSink, CsvSource};
+pub use determinism::ClockSource;
+pub use error::{Error, Limit, Result};
+pub use explode::Explode;
+pub use fanout::{run_fanout, run_fanout_isolated};
+pub use json_dialect::{JsonDialect, JsonDocumentSource};
+pub use json_seq::{JsonSeqSink, JsonSeqSource};
+pub use limits::{CancellationToken, DocumentLimitPolicy};
+pub use msgpack::{MsgpackSink, MsgpackSource};
+pub use parallel::{
+    OutputOrder, ParallelOptions, run_parallel, run_parallel_into, run_parallel_with_options,
+};
+#[cfg(feature = "parquet")]
+pub use parquet_format::{ParquetOptions, ParquetSource, TimestampFormat};
+pub use pipeline::run_pipeline;
+pub use runner::{
+    DocumentEndHook, DocumentStartHook, EmitHook, EmitIter, EnvAccess, ErrorPolicy, Evaluated,
+    Failure, Hooks, KeyedOutput, MapOutput, Metric, MetricSummary, Mode, RunOptions, RunOutput,
+    RunStats, Runner, Session, run, run_channel, run_kv, run_map, run_with_options,
+    run_with_output,
+};
+#[cfg(feature = "async")]
+pub use runner::{EmitStream, run_async, run_async_into, run_stream};
+pub use sandbox::Sandbox;
+pub use sink::{JsonLinesSink, OutputSink, RawOutput};
+pub use source::{
+    InputSource, IterSource, JsonLinesSource, SlicedSource, SlurpedSource, SourcePosition,
+};
+pub use toml_format::{TomlSink, TomlSource};
+pub use trace::{TraceEvent, TraceFn};
+pub use validate::{ScriptSyntaxError, UndefinedGlobal, undefined_globals, validate};
+pub use value::{SharedValue, with_document};
+pub use xml::{XmlOptions, XmlSource};
+pub use yaml::{YamlSink, YamlSource};
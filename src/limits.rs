@@ -0,0 +1,204 @@
+use std::cell::Cell;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use mlua::{Error as LuaError, HookTriggers, Lua, Result, VmState};
+
+use crate::runner::RunOptions;
+
+/// Handle used to stop a running script from another thread.
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// What to do when a single document exceeds its per-document limits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DocumentLimitPolicy {
+    /// Fail the whole run.
+    #[default]
+    Abort,
+    /// Drop the offending document, recording it as a
+    /// [`Failure`](crate::Failure) with its index, and carry on with the next
+    /// one. Only the driver modes can resume after a document, so free-form
+    /// scripts abort regardless.
+    Skip,
+}
+
+/// Raised from inside the instruction hook to unwind the script.
+#[derive(Debug)]
+pub(crate) enum Interrupt {
+    Cancelled,
+    InstructionBudget { executed: u64 },
+    Timeout { after: Duration },
+    DocumentInstructionBudget { index: usize, executed: u64 },
+    DocumentTimeout { index: usize, after: Duration },
+}
+
+impl fmt::Display for Interrupt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Interrupt::Cancelled => write!(f, "cancelled"),
+            Interrupt::InstructionBudget { .. } => write!(f, "instruction budget exceeded"),
+            Interrupt::Timeout { after } => {
+                write!(f, "script timed out after {}s", after.as_secs_f64())
+            }
+            Interrupt::DocumentInstructionBudget { index, .. } => {
+                write!(f, "instruction budget exceeded for document {}", index + 1)
+            }
+            Interrupt::DocumentTimeout { index, after } => write!(
+                f,
+                "document {} timed out after {}s",
+                index + 1,
+                after.as_secs_f64()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Interrupt {}
+
+/// Per-state counters consulted by the instruction hook.
+pub(crate) struct HookState {
+    interval: u32,
+    cancellation: Option<CancellationToken>,
+    max_instructions: Option<u64>,
+    timeout: Option<Duration>,
+    per_document_max_instructions: Option<u64>,
+    per_document_timeout: Option<Duration>,
+    executed: Cell<u64>,
+    deadline: Cell<Option<Instant>>,
+    document: Cell<Option<usize>>,
+    document_executed: Cell<u64>,
+    document_deadline: Cell<Option<Instant>>,
+}
+
+impl HookState {
+    /// Clears the per-run counters before the next batch starts.
+    pub(crate) fn reset(&self) {
+        self.executed.set(0);
+        self.deadline
+            .set(self.timeout.map(|timeout| Instant::now() + timeout));
+        self.document.set(None);
+    }
+
+    /// Starts the per-document limits over for the document at `index`.
+    pub(crate) fn start_document(&self, index: usize) {
+        self.document.set(Some(index));
+        self.document_executed.set(0);
+        self.document_deadline.set(
+            self.per_document_timeout
+                .map(|timeout| Instant::now() + timeout),
+        );
+    }
+
+    /// Instructions executed since the last reset, rounded down to a multiple
+    /// of the hook interval.
+    pub(crate) fn executed(&self) -> u64 {
+        self.executed.get()
+    }
+
+    fn check(&self) -> Result<VmState> {
+        let executed = self.executed.get() + u64::from(self.interval);
+        self.executed.set(executed);
+
+        if let Some(token) = &self.cancellation
+            && token.is_cancelled()
+        {
+            return Err(LuaError::external(Interrupt::Cancelled));
+        }
+        if let Some(max) = self.max_instructions
+            && executed > max
+        {
+            return Err(LuaError::external(Interrupt::InstructionBudget {
+                executed,
+            }));
+        }
+        if let (Some(deadline), Some(after)) = (self.deadline.get(), self.timeout)
+            && Instant::now() >= deadline
+        {
+            return Err(LuaError::external(Interrupt::Timeout { after }));
+        }
+
+        let Some(index) = self.document.get() else {
+            return Ok(VmState::Continue);
+        };
+        let executed = self.document_executed.get() + u64::from(self.interval);
+        self.document_executed.set(executed);
+        if let Some(max) = self.per_document_max_instructions
+            && executed > max
+        {
+            return Err(LuaError::external(Interrupt::DocumentInstructionBudget {
+                index,
+                executed,
+            }));
+        }
+        if let (Some(deadline), Some(after)) =
+            (self.document_deadline.get(), self.per_document_timeout)
+            && Instant::now() >= deadline
+        {
+            return Err(LuaError::external(Interrupt::DocumentTimeout {
+                index,
+                after,
+            }));
+        }
+        Ok(VmState::Continue)
+    }
+}
+
+/// Installs the instruction-count hook when any option needs one.
+///
+/// Each check is a call from the VM into Rust, so its cost is amortized over
+/// `hook_interval` instructions; at the default of 10k it is negligible next
+/// to the script itself. The larger cost is that LuaJIT does not run hooks
+/// inside compiled traces, so the JIT is switched off for states with a hook
+/// and scripts run at interpreter speed.
+pub(crate) fn install_hook(lua: &Lua, options: &RunOptions) -> Result<Option<Rc<HookState>>> {
+    if options.cancellation.is_none()
+        && options.max_instructions.is_none()
+        && options.timeout.is_none()
+        && options.per_document_max_instructions.is_none()
+        && options.per_document_timeout.is_none()
+    {
+        return Ok(None);
+    }
+
+    let state = Rc::new(HookState {
+        interval: options.hook_interval,
+        cancellation: options.cancellation.clone(),
+        max_instructions: options.max_instructions,
+        timeout: options.timeout,
+        per_document_max_instructions: options.per_document_max_instructions,
+        per_document_timeout: options.per_document_timeout,
+        executed: Cell::new(0),
+        deadline: Cell::new(None),
+        document: Cell::new(None),
+        document_executed: Cell::new(0),
+        document_deadline: Cell::new(None),
+    });
+
+    lua.load("if jit then jit.off() end")
+        .set_name("=[mlua_play limits]")
+        .exec()?;
+    let hook_state = state.clone();
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(options.hook_interval),
+        move |_, _| hook_state.check(),
+    )?;
+    Ok(Some(state))
+}
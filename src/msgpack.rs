@@ -0,0 +1,261 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use serde::Deserialize;
+use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde_json::{Map, Number, Value};
+
+use crate::error::{Error, Result};
+use crate::sink::OutputSink;
+use crate::source::InputSource;
+
+/// MessagePack values one after the other, every value becoming a document.
+/// With [`MsgpackSource::length_prefixed`], every value is instead preceded
+/// by its length in bytes, as a big-endian 32-bit integer.
+///
+/// MessagePack holds more than JSON, which is dealt with as for strings
+/// coming from Lua:
+///
+/// - Binary values, and strings, must be UTF-8, becoming strings; other
+///   bytes are an error, as JSON strings cannot hold them.
+/// - Map keys that are integers or booleans become their decimal or
+///   `true`/`false` spelling; other non-string keys are an error.
+/// - Extension types are an error, as are NaN and infinite floats.
+pub struct MsgpackSource<R: BufRead> {
+    reader: R,
+    length_prefixed: bool,
+    /// Documents read so far.
+    read: usize,
+}
+
+impl<R: BufRead> MsgpackSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            length_prefixed: false,
+            read: 0,
+        }
+    }
+
+    /// Expects every value to be preceded by its length.
+    pub fn length_prefixed(mut self) -> Self {
+        self.length_prefixed = true;
+        self
+    }
+
+    fn invalid(&self, message: impl fmt::Display) -> Error {
+        Error::InvalidMsgpack {
+            document: self.read,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl<R: BufRead> InputSource for MsgpackSource<R> {
+    fn next_doc(&mut self) -> Result<Option<Value>> {
+        if self.reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        self.read += 1;
+        let value = if self.length_prefixed {
+            let mut len = [0; 4];
+            self.reader
+                .read_exact(&mut len)
+                .map_err(|err| self.invalid(err))?;
+            let mut bytes = vec![0; u32::from_be_bytes(len) as usize];
+            self.reader
+                .read_exact(&mut bytes)
+                .map_err(|err| self.invalid(err))?;
+            rmp_serde::from_slice::<JsonValue>(&bytes)
+        } else {
+            JsonValue::deserialize(&mut rmp_serde::Deserializer::new(&mut self.reader))
+        };
+        value
+            .map(|JsonValue(value)| Some(value))
+            .map_err(|err| self.invalid(err))
+    }
+}
+
+/// A [`Value`] deserialized from MessagePack by the rules of
+/// [`MsgpackSource`].
+struct JsonValue(Value);
+
+impl<'de> Deserialize<'de> for JsonValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        deserializer
+            .deserialize_any(JsonValueVisitor)
+            .map(JsonValue)
+    }
+}
+
+struct JsonValueVisitor;
+
+impl<'de> Visitor<'de> for JsonValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a MessagePack value with a JSON representation")
+    }
+
+    fn visit_unit<E>(self) -> std::result::Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_none<E>(self) -> std::result::Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, inner: D) -> std::result::Result<Value, D::Error> {
+        JsonValue::deserialize(inner).map(|JsonValue(value)| value)
+    }
+
+    fn visit_bool<E>(self, b: bool) -> std::result::Result<Value, E> {
+        Ok(Value::Bool(b))
+    }
+
+    fn visit_i64<E>(self, n: i64) -> std::result::Result<Value, E> {
+        Ok(Value::from(n))
+    }
+
+    fn visit_u64<E>(self, n: u64) -> std::result::Result<Value, E> {
+        Ok(Value::from(n))
+    }
+
+    fn visit_f64<E: de::Error>(self, f: f64) -> std::result::Result<Value, E> {
+        Number::from_f64(f)
+            .map(Value::Number)
+            .ok_or_else(|| E::custom(format!("{f} has no JSON representation")))
+    }
+
+    fn visit_str<E>(self, s: &str) -> std::result::Result<Value, E> {
+        Ok(Value::String(s.to_string()))
+    }
+
+    fn visit_string<E>(self, s: String) -> std::result::Result<Value, E> {
+        Ok(Value::String(s))
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> std::result::Result<Value, E> {
+        utf8(bytes).map(Value::String)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<Value, A::Error> {
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(JsonValue(item)) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> std::result::Result<Value, A::Error> {
+        let mut object = Map::new();
+        while let Some(JsonKey(key)) = map.next_key()? {
+            let JsonValue(value) = map.next_value()?;
+            object.insert(key, value);
+        }
+        Ok(Value::Object(object))
+    }
+}
+
+fn utf8<E: de::Error>(bytes: &[u8]) -> std::result::Result<String, E> {
+    String::from_utf8(bytes.to_vec())
+        .map_err(|_| E::custom("binary value is not UTF-8, which JSON strings cannot hold"))
+}
+
+/// A map key, as an object key.
+struct JsonKey(String);
+
+impl<'de> Deserialize<'de> for JsonKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_any(JsonKeyVisitor).map(JsonKey)
+    }
+}
+
+struct JsonKeyVisitor;
+
+impl Visitor<'_> for JsonKeyVisitor {
+    type Value = String;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a string, integer or boolean map key")
+    }
+
+    fn visit_bool<E>(self, b: bool) -> std::result::Result<String, E> {
+        Ok(b.to_string())
+    }
+
+    fn visit_i64<E>(self, n: i64) -> std::result::Result<String, E> {
+        Ok(n.to_string())
+    }
+
+    fn visit_u64<E>(self, n: u64) -> std::result::Result<String, E> {
+        Ok(n.to_string())
+    }
+
+    fn visit_str<E>(self, s: &str) -> std::result::Result<String, E> {
+        Ok(s.to_string())
+    }
+
+    fn visit_string<E>(self, s: String) -> std::result::Result<String, E> {
+        Ok(s)
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> std::result::Result<String, E> {
+        utf8(bytes)
+    }
+}
+
+/// Writes every document as a MessagePack value, one after the other, or
+/// preceded by its length with [`MsgpackSink::length_prefixed`], the way
+/// [`MsgpackSource`] reads them. Documents emitted with `emit_kv` are written
+/// as `{key, value}` maps.
+pub struct MsgpackSink<W: Write> {
+    writer: W,
+    length_prefixed: bool,
+}
+
+impl<W: Write> MsgpackSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            length_prefixed: false,
+        }
+    }
+
+    /// Precedes every value with its length.
+    pub fn length_prefixed(mut self) -> Self {
+        self.length_prefixed = true;
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> OutputSink for MsgpackSink<W> {
+    fn emit(&mut self, _channel: &str, value: Value) -> Result<()> {
+        let bytes = rmp_serde::to_vec(&value)
+            .map_err(|err| Error::Io(io::Error::new(io::ErrorKind::InvalidData, err)))?;
+        if self.length_prefixed {
+            let len = u32::try_from(bytes.len()).map_err(|_| {
+                Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "document is too large to be length-prefixed",
+                ))
+            })?;
+            self.writer.write_all(&len.to_be_bytes())?;
+        }
+        self.writer.write_all(&bytes)?;
+        Ok(())
+    }
+
+    fn emit_kv(&mut self, channel: &str, key: Value, value: Value) -> Result<()> {
+        self.emit(channel, serde_json::json!({ "key": key, "value": value }))
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,410 @@
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, ScopedJoinHandle};
+use std::time::Instant;
+
+use serde_json::Value;
+
+use crate::error::{Error, Result};
+use crate::runner::{DEFAULT_CHANNEL, Mode, RunOptions, RunStats, Runner, panic_message};
+use crate::sink::OutputSink;
+use crate::source::InputSource;
+
+type WorkerResult = (Option<usize>, Result<Vec<Value>>);
+type Jobs = Arc<Mutex<Receiver<(usize, Value)>>>;
+
+/// Order in which [`run_parallel`] returns the outputs of different documents.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputOrder {
+    /// Outputs are grouped by input document, in input order.
+    #[default]
+    Input,
+    /// Outputs are returned as workers finish documents.
+    Arrival,
+}
+
+pub struct ParallelOptions {
+    pub workers: usize,
+    pub order: OutputOrder,
+    /// Builds the options of each worker's runner, on that worker's thread.
+    /// Runs in [`Mode::Map`] by default. Whatever sink the options name is
+    /// replaced by one collecting each document's outputs.
+    pub run_options: Arc<dyn Fn() -> RunOptions + Send + Sync>,
+}
+
+impl Default for ParallelOptions {
+    fn default() -> Self {
+        Self {
+            workers: thread::available_parallelism().map_or(1, |n| n.get()),
+            order: OutputOrder::default(),
+            run_options: Arc::new(|| RunOptions {
+                mode: Mode::Map,
+                ..RunOptions::default()
+            }),
+        }
+    }
+}
+
+pub fn run_parallel<I>(script: &str, input: I, workers: usize) -> Result<Vec<Value>>
+where
+    I: IntoIterator<Item = Value>,
+{
+    run_parallel_with_options(
+        script,
+        input,
+        ParallelOptions {
+            workers,
+            ..ParallelOptions::default()
+        },
+    )
+}
+
+/// Runs `script` over `input` on several threads, each owning its own Lua
+/// state.
+///
+/// Every worker runs the script as a single batch over the documents it is
+/// handed, so in [`Mode::Map`] the chunk is loaded once per worker and
+/// `transform` called for every document. The script must treat documents
+/// independently: globals are neither shared between workers nor reset
+/// between documents. Errors are reported for the lowest failing document
+/// index, wrapped in [`Error::Document`], and a worker panicking fails the
+/// run with [`Error::WorkerPanicked`].
+pub fn run_parallel_with_options<I>(
+    script: &str,
+    input: I,
+    options: ParallelOptions,
+) -> Result<Vec<Value>>
+where
+    I: IntoIterator<Item = Value>,
+{
+    let workers = options.workers.max(1);
+    let (job_tx, job_rx) = mpsc::sync_channel::<(usize, Value)>(workers * 2);
+    let job_rx = Arc::new(Mutex::new(job_rx));
+    let (result_tx, result_rx) = mpsc::channel();
+    let failed = Arc::new(AtomicBool::new(false));
+
+    thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|_| {
+                let (job_rx, result_tx, failed) =
+                    (job_rx.clone(), result_tx.clone(), failed.clone());
+                let run_options = &options.run_options;
+                scope.spawn(move || worker(script, run_options(), job_rx, result_tx, failed))
+            })
+            .collect::<Vec<_>>();
+        // Left to the workers alone, so that feeding stops once they are all
+        // gone.
+        drop(job_rx);
+        drop(result_tx);
+
+        for job in input.into_iter().enumerate() {
+            if failed.load(Ordering::Relaxed) || job_tx.send(job).is_err() {
+                break;
+            }
+        }
+        drop(job_tx);
+
+        let outputs = collect(result_rx, options.order);
+        join(handles)?;
+        outputs
+    })
+}
+
+/// Like [`run_parallel_with_options`], but reads documents from `source` only
+/// as workers are ready for them, and hands outputs to `sink` as soon as
+/// [`ParallelOptions::order`] allows rather than collecting them, so that
+/// memory stays bounded by the documents in flight. Returns the statistics
+/// of every worker added up.
+///
+/// Only what the script emits to the default channel reaches `sink`. When a
+/// document fails, outputs already handed over stay written; under
+/// [`OutputOrder::Input`] those of later documents never are.
+pub fn run_parallel_into(
+    script: &str,
+    mut source: impl InputSource,
+    options: ParallelOptions,
+    sink: &mut dyn OutputSink,
+) -> Result<RunStats> {
+    let started = Instant::now();
+    let workers = options.workers.max(1);
+    let (job_tx, job_rx) = mpsc::sync_channel::<(usize, Value)>(workers * 2);
+    let job_rx = Arc::new(Mutex::new(job_rx));
+    let (result_tx, result_rx) = mpsc::channel();
+    let failed = Arc::new(AtomicBool::new(false));
+
+    let mut output = InOrder {
+        sink,
+        order: options.order,
+        pending: BTreeMap::new(),
+        next: 0,
+        first_error: None,
+    };
+    let (input_error, stats) = thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|_| {
+                let (job_rx, result_tx, failed) =
+                    (job_rx.clone(), result_tx.clone(), failed.clone());
+                let run_options = &options.run_options;
+                scope.spawn(move || worker(script, run_options(), job_rx, result_tx, failed))
+            })
+            .collect::<Vec<_>>();
+        // Left to the workers alone, so that feeding stops once they are all
+        // gone.
+        drop(job_rx);
+        drop(result_tx);
+
+        let mut input_error = None;
+        for index in 0.. {
+            if failed.load(Ordering::Relaxed) {
+                break;
+            }
+            let doc = match source.next_doc() {
+                Ok(Some(doc)) => doc,
+                Ok(None) => break,
+                Err(err) => {
+                    input_error = Some(Error::InputError {
+                        index,
+                        source: Box::new(err),
+                    });
+                    failed.store(true, Ordering::Relaxed);
+                    break;
+                }
+            };
+            if job_tx.send((index, doc)).is_err() {
+                break;
+            }
+            for result in result_rx.try_iter() {
+                output.receive(result, &failed);
+            }
+        }
+        drop(job_tx);
+        for result in result_rx {
+            output.receive(result, &failed);
+        }
+
+        (input_error, join(handles))
+    });
+    let stats = stats?;
+
+    match output.first_error {
+        Some((Some(index), err)) => Err(Error::Document {
+            index,
+            source: Box::new(err),
+        }),
+        Some((None, err)) => Err(err),
+        None => match input_error {
+            Some(err) => Err(err),
+            None => {
+                output.sink.finish()?;
+                Ok(RunStats {
+                    elapsed: started.elapsed(),
+                    ..stats
+                })
+            }
+        },
+    }
+}
+
+/// Hands outputs of documents finished in any order to a sink, in the order
+/// asked for.
+struct InOrder<'a> {
+    sink: &'a mut dyn OutputSink,
+    order: OutputOrder,
+    /// Outputs of documents finished before an earlier one, by index.
+    pending: BTreeMap<usize, Vec<Value>>,
+    /// The index of the document whose outputs are to be written next.
+    next: usize,
+    first_error: Option<(Option<usize>, Error)>,
+}
+
+impl InOrder<'_> {
+    fn receive(&mut self, (index, result): WorkerResult, failed: &AtomicBool) {
+        let written = match (index, result) {
+            (_, Err(err)) => Err(err),
+            (Some(index), Ok(output)) => match self.order {
+                OutputOrder::Input => {
+                    self.pending.insert(index, output);
+                    let mut written = Ok(());
+                    while written.is_ok()
+                        && let Some(output) = self.pending.remove(&self.next)
+                    {
+                        written = self.write(output);
+                        self.next += 1;
+                    }
+                    written
+                }
+                OutputOrder::Arrival => self.write(output),
+            },
+            (None, Ok(_)) => unreachable!("only setup failures have no document index"),
+        };
+        if let Err(err) = written {
+            failed.store(true, Ordering::Relaxed);
+            if self
+                .first_error
+                .as_ref()
+                .is_none_or(|(first, _)| index < *first)
+            {
+                self.first_error = Some((index, err));
+            }
+        }
+    }
+
+    fn write(&mut self, output: Vec<Value>) -> Result<()> {
+        for value in output {
+            self.sink.emit(DEFAULT_CHANNEL, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Processes documents until the job channel closes, returning the
+/// statistics of every document it ran.
+///
+/// The script runs once, as a single batch reading documents off the job
+/// channel, and whatever it emits between reading one document and the next
+/// is taken to be that document's output. In [`Mode::Map`], a document's
+/// outputs are sent as soon as the runner is through with it; in the other
+/// modes, once the script reads the next one, so that what `finalize` returns
+/// goes with the last document. After any failure the remaining jobs are
+/// drained unprocessed so the feeding thread never blocks.
+fn worker(
+    script: &str,
+    mut options: RunOptions,
+    jobs: Jobs,
+    results: Sender<WorkerResult>,
+    failed: Arc<AtomicBool>,
+) -> RunStats {
+    let outputs = Rc::new(RefCell::new(Vec::new()));
+    options.sink = Some(Box::new(Collected(outputs.clone())));
+    let mode = options.mode;
+    let mut runner = match Runner::with_options(script, options) {
+        Ok(runner) => runner,
+        Err(err) => {
+            failed.store(true, Ordering::Relaxed);
+            let _ = results.send((None, Err(err)));
+            drain(&jobs);
+            return RunStats::default();
+        }
+    };
+
+    // The index of the document the script is on, until its outputs are
+    // sent.
+    let current = Rc::new(Cell::new(None));
+    if mode == Mode::Map {
+        let (results, outputs, current) = (results.clone(), outputs.clone(), current.clone());
+        runner.on_document_done(move || {
+            if let Some(done) = current.take() {
+                let _ = results.send((Some(done), Ok(outputs.take())));
+            }
+        });
+    }
+    let input = {
+        let (jobs, results, failed) = (jobs.clone(), results.clone(), failed.clone());
+        let (outputs, current) = (outputs.clone(), current.clone());
+        let renumber = runner.renumber();
+        std::iter::from_fn(move || {
+            if failed.load(Ordering::Relaxed) {
+                return None;
+            }
+            let (index, doc) = jobs.lock().unwrap().recv().ok()?;
+            if let Some(done) = current.replace(Some(index)) {
+                let _ = results.send((Some(done), Ok(outputs.take())));
+            }
+            renumber(index);
+            Some(doc)
+        })
+    };
+    let result = runner.run_batch(input).map(|_| outputs.take());
+    if result.is_err() {
+        failed.store(true, Ordering::Relaxed);
+    }
+    match (current.get(), result) {
+        (Some(index), result) => {
+            let _ = results.send((Some(index), result));
+        }
+        // Nothing is emitted before the first document in the driver modes.
+        (None, Ok(_)) => {}
+        (None, Err(err)) => {
+            let _ = results.send((None, Err(err)));
+        }
+    }
+    drain(&jobs);
+    runner.stats()
+}
+
+fn drain(jobs: &Jobs) {
+    while jobs.lock().unwrap().recv().is_ok() {}
+}
+
+/// Waits for every worker, adding up their statistics, or failing with the
+/// first panic.
+fn join(handles: Vec<ScopedJoinHandle<'_, RunStats>>) -> Result<RunStats> {
+    let mut stats = RunStats::default();
+    let mut panicked = None;
+    for handle in handles {
+        match handle.join() {
+            Ok(worker) => stats.combine(worker),
+            Err(payload) => {
+                panicked.get_or_insert_with(|| Error::WorkerPanicked {
+                    message: panic_message(payload.as_ref()).to_string(),
+                });
+            }
+        }
+    }
+    match panicked {
+        Some(err) => Err(err),
+        None => Ok(stats),
+    }
+}
+
+/// Keeps what a worker's script emits to the default channel until the
+/// document it belongs to is done.
+struct Collected(Rc<RefCell<Vec<Value>>>);
+
+impl OutputSink for Collected {
+    fn emit(&mut self, channel: &str, value: Value) -> Result<()> {
+        if channel == DEFAULT_CHANNEL {
+            self.0.borrow_mut().push(value);
+        }
+        Ok(())
+    }
+}
+
+fn collect(results: Receiver<WorkerResult>, order: OutputOrder) -> Result<Vec<Value>> {
+    let mut by_index = BTreeMap::new();
+    let mut arrived = Vec::new();
+    let mut first_error: Option<(Option<usize>, Error)> = None;
+
+    for (index, result) in results {
+        match (index, result) {
+            (_, Err(err)) => {
+                if first_error.as_ref().is_none_or(|(first, _)| index < *first) {
+                    first_error = Some((index, err));
+                }
+            }
+            (Some(index), Ok(output)) => match order {
+                OutputOrder::Input => {
+                    by_index.insert(index, output);
+                }
+                OutputOrder::Arrival => arrived.extend(output),
+            },
+            (None, Ok(_)) => unreachable!("only setup failures have no document index"),
+        }
+    }
+
+    match first_error {
+        Some((Some(index), err)) => Err(Error::Document {
+            index,
+            source: Box::new(err),
+        }),
+        Some((None, err)) => Err(err),
+        None => Ok(match order {
+            OutputOrder::Input => by_index.into_values().flatten().collect(),
+            OutputOrder::Arrival => arrived,
+        }),
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, RecordBatch};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::json::writer::{JsonArray, WriterBuilder};
+use parquet::arrow::ProjectionMask;
+use parquet::arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder};
+use serde_json::Value;
+
+use crate::error::{Error, Result};
+use crate::source::InputSource;
+
+/// How Parquet timestamps reach the script.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// RFC 3339 strings, e.g. `"2024-05-01T12:00:00Z"`, taking
+    /// timestamps without a time zone as UTC. Time zones other than UTC
+    /// must be offsets, such as `+02:00`.
+    #[default]
+    Rfc3339,
+    /// Integer milliseconds since the Unix epoch.
+    EpochMillis,
+}
+
+/// Options for [`ParquetSource`].
+#[derive(Clone, Debug)]
+pub struct ParquetOptions {
+    /// The columns to read, leaving the others undecoded; every column when
+    /// `None`.
+    pub columns: Option<Vec<String>>,
+    pub timestamps: TimestampFormat,
+    /// How many rows are decoded at a time, which bounds memory along with
+    /// the size of a row group.
+    pub batch_size: usize,
+}
+
+impl Default for ParquetOptions {
+    fn default() -> Self {
+        Self {
+            columns: None,
+            timestamps: TimestampFormat::default(),
+            batch_size: 1024,
+        }
+    }
+}
+
+/// A Parquet file, every row becoming an object keyed by column name, with
+/// null columns kept as null. Rows are decoded a batch at a time.
+///
+/// - Lists become arrays, structs objects, and maps objects keyed by their
+///   keys' text.
+/// - Timestamps become what [`ParquetOptions::timestamps`] says, and
+///   decimals strings, keeping their precision, when they are top-level
+///   columns; nested inside lists or structs, timestamps are written without
+///   a time zone when they have none, and decimals as numbers.
+/// - Dates become `YYYY-MM-DD` strings, and binary columns hex strings.
+pub struct ParquetSource {
+    batches: ParquetRecordBatchReader,
+    rows: VecDeque<Value>,
+    timestamps: TimestampFormat,
+}
+
+fn invalid(err: impl ToString) -> Error {
+    Error::InvalidParquet {
+        message: err.to_string(),
+    }
+}
+
+impl ParquetSource {
+    pub fn new(file: File, options: &ParquetOptions) -> Result<Self> {
+        let mut builder = ParquetRecordBatchReaderBuilder::try_new(file)
+            .map_err(invalid)?
+            .with_batch_size(options.batch_size);
+        if let Some(columns) = &options.columns {
+            let schema = builder.schema();
+            if let Some(unknown) = columns
+                .iter()
+                .find(|column| schema.field_with_name(column.as_str()).is_err())
+            {
+                return Err(invalid(format!("no column named '{unknown}'")));
+            }
+            let mask = ProjectionMask::columns(
+                builder.parquet_schema(),
+                columns.iter().map(String::as_str),
+            );
+            builder = builder.with_projection(mask);
+        }
+        Ok(Self {
+            batches: builder.build().map_err(invalid)?,
+            rows: VecDeque::new(),
+            timestamps: options.timestamps,
+        })
+    }
+
+    /// The column as the script sees it, before writing as JSON.
+    fn prepare(&self, column: &ArrayRef) -> Result<ArrayRef> {
+        let to = match (column.data_type(), self.timestamps) {
+            (DataType::Timestamp(_, tz), TimestampFormat::EpochMillis) => {
+                let millis = cast(
+                    column,
+                    &DataType::Timestamp(TimeUnit::Millisecond, tz.clone()),
+                )
+                .map_err(invalid)?;
+                return cast(&millis, &DataType::Int64).map_err(invalid);
+            }
+            // Arrow only formats named time zones with its `chrono-tz`
+            // feature, so UTC, as most writers name it, is made an offset.
+            (DataType::Timestamp(unit, tz), TimestampFormat::Rfc3339)
+                if tz.as_deref().is_none_or(is_utc) =>
+            {
+                DataType::Timestamp(*unit, Some("+00:00".into()))
+            }
+            (DataType::Decimal128(..) | DataType::Decimal256(..), _) => DataType::Utf8,
+            _ => return Ok(column.clone()),
+        };
+        cast(column, &to).map_err(invalid)
+    }
+
+    fn read_batch(&mut self, batch: RecordBatch) -> Result<()> {
+        let columns = batch
+            .columns()
+            .iter()
+            .map(|column| self.prepare(column))
+            .collect::<Result<Vec<_>>>()?;
+        let fields = batch
+            .schema()
+            .fields()
+            .iter()
+            .zip(&columns)
+            .map(|(field, column)| {
+                Field::new(
+                    field.name(),
+                    column.data_type().clone(),
+                    field.is_nullable(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let batch =
+            RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).map_err(invalid)?;
+
+        let mut writer = WriterBuilder::new()
+            .with_explicit_nulls(true)
+            .build::<_, JsonArray>(Vec::new());
+        writer.write(&batch).map_err(invalid)?;
+        writer.finish().map_err(invalid)?;
+        let json = writer.into_inner();
+        if !json.is_empty() {
+            let rows: Vec<Value> = serde_json::from_slice(&json).map_err(invalid)?;
+            self.rows.extend(rows);
+        }
+        Ok(())
+    }
+}
+
+fn is_utc(tz: &str) -> bool {
+    matches!(tz, "UTC" | "Etc/UTC" | "Z")
+}
+
+impl InputSource for ParquetSource {
+    fn next_doc(&mut self) -> Result<Option<Value>> {
+        loop {
+            if let Some(row) = self.rows.pop_front() {
+                return Ok(Some(row));
+            }
+            match self.batches.next() {
+                Some(batch) => self.read_batch(batch.map_err(invalid)?)?,
+                None => return Ok(None),
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use mlua::Error as LuaError;
+use serde_json::Value;
+
+use crate::error::{Error, Result};
+use crate::runner::{InputIter, Runner};
+
+/// Runs `scripts` as a chain where everything stage N emits is what stage
+/// N + 1 reads from `get_next`.
+///
+/// Each stage has its own Lua state. Stages are interleaved lazily, so a
+/// document flows through the whole chain before the first stage reads the
+/// next one and nothing between stages is buffered beyond a single emit.
+/// Failures are reported as [`Error::Stage`] naming the stage that failed
+/// first, even if the failure also broke the stages downstream of it.
+pub fn run_pipeline<I>(scripts: &[&str], input: I) -> Result<Vec<Value>>
+where
+    I: IntoIterator<Item = Value>,
+    I::IntoIter: 'static,
+{
+    let Some((last, upstream)) = scripts.split_last() else {
+        return Ok(input.into_iter().collect());
+    };
+
+    let failure: Rc<RefCell<Option<(usize, Error)>>> = Rc::default();
+    let mut docs: InputIter = Box::new(input.into_iter().map(Ok));
+    for (index, script) in upstream.iter().enumerate() {
+        let runner = Runner::new(script).map_err(|err| stage_error(index, err))?;
+        let failure = failure.clone();
+        docs = Box::new(Runner::emit_iter(runner, docs).map(move |item| {
+            item.map_err(|err| {
+                failure.borrow_mut().get_or_insert((index, err));
+                Error::Lua(LuaError::runtime(format!(
+                    "pipeline stage {} failed",
+                    index + 1
+                )))
+            })
+        }));
+    }
+
+    let result = Runner::new(last).and_then(|mut runner| runner.run_batch_inner(docs));
+    if let Some((index, err)) = failure.borrow_mut().take() {
+        return Err(stage_error(index, err));
+    }
+    result.map_err(|err| stage_error(upstream.len(), err))
+}
+
+fn stage_error(index: usize, err: Error) -> Error {
+    Error::Stage {
+        index,
+        source: Box::new(err),
+    }
+}
@@ -0,0 +1,1084 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::rc::Rc;
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::{Duration, Instant};
+
+use mlua::{
+    Error as LuaError, Function as LuaFunction, Lua, MultiValue as LuaMultiValue,
+    Result as LuaResult, Table as LuaTable, Value as LuaValue,
+};
+use serde_json::Value;
+
+use crate::determinism::{
+    ClockSource, install_clock, install_ids, install_random, install_sampling,
+};
+use crate::error::{Error, ErrorContext, Limit, Result};
+use crate::explode::{Explode, Exploder};
+use crate::limits::{CancellationToken, DocumentLimitPolicy, HookState, install_hook};
+use crate::sandbox::Sandbox;
+use crate::sink::OutputSink;
+use crate::source::{InputSource, SourcePosition};
+use crate::trace::{TraceEvent, TraceFn, Tracer};
+use crate::value::{SharedValue, json_to_lua, lua_to_json};
+
+mod aggregate;
+mod args;
+mod channels;
+mod collections;
+mod csv;
+mod encoding;
+mod env;
+mod feed;
+mod flatten;
+mod hash;
+mod hooks;
+mod inputs;
+mod inspect;
+mod iter;
+mod join;
+mod json;
+mod kv;
+mod logging;
+mod many;
+mod map;
+mod metrics;
+mod modules;
+mod position;
+mod print;
+mod re;
+mod repl;
+mod schema;
+mod state;
+#[cfg(feature = "async")]
+mod streaming;
+mod strings;
+mod template;
+mod time;
+mod urls;
+mod window;
+mod xml;
+
+use args::{install_args, install_argv};
+use channels::Channels;
+pub(crate) use channels::DEFAULT_CHANNEL;
+use collections::install_collections;
+use csv::install_csv;
+use encoding::install_encoding;
+pub use env::EnvAccess;
+use env::install_env;
+pub(crate) use feed::Feeder;
+pub(crate) use flatten::flatten_into;
+use flatten::install_flatten;
+use hash::install_hash;
+pub(crate) use hooks::panic_message;
+pub use hooks::{DocumentEndHook, DocumentStartHook, EmitHook, Hooks};
+use inputs::NamedInput;
+use inspect::install_inspect;
+pub use iter::EmitIter;
+use join::install_join;
+use json::install_json;
+pub use kv::{KeyedOutput, run_kv};
+use logging::install_log;
+pub use map::{ErrorPolicy, Failure, MapOutput, run_map};
+use metrics::install_metrics;
+pub use metrics::{Metric, MetricSummary};
+use position::install_position;
+use print::{PrintLog, install_print};
+use re::install_re;
+pub use repl::{Evaluated, Session};
+use schema::{InputSchema, install_validate};
+use state::restore_globals;
+#[cfg(feature = "async")]
+pub use streaming::{EmitStream, run_async, run_async_into, run_stream};
+use strings::install_str;
+use template::install_fmt;
+use time::install_time;
+use urls::install_url;
+use window::{Window, install_window};
+use xml::install_xml;
+
+const DEFAULT_HOOK_INTERVAL: u32 = 10_000;
+const DEFAULT_SCRIPT_NAME: &str = "script";
+const DEFAULT_MAX_FAILURES: usize = 1_000;
+
+/// Helper functions preloaded as the `util` global.
+const UTIL: &str = include_str!("runner/util.lua");
+
+const PREAMBLE: &str = r#"
+local original_pairs = pairs
+function pairs(t)
+    if type(t) == "userdata" and t.__pairs_impl then
+        local ok, iter, state, key = pcall(function() return t:__pairs_impl() end)
+        if ok then return iter, state, key end
+    end
+    return original_pairs(t)
+end
+"#;
+
+/// How the runner drives the script.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// The script runs top to bottom, reading input with `get_next` and
+    /// writing output with `emit`.
+    #[default]
+    FreeForm,
+    /// The script defines `transform(doc)`, which the runner calls for every
+    /// document; whatever it returns is emitted, with nil dropping the
+    /// document and multiple return values each emitted in turn. Once the
+    /// script calls `window(n)`, `transform` also gets an array of copies of
+    /// the previous documents, most recent first, as its second argument.
+    ///
+    /// Honored by [`Runner::run_batch`] and everything built on it; the
+    /// streaming entry points always run the script free-form.
+    Map,
+    /// The script defines `init()`, `accumulate(state, doc)` and
+    /// `finalize(state)`. The runner threads the state `init` returns through
+    /// `accumulate` for every document, replacing it with whatever
+    /// `accumulate` returns, and emits what `finalize` returns at the end.
+    /// `accumulate` returning nil keeps the state, so it can be updated in
+    /// place, and nil values `finalize` returns are not emitted. Documents
+    /// the three functions `emit` go out as they are emitted, ahead of those
+    /// `finalize` returns.
+    /// Honored by the same entry points as [`Mode::Map`].
+    Aggregate,
+}
+
+pub struct RunOptions {
+    /// Chunk name used in Lua error messages and our own error reports.
+    pub script_name: Option<String>,
+    pub mode: Mode,
+    pub sandbox: Sandbox,
+    /// Stops the script with [`Error::Cancelled`](crate::Error::Cancelled)
+    /// once cancelled.
+    pub cancellation: Option<CancellationToken>,
+    /// Caps the Lua VM instructions a single run may execute, failing with
+    /// [`Error::LimitExceeded`](crate::Error::LimitExceeded).
+    /// Enforced at `hook_interval` granularity.
+    pub max_instructions: Option<u64>,
+    /// Wall-clock limit for a single run, counted from when the runner starts
+    /// executing the script; time spent producing input counts against it.
+    pub timeout: Option<Duration>,
+    /// Like `max_instructions`, but counted from the start of each document.
+    /// In free-form mode a document starts when `get_next` returns it.
+    pub per_document_max_instructions: Option<u64>,
+    /// Like `timeout`, but counted from the start of each document. Both
+    /// limits apply at once; whichever fires first wins.
+    pub per_document_timeout: Option<Duration>,
+    pub on_document_limit: DocumentLimitPolicy,
+    /// What the driver modes do when the script fails on a document.
+    pub on_error: ErrorPolicy,
+    /// Most failures kept under [`ErrorPolicy::Collect`]; any past it are only
+    /// counted, so a script failing on everything cannot exhaust memory.
+    pub max_failures: usize,
+    /// Number of Lua VM instructions between checks of the limits above;
+    /// lower values react faster at the cost of throughput.
+    pub hook_interval: u32,
+    /// Caps the memory the Lua state may allocate, failing with
+    /// [`Error::LimitExceeded`](crate::Error::LimitExceeded).
+    pub max_lua_memory: Option<usize>,
+    /// Called as the run progresses, e.g. to log what the script is doing.
+    /// The runner itself never prints anything.
+    pub trace: Option<TraceFn>,
+    /// Receives whatever the script prints, as it prints it, instead of the
+    /// log kept by the runner.
+    pub print_to: Option<Box<dyn Write>>,
+    pub hooks: Hooks,
+    /// Channels `emit_to` can send to besides `out`. They are part of the
+    /// output even when nothing is sent to them.
+    pub channels: Vec<String>,
+    /// Makes `emit_to` fail on channels missing from `channels`, instead of
+    /// creating them as they are first used.
+    pub strict_channels: bool,
+    /// Globals [`Runner::snapshot_globals`] saves, so that a later run, in
+    /// this process or another, can pick up where this one stopped.
+    pub persist_globals: Vec<String>,
+    /// A snapshot taken by [`Runner::snapshot_globals`], loaded into globals
+    /// before the script first runs.
+    pub restore_globals: Option<Value>,
+    /// Seeds a generator that replaces `math.random` and `math.randomseed`,
+    /// another behind `uuid` and `ulid`, and another behind `sample` and
+    /// `reservoir`, making scripts that use them reproducible.
+    pub random_seed: Option<u64>,
+    /// Replaces the clock behind `os.time`, `os.clock`, `os.date`,
+    /// `time.now_ms` and the timestamps of `ulid`.
+    pub clock: Option<ClockSource>,
+    /// Parameters the script sees as the read-only global `args`; see
+    /// [`Runner::set_args`].
+    pub args: Option<Value>,
+    /// Positional string arguments the script sees as `ARGV`.
+    pub argv: Vec<String>,
+    /// Preloads the `util` global with map, filter, reduce and friends. Turn
+    /// off for a pristine environment.
+    pub preload_util: bool,
+    /// Takes every emitted document, from any channel, as it is emitted;
+    /// [`Runner::run_batch`] and friends then return no outputs. Not used by
+    /// [`Runner::run_iter`] or the streaming entry points, which hand
+    /// documents over themselves.
+    pub sink: Option<Box<dyn OutputSink>>,
+    /// Splits every input document into one per element of an array inside
+    /// it before the script sees them. Honored by [`Runner::run_batch`] and
+    /// everything built on it, and by [`Runner::run_iter`].
+    pub explode: Option<Explode>,
+    /// A JSON Schema every input document is checked against before the
+    /// script sees it, under the same entry points as `explode`. Documents
+    /// that do not match fail to read with
+    /// [`Error::SchemaViolation`](crate::Error::SchemaViolation), unless
+    /// `invalid_input_channel` is set.
+    pub input_schema: Option<Value>,
+    /// Fails the run with
+    /// [`Error::OutputLimitExceeded`](crate::Error::OutputLimitExceeded) once
+    /// the script tries to emit more documents than this, from any channel.
+    pub max_emits: Option<u64>,
+    /// Like `max_emits`, but caps the approximate size of everything emitted
+    /// as compact JSON.
+    pub max_output_bytes: Option<u64>,
+    /// Sends input documents that do not match `input_schema` to this
+    /// channel instead of to the script.
+    pub invalid_input_channel: Option<String>,
+    /// Environment variables the script can read with `env(name)`; see
+    /// [`Runner::set_env`].
+    pub env: Option<EnvAccess>,
+    /// Makes `env(name)` fail on names that are not allowed, instead of
+    /// returning nil.
+    pub strict_env: bool,
+    /// Added to what `doc_index()` and `doc_meta()` count, for input that
+    /// starts this many documents in, e.g. past the ones a
+    /// [`SlicedSource`](crate::SlicedSource) skipped.
+    pub index_offset: usize,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            script_name: None,
+            mode: Mode::default(),
+            sandbox: Sandbox::default(),
+            cancellation: None,
+            max_instructions: None,
+            timeout: None,
+            per_document_max_instructions: None,
+            per_document_timeout: None,
+            on_document_limit: DocumentLimitPolicy::default(),
+            on_error: ErrorPolicy::default(),
+            max_failures: DEFAULT_MAX_FAILURES,
+            hook_interval: DEFAULT_HOOK_INTERVAL,
+            max_lua_memory: None,
+            trace: None,
+            print_to: None,
+            hooks: Hooks::default(),
+            channels: Vec::new(),
+            strict_channels: false,
+            persist_globals: Vec::new(),
+            restore_globals: None,
+            random_seed: None,
+            clock: None,
+            args: None,
+            argv: Vec::new(),
+            preload_util: true,
+            sink: None,
+            explode: None,
+            input_schema: None,
+            invalid_input_channel: None,
+            max_emits: None,
+            max_output_bytes: None,
+            env: None,
+            strict_env: false,
+            index_offset: 0,
+        }
+    }
+}
+
+/// Input documents as the runner consumes them. Failures are raised as Lua
+/// errors from `get_next` and reported as [`Error::InputError`].
+pub(crate) type InputIter = Box<dyn Iterator<Item = Result<Value>>>;
+
+/// State shared between the runner and the globals it installs into Lua,
+/// swapped out at the start and end of every batch.
+#[derive(Default)]
+struct Batch {
+    input: RefCell<Option<InputIter>>,
+    named_inputs: RefCell<HashMap<String, NamedInput>>,
+    output: RefCell<Vec<Value>>,
+    /// Keys given by `emit_kv`, with the position in `output` of the
+    /// document they belong to.
+    keys: RefCell<Vec<(usize, Value)>>,
+    channels: RefCell<Channels>,
+    sink: RefCell<Option<Box<dyn OutputSink>>>,
+    alive: RefCell<Rc<Cell<bool>>>,
+    documents_read: Cell<usize>,
+    /// Where the document last pulled from the input came from, and where
+    /// the one the script is on came from, when the input knows.
+    read_position: RefCell<Option<SourcePosition>>,
+    position: RefCell<Option<SourcePosition>>,
+    peeking: Cell<bool>,
+    peek_pending: Cell<bool>,
+    window: RefCell<Window>,
+    /// A failure of ours, like bad input, that a global raised into the
+    /// script; kept so it can be reported as such rather than as whatever
+    /// the script made of it.
+    raised: RefCell<Option<Error>>,
+    failures: RefCell<Vec<Failure>>,
+    failures_dropped: Cell<usize>,
+    documents_failed: Cell<usize>,
+    metrics: RefCell<BTreeMap<String, Metric>>,
+    emitted: Cell<usize>,
+    emitted_clones: Cell<usize>,
+    emitted_by_channel: RefCell<BTreeMap<String, usize>>,
+    /// Approximate size of everything emitted, only tracked under
+    /// `max_output_bytes`.
+    output_bytes: Cell<u64>,
+    max_emits: Option<u64>,
+    max_output_bytes: Option<u64>,
+    index_offset: usize,
+    /// Documents of the input before the one being read that the runner
+    /// never got, set through [`Runner::renumber`].
+    unseen: Cell<usize>,
+    /// Called once the runner is through with a document in the driver
+    /// modes, set through [`Runner::on_document_done`].
+    document_done: RefCell<Option<Box<dyn FnMut()>>>,
+    started: Cell<Option<Instant>>,
+    elapsed: Cell<Duration>,
+    peak_memory: Cell<usize>,
+    log: RefCell<PrintLog>,
+    hooks: RefCell<Hooks>,
+    hook: Option<Rc<HookState>>,
+    tracer: Option<Tracer>,
+}
+
+impl Batch {
+    fn begin(&self, input: Option<InputIter>) {
+        *self.input.borrow_mut() = input;
+        *self.alive.borrow_mut() = Rc::new(Cell::new(true));
+        self.keys.borrow_mut().clear();
+        self.documents_read.set(0);
+        self.unseen.set(0);
+        self.read_position.borrow_mut().take();
+        self.position.borrow_mut().take();
+        self.peeking.set(false);
+        self.peek_pending.set(false);
+        self.window.borrow_mut().reset();
+        self.raised.borrow_mut().take();
+        self.failures.borrow_mut().clear();
+        self.failures_dropped.set(0);
+        self.documents_failed.set(0);
+        self.metrics.borrow_mut().clear();
+        self.emitted.set(0);
+        self.emitted_clones.set(0);
+        self.emitted_by_channel.borrow_mut().clear();
+        self.output_bytes.set(0);
+        self.started.set(Some(Instant::now()));
+        self.elapsed.set(Duration::ZERO);
+        self.peak_memory.set(0);
+        self.log.borrow_mut().take();
+        self.channels.borrow_mut().reset();
+        if let Some(hook) = &self.hook {
+            hook.reset();
+        }
+        if let Some(tracer) = &self.tracer {
+            tracer.script_starting();
+        }
+    }
+
+    /// Invalidates the batch's handles and hands back what it emitted. Safe to
+    /// call more than once.
+    fn end(&self) -> Vec<Value> {
+        if let Some(started) = self.started.take() {
+            self.elapsed.set(started.elapsed());
+        }
+        self.alive.borrow().set(false);
+        self.input.borrow_mut().take();
+        std::mem::take(&mut *self.output.borrow_mut())
+    }
+
+    /// Pulls the next document of the default input, raising input failures
+    /// into the script.
+    fn next_input(&self) -> LuaResult<Option<Value>> {
+        let next = self.input.borrow_mut().as_mut().and_then(|it| it.next());
+        next.transpose().map_err(|e| {
+            self.raise(Error::InputError {
+                index: self.documents_read.get(),
+                source: Box::new(e),
+            })
+        })
+    }
+
+    /// The 1-based ordinal the script sees for the document it is on, or 0
+    /// before the first.
+    fn doc_index(&self) -> usize {
+        match self.documents_read.get() {
+            0 => 0,
+            read => read + self.unseen.get() + self.index_offset,
+        }
+    }
+
+    /// Records that the script received `doc`. A document fetched by `peek`
+    /// only starts once `get_next` hands it over, through
+    /// [`Batch::start_peeked`].
+    fn start_document(&self, lua: &Lua, doc: &Value) {
+        self.window.borrow_mut().read(doc, self.peeking.get());
+        if self.peeking.get() {
+            self.peek_pending.set(true);
+            return;
+        }
+        self.count_document(lua);
+    }
+
+    fn document_done(&self) {
+        if let Some(done) = &mut *self.document_done.borrow_mut() {
+            done();
+        }
+    }
+
+    fn start_peeked(&self, lua: &Lua) {
+        if self.peek_pending.replace(false) {
+            self.window.borrow_mut().start_peeked();
+            self.count_document(lua);
+        }
+    }
+
+    fn count_document(&self, lua: &Lua) {
+        self.sample_memory(lua);
+        let index = self.documents_read.get();
+        self.documents_read.set(index + 1);
+        *self.position.borrow_mut() = self.read_position.borrow().clone();
+        if let Some(hook) = &self.hook {
+            hook.start_document(index);
+        }
+        if let Some(tracer) = &self.tracer {
+            tracer.send(TraceEvent::DocumentRead { index });
+        }
+    }
+
+    /// Records that the script is emitting `value`, through `emit_clone` if
+    /// `clone` is set, failing if the emit hook vetoes it.
+    fn record_emit(&self, lua: &Lua, clone: bool, value: &Value) -> LuaResult<()> {
+        self.sample_memory(lua);
+        let index = self.emitted.get() + self.emitted_clones.get();
+        self.check_output_limits(index, value)?;
+        let vetoed = self.hooks.borrow_mut().emit(index, value);
+        vetoed.map_err(|err| self.raise(err))?;
+        let counter = if clone {
+            &self.emitted_clones
+        } else {
+            &self.emitted
+        };
+        counter.set(counter.get() + 1);
+        if let Some(tracer) = &self.tracer {
+            tracer.send(TraceEvent::Emitted { index });
+        }
+        Ok(())
+    }
+
+    /// Fails emitting `value` as the `index`th document when it would exceed
+    /// an output limit.
+    fn check_output_limits(&self, index: usize, value: &Value) -> LuaResult<()> {
+        let which = match (self.max_emits, self.max_output_bytes) {
+            (Some(limit), _) if index as u64 >= limit => Limit::Emits { limit },
+            (_, Some(limit)) => {
+                let total = self.output_bytes.get() + approximate_size(value);
+                if total <= limit {
+                    self.output_bytes.set(total);
+                    return Ok(());
+                }
+                Limit::OutputBytes { limit }
+            }
+            _ => return Ok(()),
+        };
+        Err(self.raise(Error::OutputLimitExceeded {
+            which,
+            outputs: Vec::new(),
+        }))
+    }
+
+    /// Turns one of our errors into a Lua error to raise from a global,
+    /// remembering the original for [`Runner::convert_error`].
+    fn raise(&self, err: Error) -> LuaError {
+        let lua_err = LuaError::runtime(err.to_string());
+        *self.raised.borrow_mut() = Some(err);
+        lua_err
+    }
+
+    fn sample_memory(&self, lua: &Lua) {
+        self.peak_memory
+            .set(self.peak_memory.get().max(lua.used_memory()));
+    }
+}
+
+/// Counters for the last batch a [`Runner`] ran, whether or not it succeeded.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RunStats {
+    /// Documents handed to the script.
+    pub documents_read: usize,
+    /// Documents emitted with `emit`, or returned from a driver mode callback.
+    pub emitted: usize,
+    /// Documents emitted with `emit_clone`.
+    pub emitted_clones: usize,
+    /// Documents emitted, cloned or not, by the channel they went to.
+    pub emitted_by_channel: BTreeMap<String, usize>,
+    pub elapsed: Duration,
+    /// Highest Lua memory use seen, sampled whenever the script reads or
+    /// emits a document.
+    pub peak_memory: usize,
+    /// Approximate instructions executed, when a limit needing the
+    /// instruction hook is set.
+    pub instructions: Option<u64>,
+    /// Documents the script failed on under [`ErrorPolicy::Collect`], or
+    /// skipped under [`DocumentLimitPolicy::Skip`], kept or not.
+    pub failures: usize,
+    /// Failures past [`RunOptions::max_failures`], counted but not kept.
+    pub failures_dropped: usize,
+    /// What the script recorded through the `metrics` global, by name.
+    pub metrics: BTreeMap<String, Metric>,
+}
+
+impl RunStats {
+    /// Adds up `other` and these, as for batches run side by side; `elapsed`
+    /// is left for the caller, who knows how long they took together.
+    pub(crate) fn combine(&mut self, other: RunStats) {
+        self.documents_read += other.documents_read;
+        self.emitted += other.emitted;
+        self.emitted_clones += other.emitted_clones;
+        for (channel, count) in other.emitted_by_channel {
+            *self.emitted_by_channel.entry(channel).or_default() += count;
+        }
+        self.peak_memory = self.peak_memory.max(other.peak_memory);
+        self.instructions = match (self.instructions, other.instructions) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, b) => a.or(b),
+        };
+        self.failures += other.failures;
+        self.failures_dropped += other.failures_dropped;
+        for (name, metric) in other.metrics {
+            match self.metrics.get_mut(&name) {
+                Some(existing) => existing.merge(metric),
+                None => {
+                    self.metrics.insert(name, metric);
+                }
+            }
+        }
+    }
+}
+
+/// Converts an emitted Lua value, moving handles out of their document unless
+/// `clone` is set.
+fn output_value(val: LuaValue, clone: bool) -> LuaResult<Value> {
+    Ok(match val {
+        LuaValue::UserData(data) => match data.borrow::<SharedValue>() {
+            Ok(v) if clone => v.resolve()?.clone(),
+            Ok(v) => v.clone().take()?,
+            Err(_) => Value::Null,
+        },
+        _ => lua_to_json(val)?,
+    })
+}
+
+/// Length of `value` as compact JSON, give or take escapes and number
+/// formatting, without serializing it.
+fn approximate_size(value: &Value) -> u64 {
+    match value {
+        Value::Null => 4,
+        Value::Bool(true) => 4,
+        Value::Bool(false) => 5,
+        Value::Number(n) => n.as_i64().map_or(8, |n| {
+            let digits = n.unsigned_abs().checked_ilog10().unwrap_or(0) + 1;
+            u64::from(digits) + u64::from(n < 0)
+        }),
+        Value::String(s) => s.len() as u64 + 2,
+        Value::Array(items) => {
+            let commas = items.len().saturating_sub(1) as u64;
+            2 + commas + items.iter().map(approximate_size).sum::<u64>()
+        }
+        Value::Object(fields) => {
+            let commas = fields.len().saturating_sub(1) as u64;
+            2 + commas
+                + fields
+                    .iter()
+                    .map(|(key, value)| key.len() as u64 + 3 + approximate_size(value))
+                    .sum::<u64>()
+        }
+    }
+}
+
+/// Converts the arguments of an `emit` call, each into a document of its own.
+fn emit_values(args: LuaMultiValue, clone: bool) -> LuaResult<Vec<Value>> {
+    if args.is_empty() {
+        return Err(LuaError::runtime("emit expects at least one value"));
+    }
+    args.into_iter()
+        .map(|val| output_value(val, clone))
+        .collect()
+}
+
+/// Owns a Lua state and a compiled script, so that globals created by the
+/// script survive across calls to [`Runner::run_batch`].
+pub struct Runner {
+    lua: Lua,
+    chunk: LuaFunction,
+    mode: Mode,
+    batch: Rc<Batch>,
+    script_name: String,
+    max_lua_memory: Option<usize>,
+    on_document_limit: DocumentLimitPolicy,
+    on_error: ErrorPolicy,
+    max_failures: usize,
+    persist_globals: Vec<String>,
+    /// Set once a module is registered and `require` is ours.
+    register_module: Option<LuaFunction>,
+    explode: Option<Rc<Exploder>>,
+    input_schema: Option<Rc<InputSchema>>,
+    strict_env: bool,
+}
+
+impl Runner {
+    pub fn new(script: &str) -> Result<Self> {
+        Self::with_options(script, RunOptions::default())
+    }
+
+    pub fn with_options(script: &str, options: RunOptions) -> Result<Self> {
+        let lua = options.sandbox.create_lua()?;
+        lua.load(PREAMBLE)
+            .set_name("=[mlua_play preamble]")
+            .exec()?;
+        if options.preload_util {
+            let util: LuaTable = lua.load(UTIL).set_name("=util").call(())?;
+            lua.globals().set("util", util)?;
+        }
+        let hook = install_hook(&lua, &options)?;
+        let explode = options.explode.map(Explode::compile).transpose()?;
+        let input_schema = options
+            .input_schema
+            .as_ref()
+            .map(|schema| InputSchema::new(schema, options.invalid_input_channel))
+            .transpose()?;
+        let script_name = options
+            .script_name
+            .unwrap_or_else(|| DEFAULT_SCRIPT_NAME.to_string());
+        let batch = Rc::new(Batch {
+            hook,
+            max_emits: options.max_emits,
+            index_offset: options.index_offset,
+            max_output_bytes: options.max_output_bytes,
+            tracer: options
+                .trace
+                .map(|callback| Tracer::new(callback, &script_name, script)),
+            log: RefCell::new(PrintLog::new(options.print_to)),
+            hooks: RefCell::new(options.hooks),
+            channels: RefCell::new(Channels::new(options.channels, options.strict_channels)),
+            sink: RefCell::new(options.sink),
+            ..Batch::default()
+        });
+        install_print(&lua, batch.clone())?;
+        install_log(&lua, batch.clone(), script_name.clone())?;
+        install_json(&lua, batch.clone())?;
+        install_inspect(&lua)?;
+        install_collections(&lua, batch.clone())?;
+        install_join(&lua, batch.clone())?;
+        install_flatten(&lua, batch.clone())?;
+        install_validate(&lua)?;
+        install_fmt(&lua)?;
+        install_url(&lua)?;
+        install_re(&lua)?;
+        install_str(&lua)?;
+        install_csv(&lua)?;
+        install_xml(&lua, batch.clone())?;
+        install_encoding(&lua)?;
+        install_hash(&lua)?;
+        install_metrics(&lua, batch.clone())?;
+        install_position(&lua, batch.clone())?;
+        install_window(&lua, batch.clone())?;
+        install_env(&lua, options.env, options.strict_env)?;
+        if let Some(seed) = options.random_seed {
+            install_random(&lua, seed)?;
+        }
+        let clock = options.clock.map(Rc::new);
+        if let Some(clock) = &clock {
+            install_clock(&lua, clock.clone())?;
+        }
+        install_ids(&lua, options.random_seed, clock.clone())?;
+        install_sampling(&lua, options.random_seed)?;
+        install_time(&lua, clock)?;
+        options.sandbox.restrict(&lua)?;
+        if let Some(state) = &options.restore_globals {
+            restore_globals(&lua, state)?;
+        }
+        if let Some(args) = &options.args {
+            install_args(&lua, args)?;
+        }
+        install_argv(&lua, &options.argv)?;
+        let chunk = lua
+            .load(script)
+            .set_name(format!("={script_name}"))
+            .into_function()
+            .map_err(|err| {
+                let ctx = ErrorContext {
+                    script: &script_name,
+                    document: None,
+                    memory_limit: options.max_lua_memory,
+                };
+                Error::from_lua(err, &ctx)
+            })?;
+        if let Some(limit) = options.max_lua_memory {
+            lua.set_memory_limit(limit)?;
+        }
+        Ok(Self {
+            lua,
+            chunk,
+            mode: options.mode,
+            batch,
+            script_name,
+            max_lua_memory: options.max_lua_memory,
+            on_document_limit: options.on_document_limit,
+            on_error: options.on_error,
+            max_failures: options.max_failures,
+            persist_globals: options.persist_globals,
+            register_module: None,
+            explode: explode.map(Rc::new),
+            input_schema: input_schema.map(Rc::new),
+            strict_env: options.strict_env,
+        })
+    }
+
+    /// Runs the script once over `input`, returning everything it emitted.
+    /// Documents failing under [`ErrorPolicy::Collect`] are left for
+    /// [`Runner::take_failures`].
+    ///
+    /// Document handles obtained during a batch become stale when it ends, so
+    /// a script stashing one in a global gets an error when touching it in a
+    /// later batch rather than seeing a document it no longer owns.
+    pub fn run_batch<I>(&mut self, input: I) -> Result<Vec<Value>>
+    where
+        I: IntoIterator<Item = Value>,
+        I::IntoIter: 'static,
+    {
+        self.run_batch_inner(Box::new(input.into_iter().map(Ok)))
+    }
+
+    /// Like [`Runner::run_batch`], but reads documents from `source` only as
+    /// the script asks for them.
+    pub fn run_source(&mut self, mut source: impl InputSource + 'static) -> Result<Vec<Value>> {
+        let batch = self.batch.clone();
+        self.run_batch_inner(Box::new(std::iter::from_fn(move || {
+            let next = source.next_doc().transpose();
+            *batch.read_position.borrow_mut() = source.position();
+            next
+        })))
+    }
+
+    /// Like [`Runner::run_batch`], but over tables living in this runner's
+    /// Lua state, e.g. ones built by code run through [`Runner::lua`], saving
+    /// a trip through JSON text. Each table is converted into a document only
+    /// once the script asks for it.
+    pub fn run_tables<I>(&mut self, tables: I) -> Result<Vec<Value>>
+    where
+        I: IntoIterator<Item = LuaTable>,
+        I::IntoIter: 'static,
+    {
+        let script = self.script_name.clone();
+        let memory_limit = self.max_lua_memory;
+        let input = tables.into_iter().map(move |table| {
+            lua_to_json(LuaValue::Table(table)).map_err(|err| {
+                let ctx = ErrorContext {
+                    script: &script,
+                    document: None,
+                    memory_limit,
+                };
+                Error::from_lua(err, &ctx)
+            })
+        });
+        self.run_batch_inner(Box::new(input))
+    }
+
+    pub(crate) fn run_batch_inner(&mut self, input: InputIter) -> Result<Vec<Value>> {
+        let input = self.prepare_input(input);
+        match self.mode {
+            Mode::FreeForm => {}
+            Mode::Map => return self.map_batch(input),
+            Mode::Aggregate => return self.aggregate_batch(input),
+        }
+        self.batch.begin(Some(input));
+        let result = self
+            .install_globals()
+            .and_then(|()| self.chunk.call::<()>(()));
+        let output = self.batch.end();
+        if let Err(err) = result {
+            return Err(self.convert_error(err).with_outputs(output));
+        }
+        self.batch.finish_sink()?;
+        Ok(output)
+    }
+
+    /// Applies [`RunOptions::explode`] and then
+    /// [`RunOptions::input_schema`] to `input`.
+    fn prepare_input(&self, input: InputIter) -> InputIter {
+        let input = match &self.explode {
+            Some(explode) => explode.clone().wrap(input),
+            None => input,
+        };
+        self.validate_input(input)
+    }
+
+    /// Takes the lines the script printed during the last batch; none when
+    /// they went to [`RunOptions::print_to`].
+    pub fn take_log(&self) -> Vec<String> {
+        self.batch.log.borrow_mut().take()
+    }
+
+    /// Statistics for the last batch, or the one still running.
+    pub fn stats(&self) -> RunStats {
+        let batch = &self.batch;
+        RunStats {
+            documents_read: batch.documents_read.get(),
+            emitted: batch.emitted.get(),
+            emitted_clones: batch.emitted_clones.get(),
+            emitted_by_channel: batch.emitted_by_channel.borrow().clone(),
+            elapsed: match batch.started.get() {
+                Some(started) => started.elapsed(),
+                None => batch.elapsed.get(),
+            },
+            peak_memory: batch.peak_memory.get(),
+            instructions: self.instructions_executed(),
+            failures: batch.documents_failed.get(),
+            failures_dropped: batch.failures_dropped.get(),
+            metrics: batch.metrics.borrow().clone(),
+        }
+    }
+
+    /// Takes what the last batch sent to channels other than `out`, by name.
+    pub fn take_channels(&self) -> BTreeMap<String, Vec<Value>> {
+        self.batch.channels.borrow_mut().take()
+    }
+
+    /// Sends what later batches emit to `sink`, replacing
+    /// [`RunOptions::sink`].
+    pub fn set_sink(&mut self, sink: impl OutputSink + 'static) {
+        *self.batch.sink.borrow_mut() = Some(Box::new(sink));
+    }
+
+    /// The Lua state the script runs in, for hosting code of your own next
+    /// to it.
+    pub fn lua(&self) -> &Lua {
+        &self.lua
+    }
+
+    /// Bytes currently allocated by the Lua state.
+    pub fn used_memory(&self) -> usize {
+        self.lua.used_memory()
+    }
+
+    fn convert_error(&self, err: mlua::Error) -> Error {
+        if let Some(err) = self.batch.raised.borrow_mut().take() {
+            return err;
+        }
+        let ctx = ErrorContext {
+            script: &self.script_name,
+            document: self
+                .batch
+                .documents_read
+                .get()
+                .checked_sub(1)
+                .map(|index| index + self.batch.unseen.get()),
+            memory_limit: self.max_lua_memory,
+        };
+        Error::from_lua(err, &ctx)
+    }
+
+    /// Returns a function making the next document read count as the one at
+    /// `index` of the input, for `doc_index()`, `doc_meta()` and errors, when
+    /// the runner only gets some of the documents of a larger input. Indices
+    /// must grow from one document to the next.
+    pub(crate) fn renumber(&self) -> impl Fn(usize) + 'static {
+        let batch = self.batch.clone();
+        move |index| {
+            batch
+                .unseen
+                .set(index.saturating_sub(batch.documents_read.get()))
+        }
+    }
+
+    /// Calls `done` whenever the runner is through with a document and moves
+    /// on, in the driver modes: after it went through, or after its failure
+    /// was kept. Everything the document emitted has been emitted by then.
+    pub(crate) fn on_document_done(&self, done: impl FnMut() + 'static) {
+        *self.batch.document_done.borrow_mut() = Some(Box::new(done));
+    }
+
+    /// Approximate number of Lua instructions the last batch executed, when
+    /// an instruction hook is installed.
+    pub fn instructions_executed(&self) -> Option<u64> {
+        self.batch.hook.as_ref().map(|hook| hook.executed())
+    }
+
+    fn install_globals(&self) -> LuaResult<()> {
+        let lua = &self.lua;
+
+        {
+            let batch = self.batch.clone();
+            lua.globals().set(
+                "get_next",
+                lua.create_function(move |lua, ()| {
+                    let Some(v) = batch.next_input()? else {
+                        return Ok((LuaValue::Nil, false));
+                    };
+                    batch.start_document(lua, &v);
+                    Ok((json_to_lua(lua, v, &batch.alive.borrow())?, true))
+                })?,
+            )?;
+        }
+
+        let batch = self.batch.clone();
+        let get_many =
+            lua.create_function(move |lua, (docs, mut count, n): (LuaTable, usize, usize)| {
+                for _ in 0..n {
+                    let Some(v) = batch.next_input()? else {
+                        break;
+                    };
+                    batch.start_document(lua, &v);
+                    count += 1;
+                    docs.raw_set(count, json_to_lua(lua, v, &batch.alive.borrow())?)?;
+                }
+                Ok(count)
+            })?;
+
+        for (name, clone) in [("emit_clone", true), ("emit", false)] {
+            let batch = self.batch.clone();
+            lua.globals().set(
+                name,
+                lua.create_function(move |lua, args: LuaMultiValue| {
+                    for json_val in emit_values(args, clone)? {
+                        batch.record_emit(lua, clone, &json_val)?;
+                        batch.push_output(DEFAULT_CHANNEL, json_val)?;
+                    }
+                    Ok(())
+                })?,
+            )?;
+        }
+
+        // Builds a document handle out of a Lua value, an empty object by
+        // default, so scripts can assemble documents the way they edit input.
+        let batch = self.batch.clone();
+        lua.globals().set(
+            "new_doc",
+            lua.create_function(move |lua, val: LuaValue| {
+                let doc = match output_value(val, true)? {
+                    // An empty table converts to an empty array.
+                    Value::Null => Value::Object(serde_json::Map::new()),
+                    Value::Array(arr) if arr.is_empty() => Value::Object(serde_json::Map::new()),
+                    doc => doc,
+                };
+                json_to_lua(lua, doc, &batch.alive.borrow())
+            })?,
+        )?;
+
+        // Like `new_doc`, but for arrays, starting out empty unless given a
+        // sequence.
+        let batch = self.batch.clone();
+        lua.globals().set(
+            "new_array",
+            lua.create_function(move |lua, val: LuaValue| {
+                let arr = match output_value(val, true)? {
+                    Value::Null => Value::Array(Vec::new()),
+                    arr @ Value::Array(_) => arr,
+                    _ => return Err(LuaError::runtime("new_array expects a sequence")),
+                };
+                json_to_lua(lua, arr, &batch.alive.borrow())
+            })?,
+        )?;
+
+        self.install_emit_to()?;
+        self.install_emit_many()?;
+        self.install_emit_kv()?;
+        self.install_input_helpers(Some(get_many))
+    }
+}
+
+/// The name of every global a script run with default options can count on,
+/// Lua's and the runner's, along with `args` and `ARGV`, which are there when
+/// given.
+pub(crate) fn global_names() -> Result<Vec<String>> {
+    let runner = Runner::with_options("", RunOptions::default())?;
+    runner
+        .install_globals()
+        .map_err(|err| runner.convert_error(err))?;
+    let mut names = vec!["args".to_string(), "ARGV".to_string()];
+    for pair in runner.lua.globals().pairs::<LuaValue, LuaValue>() {
+        if let (LuaValue::String(name), _) = pair.map_err(|err| runner.convert_error(err))? {
+            names.push(name.to_string_lossy());
+        }
+    }
+    Ok(names)
+}
+
+/// What a run emitted, along with what the script printed and how it went.
+#[derive(Debug, Default)]
+pub struct RunOutput {
+    /// Documents emitted to the default `out` channel.
+    pub outputs: Vec<Value>,
+    /// Documents sent to other channels with `emit_to`, by channel name.
+    pub channels: BTreeMap<String, Vec<Value>>,
+    /// Documents the script failed on under [`ErrorPolicy::Collect`].
+    pub failures: Vec<Failure>,
+    /// The globals listed in [`RunOptions::persist_globals`], as of the end
+    /// of the run.
+    pub state: Option<Value>,
+    pub log: Vec<String>,
+    pub stats: RunStats,
+}
+
+pub fn run<I>(script: &str, input: I) -> Result<Vec<Value>>
+where
+    I: IntoIterator<Item = Value>,
+    I::IntoIter: 'static,
+{
+    run_with_options(script, input, RunOptions::default())
+}
+
+pub fn run_with_options<I>(script: &str, input: I, options: RunOptions) -> Result<Vec<Value>>
+where
+    I: IntoIterator<Item = Value>,
+    I::IntoIter: 'static,
+{
+    Runner::with_options(script, options)?.run_batch(input)
+}
+
+/// Like [`run_with_options`], but also returns what the script printed and
+/// the run's statistics. Use a [`Runner`] to get at the statistics of a run
+/// that failed.
+pub fn run_with_output<I>(script: &str, input: I, options: RunOptions) -> Result<RunOutput>
+where
+    I: IntoIterator<Item = Value>,
+    I::IntoIter: 'static,
+{
+    let mut runner = Runner::with_options(script, options)?;
+    let outputs = runner.run_batch(input)?;
+    Ok(RunOutput {
+        outputs,
+        channels: runner.take_channels(),
+        failures: runner.take_failures(),
+        state: runner.snapshot_globals()?,
+        log: runner.take_log(),
+        stats: runner.stats(),
+    })
+}
+
+/// Runs `script` over documents received from `input`, sending what it emits
+/// to `output` as soon as it is emitted. Overrides [`RunOptions::sink`].
+///
+/// Meant to run on a worker thread, fed from another: the input ends once
+/// every sender of `input` is dropped, and dropping the receiver of `output`
+/// fails the next emit with [`Error::Sink`], aborting the script. Build the
+/// options on the worker thread, since they need not be `Send`.
+pub fn run_channel(
+    script: &str,
+    input: Receiver<Value>,
+    output: Sender<Value>,
+    options: RunOptions,
+) -> Result<()> {
+    let options = RunOptions {
+        sink: Some(Box::new(output)),
+        ..options
+    };
+    Runner::with_options(script, options)?.run_source(input)?;
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use std::cell::RefCell;
+
+use mlua::{MultiValue as LuaMultiValue, Value as LuaValue};
+use serde_json::Value;
+
+use super::{DEFAULT_CHANNEL, InputIter, Runner, output_value};
+use crate::error::Result;
+
+impl Runner {
+    /// Runs a batch in [`Mode::Aggregate`](super::Mode::Aggregate): the state
+    /// `init()` returns is threaded through `accumulate(state, doc)` for every
+    /// document, and whatever `finalize(state)` returns is emitted.
+    pub(super) fn aggregate_batch(&mut self, mut input: InputIter) -> Result<Vec<Value>> {
+        self.batch.begin(None);
+        let result = self.aggregate_documents(&mut input);
+        let outputs = self.batch.end();
+        if let Err(err) = result {
+            return Err(err.with_outputs(outputs));
+        }
+        self.batch.finish_sink()?;
+        Ok(outputs)
+    }
+
+    fn aggregate_documents(&self, input: &mut InputIter) -> Result<()> {
+        self.load_driven_script()?;
+        let init = self.required_function("init")?;
+        let accumulate = self.required_function("accumulate")?;
+        let finalize = self.required_function("finalize")?;
+
+        let state = init
+            .call::<LuaValue>(())
+            .map_err(|err| self.convert_error(err))?;
+        let state = RefCell::new(state);
+        self.drive_documents(input, |handle| {
+            let current = state.borrow().clone();
+            let returned = accumulate.call::<LuaMultiValue>((current, handle))?;
+            // Returning nil keeps the state, for scripts that update it in
+            // place.
+            if let Some(next) = returned.into_iter().next()
+                && !next.is_nil()
+            {
+                *state.borrow_mut() = next;
+            }
+            Ok(())
+        })?;
+
+        let returned = finalize
+            .call::<LuaMultiValue>(state.into_inner())
+            .map_err(|err| self.convert_error(err))?;
+        for ret in returned {
+            if ret.is_nil() {
+                continue;
+            }
+            let value = output_value(ret, false).map_err(|err| self.convert_error(err))?;
+            self.batch
+                .record_emit(&self.lua, false, &value)
+                .and_then(|()| self.batch.push_output(DEFAULT_CHANNEL, value))
+                .map_err(|err| self.convert_error(err))?;
+        }
+        Ok(())
+    }
+}
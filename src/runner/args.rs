@@ -0,0 +1,93 @@
+use mlua::{Function as LuaFunction, Lua, Result as LuaResult};
+use serde_json::Value;
+
+use super::Runner;
+use crate::error::Result;
+use crate::value::json_to_table;
+
+/// Sets the `args` global to a read-only view of the table passed in, nested
+/// tables included.
+const FROZEN_ARGS: &str = r#"
+local next, ipairs, type, error, setmetatable = next, ipairs, type, error, setmetatable
+
+local function freeze(t)
+    if type(t) ~= "table" then
+        return t
+    end
+    local frozen = {}
+    for k, v in next, t do
+        frozen[k] = freeze(v)
+    end
+    return setmetatable({}, {
+        __index = frozen,
+        __newindex = function()
+            error("args is read-only", 2)
+        end,
+        __pairs = function()
+            return next, frozen, nil
+        end,
+        __ipairs = function()
+            return ipairs(frozen)
+        end,
+        __len = function()
+            return #frozen
+        end,
+        __metatable = false,
+    })
+end
+
+args = freeze(...)
+"#;
+
+impl Runner {
+    /// Exposes `args` to the script as the read-only global `args`, for
+    /// parameters like thresholds or field names.
+    pub fn set_args(&mut self, args: &Value) -> Result<()> {
+        Ok(install_args(&self.lua, args)?)
+    }
+
+    /// Builder-style [`Runner::set_args`].
+    pub fn with_args(mut self, args: &Value) -> Result<Self> {
+        self.set_args(args)?;
+        Ok(self)
+    }
+
+    /// Exposes positional string arguments as the `ARGV` array.
+    pub fn set_argv<I>(&mut self, argv: I) -> Result<()>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        Ok(install_argv(&self.lua, argv)?)
+    }
+
+    /// Builder-style [`Runner::set_argv`].
+    pub fn with_argv<I>(mut self, argv: I) -> Result<Self>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.set_argv(argv)?;
+        Ok(self)
+    }
+}
+
+pub(super) fn install_args(lua: &Lua, args: &Value) -> LuaResult<()> {
+    let setup: LuaFunction = lua
+        .load(FROZEN_ARGS)
+        .set_name("=[mlua_play args]")
+        .into_function()?;
+    setup.call(json_to_table(lua, args)?)
+}
+
+pub(super) fn install_argv<I>(lua: &Lua, argv: I) -> LuaResult<()>
+where
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    let table = lua.create_table()?;
+    for (i, arg) in argv.into_iter().enumerate() {
+        table.raw_set(i + 1, arg.as_ref())?;
+    }
+    lua.globals().set("ARGV", table)
+}
@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+
+use mlua::{Error as LuaError, Function as LuaFunction, Result as LuaResult, Value as LuaValue};
+use serde_json::Value;
+
+use super::{Batch, Runner, output_value};
+use crate::error::{Error, Result};
+
+/// The channel plain `emit` writes to.
+pub(crate) const DEFAULT_CHANNEL: &str = "out";
+
+/// Sends the default channel through whatever `emit` the current mode
+/// installed, so `emit_to("out", ...)` streams and yields just like `emit`.
+const EMIT_TO: &str = r#"
+local push = ...
+return function(channel, value)
+    if channel == "out" then
+        return emit(value)
+    end
+    return push(channel, value)
+end
+"#;
+
+/// Documents emitted to named channels other than the default one.
+#[derive(Default)]
+pub(crate) struct Channels {
+    registered: Vec<String>,
+    strict: bool,
+    outputs: BTreeMap<String, Vec<Value>>,
+}
+
+impl Channels {
+    pub(crate) fn new(registered: Vec<String>, strict: bool) -> Self {
+        Self {
+            registered,
+            strict,
+            outputs: BTreeMap::new(),
+        }
+    }
+
+    /// Empties every channel, keeping the registered ones around so they show
+    /// up in the output even when nothing was sent to them.
+    pub(crate) fn reset(&mut self) {
+        self.outputs = self
+            .registered
+            .iter()
+            .map(|name| (name.clone(), Vec::new()))
+            .collect();
+    }
+
+    pub(crate) fn take(&mut self) -> BTreeMap<String, Vec<Value>> {
+        std::mem::take(&mut self.outputs)
+    }
+
+    /// Fails on channels that are not registered, when strict.
+    fn check(&self, channel: &str) -> LuaResult<()> {
+        if !self.strict
+            || channel == DEFAULT_CHANNEL
+            || self.registered.iter().any(|c| c == channel)
+        {
+            return Ok(());
+        }
+        let known = std::iter::once(DEFAULT_CHANNEL)
+            .chain(self.registered.iter().map(String::as_str))
+            .collect::<Vec<_>>();
+        Err(LuaError::runtime(format!(
+            "unknown output channel '{channel}' (registered: {})",
+            known.join(", ")
+        )))
+    }
+
+    fn push(&mut self, channel: &str, value: Value) {
+        self.outputs
+            .entry(channel.to_string())
+            .or_default()
+            .push(value);
+    }
+}
+
+impl Batch {
+    /// Hands an emitted document to the sink if there is one, or keeps it
+    /// with the rest of the batch's output otherwise.
+    pub(super) fn push_output(&self, channel: &str, value: Value) -> LuaResult<()> {
+        self.admit(channel)?;
+        if let Some(sink) = &mut *self.sink.borrow_mut() {
+            return sink.emit(channel, value).map_err(|source| {
+                self.raise(Error::Sink {
+                    channel: Some(channel.to_string()),
+                    source: Box::new(source),
+                })
+            });
+        }
+        if channel == DEFAULT_CHANNEL {
+            self.output.borrow_mut().push(value);
+        } else {
+            self.channels.borrow_mut().push(channel, value);
+        }
+        Ok(())
+    }
+
+    /// Counts a document going to `channel`, failing when the channel is not
+    /// one it may go to.
+    pub(super) fn admit(&self, channel: &str) -> LuaResult<()> {
+        self.channels.borrow().check(channel)?;
+        self.count_emit(channel);
+        Ok(())
+    }
+
+    pub(super) fn count_emit(&self, channel: &str) {
+        let mut counts = self.emitted_by_channel.borrow_mut();
+        match counts.get_mut(channel) {
+            Some(count) => *count += 1,
+            None => {
+                counts.insert(channel.to_string(), 1);
+            }
+        }
+    }
+
+    /// Lets the sink know a batch completed.
+    pub(super) fn finish_sink(&self) -> Result<()> {
+        match &mut *self.sink.borrow_mut() {
+            Some(sink) => sink.finish().map_err(|source| Error::Sink {
+                channel: None,
+                source: Box::new(source),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Runner {
+    pub(super) fn install_emit_to(&self) -> LuaResult<()> {
+        let lua = &self.lua;
+        let batch = self.batch.clone();
+        let push = lua.create_function(move |lua, (channel, val): (String, LuaValue)| {
+            let json_val = output_value(val, false)?;
+            batch.record_emit(lua, false, &json_val)?;
+            batch.push_output(&channel, json_val)
+        })?;
+        self.set_emit_to(push)
+    }
+
+    /// Installs `emit_to`, sending documents for channels other than `out` to
+    /// `push(channel, value)`.
+    pub(super) fn set_emit_to(&self, push: LuaFunction) -> LuaResult<()> {
+        let emit_to: LuaFunction = self
+            .lua
+            .load(EMIT_TO)
+            .set_name("=[mlua_play channels]")
+            .call(push)?;
+        self.lua.globals().set("emit_to", emit_to)
+    }
+}
@@ -0,0 +1,225 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+use std::rc::Rc;
+
+use mlua::{
+    Error as LuaError, Function as LuaFunction, Lua, Result as LuaResult, Table as LuaTable,
+    Value as LuaValue,
+};
+use serde_json::Value;
+
+use super::{Batch, output_value};
+use crate::value::json_to_lua;
+
+/// What to sort or group the elements of an array by.
+pub(super) enum Key {
+    Field(String),
+    /// A JSON pointer, told apart from a field name by its leading `/`.
+    Pointer(String),
+    Function(LuaFunction),
+}
+
+impl Key {
+    pub(super) fn from_lua(val: LuaValue) -> LuaResult<Self> {
+        match val {
+            LuaValue::String(s) => {
+                let s = s.to_str()?.to_string();
+                Ok(if s.starts_with('/') {
+                    Key::Pointer(s)
+                } else {
+                    Key::Field(s)
+                })
+            }
+            LuaValue::Function(f) => Ok(Key::Function(f)),
+            other => Err(LuaError::runtime(format!(
+                "key must be a field name, a JSON pointer or a function, got {}",
+                other.type_name()
+            ))),
+        }
+    }
+
+    /// The key of `elem`, or `None` when it has none. Functions get a copy of
+    /// the element and return its key.
+    pub(super) fn of(&self, lua: &Lua, batch: &Batch, elem: &Value) -> LuaResult<Option<Value>> {
+        Ok(match self {
+            Key::Field(name) => elem.get(name).cloned(),
+            Key::Pointer(pointer) => elem.pointer(pointer).cloned(),
+            Key::Function(f) => {
+                let handle = json_to_lua(lua, elem.clone(), &batch.alive.borrow())?;
+                match f.call::<LuaValue>(handle)? {
+                    LuaValue::Nil => None,
+                    key => Some(output_value(key, true)?),
+                }
+            }
+        })
+    }
+}
+
+/// The elements of a handle to an array or of a sequence, copied.
+pub(super) fn elements(val: LuaValue, name: &str) -> LuaResult<Vec<Value>> {
+    match output_value(val, true)? {
+        Value::Array(values) => Ok(values),
+        _ => Err(LuaError::runtime(format!("{name} expects an array"))),
+    }
+}
+
+/// Installs the helpers working on whole arrays:
+///
+/// - `sorted(list, key, desc)` returns a sorted copy of `list`, ordered by
+///   `key`: a field name, a JSON pointer or a function of the element.
+///   Elements without the key come last, also when `desc` is set. Keys of
+///   different types order null, booleans, numbers, strings, arrays, then
+///   objects, and the sort is stable.
+/// - `group_by(list, key, map, opts)` returns an object mapping every key,
+///   as a string, to the elements having it, in order and passed through
+///   `map` when given. Elements without the key are grouped under
+///   `opts.missing` when it is set and left out otherwise. Elements `map`
+///   returns nil for are left out.
+/// - `unique(list, key, opts)` returns a copy of `list` keeping only the
+///   first element with each key, or the first of every set of equal
+///   elements when `key` is nil, in their original order. Values are equal when they
+///   serialize the same, so objects compare regardless of key order but `1`
+///   and `1.0` differ. Elements without the key count as sharing one, unless
+///   `opts.keep_missing` is set, which keeps them all.
+pub(crate) fn install_collections(lua: &Lua, batch: Rc<Batch>) -> LuaResult<()> {
+    let unique_batch = batch.clone();
+    lua.globals().set(
+        "unique",
+        lua.create_function(
+            move |lua, (list, key, opts): (LuaValue, LuaValue, Option<LuaTable>)| {
+                let batch = &*unique_batch;
+                let key = match key {
+                    LuaValue::Nil => None,
+                    key => Some(Key::from_lua(key)?),
+                };
+                let keep_missing = match opts {
+                    Some(opts) => opts.get::<Option<bool>>("keep_missing")?.unwrap_or(false),
+                    None => false,
+                };
+                let mut seen = HashSet::new();
+                let mut missing_seen = false;
+                let mut kept = Vec::new();
+                for elem in elements(list, "unique")? {
+                    let first = match &key {
+                        None => seen.insert(canonical(&elem)?),
+                        Some(key) => match key.of(lua, batch, &elem)? {
+                            Some(k) => seen.insert(canonical(&k)?),
+                            None if keep_missing => true,
+                            None => !std::mem::replace(&mut missing_seen, true),
+                        },
+                    };
+                    if first {
+                        kept.push(elem);
+                    }
+                }
+                json_to_lua(lua, Value::Array(kept), &batch.alive.borrow())
+            },
+        )?,
+    )?;
+
+    let group_batch = batch.clone();
+    lua.globals().set(
+        "group_by",
+        lua.create_function(
+            move |lua,
+                  (list, key, map, opts): (
+                LuaValue,
+                LuaValue,
+                Option<LuaFunction>,
+                Option<LuaTable>,
+            )| {
+                let batch = &*group_batch;
+                let key = Key::from_lua(key)?;
+                let missing = match opts {
+                    Some(opts) => opts.get::<Option<String>>("missing")?,
+                    None => None,
+                };
+                let mut grouped = BTreeMap::<String, Vec<Value>>::new();
+                for elem in elements(list, "group_by")? {
+                    let group = match (key.of(lua, batch, &elem)?, &missing) {
+                        (Some(group), _) => group,
+                        (None, Some(missing)) => Value::String(missing.clone()),
+                        (None, None) => continue,
+                    };
+                    let member = match &map {
+                        Some(map) => {
+                            let handle = json_to_lua(lua, elem, &batch.alive.borrow())?;
+                            match map.call::<LuaValue>(handle)? {
+                                LuaValue::Nil => continue,
+                                member => output_value(member, true)?,
+                            }
+                        }
+                        None => elem,
+                    };
+                    let group = match group {
+                        Value::String(s) => s,
+                        other => other.to_string(),
+                    };
+                    grouped.entry(group).or_default().push(member);
+                }
+                let grouped = grouped
+                    .into_iter()
+                    .map(|(group, members)| (group, Value::Array(members)))
+                    .collect();
+                json_to_lua(lua, Value::Object(grouped), &batch.alive.borrow())
+            },
+        )?,
+    )?;
+
+    lua.globals().set(
+        "sorted",
+        lua.create_function(
+            move |lua, (list, key, desc): (LuaValue, LuaValue, Option<bool>)| {
+                let key = Key::from_lua(key)?;
+                let mut keyed = elements(list, "sorted")?
+                    .into_iter()
+                    .map(|elem| Ok((key.of(lua, &batch, &elem)?, elem)))
+                    .collect::<LuaResult<Vec<_>>>()?;
+                let desc = desc.unwrap_or(false);
+                keyed.sort_by(|(a, _), (b, _)| match (a, b) {
+                    (Some(a), Some(b)) if desc => compare(b, a),
+                    (Some(a), Some(b)) => compare(a, b),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => Ordering::Equal,
+                });
+                let sorted = keyed.into_iter().map(|(_, elem)| elem).collect();
+                json_to_lua(lua, Value::Array(sorted), &batch.alive.borrow())
+            },
+        )?,
+    )
+}
+
+/// Compact JSON, which has object keys sorted since serde_json keeps them
+/// in a `BTreeMap`.
+pub(super) fn canonical(val: &Value) -> LuaResult<String> {
+    serde_json::to_string(val).map_err(LuaError::external)
+}
+
+fn rank(val: &Value) -> u8 {
+    match val {
+        Value::Null => 0,
+        Value::Bool(_) => 1,
+        Value::Number(_) => 2,
+        Value::String(_) => 3,
+        Value::Array(_) => 4,
+        Value::Object(_) => 5,
+    }
+}
+
+/// Orders values of the same type by value, arrays and objects aside, and
+/// values of different types by [`rank`].
+fn compare(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Number(a), Value::Number(b)) => match (a.as_i64(), b.as_i64()) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            _ => a
+                .as_f64()
+                .partial_cmp(&b.as_f64())
+                .unwrap_or(Ordering::Equal),
+        },
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (a, b) => rank(a).cmp(&rank(b)),
+    }
+}
@@ -0,0 +1,201 @@
+use mlua::{
+    Error as LuaError, Lua, Result as LuaResult, String as LuaString, Table as LuaTable,
+    Value as LuaValue,
+};
+use serde_json::Value;
+
+use super::output_value;
+
+/// Installs the `csv` global, for CSV carried inside documents:
+///
+/// - `csv.parse_line(s, opts)` splits a single record into its fields.
+/// - `csv.format_line(fields, opts)` joins an array into a record, quoting
+///   fields as needed. Numbers and booleans are written as in JSON, nil and
+///   null as empty fields.
+/// - `csv.parse(s, opts)` splits every record of `s` into an array of
+///   fields, skipping blank lines. With `opts.header` set, the first record
+///   names the fields and every other one becomes a table keyed by them.
+///
+/// Fields are quoted with `"`, doubled to stand for itself, and may span
+/// lines when quoted. `opts.delimiter` replaces the default `,`.
+pub(crate) fn install_csv(lua: &Lua) -> LuaResult<()> {
+    let csv = lua.create_table()?;
+
+    csv.set(
+        "parse_line",
+        lua.create_function(|lua, (s, opts): (LuaString, Option<LuaTable>)| {
+            let delimiter = delimiter(opts.as_ref())?;
+            let mut records = parse(&s.to_str()?, delimiter)?;
+            if records.len() > 1 {
+                return Err(LuaError::runtime(format!(
+                    "expected a single record, found {}",
+                    records.len()
+                )));
+            }
+            lua.create_sequence_from(records.pop().unwrap_or_else(|| vec![String::new()]))
+        })?,
+    )?;
+
+    csv.set(
+        "format_line",
+        lua.create_function(|_, (fields, opts): (LuaValue, Option<LuaTable>)| {
+            let delimiter = delimiter(opts.as_ref())?;
+            let fields = match output_value(fields, true)? {
+                Value::Array(fields) => fields,
+                Value::Object(fields) if fields.is_empty() => Vec::new(),
+                _ => return Err(LuaError::runtime("format_line expects an array of fields")),
+            };
+            let mut line = String::new();
+            for (i, field) in fields.iter().enumerate() {
+                if i > 0 {
+                    line.push(delimiter);
+                }
+                write_field(&mut line, &field_text(field, i)?, delimiter);
+            }
+            Ok(line)
+        })?,
+    )?;
+
+    csv.set(
+        "parse",
+        lua.create_function(|lua, (s, opts): (LuaString, Option<LuaTable>)| {
+            let delimiter = delimiter(opts.as_ref())?;
+            let header = match &opts {
+                Some(opts) => opts.get::<Option<bool>>("header")?.unwrap_or(false),
+                None => false,
+            };
+            let records = parse(&s.to_str()?, delimiter)?;
+            if !header {
+                let rows = lua.create_table_with_capacity(records.len(), 0)?;
+                for record in records {
+                    rows.raw_push(lua.create_sequence_from(record)?)?;
+                }
+                return Ok(rows);
+            }
+
+            let mut records = records.into_iter();
+            let names = records.next().unwrap_or_default();
+            let rows = lua.create_table_with_capacity(records.len(), 0)?;
+            for (i, record) in records.enumerate() {
+                if record.len() > names.len() {
+                    return Err(LuaError::runtime(format!(
+                        "row {} has {} fields, but the header only names {}",
+                        i + 1,
+                        record.len(),
+                        names.len()
+                    )));
+                }
+                let row = lua.create_table_with_capacity(0, record.len())?;
+                for (name, field) in names.iter().zip(record) {
+                    row.raw_set(name.as_str(), field)?;
+                }
+                rows.raw_push(row)?;
+            }
+            Ok(rows)
+        })?,
+    )?;
+
+    lua.globals().set("csv", csv)
+}
+
+fn delimiter(opts: Option<&LuaTable>) -> LuaResult<char> {
+    let Some(delimiter) = opts
+        .map(|opts| opts.get::<Option<String>>("delimiter"))
+        .transpose()?
+        .flatten()
+    else {
+        return Ok(',');
+    };
+    let mut chars = delimiter.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if !matches!(c, '"' | '\r' | '\n') => Ok(c),
+        _ => Err(LuaError::runtime(format!(
+            "invalid delimiter '{delimiter}': expected a single character other than a quote or newline"
+        ))),
+    }
+}
+
+/// Splits `s` into records of fields. Blank lines hold no record, and a
+/// trailing newline does not start one.
+fn parse(s: &str, delimiter: char) -> LuaResult<Vec<Vec<String>>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    // Whether the current field was quoted, which makes it count even when
+    // empty, and whether we are still inside its quotes.
+    let mut quoted = false;
+    let mut in_quotes = false;
+
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            if c != '"' {
+                field.push(c);
+            } else if chars.next_if_eq(&'"').is_some() {
+                field.push('"');
+            } else {
+                in_quotes = false;
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() && !quoted => {
+                quoted = true;
+                in_quotes = true;
+            }
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                if quoted || !field.is_empty() || !record.is_empty() {
+                    record.push(std::mem::take(&mut field));
+                    records.push(std::mem::take(&mut record));
+                }
+                quoted = false;
+            }
+            c if c == delimiter => {
+                record.push(std::mem::take(&mut field));
+                quoted = false;
+            }
+            c => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(LuaError::runtime("unterminated quoted field"));
+    }
+    if quoted || !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+fn field_text(field: &Value, index: usize) -> LuaResult<String> {
+    Ok(match field {
+        Value::Null => String::new(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => s.clone(),
+        Value::Array(_) | Value::Object(_) => {
+            return Err(LuaError::runtime(format!(
+                "field {} is a table, which cannot be written as CSV",
+                index + 1
+            )));
+        }
+    })
+}
+
+/// Appends `text`, quoted when it holds anything that would otherwise end
+/// the field early.
+fn write_field(line: &mut String, text: &str, delimiter: char) {
+    if !text.contains([delimiter, '"', '\r', '\n']) {
+        line.push_str(text);
+        return;
+    }
+    line.push('"');
+    for c in text.chars() {
+        if c == '"' {
+            line.push('"');
+        }
+        line.push(c);
+    }
+    line.push('"');
+}
@@ -0,0 +1,84 @@
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE};
+use mlua::{Error as LuaError, Lua, Result as LuaResult, String as LuaString, Table as LuaTable};
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Installs the `encoding` global, converting Lua byte strings to and from
+/// text:
+///
+/// - `encoding.base64_encode(s, opts)` and `encoding.base64_decode(s, opts)`,
+///   using the URL-safe alphabet when `opts.url_safe` is set.
+/// - `encoding.hex_encode(s)` and `encoding.hex_decode(s)`.
+///
+/// Decoding can produce bytes that are not UTF-8, which fail to convert with
+/// [`Error::Conversion`](crate::Error::Conversion) if emitted as they are.
+pub(crate) fn install_encoding(lua: &Lua) -> LuaResult<()> {
+    let encoding = lua.create_table()?;
+
+    encoding.set(
+        "base64_encode",
+        lua.create_function(|_, (s, opts): (LuaString, Option<LuaTable>)| {
+            Ok(base64_engine(opts)?.encode(&*s.as_bytes()))
+        })?,
+    )?;
+    encoding.set(
+        "base64_decode",
+        lua.create_function(|lua, (s, opts): (LuaString, Option<LuaTable>)| {
+            let bytes = base64_engine(opts)?
+                .decode(&*s.as_bytes())
+                .map_err(|err| LuaError::runtime(format!("invalid base64: {err}")))?;
+            lua.create_string(bytes)
+        })?,
+    )?;
+    encoding.set(
+        "hex_encode",
+        lua.create_function(|_, s: LuaString| {
+            let bytes = s.as_bytes();
+            let mut hex = String::with_capacity(bytes.len() * 2);
+            for b in bytes.iter() {
+                hex.push(HEX_DIGITS[usize::from(b >> 4)] as char);
+                hex.push(HEX_DIGITS[usize::from(b & 0xf)] as char);
+            }
+            Ok(hex)
+        })?,
+    )?;
+    encoding.set(
+        "hex_decode",
+        lua.create_function(|lua, s: LuaString| lua.create_string(hex_decode(&s.as_bytes())?))?,
+    )?;
+
+    lua.globals().set("encoding", encoding)
+}
+
+fn base64_engine(opts: Option<LuaTable>) -> LuaResult<&'static base64::engine::GeneralPurpose> {
+    let url_safe = match opts {
+        Some(opts) => opts.get::<Option<bool>>("url_safe")?.unwrap_or(false),
+        None => false,
+    };
+    Ok(if url_safe { &URL_SAFE } else { &STANDARD })
+}
+
+fn hex_decode(hex: &[u8]) -> LuaResult<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(LuaError::runtime(format!(
+            "invalid hex: odd length {}",
+            hex.len()
+        )));
+    }
+    hex.chunks(2)
+        .enumerate()
+        .map(|(i, pair)| Ok((hex_digit(pair[0], 2 * i)? << 4) | hex_digit(pair[1], 2 * i + 1)?))
+        .collect()
+}
+
+fn hex_digit(digit: u8, offset: usize) -> LuaResult<u8> {
+    match digit {
+        b'0'..=b'9' => Ok(digit - b'0'),
+        b'a'..=b'f' => Ok(digit - b'a' + 10),
+        b'A'..=b'F' => Ok(digit - b'A' + 10),
+        _ => Err(LuaError::runtime(format!(
+            "invalid hex: unexpected byte 0x{digit:02x} at offset {offset}"
+        ))),
+    }
+}
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+use mlua::{Error as LuaError, Lua, Result as LuaResult};
+
+use super::Runner;
+use crate::error::Result;
+
+/// Which environment variables the `env` global can read. Without one every
+/// name reads as nil; the process environment is never exposed wholesale.
+#[derive(Clone, Debug)]
+pub enum EnvAccess {
+    /// These variables of the process environment, read once when installed.
+    Allowlist(Vec<String>),
+    /// Exactly these names and values, without touching the process
+    /// environment at all.
+    Values(HashMap<String, String>),
+}
+
+impl EnvAccess {
+    /// Every name the script may read, with its value if it has one.
+    fn resolve(self) -> HashMap<String, Option<String>> {
+        match self {
+            EnvAccess::Allowlist(names) => names
+                .into_iter()
+                .map(|name| {
+                    let value = std::env::var(&name).ok();
+                    (name, value)
+                })
+                .collect(),
+            EnvAccess::Values(values) => values
+                .into_iter()
+                .map(|(name, value)| (name, Some(value)))
+                .collect(),
+        }
+    }
+}
+
+impl Runner {
+    /// Lets `env(name)` read the variables of `access`, replacing whatever
+    /// it could read before.
+    pub fn set_env(&mut self, access: EnvAccess) -> Result<()> {
+        Ok(install_env(&self.lua, Some(access), self.strict_env)?)
+    }
+
+    /// Builder-style [`Runner::set_env`] with an [`EnvAccess::Allowlist`].
+    pub fn with_env_allowlist<I>(mut self, names: I) -> Result<Self>
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let names = names.into_iter().map(Into::into).collect();
+        self.set_env(EnvAccess::Allowlist(names))?;
+        Ok(self)
+    }
+
+    /// Builder-style [`Runner::set_env`] with an [`EnvAccess::Values`].
+    pub fn with_env_values(mut self, values: HashMap<String, String>) -> Result<Self> {
+        self.set_env(EnvAccess::Values(values))?;
+        Ok(self)
+    }
+}
+
+/// Installs `env(name)`, returning the variable's value, or nil when it is
+/// unset or not allowed. Under `strict`, reading a name `access` does not
+/// allow is an error instead, so typos do not go unnoticed.
+pub(crate) fn install_env(lua: &Lua, access: Option<EnvAccess>, strict: bool) -> LuaResult<()> {
+    let vars = access.map(EnvAccess::resolve).unwrap_or_default();
+    lua.globals().set(
+        "env",
+        lua.create_function(move |_, name: String| match vars.get(&name) {
+            Some(value) => Ok(value.clone()),
+            None if strict => Err(LuaError::runtime(format!(
+                "environment variable '{name}' is not allowed"
+            ))),
+            None => Ok(None),
+        })?,
+    )
+}
@@ -0,0 +1,172 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use mlua::{Function as LuaFunction, Result as LuaResult, Thread, ThreadStatus, Value as LuaValue};
+use serde_json::Value;
+
+use super::Runner;
+use crate::error::{Error, Result};
+use crate::value::json_to_lua;
+
+/// Replaces `get_next` with a version that suspends the script's coroutine
+/// until the driver has pushed the next document.
+///
+/// Called from a coroutine of the script's own, the wait is passed up through
+/// every `coroutine.resume` in between, which then resumes the coroutine where
+/// it waited. Inside a C function, such as a `table.sort` comparator, nothing
+/// can be suspended, so waiting there is an error.
+const WAITING_GET_NEXT: &str = r#"
+local pull = ...
+local create, resume, status = coroutine.create, coroutine.resume, coroutine.status
+local isyieldable, yield = coroutine.isyieldable, coroutine.yield
+-- Yielded while waiting, to tell the wait apart from the script's own yields.
+local WAIT = {}
+
+local function wait()
+    if not isyieldable() then
+        error("get_next cannot wait for the next document inside a C function", 3)
+    end
+    yield(WAIT)
+end
+
+local function forward(co, ok, ...)
+    if ok and ... == WAIT and status(co) == "suspended" then
+        wait()
+        return forward(co, resume(co))
+    end
+    return ok, ...
+end
+
+local function forwarding_resume(co, ...)
+    return forward(co, resume(co, ...))
+end
+
+local function unwrap(ok, ...)
+    if not ok then
+        error(..., 0)
+    end
+    return ...
+end
+
+coroutine.resume = forwarding_resume
+coroutine.wrap = function(f)
+    local co = create(f)
+    return function(...)
+        return unwrap(forwarding_resume(co, ...))
+    end
+end
+
+return function()
+    while true do
+        local ready, doc, ok = pull()
+        if ready then return doc, ok end
+        wait()
+    end
+end
+"#;
+
+#[derive(Default)]
+struct Inbox {
+    doc: RefCell<Option<Value>>,
+    closed: Cell<bool>,
+}
+
+/// Drives a runner's script as a coroutine that is handed input documents one
+/// at a time, for callers juggling several scripts over the same input.
+pub(crate) struct Feeder {
+    thread: Option<Thread>,
+    inbox: Rc<Inbox>,
+    error: Option<Error>,
+    runner: Runner,
+}
+
+impl Feeder {
+    pub(crate) fn new(runner: Runner) -> Self {
+        let inbox = Rc::new(Inbox::default());
+        runner.batch.begin(None);
+        let (thread, error) = match runner
+            .install_feed_globals(inbox.clone())
+            .and_then(|()| runner.lua.create_thread(runner.chunk.clone()))
+        {
+            Ok(thread) => (Some(thread), None),
+            Err(err) => (None, Some(runner.convert_error(err))),
+        };
+        let mut feeder = Self {
+            thread,
+            inbox,
+            error,
+            runner,
+        };
+        // Run up to the first get_next, so that pushes land on a waiting script.
+        feeder.resume();
+        feeder
+    }
+
+    pub(crate) fn is_running(&self) -> bool {
+        self.thread.is_some()
+    }
+
+    pub(crate) fn has_failed(&self) -> bool {
+        self.error.is_some()
+    }
+
+    pub(crate) fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+
+    /// Hands `doc` to the script and runs it until it asks for the next one.
+    /// Documents pushed after the script finished are dropped.
+    pub(crate) fn push(&mut self, doc: Value) {
+        if self.is_running() {
+            *self.inbox.doc.borrow_mut() = Some(doc);
+            self.resume();
+        }
+    }
+
+    /// Signals the end of input, lets the script run to completion and
+    /// returns what it emitted.
+    pub(crate) fn finish(mut self) -> Result<Vec<Value>> {
+        self.inbox.closed.set(true);
+        while self.is_running() {
+            self.resume();
+        }
+        let output = self.runner.batch.end();
+        match self.error.take() {
+            Some(err) => Err(err.with_outputs(output)),
+            None => Ok(output),
+        }
+    }
+
+    fn resume(&mut self) {
+        let Some(thread) = &self.thread else {
+            return;
+        };
+        let result = thread.resume::<()>(());
+        if result.is_err() || thread.status() != ThreadStatus::Resumable {
+            self.thread = None;
+            self.error = result.err().map(|err| self.runner.convert_error(err));
+        }
+    }
+}
+
+impl Runner {
+    fn install_feed_globals(&self, inbox: Rc<Inbox>) -> LuaResult<()> {
+        self.install_globals()?;
+        let lua = &self.lua;
+
+        let batch = self.batch.clone();
+        let pull = lua.create_function(move |lua, ()| {
+            let Some(doc) = inbox.doc.borrow_mut().take() else {
+                return Ok((inbox.closed.get(), LuaValue::Nil, false));
+            };
+            batch.start_document(lua, &doc);
+            Ok((true, json_to_lua(lua, doc, &batch.alive.borrow())?, true))
+        })?;
+        let get_next: LuaFunction = lua
+            .load(WAITING_GET_NEXT)
+            .set_name("=[mlua_play fan-out]")
+            .call(pull)?;
+        lua.globals().set("get_next", get_next)?;
+        self.install_input_helpers(None)
+    }
+}
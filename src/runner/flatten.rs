@@ -0,0 +1,151 @@
+use std::rc::Rc;
+
+use mlua::{Error as LuaError, Lua, Result as LuaResult, Value as LuaValue};
+use serde_json::{Map, Value};
+
+use super::{Batch, output_value};
+use crate::value::json_to_lua;
+
+const DEFAULT_SEPARATOR: &str = ".";
+
+/// Installs `flatten(value, sep)` and `unflatten(value, sep)`.
+///
+/// `flatten` turns a nested object or array into a single-level object, with
+/// keys made of the path to every leaf joined by `sep`, `.` by default, and
+/// array indices counted from 0. Empty objects and arrays are kept as leaves.
+/// `unflatten` reverses it, turning numeric segments back into arrays. Both
+/// fail on keys that collide, and `unflatten` on an index past the number of
+/// keys, which no flattened array reaches.
+pub(crate) fn install_flatten(lua: &Lua, batch: Rc<Batch>) -> LuaResult<()> {
+    let flatten_batch = batch.clone();
+    lua.globals().set(
+        "flatten",
+        lua.create_function(move |lua, (value, sep): (LuaValue, Option<String>)| {
+            let sep = sep.as_deref().unwrap_or(DEFAULT_SEPARATOR);
+            let mut flat = Map::new();
+            match output_value(value, true)? {
+                Value::Object(map) => {
+                    for (key, value) in map {
+                        flatten_into(&mut flat, key, value, sep).map_err(|key| collision(&key))?;
+                    }
+                }
+                Value::Array(arr) => {
+                    for (i, value) in arr.into_iter().enumerate() {
+                        flatten_into(&mut flat, i.to_string(), value, sep)
+                            .map_err(|key| collision(&key))?;
+                    }
+                }
+                _ => return Err(LuaError::runtime("flatten expects an object or array")),
+            }
+            json_to_lua(lua, Value::Object(flat), &flatten_batch.alive.borrow())
+        })?,
+    )?;
+
+    lua.globals().set(
+        "unflatten",
+        lua.create_function(move |lua, (value, sep): (LuaValue, Option<String>)| {
+            let sep = sep.as_deref().unwrap_or(DEFAULT_SEPARATOR);
+            let Value::Object(flat) = output_value(value, true)? else {
+                return Err(LuaError::runtime("unflatten expects an object"));
+            };
+            let limit = flat.len();
+            let mut root = Value::Object(Map::new());
+            for (key, value) in flat {
+                let segments = key.split(sep).collect::<Vec<_>>();
+                insert(&mut root, &segments, value, limit).map_err(|err| match err {
+                    Misfit::Collision => collision(&key),
+                    Misfit::Index(index) => {
+                        LuaError::runtime(format!("index {index} in key '{key}' is out of range"))
+                    }
+                })?;
+            }
+            json_to_lua(lua, root, &batch.alive.borrow())
+        })?,
+    )
+}
+
+fn collision(key: &str) -> LuaError {
+    LuaError::runtime(format!("key '{key}' collides with another one"))
+}
+
+/// Adds the leaves of `value` to `flat`, under keys starting with `key`,
+/// failing with the first key that is already there.
+pub(crate) fn flatten_into(
+    flat: &mut Map<String, Value>,
+    key: String,
+    value: Value,
+    sep: &str,
+) -> Result<(), String> {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (child, value) in map {
+                flatten_into(flat, format!("{key}{sep}{child}"), value, sep)?;
+            }
+        }
+        Value::Array(arr) if !arr.is_empty() => {
+            for (i, value) in arr.into_iter().enumerate() {
+                flatten_into(flat, format!("{key}{sep}{i}"), value, sep)?;
+            }
+        }
+        leaf => {
+            if flat.contains_key(&key) {
+                return Err(key);
+            }
+            flat.insert(key, leaf);
+        }
+    }
+    Ok(())
+}
+
+/// Why a value could not be put in place by [`insert`].
+enum Misfit {
+    /// Something else is already there.
+    Collision,
+    /// An array index too large to have come from a flattened array.
+    Index(usize),
+}
+
+/// Puts `value` at the path `segments` below `root`, creating containers on
+/// the way, or fails if something else is already there or an array index
+/// is not below `limit`.
+fn insert(root: &mut Value, segments: &[&str], value: Value, limit: usize) -> Result<(), Misfit> {
+    let mut node = root;
+    for (i, segment) in segments.iter().enumerate() {
+        let slot = child(node, segment, limit)?;
+        let Some(next) = segments.get(i + 1) else {
+            if !slot.is_null() {
+                return Err(Misfit::Collision);
+            }
+            *slot = value;
+            return Ok(());
+        };
+        if slot.is_null() {
+            *slot = if next.parse::<usize>().is_ok() {
+                Value::Array(Vec::new())
+            } else {
+                Value::Object(Map::new())
+            };
+        }
+        node = slot;
+    }
+    Ok(())
+}
+
+/// The child of `node` at `segment`, added as null if missing. Arrays grow
+/// to fit, padded with nulls, up to `limit` elements.
+fn child<'a>(node: &'a mut Value, segment: &str, limit: usize) -> Result<&'a mut Value, Misfit> {
+    match node {
+        Value::Object(map) => Ok(map.entry(segment).or_insert(Value::Null)),
+        Value::Array(arr) => {
+            let index = segment.parse::<usize>().map_err(|_| Misfit::Collision)?;
+            if index >= limit {
+                return Err(Misfit::Index(index));
+            }
+            if arr.len() <= index {
+                arr.resize(index + 1, Value::Null);
+            }
+            Ok(&mut arr[index])
+        }
+        _ => Err(Misfit::Collision),
+    }
+}
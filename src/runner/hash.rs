@@ -0,0 +1,50 @@
+use md5::Md5;
+use mlua::{Lua, Result as LuaResult, Value as LuaValue};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use xxhash_rust::xxh3::xxh3_64;
+
+use super::output_value;
+
+/// Installs the `hash` global: `hash.sha256(v)`, `hash.sha1(v)` and
+/// `hash.md5(v)` return lowercase hex digests, and `hash.fast(v)` a
+/// non-cryptographic integer hash for partitioning.
+///
+/// Strings are hashed as they are. Anything else, handles and tables
+/// included, is hashed as compact JSON with object keys sorted, so
+/// documents that are equal hash the same whatever order their keys were
+/// set in.
+pub(crate) fn install_hash(lua: &Lua) -> LuaResult<()> {
+    let hash = lua.create_table()?;
+    hash.set(
+        "sha256",
+        lua.create_function(|_, v: LuaValue| Ok(hex_digest::<Sha256>(&hash_input(v)?)))?,
+    )?;
+    hash.set(
+        "sha1",
+        lua.create_function(|_, v: LuaValue| Ok(hex_digest::<Sha1>(&hash_input(v)?)))?,
+    )?;
+    hash.set(
+        "md5",
+        lua.create_function(|_, v: LuaValue| Ok(hex_digest::<Md5>(&hash_input(v)?)))?,
+    )?;
+    // Cut down to 53 bits, which is all a LuaJIT number holds exactly.
+    hash.set(
+        "fast",
+        lua.create_function(|_, v: LuaValue| Ok((xxh3_64(&hash_input(v)?) >> 11) as i64))?,
+    )?;
+    lua.globals().set("hash", hash)
+}
+
+/// The bytes hashed for `v`. Objects serialize with sorted keys since
+/// serde_json keeps them in a `BTreeMap`.
+fn hash_input(v: LuaValue) -> LuaResult<Vec<u8>> {
+    match v {
+        LuaValue::String(s) => Ok(s.as_bytes().to_vec()),
+        v => serde_json::to_vec(&output_value(v, true)?).map_err(mlua::Error::external),
+    }
+}
+
+fn hex_digest<D: Digest>(data: &[u8]) -> String {
+    D::digest(data).iter().map(|b| format!("{b:02x}")).collect()
+}
@@ -0,0 +1,78 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+
+use serde_json::Value;
+
+use crate::error::{Error, Result};
+
+/// Called with a document's index and the document.
+pub type DocumentStartHook = Box<dyn FnMut(usize, &Value)>;
+/// Called with a document's index and how the script did on it.
+pub type DocumentEndHook = Box<dyn FnMut(usize, std::result::Result<(), &Error>)>;
+/// Called with an emit's index and the document emitted, vetoing it with an
+/// error message.
+pub type EmitHook = Box<dyn FnMut(usize, &Value) -> std::result::Result<(), String>>;
+
+/// Callbacks around the runner's own work. The document hooks only fire in
+/// the driver modes, where the runner hands out documents one at a time;
+/// `on_emit` fires in every mode.
+///
+/// Hooks see documents by reference and cannot change them. A hook that
+/// panics fails the run with [`Error::Hook`] instead of unwinding through Lua.
+#[derive(Default)]
+pub struct Hooks {
+    /// Called with each document's index before the script sees it.
+    pub on_document_start: Option<DocumentStartHook>,
+    /// Called once the script is done with a document, with how that went.
+    pub on_document_end: Option<DocumentEndHook>,
+    /// Called with every emitted document and the index of the emit within
+    /// the batch. Returning an error aborts the script.
+    pub on_emit: Option<EmitHook>,
+}
+
+impl Hooks {
+    pub(crate) fn document_start(&mut self, index: usize, doc: &Value) -> Result<()> {
+        match &mut self.on_document_start {
+            Some(hook) => guard("on_document_start", || hook(index, doc)),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn document_end(
+        &mut self,
+        index: usize,
+        result: std::result::Result<(), &Error>,
+    ) -> Result<()> {
+        match &mut self.on_document_end {
+            Some(hook) => guard("on_document_end", || hook(index, result)),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn emit(&mut self, index: usize, value: &Value) -> Result<()> {
+        let Some(hook) = &mut self.on_emit else {
+            return Ok(());
+        };
+        guard("on_emit", || hook(index, value))?.map_err(|message| Error::Hook {
+            hook: "on_emit",
+            message,
+        })
+    }
+}
+
+fn guard<T>(hook: &'static str, f: impl FnOnce() -> T) -> Result<T> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| Error::Hook {
+        hook,
+        message: format!("panicked: {}", panic_message(payload.as_ref())),
+    })
+}
+
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic payload"
+    }
+}
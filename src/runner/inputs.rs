@@ -0,0 +1,225 @@
+use mlua::{Error as LuaError, Function as LuaFunction, Result as LuaResult, Value as LuaValue};
+use serde_json::Value;
+
+use super::Runner;
+use crate::value::json_to_lua;
+
+use std::iter::Peekable;
+
+/// Named inputs the script reads with `get_next(name)` and `read_all(name)`.
+pub(crate) type NamedInput = Peekable<Box<dyn Iterator<Item = Value>>>;
+
+/// Wraps the `get_next` the current mode installed so that it reads named
+/// inputs when given a name, and adds `peek`, `has_next` and `get_batch` on
+/// top of it. A peeked document is kept here and handed out by the next
+/// `get_next`, so both calls return the very same handle. `peek` and
+/// `has_next` take an input name too; a document peeked from a named input
+/// stays in it until read, so one the batch leaves unread is there for the
+/// next.
+///
+/// Every mode's `get_next` returns the document and whether there was one,
+/// since a null document is nil to Lua just like the end of the input. That
+/// status backs `has_next`; `get_next` and `peek` return the document alone,
+/// so `emit(get_next())` emits just the document.
+///
+/// `get_many(docs, count, n)` appends up to `n` documents to `docs` after
+/// its first `count` and returns the new count. Modes without a fast way to
+/// do that pass nil and get a loop over `get_next`.
+const INPUT_HELPERS: &str = r#"
+local get_default, get_named, peek_named, skip_named, set_peeking, start_peeked, get_many =
+    get_next, ...
+local peeked, peeked_ok, has_peeked = nil, false, false
+-- What `peek(name)` returned, by input name.
+local peeked_named = {}
+
+local function look(name)
+    if name ~= nil then
+        local slot = peeked_named[name]
+        if slot == nil then
+            local doc, ok = peek_named(name)
+            slot = { doc = doc, ok = ok }
+            peeked_named[name] = slot
+        end
+        return slot.doc, slot.ok
+    end
+    if not has_peeked then
+        set_peeking(true)
+        peeked, peeked_ok = get_default()
+        set_peeking(false)
+        has_peeked = true
+    end
+    return peeked, peeked_ok
+end
+
+local function take(name)
+    if name ~= nil then
+        local slot = peeked_named[name]
+        if slot ~= nil then
+            peeked_named[name] = nil
+            skip_named(name)
+            return slot.doc, slot.ok
+        end
+        return get_named(name)
+    end
+    if has_peeked then
+        local doc, ok = peeked, peeked_ok
+        peeked, peeked_ok, has_peeked = nil, false, false
+        start_peeked()
+        return doc, ok
+    end
+    return get_default()
+end
+
+function peek(name)
+    return (look(name))
+end
+
+function has_next(name)
+    local _, ok = look(name)
+    return ok
+end
+
+function get_next(name)
+    return (take(name))
+end
+
+get_many = get_many or function(docs, count, n)
+    for _ = 1, n do
+        local doc, ok = get_default()
+        if not ok then
+            break
+        end
+        count = count + 1
+        docs[count] = doc
+    end
+    return count
+end
+
+function get_batch(n)
+    local docs, count = {}, 0
+    if n > 0 and has_peeked then
+        local doc, ok = take()
+        if not ok then
+            return nil
+        end
+        docs[1], count = doc, 1
+    end
+    count = get_many(docs, count, n - count)
+    if count == 0 then
+        return nil
+    end
+    return docs
+end
+
+local read_named = read_all
+function read_all(name)
+    peeked_named[name] = nil
+    return read_named(name)
+end
+"#;
+
+impl Runner {
+    /// Registers an input the script can read with `get_next(name)`, next to
+    /// the default one `get_next()` reads.
+    ///
+    /// Named inputs are not tied to a batch: whatever one batch leaves unread
+    /// is there for the next. Documents read from them do not count towards
+    /// the per-document limits or statistics, which follow the default input.
+    pub fn set_input<I>(&mut self, name: impl Into<String>, input: I)
+    where
+        I: IntoIterator<Item = Value>,
+        I::IntoIter: 'static,
+    {
+        let input: Box<dyn Iterator<Item = Value>> = Box::new(input.into_iter());
+        self.batch
+            .named_inputs
+            .borrow_mut()
+            .insert(name.into(), input.peekable());
+    }
+
+    /// Builder-style [`Runner::set_input`].
+    pub fn with_input<I>(mut self, name: impl Into<String>, input: I) -> Self
+    where
+        I: IntoIterator<Item = Value>,
+        I::IntoIter: 'static,
+    {
+        self.set_input(name, input);
+        self
+    }
+
+    /// Installs `peek`, `has_next`, `get_batch` and `read_all`, and makes
+    /// `get_next`, `peek` and `has_next` accept an input name. Runs after each
+    /// mode has set up its own `get_next`.
+    ///
+    /// `get_batch(n)` returns an array of up to `n` documents, each its own
+    /// handle, or nil once the input is exhausted. Null documents leave holes
+    /// in the array, so iterate up to `n` rather than with `ipairs`.
+    pub(super) fn install_input_helpers(&self, get_many: Option<LuaFunction>) -> LuaResult<()> {
+        let lua = &self.lua;
+
+        let batch = self.batch.clone();
+        let get_named = lua.create_function(move |lua, name: String| {
+            let next = match batch.named_inputs.borrow_mut().get_mut(&name) {
+                Some(input) => input.next(),
+                None => return Err(unknown_input(&name)),
+            };
+            match next {
+                Some(v) => Ok((json_to_lua(lua, v, &batch.alive.borrow())?, true)),
+                None => Ok((LuaValue::Nil, false)),
+            }
+        })?;
+        let batch = self.batch.clone();
+        let peek_named = lua.create_function(move |lua, name: String| {
+            let mut inputs = batch.named_inputs.borrow_mut();
+            let Some(input) = inputs.get_mut(&name) else {
+                return Err(unknown_input(&name));
+            };
+            match input.peek() {
+                Some(v) => Ok((json_to_lua(lua, v.clone(), &batch.alive.borrow())?, true)),
+                None => Ok((LuaValue::Nil, false)),
+            }
+        })?;
+        let batch = self.batch.clone();
+        let skip_named = lua.create_function(move |_, name: String| {
+            if let Some(input) = batch.named_inputs.borrow_mut().get_mut(&name) {
+                input.next();
+            }
+            Ok(())
+        })?;
+        let batch = self.batch.clone();
+        let set_peeking = lua.create_function(move |_, peeking: bool| {
+            batch.peeking.set(peeking);
+            Ok(())
+        })?;
+        let batch = self.batch.clone();
+        let start_peeked = lua.create_function(move |lua, ()| {
+            batch.start_peeked(lua);
+            Ok(())
+        })?;
+        let batch = self.batch.clone();
+        lua.globals().set(
+            "read_all",
+            lua.create_function(move |lua, name: String| {
+                let docs = match batch.named_inputs.borrow_mut().get_mut(&name) {
+                    Some(input) => input.collect(),
+                    None => return Err(unknown_input(&name)),
+                };
+                json_to_lua(lua, Value::Array(docs), &batch.alive.borrow())
+            })?,
+        )?;
+        lua.load(INPUT_HELPERS)
+            .set_name("=[mlua_play inputs]")
+            .call::<()>((
+                get_named,
+                peek_named,
+                skip_named,
+                set_peeking,
+                start_peeked,
+                get_many,
+            ))
+    }
+}
+
+pub(super) fn unknown_input(name: &str) -> LuaError {
+    LuaError::runtime(format!("unknown input '{name}'"))
+}
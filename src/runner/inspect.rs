@@ -0,0 +1,102 @@
+use mlua::{Lua, Result as LuaResult, Table as LuaTable, Value as LuaValue};
+use serde_json::{Number, Value};
+
+use super::output_value;
+use crate::value::SharedValue;
+
+/// Installs `typeof(v)`, which tells what kind of JSON value `v` is or would
+/// become when emitted: `object`, `array`, `string`, `number`, `integer`,
+/// `boolean` or `null`, the latter for `json.null` too, or `nil` for nil
+/// itself. Values with no JSON representation get their Lua type.
+///
+/// Also installs `deep_equal(a, b)`, which compares handles, tables and scalars by
+/// their JSON representation, never changing either side.
+///
+/// Numbers are equal when they have the same value, whether integer or float,
+/// so `1` equals `1.0`. Since tables cannot hold nil, a key set to nil counts
+/// as absent, while a handle's null is null, and so is a key set to
+/// `json.null`.
+pub(crate) fn install_inspect(lua: &Lua) -> LuaResult<()> {
+    lua.globals().set(
+        "typeof",
+        lua.create_function(|_, val: LuaValue| json_type(&val))?,
+    )?;
+    lua.globals().set(
+        "deep_equal",
+        lua.create_function(|_, (a, b): (LuaValue, LuaValue)| {
+            Ok(json_equal(&output_value(a, true)?, &output_value(b, true)?))
+        })?,
+    )
+}
+
+fn json_type(val: &LuaValue) -> LuaResult<&'static str> {
+    Ok(match val {
+        LuaValue::Nil => "nil",
+        val if val.is_null() => "null",
+        LuaValue::Boolean(_) => "boolean",
+        LuaValue::Integer(_) => "integer",
+        // Converted to null, having no JSON representation.
+        LuaValue::Number(f) if !f.is_finite() => "null",
+        LuaValue::Number(_) => "number",
+        LuaValue::String(_) => "string",
+        LuaValue::Table(t) if is_sequence(t)? => "array",
+        LuaValue::Table(_) => "object",
+        LuaValue::UserData(data) if data.is::<SharedValue>() => {
+            let handle = data.borrow::<SharedValue>()?;
+            let value = handle.resolve()?;
+            value_type(&value)
+        }
+        other => other.type_name(),
+    })
+}
+
+fn value_type(val: &Value) -> &'static str {
+    match val {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_f64() => "number",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Whether `t` converts to an array, which takes keys running from 1 up
+/// without gaps; empty tables included.
+fn is_sequence(t: &LuaTable) -> LuaResult<bool> {
+    let mut expected = 1;
+    for pair in t.pairs::<LuaValue, LuaValue>() {
+        match pair? {
+            (LuaValue::Integer(i), _) if i == expected => expected += 1,
+            _ => return Ok(false),
+        }
+    }
+    Ok(true)
+}
+
+fn json_equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => numbers_equal(a, b),
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| json_equal(a, b))
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(k, v)| b.get(k).is_some_and(|other| json_equal(v, other)))
+        }
+        (a, b) => a == b,
+    }
+}
+
+/// Compares integers exactly, falling back to floats when either side is one.
+fn numbers_equal(a: &Number, b: &Number) -> bool {
+    if let (Some(a), Some(b)) = (a.as_i64(), b.as_i64()) {
+        return a == b;
+    }
+    if let (Some(a), Some(b)) = (a.as_u64(), b.as_u64()) {
+        return a == b;
+    }
+    a.as_f64() == b.as_f64()
+}
@@ -0,0 +1,147 @@
+use std::borrow::Borrow;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use mlua::{
+    Function as LuaFunction, MultiValue as LuaMultiValue, Result as LuaResult, Thread, ThreadStatus,
+};
+use serde_json::Value;
+
+use super::{InputIter, Runner, emit_values};
+use crate::error::{Error, Result};
+
+/// Wraps the Rust side of `emit` so that the script's coroutine is suspended
+/// after every call to it. Values emitted from a coroutine of the script's own,
+/// or from inside a C function such as a `table.sort` comparator, cannot
+/// suspend it there; they wait in the queue until the next emit that can, or
+/// the end of the script, so the order is the same as in a batch.
+const YIELDING_EMIT: &str = r#"
+local push, driver = ...
+local running, isyieldable, yield = coroutine.running, coroutine.isyieldable, coroutine.yield
+return function(...)
+    push(...)
+    if running() == driver and isyieldable() then
+        yield()
+    end
+end
+"#;
+
+/// Iterator over the documents a script emits, running the script as a
+/// coroutine that only advances when the next document is requested.
+///
+/// Dropping the iterator abandons the coroutine where it was suspended.
+pub struct EmitIter<R: Borrow<Runner>> {
+    thread: Option<Thread>,
+    queue: Rc<RefCell<VecDeque<Value>>>,
+    error: Option<Error>,
+    runner: R,
+}
+
+impl Runner {
+    /// Runs the script over `input` lazily: every call to `next` resumes it
+    /// only until it emits another document. Free-form scripts only; the
+    /// iterator ignores [`RunOptions::mode`](super::RunOptions::mode).
+    ///
+    /// An error ends the iteration after being yielded, and dropping the
+    /// iterator early leaves the rest of the script unrun. What the script
+    /// emits from a coroutine of its own, or from a callback such as a
+    /// `table.sort` comparator, comes out in the same order as from
+    /// [`Runner::run_batch`], only once the script is back where it can be
+    /// suspended.
+    pub fn run_iter<I>(&mut self, input: I) -> Result<EmitIter<&Runner>>
+    where
+        I: IntoIterator<Item = Value>,
+        I::IntoIter: 'static,
+    {
+        let input = self.prepare_input(Box::new(input.into_iter().map(Ok)));
+        let mut iter = Runner::emit_iter(&*self, input);
+        match iter.error.take() {
+            Some(err) => Err(err),
+            None => Ok(iter),
+        }
+    }
+
+    pub(crate) fn emit_iter<R: Borrow<Runner>>(runner: R, input: InputIter) -> EmitIter<R> {
+        let queue = Rc::new(RefCell::new(VecDeque::new()));
+        let this = runner.borrow();
+        this.batch.begin(Some(input));
+        let thread = this.lua.create_thread(this.chunk.clone());
+        let thread = thread.and_then(|thread| {
+            this.install_iter_globals(queue.clone(), &thread)?;
+            Ok(thread)
+        });
+        let (thread, error) = match thread {
+            Ok(thread) => (Some(thread), None),
+            Err(err) => {
+                this.batch.end();
+                (None, Some(this.convert_error(err)))
+            }
+        };
+        EmitIter {
+            thread,
+            queue,
+            error,
+            runner,
+        }
+    }
+
+    fn install_iter_globals(
+        &self,
+        queue: Rc<RefCell<VecDeque<Value>>>,
+        driver: &Thread,
+    ) -> LuaResult<()> {
+        self.install_globals()?;
+        let lua = &self.lua;
+
+        for (name, clone) in [("emit_clone", true), ("emit", false)] {
+            let queue = queue.clone();
+            let batch = self.batch.clone();
+            let push = lua.create_function(move |lua, args: LuaMultiValue| {
+                for value in emit_values(args, clone)? {
+                    batch.record_emit(lua, clone, &value)?;
+                    queue.borrow_mut().push_back(value);
+                }
+                Ok(())
+            })?;
+            let emit: LuaFunction = lua
+                .load(YIELDING_EMIT)
+                .set_name("=[mlua_play run_iter]")
+                .call((push, driver))?;
+            lua.globals().set(name, emit)?;
+        }
+        self.install_emit_each()
+    }
+}
+
+impl<R: Borrow<Runner>> Iterator for EmitIter<R> {
+    type Item = Result<Value>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(v) = self.queue.borrow_mut().pop_front() {
+                return Some(Ok(v));
+            }
+            if let Some(err) = self.error.take() {
+                return Some(Err(err));
+            }
+
+            let thread = self.thread.as_ref()?;
+            let result = thread.resume::<()>(());
+            if result.is_err() || thread.status() != ThreadStatus::Resumable {
+                self.thread = None;
+                let runner = self.runner.borrow();
+                runner.batch.end();
+                self.error = result.err().map(|err| runner.convert_error(err));
+            }
+        }
+    }
+}
+
+impl<R: Borrow<Runner>> Drop for EmitIter<R> {
+    fn drop(&mut self) {
+        if self.thread.take().is_some() {
+            self.runner.borrow().batch.end();
+        }
+    }
+}
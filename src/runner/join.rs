@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use mlua::{Error as LuaError, Lua, Result as LuaResult, Table as LuaTable, Value as LuaValue};
+use serde_json::{Map, Value};
+
+use super::Batch;
+use super::collections::{Key, canonical, elements};
+use super::inputs::unknown_input;
+use crate::value::json_to_lua;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum JoinType {
+    Inner,
+    /// Also keeps left elements matching nothing.
+    Left,
+}
+
+/// How a left and a right element become one.
+#[derive(Clone, Copy)]
+enum Merge {
+    /// `{ left = l, right = r }`.
+    Nest,
+    /// The fields of both, which must be objects.
+    Shallow(Conflict),
+}
+
+/// Which side wins a field both have when merging shallowly.
+#[derive(Clone, Copy)]
+enum Conflict {
+    Left,
+    Right,
+    Error,
+}
+
+struct JoinOptions {
+    left_key: Key,
+    right_key: Key,
+    join_type: JoinType,
+    merge: Merge,
+}
+
+impl JoinOptions {
+    fn from_lua(opts: LuaTable) -> LuaResult<Self> {
+        let on = opts.get::<LuaValue>("on")?;
+        let key = |side: &str| match opts.get::<LuaValue>(side)? {
+            LuaValue::Nil if on.is_nil() => Err(LuaError::runtime(format!(
+                "join needs opts.on or opts.{side}"
+            ))),
+            LuaValue::Nil => Key::from_lua(on.clone()),
+            key => Key::from_lua(key),
+        };
+        let left_key = key("left_key")?;
+        let right_key = key("right_key")?;
+
+        let join_type = match opts.get::<Option<String>>("type")?.as_deref() {
+            None | Some("inner") => JoinType::Inner,
+            Some("left") => JoinType::Left,
+            Some(other) => {
+                return Err(LuaError::runtime(format!(
+                    "unknown join type '{other}' (expected inner or left)"
+                )));
+            }
+        };
+        let conflict = match opts.get::<Option<String>>("conflict")?.as_deref() {
+            None | Some("right") => Conflict::Right,
+            Some("left") => Conflict::Left,
+            Some("error") => Conflict::Error,
+            Some(other) => {
+                return Err(LuaError::runtime(format!(
+                    "unknown conflict policy '{other}' (expected left, right or error)"
+                )));
+            }
+        };
+        let merge = match opts.get::<Option<String>>("merge")?.as_deref() {
+            None | Some("nest") => Merge::Nest,
+            Some("shallow") => Merge::Shallow(conflict),
+            Some(other) => {
+                return Err(LuaError::runtime(format!(
+                    "unknown merge strategy '{other}' (expected nest or shallow)"
+                )));
+            }
+        };
+        Ok(Self {
+            left_key,
+            right_key,
+            join_type,
+            merge,
+        })
+    }
+}
+
+/// The elements of one side, read to the end when it names an input.
+fn side(batch: &Batch, val: LuaValue) -> LuaResult<Vec<Value>> {
+    if let LuaValue::String(name) = &val {
+        let name = name.to_str()?;
+        return match batch.named_inputs.borrow_mut().get_mut(&*name) {
+            Some(input) => Ok(input.collect()),
+            None => Err(unknown_input(&name)),
+        };
+    }
+    elements(val, "join")
+}
+
+/// The hashable key of every element, `None` for ones without it.
+fn keys(lua: &Lua, batch: &Batch, key: &Key, elems: &[Value]) -> LuaResult<Vec<Option<String>>> {
+    elems
+        .iter()
+        .map(|elem| {
+            key.of(lua, batch, elem)?
+                .as_ref()
+                .map(canonical)
+                .transpose()
+        })
+        .collect()
+}
+
+/// Positions of the elements having every key.
+fn index(keys: Vec<Option<String>>) -> HashMap<String, Vec<usize>> {
+    let mut index = HashMap::<String, Vec<usize>>::new();
+    for (i, key) in keys.into_iter().enumerate() {
+        if let Some(key) = key {
+            index.entry(key).or_default().push(i);
+        }
+    }
+    index
+}
+
+fn merge(merge: Merge, left: &Value, right: Option<&Value>) -> LuaResult<Value> {
+    let conflict = match merge {
+        Merge::Nest => {
+            let mut nested = Map::new();
+            nested.insert("left".to_string(), left.clone());
+            nested.insert("right".to_string(), right.cloned().unwrap_or(Value::Null));
+            return Ok(Value::Object(nested));
+        }
+        Merge::Shallow(conflict) => conflict,
+    };
+    let not_objects = || LuaError::runtime("shallow merge needs objects on both sides");
+    let Value::Object(left) = left else {
+        return Err(not_objects());
+    };
+    let right = match right {
+        Some(Value::Object(right)) => Some(right),
+        Some(_) => return Err(not_objects()),
+        None => None,
+    };
+    let mut merged = left.clone();
+    for (field, value) in right.into_iter().flatten() {
+        match merged.get_mut(field) {
+            None => {
+                merged.insert(field.clone(), value.clone());
+            }
+            Some(existing) => match conflict {
+                Conflict::Left => {}
+                Conflict::Right => *existing = value.clone(),
+                Conflict::Error => {
+                    return Err(LuaError::runtime(format!(
+                        "field '{field}' is on both sides of the join"
+                    )));
+                }
+            },
+        }
+    }
+    Ok(Value::Object(merged))
+}
+
+/// Installs `join(left, right, opts)`, a hash join of two arrays, handles or
+/// sequences, or named inputs, which are read to the end. It returns an
+/// array with a merged element for every pair of left and right elements
+/// whose keys are equal, in the order of the left side, then the right.
+///
+/// - `opts.on` is the key of both sides: a field name, a JSON pointer or a
+///   function of the element, as with `sorted`. `opts.left_key` and
+///   `opts.right_key` set the key of one side instead. Elements without a
+///   key match nothing.
+/// - `opts.type` is `inner`, the default, or `left`, which also keeps left
+///   elements matching nothing, with a null right side.
+/// - `opts.merge` is `nest`, the default, making every pair
+///   `{ left = l, right = r }`, or `shallow`, merging the fields of both
+///   into one object, with `opts.conflict` saying which side wins a field
+///   both have: `right`, the default, `left`, or `error`.
+///
+/// The smaller side is the one hashed.
+pub(crate) fn install_join(lua: &Lua, batch: Rc<Batch>) -> LuaResult<()> {
+    lua.globals().set(
+        "join",
+        lua.create_function(
+            move |lua, (left, right, opts): (LuaValue, LuaValue, LuaTable)| {
+                let opts = JoinOptions::from_lua(opts)?;
+                let left = side(&batch, left)?;
+                let right = side(&batch, right)?;
+                let left_keys = keys(lua, &batch, &opts.left_key, &left)?;
+                let right_keys = keys(lua, &batch, &opts.right_key, &right)?;
+
+                // The right elements matching every left one, in order.
+                let mut matches = vec![Vec::new(); left.len()];
+                if right.len() <= left.len() {
+                    let index = index(right_keys);
+                    for (i, key) in left_keys.iter().enumerate() {
+                        if let Some(found) = key.as_ref().and_then(|key| index.get(key)) {
+                            matches[i].clone_from(found);
+                        }
+                    }
+                } else {
+                    let index = index(left_keys);
+                    for (j, key) in right_keys.iter().enumerate() {
+                        for &i in key
+                            .as_ref()
+                            .and_then(|key| index.get(key))
+                            .into_iter()
+                            .flatten()
+                        {
+                            matches[i].push(j);
+                        }
+                    }
+                }
+
+                let mut joined = Vec::new();
+                for (elem, found) in left.iter().zip(matches) {
+                    if found.is_empty() && opts.join_type == JoinType::Left {
+                        joined.push(merge(opts.merge, elem, None)?);
+                    }
+                    for j in found {
+                        joined.push(merge(opts.merge, elem, Some(&right[j]))?);
+                    }
+                }
+                json_to_lua(lua, Value::Array(joined), &batch.alive.borrow())
+            },
+        )?,
+    )
+}
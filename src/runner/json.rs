@@ -0,0 +1,70 @@
+use std::rc::Rc;
+
+use mlua::{
+    Error as LuaError, Lua, Result as LuaResult, String as LuaString, Table as LuaTable,
+    Value as LuaValue,
+};
+
+use super::{Batch, output_value};
+use crate::value::json_to_lua;
+
+/// Bytes of input shown on either side of where parsing failed.
+const SNIPPET_RADIUS: usize = 16;
+
+/// Installs the `json` global: `json.decode(text)` parses JSON into a
+/// document handle, or a plain value for scalars, and `json.encode(value,
+/// opts)` turns handles, tables and scalars into JSON text, pretty-printed
+/// when `opts.pretty` is set.
+///
+/// `json.null` stands for JSON null where nil cannot, as in a table, where a
+/// key set to nil is absent: `{ a = json.null }` becomes `{"a": null}`.
+/// Documents still read null as nil.
+///
+/// `parse_json` is `json.decode` under a name of its own. Either way the
+/// result behaves just like a document from `get_next`, so emitting it moves
+/// it out rather than copying it.
+pub(crate) fn install_json(lua: &Lua, batch: Rc<Batch>) -> LuaResult<()> {
+    let decode = lua.create_function(move |lua, text: LuaString| {
+        let text = text.as_bytes();
+        let value = serde_json::from_slice(&text).map_err(|err| parse_error(&text, &err))?;
+        json_to_lua(lua, value, &batch.alive.borrow())
+    })?;
+    lua.globals().set("parse_json", decode.clone())?;
+
+    let json = lua.create_table()?;
+    json.set("decode", decode)?;
+    json.set("null", LuaValue::NULL)?;
+    json.set(
+        "encode",
+        lua.create_function(|_, (value, opts): (LuaValue, Option<LuaTable>)| {
+            let value = output_value(value, true)?;
+            let pretty = match opts {
+                Some(opts) => opts.get::<Option<bool>>("pretty")?.unwrap_or(false),
+                None => false,
+            };
+            let text = if pretty {
+                serde_json::to_string_pretty(&value)
+            } else {
+                serde_json::to_string(&value)
+            };
+            text.map_err(LuaError::external)
+        })?,
+    )?;
+    lua.globals().set("json", json)
+}
+
+/// Describes where `text` failed to parse, with a bit of the text around it.
+fn parse_error(text: &[u8], err: &serde_json::Error) -> LuaError {
+    let line_start: usize = text
+        .split_inclusive(|&b| b == b'\n')
+        .take(err.line().saturating_sub(1))
+        .map(<[u8]>::len)
+        .sum();
+    let offset = (line_start + err.column().saturating_sub(1)).min(text.len());
+    let snippet =
+        &text[offset.saturating_sub(SNIPPET_RADIUS)..(offset + SNIPPET_RADIUS).min(text.len())];
+    LuaError::runtime(format!(
+        "invalid JSON at byte {offset}: {err} (near '{}')",
+        String::from_utf8_lossy(snippet)
+    ))
+}
@@ -0,0 +1,107 @@
+use mlua::{Error as LuaError, Function as LuaFunction, Result as LuaResult, Value as LuaValue};
+use serde_json::Value;
+
+use super::{Batch, DEFAULT_CHANNEL, RunOptions, Runner, output_value};
+use crate::error::{Error, Result};
+
+/// `emit_kv` for modes whose `emit` hands over bare documents, where the key
+/// has nowhere to go.
+const EMIT_VALUE: &str = r#"
+local emit = ...
+local error = error
+return function(key, value)
+    if key == nil then
+        error("emit_kv expects a key", 2)
+    end
+    return emit(value)
+end
+"#;
+
+/// A document emitted to `out`, with the key it was given by `emit_kv`, if
+/// any.
+pub type KeyedOutput = (Option<Value>, Value);
+
+impl Batch {
+    /// Like [`Batch::push_output`] to the default channel, remembering `key`
+    /// alongside the document.
+    fn push_keyed(&self, key: Value, value: Value) -> LuaResult<()> {
+        self.count_emit(DEFAULT_CHANNEL);
+        if let Some(sink) = &mut *self.sink.borrow_mut() {
+            return sink.emit_kv(DEFAULT_CHANNEL, key, value).map_err(|source| {
+                self.raise(Error::Sink {
+                    channel: Some(DEFAULT_CHANNEL.to_string()),
+                    source: Box::new(source),
+                })
+            });
+        }
+        let mut output = self.output.borrow_mut();
+        self.keys.borrow_mut().push((output.len(), key));
+        output.push(value);
+        Ok(())
+    }
+}
+
+impl Runner {
+    /// Installs `emit_kv(key, value)`, which emits `value` to `out` like
+    /// `emit`, along with a key, a string or anything else that converts to
+    /// JSON. The key reaches [`Runner::run_kv`] and sinks, through
+    /// [`OutputSink::emit_kv`](crate::OutputSink::emit_kv); everything else
+    /// only sees the value.
+    pub(super) fn install_emit_kv(&self) -> LuaResult<()> {
+        let batch = self.batch.clone();
+        let emit_kv =
+            self.lua
+                .create_function(move |lua, (key, value): (LuaValue, LuaValue)| {
+                    if key.is_nil() {
+                        return Err(LuaError::runtime("emit_kv expects a key"));
+                    }
+                    let key = output_value(key, true)?;
+                    let value = output_value(value, false)?;
+                    batch.record_emit(lua, false, &value)?;
+                    batch.push_keyed(key, value)
+                })?;
+        self.lua.globals().set("emit_kv", emit_kv)
+    }
+
+    /// Replaces `emit_kv` with one passing the value to the current `emit`,
+    /// dropping the key.
+    pub(super) fn install_unkeyed_emit_kv(&self) -> LuaResult<()> {
+        let lua = &self.lua;
+        let emit: LuaFunction = lua.globals().get("emit")?;
+        let emit_kv: LuaFunction = lua
+            .load(EMIT_VALUE)
+            .set_name("=[mlua_play kv]")
+            .call(emit)?;
+        lua.globals().set("emit_kv", emit_kv)
+    }
+
+    /// Like [`Runner::run_batch`], but pairs every document emitted to `out`
+    /// with the key `emit_kv` gave it, or `None` for ones emitted otherwise,
+    /// in the order they were emitted.
+    pub fn run_kv<I>(&mut self, input: I) -> Result<Vec<KeyedOutput>>
+    where
+        I: IntoIterator<Item = Value>,
+        I::IntoIter: 'static,
+    {
+        let outputs = self.run_batch(input)?;
+        let mut keys = std::mem::take(&mut *self.batch.keys.borrow_mut())
+            .into_iter()
+            .peekable();
+        Ok(outputs
+            .into_iter()
+            .enumerate()
+            .map(|(i, value)| {
+                let key = keys.next_if(|(at, _)| *at == i).map(|(_, key)| key);
+                (key, value)
+            })
+            .collect())
+    }
+}
+
+pub fn run_kv<I>(script: &str, input: I, options: RunOptions) -> Result<Vec<KeyedOutput>>
+where
+    I: IntoIterator<Item = Value>,
+    I::IntoIter: 'static,
+{
+    Runner::with_options(script, options)?.run_kv(input)
+}
@@ -0,0 +1,64 @@
+use std::rc::Rc;
+
+use log::Level;
+use mlua::{Error as LuaError, Lua, Result as LuaResult, String as LuaString, Value as LuaValue};
+
+use super::{Batch, output_value};
+
+/// Target of the records scripts log, for filtering them apart from ours.
+const LOG_TARGET: &str = "mlua_play::script";
+
+/// Builds the `log` global: `log(level, message)`, plus a `log.<level>`
+/// shorthand for every level.
+const LOG_TABLE: &str = r#"
+local write = ...
+local log = setmetatable({}, {
+    __call = function(_, level, message) return write(level, message) end,
+})
+for _, level in ipairs({ "trace", "debug", "info", "warn", "error" }) do
+    log[level] = function(message) return write(level, message) end
+end
+return log
+"#;
+
+/// Installs the `log` global, which hands records to the `log` crate tagged
+/// with the script's name and the index of the document being processed.
+/// Tables and documents are logged as JSON.
+pub(crate) fn install_log(lua: &Lua, batch: Rc<Batch>, script: String) -> LuaResult<()> {
+    let write = lua.create_function(move |_, (level, message): (LuaString, LuaValue)| {
+        let level = parse_level(&level.to_string_lossy())?;
+        let message = match message {
+            LuaValue::String(s) => s.to_string_lossy(),
+            other => output_value(other, true)?.to_string(),
+        };
+        let document = batch.documents_read.get().checked_sub(1);
+        log::log!(
+            target: LOG_TARGET,
+            level,
+            script = script.as_str(),
+            document = document;
+            "{message}"
+        );
+        Ok(())
+    })?;
+    let log: LuaValue = lua
+        .load(LOG_TABLE)
+        .set_name("=[mlua_play log]")
+        .call(write)?;
+    lua.globals().set("log", log)
+}
+
+fn parse_level(level: &str) -> LuaResult<Level> {
+    Ok(match level {
+        "trace" => Level::Trace,
+        "debug" => Level::Debug,
+        "info" => Level::Info,
+        "warn" => Level::Warn,
+        "error" => Level::Error,
+        _ => {
+            return Err(LuaError::runtime(format!(
+                "invalid log level '{level}' (expected trace, debug, info, warn or error)"
+            )));
+        }
+    })
+}
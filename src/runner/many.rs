@@ -0,0 +1,100 @@
+use mlua::{
+    Error as LuaError, Function as LuaFunction, Result as LuaResult, Table as LuaTable,
+    Value as LuaValue,
+};
+use serde_json::Value;
+
+use super::{DEFAULT_CHANNEL, Runner, output_value};
+use crate::value::json_to_lua;
+
+/// `emit_many` for modes whose `emit` suspends the script, which has to be
+/// called for every element so the script is suspended between them.
+const EMIT_EACH: &str = r#"
+local emit, elements = ...
+return function(list)
+    local values, n = elements(list)
+    for i = 1, n do
+        emit(values[i])
+    end
+end
+"#;
+
+impl Runner {
+    /// Installs `emit_many(list)`, which emits every element of a sequence,
+    /// or of a handle to an array, in order and in a single call. Handles are
+    /// moved out of their documents, as with `emit`.
+    pub(super) fn install_emit_many(&self) -> LuaResult<()> {
+        let batch = self.batch.clone();
+        let emit_many = self.lua.create_function(move |lua, list: LuaValue| {
+            for value in sequence_values(list)? {
+                batch.record_emit(lua, false, &value)?;
+                batch.push_output(DEFAULT_CHANNEL, value)?;
+            }
+            Ok(())
+        })?;
+        self.lua.globals().set("emit_many", emit_many)
+    }
+
+    /// Replaces `emit_many` with one going through the current `emit` element
+    /// by element, and `emit_kv` with one going through it too.
+    pub(super) fn install_emit_each(&self) -> LuaResult<()> {
+        let lua = &self.lua;
+        let batch = self.batch.clone();
+        let elements = lua.create_function(move |lua, list: LuaValue| {
+            if let LuaValue::Table(t) = list {
+                let n = sequence_len(&t)?;
+                return Ok((t, n));
+            }
+            let values = sequence_values(list)?;
+            let t = lua.create_table_with_capacity(values.len(), 0)?;
+            let n = values.len();
+            for value in values {
+                t.raw_push(json_to_lua(lua, value, &batch.alive.borrow())?)?;
+            }
+            Ok((t, n))
+        })?;
+        let emit: LuaFunction = lua.globals().get("emit")?;
+        let emit_many: LuaFunction = lua
+            .load(EMIT_EACH)
+            .set_name("=[mlua_play emit_many]")
+            .call((emit, elements))?;
+        lua.globals().set("emit_many", emit_many)?;
+        self.install_unkeyed_emit_kv()
+    }
+}
+
+fn not_a_sequence() -> LuaError {
+    LuaError::runtime("emit_many expects a sequence or an array")
+}
+
+/// Length of `t`, failing unless its keys are exactly `1..=n`.
+fn sequence_len(t: &LuaTable) -> LuaResult<usize> {
+    let n = t.raw_len();
+    let mut count = 0;
+    for pair in t.pairs::<LuaValue, LuaValue>() {
+        match pair? {
+            (LuaValue::Integer(i), _) if i >= 1 && i as usize <= n => count += 1,
+            _ => return Err(not_a_sequence()),
+        }
+    }
+    if count != n {
+        return Err(not_a_sequence());
+    }
+    Ok(n)
+}
+
+fn sequence_values(list: LuaValue) -> LuaResult<Vec<Value>> {
+    match list {
+        LuaValue::Table(t) => {
+            let n = sequence_len(&t)?;
+            (1..=n)
+                .map(|i| output_value(t.raw_get(i)?, false))
+                .collect()
+        }
+        LuaValue::UserData(_) => match output_value(list, false)? {
+            Value::Array(values) => Ok(values),
+            _ => Err(not_a_sequence()),
+        },
+        _ => Err(not_a_sequence()),
+    }
+}
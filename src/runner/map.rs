@@ -0,0 +1,208 @@
+use mlua::{
+    Error as LuaError, Function as LuaFunction, MultiValue as LuaMultiValue, Result as LuaResult,
+    Value as LuaValue,
+};
+use serde_json::Value;
+
+use super::{DEFAULT_CHANNEL, InputIter, RunOptions, Runner, output_value};
+use crate::error::{Error, Limit, Result};
+use crate::limits::DocumentLimitPolicy;
+use crate::value::json_to_lua;
+
+/// What the driver modes do when the script fails on a single document.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Fail the whole run.
+    #[default]
+    Abort,
+    /// Record the failure and carry on with the next document.
+    Collect,
+}
+
+/// A document the script failed on under [`ErrorPolicy::Collect`], or one
+/// skipped for exceeding a per-document limit under
+/// [`DocumentLimitPolicy::Skip`], with the [`Error::LimitExceeded`] saying
+/// which.
+#[derive(Debug)]
+pub struct Failure {
+    pub index: usize,
+    pub error: Error,
+    /// The document as it was read, before the script got to change it.
+    pub document: Value,
+}
+
+/// What a run in a driver mode produced.
+#[derive(Debug, Default)]
+pub struct MapOutput {
+    pub outputs: Vec<Value>,
+    pub failures: Vec<Failure>,
+    /// Failures past [`RunOptions::max_failures`], counted but not kept.
+    pub failures_dropped: usize,
+}
+
+impl Runner {
+    /// Runs the script once so it can define a global `transform(doc)`, then
+    /// calls that for every document of `input`, emitting every non-nil value
+    /// it returns. Works whatever [`RunOptions::mode`] says.
+    pub fn run_map<I>(&mut self, input: I) -> Result<MapOutput>
+    where
+        I: IntoIterator<Item = Value>,
+        I::IntoIter: 'static,
+    {
+        let outputs = self.map_batch(Box::new(input.into_iter().map(Ok)))?;
+        Ok(MapOutput {
+            outputs,
+            failures: self.take_failures(),
+            failures_dropped: self.batch.failures_dropped.get(),
+        })
+    }
+
+    /// Runs a batch in [`Mode::Map`](super::Mode::Map), leaving collected
+    /// failures in the batch.
+    pub(super) fn map_batch(&mut self, mut input: InputIter) -> Result<Vec<Value>> {
+        self.batch.begin(None);
+        let result = self.map_documents(&mut input);
+        let outputs = self.batch.end();
+        if let Err(err) = result {
+            return Err(err.with_outputs(outputs));
+        }
+        self.batch.finish_sink()?;
+        Ok(outputs)
+    }
+
+    fn map_documents(&self, input: &mut InputIter) -> Result<()> {
+        self.load_driven_script()?;
+        let transform = self.required_function("transform")?;
+        self.drive_documents(input, |handle| {
+            let window = self.batch.window.borrow().snapshot();
+            let window = window
+                .map(|docs| json_to_lua(&self.lua, docs, &self.batch.alive.borrow()))
+                .transpose()?;
+            let returned = transform.call::<LuaMultiValue>((handle, window))?;
+            for ret in returned {
+                if ret.is_nil() {
+                    continue;
+                }
+                let value = output_value(ret, false)?;
+                self.batch.record_emit(&self.lua, false, &value)?;
+                self.batch.push_output(DEFAULT_CHANNEL, value)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Runs the chunk of a driver-mode script, which only defines the
+    /// functions the runner calls.
+    pub(super) fn load_driven_script(&self) -> Result<()> {
+        self.install_globals()
+            .and_then(|()| self.chunk.call::<()>(()))
+            .map_err(|err| self.convert_error(err))
+    }
+
+    pub(super) fn required_function(&self, name: &str) -> Result<LuaFunction> {
+        match self.lua.globals().get::<LuaValue>(name) {
+            Ok(LuaValue::Function(f)) => Ok(f),
+            Ok(_) => Err(self.convert_error(LuaError::runtime(format!(
+                "script must define a global function '{name}'"
+            )))),
+            Err(err) => Err(self.convert_error(err)),
+        }
+    }
+
+    /// Hands every document of `input` to `process` as a handle, applying the
+    /// hooks, the per-document limit policy and the error policy around it.
+    pub(super) fn drive_documents(
+        &self,
+        input: &mut InputIter,
+        mut process: impl FnMut(LuaValue) -> LuaResult<()>,
+    ) -> Result<()> {
+        for next in input {
+            let index = self.batch.documents_read.get();
+            let doc = next.map_err(|source| Error::InputError {
+                index,
+                source: Box::new(source),
+            })?;
+            // Only kept around when there is a failure record to put it in.
+            let original = (self.on_error == ErrorPolicy::Collect
+                || self.on_document_limit == DocumentLimitPolicy::Skip)
+                .then(|| doc.clone());
+            self.batch.start_document(&self.lua, &doc);
+            self.batch.hooks.borrow_mut().document_start(index, &doc)?;
+            let handle = json_to_lua(&self.lua, doc, &self.batch.alive.borrow());
+            let result = handle
+                .and_then(&mut process)
+                .map_err(|err| self.convert_error(err));
+            let ended = self
+                .batch
+                .hooks
+                .borrow_mut()
+                .document_end(index, result.as_ref().map(|_| ()));
+            ended?;
+            let Err(error) = result else {
+                self.batch.document_done();
+                continue;
+            };
+
+            let resumable = if is_document_limit(&error) {
+                self.on_document_limit == DocumentLimitPolicy::Skip
+            } else {
+                self.on_error == ErrorPolicy::Collect && is_document_failure(&error)
+            };
+            match original {
+                Some(document) if resumable => self.record_failure(Failure {
+                    index,
+                    error,
+                    document,
+                }),
+                _ => return Err(error),
+            }
+            self.batch.document_done();
+        }
+        Ok(())
+    }
+
+    /// Keeps `failure` for [`Runner::take_failures`], or only counts it once
+    /// [`RunOptions::max_failures`] are kept.
+    fn record_failure(&self, failure: Failure) {
+        let failed = &self.batch.documents_failed;
+        failed.set(failed.get() + 1);
+        let mut failures = self.batch.failures.borrow_mut();
+        if failures.len() < self.max_failures {
+            failures.push(failure);
+        } else {
+            let dropped = &self.batch.failures_dropped;
+            dropped.set(dropped.get() + 1);
+        }
+    }
+
+    /// Takes the failures the last batch collected under
+    /// [`ErrorPolicy::Collect`] and the documents it skipped under
+    /// [`DocumentLimitPolicy::Skip`].
+    pub fn take_failures(&self) -> Vec<Failure> {
+        std::mem::take(&mut *self.batch.failures.borrow_mut())
+    }
+}
+
+/// Errors confined to the document being transformed, as opposed to ones that
+/// leave nothing worth continuing, like cancellation or running out of memory.
+fn is_document_failure(err: &Error) -> bool {
+    matches!(err, Error::ScriptRuntime { .. } | Error::Conversion { .. })
+}
+
+fn is_document_limit(err: &Error) -> bool {
+    matches!(
+        err,
+        Error::LimitExceeded {
+            which: Limit::DocumentInstructions { .. } | Limit::DocumentTimeout { .. },
+            ..
+        }
+    )
+}
+
+pub fn run_map<I>(script: &str, input: I, options: RunOptions) -> Result<MapOutput>
+where
+    I: IntoIterator<Item = Value>,
+    I::IntoIter: 'static,
+{
+    Runner::with_options(script, options)?.run_map(input)
+}
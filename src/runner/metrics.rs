@@ -0,0 +1,169 @@
+use std::rc::Rc;
+
+use mlua::{Error as LuaError, Lua, Result as LuaResult, String as LuaString, Table as LuaTable};
+
+use super::Batch;
+
+/// What the script recorded under one name through the `metrics` global.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Metric {
+    /// The sum of every `metrics.incr`.
+    Counter(i64),
+    /// The last value given to `metrics.gauge`.
+    Gauge(f64),
+    /// The values given to `metrics.observe`.
+    Summary(MetricSummary),
+}
+
+impl Metric {
+    fn kind(&self) -> &'static str {
+        match self {
+            Metric::Counter(_) => "counter",
+            Metric::Gauge(_) => "gauge",
+            Metric::Summary(_) => "summary",
+        }
+    }
+
+    /// Folds in what `other` recorded later, or elsewhere, under the same
+    /// name: counters add up, a gauge takes the other's value and summaries
+    /// cover both. A metric of another kind is ignored.
+    pub(crate) fn merge(&mut self, other: Metric) {
+        match (self, other) {
+            (Metric::Counter(total), Metric::Counter(more)) => {
+                *total = total.wrapping_add(more);
+            }
+            (Metric::Gauge(current), Metric::Gauge(value)) => *current = value,
+            (Metric::Summary(summary), Metric::Summary(more)) => {
+                summary.count += more.count;
+                summary.sum += more.sum;
+                summary.min = summary.min.min(more.min);
+                summary.max = summary.max.max(more.max);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Count, sum and range of observed values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricSummary {
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl MetricSummary {
+    fn new(value: f64) -> Self {
+        Self {
+            count: 1,
+            sum: value,
+            min: value,
+            max: value,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+}
+
+/// Applies `update` to the metric called `name`, or creates it with `create`.
+/// A name keeps the kind it was first used with for the rest of the batch.
+fn record(
+    batch: &Batch,
+    name: &LuaString,
+    kind: &'static str,
+    create: impl FnOnce() -> Metric,
+    update: impl FnOnce(&mut Metric) -> bool,
+) -> LuaResult<()> {
+    let name = name.to_str()?;
+    if name.is_empty() {
+        return Err(LuaError::runtime("metric name must not be empty"));
+    }
+    let mut metrics = batch.metrics.borrow_mut();
+    if let Some(metric) = metrics.get_mut(&*name) {
+        if update(metric) {
+            return Ok(());
+        }
+        return Err(LuaError::runtime(format!(
+            "metric '{}' is a {}, not a {kind}",
+            &*name,
+            metric.kind()
+        )));
+    }
+    metrics.insert(name.to_string(), create());
+    Ok(())
+}
+
+/// Installs the `metrics` global, whose `incr(name, delta)`,
+/// `gauge(name, value)` and `observe(name, value)` record into the batch's
+/// [`RunStats::metrics`](super::RunStats::metrics) rather than its output.
+pub(super) fn install_metrics(lua: &Lua, batch: Rc<Batch>) -> LuaResult<()> {
+    let metrics: LuaTable = lua.create_table()?;
+
+    let state = batch.clone();
+    metrics.set(
+        "incr",
+        lua.create_function(move |_, (name, delta): (LuaString, Option<i64>)| {
+            let delta = delta.unwrap_or(1);
+            record(
+                &state,
+                &name,
+                "counter",
+                || Metric::Counter(delta),
+                |metric| match metric {
+                    Metric::Counter(total) => {
+                        *total = total.wrapping_add(delta);
+                        true
+                    }
+                    _ => false,
+                },
+            )
+        })?,
+    )?;
+
+    let state = batch.clone();
+    metrics.set(
+        "gauge",
+        lua.create_function(move |_, (name, value): (LuaString, f64)| {
+            record(
+                &state,
+                &name,
+                "gauge",
+                || Metric::Gauge(value),
+                |metric| match metric {
+                    Metric::Gauge(current) => {
+                        *current = value;
+                        true
+                    }
+                    _ => false,
+                },
+            )
+        })?,
+    )?;
+
+    metrics.set(
+        "observe",
+        lua.create_function(move |_, (name, value): (LuaString, f64)| {
+            record(
+                &batch,
+                &name,
+                "summary",
+                || Metric::Summary(MetricSummary::new(value)),
+                |metric| match metric {
+                    Metric::Summary(summary) => {
+                        summary.observe(value);
+                        true
+                    }
+                    _ => false,
+                },
+            )
+        })?,
+    )?;
+
+    lua.globals().set("metrics", metrics)
+}
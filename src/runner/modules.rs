@@ -0,0 +1,89 @@
+use mlua::Function as LuaFunction;
+
+use super::Runner;
+use crate::error::{Error, ErrorContext, Result};
+
+/// Replaces `require` with one that only resolves modules registered through
+/// [`Runner::with_module`], caching what each returns like `package.loaded`.
+/// Returns the function registering a module's loader.
+const RESTRICTED_REQUIRE: &str = r#"
+local error, next, pcall, tostring = error, next, pcall, tostring
+local concat, sort = table.concat, table.sort
+local modules, loaded, loading = {}, {}, {}
+
+function require(name)
+    local value = loaded[name]
+    if value ~= nil then
+        return value
+    end
+    local loader = modules[name]
+    if loader == nil then
+        local available = {}
+        for known in next, modules do
+            available[#available + 1] = known
+        end
+        sort(available)
+        error("module '" .. tostring(name) .. "' not found (available: "
+            .. concat(available, ", ") .. ")", 2)
+    end
+    if loading[name] then
+        error("module '" .. name .. "' requires itself", 2)
+    end
+    loading[name] = true
+    local ok, result = pcall(loader, name)
+    loading[name] = nil
+    if not ok then
+        error(result, 0)
+    end
+    if result == nil then
+        result = true
+    end
+    loaded[name] = result
+    return result
+end
+
+return function(name, loader)
+    modules[name] = loader
+    loaded[name] = nil
+end
+"#;
+
+impl Runner {
+    /// Registers Lua source the script can load with `require(name)`. Once a
+    /// module is registered, `require` resolves registered modules only, so
+    /// scripts can share code without access to the filesystem.
+    pub fn add_module(&mut self, name: &str, source: &str) -> Result<()> {
+        let loader = self
+            .lua
+            .load(source)
+            .set_name(format!("={name}"))
+            .into_function()
+            .map_err(|err| {
+                let ctx = ErrorContext {
+                    script: name,
+                    document: None,
+                    memory_limit: self.max_lua_memory,
+                };
+                Error::from_lua(err, &ctx)
+            })?;
+        let register = match &self.register_module {
+            Some(register) => register.clone(),
+            None => {
+                let register: LuaFunction = self
+                    .lua
+                    .load(RESTRICTED_REQUIRE)
+                    .set_name("=[mlua_play require]")
+                    .call(())?;
+                self.register_module = Some(register.clone());
+                register
+            }
+        };
+        Ok(register.call((name, loader))?)
+    }
+
+    /// Builder-style [`Runner::add_module`].
+    pub fn with_module(mut self, name: &str, source: &str) -> Result<Self> {
+        self.add_module(name, source)?;
+        Ok(self)
+    }
+}
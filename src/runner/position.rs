@@ -0,0 +1,37 @@
+use std::rc::Rc;
+
+use mlua::{Lua, Result as LuaResult};
+
+use super::Batch;
+
+/// Installs `doc_index()`, the 1-based ordinal of the document the script is
+/// on, counting every document handed over by `get_next` or passed to a
+/// driver mode callback, or 0 before the first, plus
+/// [`RunOptions::index_offset`](crate::RunOptions::index_offset).
+///
+/// Also installs `doc_meta()`, returning `{ index, line, source }`, where
+/// `line` and `source` say where the document was read when the input knows,
+/// like [`JsonLinesSource`](crate::JsonLinesSource) does, and are nil
+/// otherwise.
+pub(super) fn install_position(lua: &Lua, batch: Rc<Batch>) -> LuaResult<()> {
+    let globals = lua.globals();
+
+    let state = batch.clone();
+    globals.set(
+        "doc_index",
+        lua.create_function(move |_, ()| Ok(state.doc_index()))?,
+    )?;
+
+    globals.set(
+        "doc_meta",
+        lua.create_function(move |lua, ()| {
+            let meta = lua.create_table_with_capacity(0, 3)?;
+            meta.raw_set("index", batch.doc_index())?;
+            if let Some(position) = &*batch.position.borrow() {
+                meta.raw_set("line", position.line)?;
+                meta.raw_set("source", position.source.as_deref())?;
+            }
+            Ok(meta)
+        })?,
+    )
+}
@@ -0,0 +1,93 @@
+use std::io::Write;
+use std::rc::Rc;
+
+use mlua::{
+    Error as LuaError, Function as LuaFunction, Lua, Result as LuaResult, String as LuaString,
+};
+
+use super::Batch;
+
+/// Routes `print` and `io.write` through `write`, so nothing a script prints
+/// ends up on the process's stdout. `tostring` honors `__tostring`, which
+/// makes `print(doc)` show the document.
+const CAPTURING_PRINT: &str = r##"
+local write = ...
+local select, tostring, type, concat = select, tostring, type, table.concat
+
+function print(...)
+    local parts = {}
+    for i = 1, select("#", ...) do
+        parts[i] = tostring((select(i, ...)))
+    end
+    write(concat(parts, "\t") .. "\n")
+end
+
+if io then
+    function io.write(...)
+        for i = 1, select("#", ...) do
+            local v = select(i, ...)
+            local t = type(v)
+            if t ~= "string" and t ~= "number" then
+                error("bad argument #" .. i .. " to 'write' (string expected, got " .. t .. ")", 2)
+            end
+            write(tostring(v))
+        end
+    end
+end
+"##;
+
+/// Lines the script printed, or a writer they go to as they come. Lines
+/// sent to a writer are not kept, so a long run's output does not pile up.
+#[derive(Default)]
+pub(crate) struct PrintLog {
+    lines: Vec<String>,
+    partial: String,
+    forward: Option<Box<dyn Write>>,
+}
+
+impl PrintLog {
+    pub(crate) fn new(forward: Option<Box<dyn Write>>) -> Self {
+        Self {
+            forward,
+            ..Self::default()
+        }
+    }
+
+    fn write(&mut self, text: &str) -> std::io::Result<()> {
+        if let Some(forward) = &mut self.forward {
+            forward.write_all(text.as_bytes())?;
+            return forward.flush();
+        }
+        let mut rest = text;
+        while let Some((line, tail)) = rest.split_once('\n') {
+            self.partial.push_str(line);
+            self.lines.push(std::mem::take(&mut self.partial));
+            rest = tail;
+        }
+        self.partial.push_str(rest);
+        Ok(())
+    }
+
+    /// Takes the lines printed so far, including an unterminated last one.
+    pub(crate) fn take(&mut self) -> Vec<String> {
+        if !self.partial.is_empty() {
+            self.lines.push(std::mem::take(&mut self.partial));
+        }
+        std::mem::take(&mut self.lines)
+    }
+}
+
+pub(crate) fn install_print(lua: &Lua, batch: Rc<Batch>) -> LuaResult<()> {
+    let write = lua.create_function(move |_, text: LuaString| {
+        batch
+            .log
+            .borrow_mut()
+            .write(&text.to_string_lossy())
+            .map_err(LuaError::external)
+    })?;
+    let setup: LuaFunction = lua
+        .load(CAPTURING_PRINT)
+        .set_name("=[mlua_play print]")
+        .into_function()?;
+    setup.call(write)
+}
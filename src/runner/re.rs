@@ -0,0 +1,140 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use mlua::{Error as LuaError, Lua, Result as LuaResult, String as LuaString, Table as LuaTable};
+use regex::bytes::Regex;
+
+/// Most compiled patterns kept around; the cache starts over once full.
+const MAX_CACHED_PATTERNS: usize = 256;
+
+/// Compiled patterns by source, shared by the `re` functions of a Lua state.
+#[derive(Default)]
+struct Cache {
+    patterns: HashMap<Vec<u8>, Regex>,
+}
+
+impl Cache {
+    fn get(&mut self, pattern: &LuaString) -> LuaResult<Regex> {
+        let source = pattern.as_bytes();
+        if let Some(re) = self.patterns.get(&*source) {
+            return Ok(re.clone());
+        }
+        let text = std::str::from_utf8(&source)
+            .map_err(|_| LuaError::runtime("regex pattern is not valid UTF-8"))?;
+        let re = Regex::new(text).map_err(|err| LuaError::runtime(err.to_string()))?;
+        if self.patterns.len() >= MAX_CACHED_PATTERNS {
+            self.patterns.clear();
+        }
+        self.patterns.insert(source.to_vec(), re.clone());
+        Ok(re)
+    }
+}
+
+/// Installs the `re` global, matching with proper regular expressions rather
+/// than Lua patterns:
+///
+/// - `re.match(pattern, s)` returns the captures of the first match, the
+///   whole match at index 0, groups from 1 and named groups by name, or nil.
+/// - `re.find_all(pattern, s)` returns every match.
+/// - `re.replace(pattern, s, replacement)` replaces every match, with `$1` or
+///   `$name` in `replacement` standing for a group.
+/// - `re.split(pattern, s)` returns the pieces between matches.
+///
+/// Patterns are compiled once per Lua state and reused.
+pub(crate) fn install_re(lua: &Lua) -> LuaResult<()> {
+    let cache = Rc::new(RefCell::new(Cache::default()));
+    let re = lua.create_table()?;
+
+    let patterns = cache.clone();
+    re.set(
+        "match",
+        lua.create_function(move |lua, (pattern, s): (LuaString, LuaString)| {
+            let re = patterns.borrow_mut().get(&pattern)?;
+            let s = s.as_bytes();
+            let Some(caps) = re.captures(&s) else {
+                return Ok(None);
+            };
+            let t = lua.create_table()?;
+            for (i, group) in caps.iter().enumerate() {
+                if let Some(group) = group {
+                    t.raw_set(i, lua.create_string(group.as_bytes())?)?;
+                }
+            }
+            for name in re.capture_names().flatten() {
+                if let Some(group) = caps.name(name) {
+                    t.raw_set(name, lua.create_string(group.as_bytes())?)?;
+                }
+            }
+            Ok(Some(t))
+        })?,
+    )?;
+
+    let patterns = cache.clone();
+    re.set(
+        "find_all",
+        lua.create_function(move |lua, (pattern, s): (LuaString, LuaString)| {
+            let re = patterns.borrow_mut().get(&pattern)?;
+            let s = s.as_bytes();
+            strings(lua, re.find_iter(&s).map(|m| m.as_bytes()))
+        })?,
+    )?;
+
+    let patterns = cache.clone();
+    re.set(
+        "replace",
+        lua.create_function(
+            move |lua, (pattern, s, replacement): (LuaString, LuaString, LuaString)| {
+                let re = patterns.borrow_mut().get(&pattern)?;
+                let (s, replacement) = (s.as_bytes(), replacement.as_bytes());
+                let replaced = re.replace_all(&s, &*replacement);
+                lua.create_string(replaced)
+            },
+        )?,
+    )?;
+
+    re.set(
+        "split",
+        lua.create_function(move |lua, (pattern, s): (LuaString, LuaString)| {
+            let re = cache.borrow_mut().get(&pattern)?;
+            let s = s.as_bytes();
+            strings(lua, re.split(&s))
+        })?,
+    )?;
+
+    lua.globals().set("re", re)
+}
+
+fn strings<'a>(lua: &Lua, items: impl Iterator<Item = &'a [u8]>) -> LuaResult<LuaTable> {
+    let t = lua.create_table()?;
+    for item in items {
+        t.raw_push(lua.create_string(item)?)?;
+    }
+    Ok(t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_cache_reuses_patterns_and_starts_over_once_full() {
+        let lua = Lua::new();
+        let mut cache = Cache::default();
+        let pattern = |n: usize| lua.create_string(format!("a{{{n}}}")).unwrap();
+        cache.get(&pattern(1)).unwrap();
+        cache.get(&pattern(1)).unwrap();
+        assert_eq!(cache.patterns.len(), 1);
+
+        for n in 2..=MAX_CACHED_PATTERNS {
+            cache.get(&pattern(n)).unwrap();
+        }
+        assert_eq!(cache.patterns.len(), MAX_CACHED_PATTERNS);
+        let re = cache.get(&pattern(MAX_CACHED_PATTERNS + 1)).unwrap();
+        assert_eq!(cache.patterns.len(), 1);
+        assert!(re.is_match(&b"a".repeat(MAX_CACHED_PATTERNS + 1)));
+
+        assert!(cache.get(&lua.create_string("(").unwrap()).is_err());
+        assert_eq!(cache.patterns.len(), 1);
+    }
+}
@@ -0,0 +1,166 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use mlua::{
+    Error as LuaError, Function as LuaFunction, Lua, MultiValue as LuaMultiValue,
+    Result as LuaResult, Table as LuaTable, Value as LuaValue,
+};
+use serde_json::Value;
+
+use super::{RunOptions, Runner, output_value};
+use crate::error::Result;
+use crate::source::InputSource;
+use crate::value::SharedValue;
+
+/// What came of code given to [`Session::eval`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Evaluated {
+    /// The code is the start of a chunk that goes on past it, such as a
+    /// `function` missing its `end`; it needs more lines before it can run.
+    Incomplete,
+    /// The code ran, returning these values: documents and tables as
+    /// compact JSON, anything else as `tostring` has it.
+    Done(Vec<String>),
+}
+
+/// Runs Lua a chunk at a time over one input, for exploring it by hand.
+/// Everything a free-form script has is there, `get_next` and `emit`
+/// included, and reading carries on from one chunk to the next.
+///
+/// Chunks share an environment of their own, falling back on the globals,
+/// which keeps whatever variables they set until [`Session::reset`]. A
+/// `local` declared at the very start of a line, which would be gone by the
+/// next chunk, is made a variable of that environment too.
+pub struct Session {
+    runner: Runner,
+    env: LuaTable,
+    /// The document the input last handed over.
+    last: Rc<RefCell<Option<Value>>>,
+}
+
+impl Session {
+    /// Starts a session over `source`. `options` are those of a script,
+    /// except that the mode is ignored.
+    pub fn new(options: RunOptions, mut source: impl InputSource + 'static) -> Result<Self> {
+        let runner = Runner::with_options("", options)?;
+        let batch = runner.batch.clone();
+        let input = runner.prepare_input(Box::new(std::iter::from_fn(move || {
+            let next = source.next_doc().transpose();
+            *batch.read_position.borrow_mut() = source.position();
+            next
+        })));
+        let last = Rc::new(RefCell::new(None));
+        let fetched = last.clone();
+        runner.batch.begin(Some(Box::new(input.inspect(move |doc| {
+            if let Ok(doc) = doc {
+                *fetched.borrow_mut() = Some(doc.clone());
+            }
+        }))));
+        let env = runner
+            .install_globals()
+            .and_then(|()| new_env(&runner.lua))
+            .map_err(|err| runner.convert_error(err))?;
+        Ok(Self { runner, env, last })
+    }
+
+    /// Runs `code`, as an expression whose values are returned when it is
+    /// one, and as statements otherwise.
+    pub fn eval(&mut self, code: &str) -> Result<Evaluated> {
+        let code = persist_locals(code);
+        let name = self.runner.script_name.clone();
+        let chunk = match self.compile(&format!("return {code}"), &name) {
+            Ok(chunk) => chunk,
+            Err(_) => match self.compile(&code, &name) {
+                Ok(chunk) => chunk,
+                Err(LuaError::SyntaxError {
+                    incomplete_input: true,
+                    ..
+                }) => return Ok(Evaluated::Incomplete),
+                Err(err) => return Err(self.runner.convert_error(err)),
+            },
+        };
+        self.call(chunk).map(Evaluated::Done)
+    }
+
+    /// Runs `code` as one whole chunk, such as a file, named `name` in
+    /// errors, returning what it returns.
+    pub fn load(&mut self, code: &str, name: &str) -> Result<Vec<String>> {
+        let chunk = self
+            .compile(code, name)
+            .map_err(|err| self.runner.convert_error(err))?;
+        self.call(chunk)
+    }
+
+    /// Forgets every variable chunks have set, leaving the input where it
+    /// is.
+    pub fn reset(&mut self) -> Result<()> {
+        self.env = new_env(&self.runner.lua).map_err(|err| self.runner.convert_error(err))?;
+        Ok(())
+    }
+
+    /// The document the input last handed over, as it was read.
+    pub fn last_document(&self) -> Option<Value> {
+        self.last.borrow().clone()
+    }
+
+    /// Takes what was emitted so far, when [`RunOptions::sink`] is not set.
+    pub fn take_output(&self) -> Vec<Value> {
+        std::mem::take(&mut *self.runner.batch.output.borrow_mut())
+    }
+
+    /// Takes the lines printed so far.
+    pub fn take_log(&self) -> Vec<String> {
+        self.runner.take_log()
+    }
+
+    fn compile(&self, code: &str, name: &str) -> LuaResult<LuaFunction> {
+        self.runner
+            .lua
+            .load(code)
+            .set_name(format!("={name}"))
+            .set_environment(self.env.clone())
+            .into_function()
+    }
+
+    fn call(&self, chunk: LuaFunction) -> Result<Vec<String>> {
+        chunk
+            .call::<LuaMultiValue>(())
+            .and_then(|values| values.into_iter().map(show).collect())
+            .map_err(|err| self.runner.convert_error(err))
+    }
+}
+
+fn new_env(lua: &Lua) -> LuaResult<LuaTable> {
+    lua.load("local globals = ... return setmetatable({}, { __index = globals })")
+        .set_name("=[mlua_play repl]")
+        .call(lua.globals())
+}
+
+/// `code` with every `local` declared at the start of a line made an
+/// assignment, or a function declaration, so that it lands in the session's
+/// environment rather than going out of scope with the chunk.
+fn persist_locals(code: &str) -> String {
+    code.lines()
+        .map(|line| match line.strip_prefix("local ") {
+            Some(rest) if rest.trim_start().starts_with("function ") => {
+                rest.trim_start().to_string()
+            }
+            Some(rest) if !rest.contains('=') => format!("{rest} = nil"),
+            Some(rest) => rest.to_string(),
+            None => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn show(value: LuaValue) -> LuaResult<String> {
+    let is_json = match &value {
+        LuaValue::Table(_) => true,
+        LuaValue::UserData(data) => data.is::<SharedValue>(),
+        _ => false,
+    };
+    if is_json && let Ok(json) = output_value(value.clone(), true) {
+        return Ok(json.to_string());
+    }
+    value.to_string()
+}
@@ -0,0 +1,117 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use jsonschema::Validator;
+use mlua::{
+    Error as LuaError, Lua, MultiValue as LuaMultiValue, Result as LuaResult, Value as LuaValue,
+};
+use serde_json::{Value, json};
+use xxhash_rust::xxh3::xxh3_64;
+
+use super::{InputIter, Runner, output_value};
+use crate::error::{Error, Result};
+use crate::value::json_to_table;
+
+/// [`RunOptions::input_schema`](super::RunOptions::input_schema), compiled.
+pub(crate) struct InputSchema {
+    validator: Validator,
+    channel: Option<String>,
+}
+
+impl InputSchema {
+    pub(crate) fn new(schema: &Value, channel: Option<String>) -> Result<Self> {
+        let validator = jsonschema::validator_for(schema).map_err(|err| Error::InvalidOption {
+            option: "input_schema",
+            message: err.to_string(),
+        })?;
+        Ok(Self { validator, channel })
+    }
+}
+
+/// Every way `doc` breaks the schema, as JSON pointer and message pairs.
+fn violations(validator: &Validator, doc: &Value) -> Vec<(String, String)> {
+    validator
+        .iter_errors(doc)
+        .map(|err| (err.instance_path.to_string(), err.to_string()))
+        .collect()
+}
+
+impl Runner {
+    /// Applies [`RunOptions::input_schema`](super::RunOptions::input_schema)
+    /// to `input`.
+    pub(super) fn validate_input(&self, input: InputIter) -> InputIter {
+        let Some(schema) = self.input_schema.clone() else {
+            return input;
+        };
+        let batch = self.batch.clone();
+        Box::new(input.filter_map(move |next| {
+            let doc = match next {
+                Ok(doc) => doc,
+                Err(err) => return Some(Err(err)),
+            };
+            let errors = violations(&schema.validator, &doc);
+            if errors.is_empty() {
+                return Some(Ok(doc));
+            }
+            let Some(channel) = &schema.channel else {
+                return Some(Err(Error::SchemaViolation {
+                    errors: errors
+                        .into_iter()
+                        .map(|(pointer, message)| format!("{pointer}: {message}"))
+                        .collect(),
+                }));
+            };
+            match batch.push_output(channel, doc) {
+                Ok(()) => None,
+                Err(err) => Some(Err(batch
+                    .raised
+                    .borrow_mut()
+                    .take()
+                    .unwrap_or(Error::Lua(err)))),
+            }
+        }))
+    }
+}
+
+/// Installs `validate(value, schema)`, which returns true when `value`
+/// matches the JSON Schema `schema`, or false and a list of `{pointer,
+/// message}` errors. The schema can be a table, a handle or JSON text, and
+/// is compiled once for every distinct schema.
+pub(crate) fn install_validate(lua: &Lua) -> LuaResult<()> {
+    let cache = RefCell::new(HashMap::<u64, Rc<Validator>>::new());
+    lua.globals().set(
+        "validate",
+        lua.create_function(move |lua, (value, schema): (LuaValue, LuaValue)| {
+            let schema = match schema {
+                LuaValue::String(s) => serde_json::from_slice(&s.as_bytes())
+                    .map_err(|err| LuaError::runtime(format!("invalid schema JSON: {err}")))?,
+                schema => output_value(schema, true)?,
+            };
+            let hash = xxh3_64(&serde_json::to_vec(&schema).map_err(LuaError::external)?);
+            let cached = cache.borrow().get(&hash).cloned();
+            let validator = match cached {
+                Some(validator) => validator,
+                None => {
+                    let validator = jsonschema::validator_for(&schema)
+                        .map_err(|err| LuaError::runtime(format!("invalid schema: {err}")))?;
+                    let validator = Rc::new(validator);
+                    cache.borrow_mut().insert(hash, validator.clone());
+                    validator
+                }
+            };
+
+            let errors = violations(&validator, &output_value(value, true)?);
+            let mut ret = LuaMultiValue::new();
+            ret.push_back(LuaValue::Boolean(errors.is_empty()));
+            if !errors.is_empty() {
+                let errors = errors
+                    .into_iter()
+                    .map(|(pointer, message)| json!({ "pointer": pointer, "message": message }))
+                    .collect();
+                ret.push_back(json_to_table(lua, &Value::Array(errors))?);
+            }
+            Ok(ret)
+        })?,
+    )
+}
@@ -0,0 +1,56 @@
+use mlua::{Error as LuaError, Lua, Value as LuaValue};
+use serde_json::Value;
+
+use super::Runner;
+use crate::error::{Error, ErrorContext, Result};
+use crate::value::{json_to_table, lua_to_json_strict};
+
+impl Runner {
+    /// Snapshots the globals named in
+    /// [`RunOptions::persist_globals`](super::RunOptions::persist_globals) as
+    /// a JSON object keyed by global name, ready to be handed to a later run
+    /// through `restore_globals`. Returns `None` when no globals are
+    /// persisted.
+    ///
+    /// Fails with [`Error::Conversion`] when a global holds something JSON
+    /// cannot represent, like a function, with the path starting at the
+    /// global's name.
+    pub fn snapshot_globals(&self) -> Result<Option<Value>> {
+        if self.persist_globals.is_empty() {
+            return Ok(None);
+        }
+        let globals = self.lua.globals();
+        let mut state = serde_json::Map::new();
+        for name in &self.persist_globals {
+            let value = globals
+                .get::<LuaValue>(name.as_str())
+                .and_then(|val| lua_to_json_strict(val, name))
+                .map_err(|err| {
+                    let ctx = ErrorContext {
+                        script: &self.script_name,
+                        document: None,
+                        memory_limit: self.max_lua_memory,
+                    };
+                    Error::from_lua(err, &ctx)
+                })?;
+            state.insert(name.clone(), value);
+        }
+        Ok(Some(Value::Object(state)))
+    }
+}
+
+/// Sets a global for every entry of a snapshot taken by
+/// [`Runner::snapshot_globals`]. The values are plain Lua tables rather than
+/// document handles, since they are meant to outlive every batch.
+pub(super) fn restore_globals(lua: &Lua, state: &Value) -> mlua::Result<()> {
+    let Value::Object(state) = state else {
+        return Err(LuaError::runtime(
+            "globals to restore must be a JSON object keyed by global name",
+        ));
+    };
+    let globals = lua.globals();
+    for (name, value) in state {
+        globals.set(name.as_str(), json_to_table(lua, value)?)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,369 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use futures::future::LocalBoxFuture;
+use futures::sink::Drain;
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use mlua::{
+    Error as LuaError, MultiValue as LuaMultiValue, Result as LuaResult, Value as LuaValue,
+};
+use serde_json::Value;
+
+use super::{Batch, DEFAULT_CHANNEL, RunOptions, Runner, emit_values, output_value};
+use crate::error::{Error, Result};
+use crate::value::json_to_lua;
+
+/// Releases the stream and sink captured by the async globals, and closes the
+/// batch, even when the future driving the script is dropped halfway.
+struct BatchGuard<S, K> {
+    batch: Rc<Batch>,
+    input: Rc<RefCell<Option<S>>>,
+    sink: Rc<RefCell<Option<K>>>,
+}
+
+impl<S, K> Drop for BatchGuard<S, K> {
+    fn drop(&mut self) {
+        self.input.borrow_mut().take();
+        self.sink.borrow_mut().take();
+        self.batch.end();
+    }
+}
+
+impl Runner {
+    /// Like [`Runner::run_batch`], but `get_next` awaits the next item of
+    /// `input`, so documents can arrive while the script runs.
+    pub async fn run_batch_async<S>(&mut self, input: S) -> Result<Vec<Value>>
+    where
+        S: Stream<Item = Value> + Unpin + 'static,
+    {
+        self.drive(input, None::<Drain<Value>>).await
+    }
+
+    /// Like [`Runner::run_batch_async`], but every emitted document is sent
+    /// into `sink` as soon as the script emits it, whether through `emit`,
+    /// `emit_to`, `emit_kv` or `emit_many`, without its channel or key. A
+    /// failing sink fails the run with [`Error::Sink`].
+    pub async fn run_batch_async_into<S, K>(&mut self, input: S, sink: K) -> Result<()>
+    where
+        S: Stream<Item = Value> + Unpin + 'static,
+        K: Sink<Value> + Unpin + 'static,
+        K::Error: std::error::Error + Send + Sync + 'static,
+    {
+        self.drive(input, Some(sink)).await.map(|_| ())
+    }
+
+    async fn drive<S, K>(&mut self, input: S, sink: Option<K>) -> Result<Vec<Value>>
+    where
+        S: Stream<Item = Value> + Unpin + 'static,
+        K: Sink<Value> + Unpin + 'static,
+        K::Error: std::error::Error + Send + Sync + 'static,
+    {
+        let has_sink = sink.is_some();
+        let guard = BatchGuard {
+            batch: self.batch.clone(),
+            input: Rc::new(RefCell::new(Some(input))),
+            sink: Rc::new(RefCell::new(sink)),
+        };
+
+        self.batch.begin(None);
+        let result = self.install_async_globals(&guard, has_sink);
+        let result = match result {
+            Ok(()) => self.chunk.call_async::<()>(()).await,
+            Err(e) => Err(e),
+        };
+        let sink = guard.sink.borrow_mut().take();
+        let closed = match sink {
+            Some(mut sink) if result.is_ok() => sink.close().await.map_err(|err| Error::Sink {
+                channel: None,
+                source: Box::new(sink_error(err)),
+            }),
+            _ => Ok(()),
+        };
+
+        let output = self.batch.end();
+        drop(guard);
+        result.map_err(|err| self.convert_error(err))?;
+        closed?;
+        Ok(output)
+    }
+
+    fn install_async_globals<S, K>(&self, guard: &BatchGuard<S, K>, has_sink: bool) -> LuaResult<()>
+    where
+        S: Stream<Item = Value> + Unpin + 'static,
+        K: Sink<Value> + Unpin + 'static,
+        K::Error: std::error::Error + Send + Sync + 'static,
+    {
+        self.install_globals()?;
+        self.install_async_input(guard.input.clone())?;
+        let lua = &self.lua;
+
+        if !has_sink {
+            return Ok(());
+        }
+
+        for (name, clone) in [("emit_clone", true), ("emit", false)] {
+            let sink = guard.sink.clone();
+            let batch = self.batch.clone();
+            lua.globals().set(
+                name,
+                lua.create_async_function(move |lua, args: LuaMultiValue| {
+                    let sink = sink.clone();
+                    let batch = batch.clone();
+                    async move {
+                        for json_val in emit_values(args, clone)? {
+                            batch.record_emit(&lua, clone, &json_val)?;
+                            send(&sink, &batch, DEFAULT_CHANNEL, json_val).await?;
+                        }
+                        Ok(())
+                    }
+                })?,
+            )?;
+        }
+
+        let sink = guard.sink.clone();
+        let batch = self.batch.clone();
+        let push =
+            lua.create_async_function(move |lua, (channel, value): (String, LuaValue)| {
+                let sink = sink.clone();
+                let batch = batch.clone();
+                async move {
+                    let json_val = output_value(value, false)?;
+                    batch.record_emit(&lua, false, &json_val)?;
+                    send(&sink, &batch, &channel, json_val).await
+                }
+            })?;
+        self.set_emit_to(push)?;
+        self.install_emit_each()
+    }
+
+    fn install_async_input<S>(&self, input: Rc<RefCell<Option<S>>>) -> LuaResult<()>
+    where
+        S: Stream<Item = Value> + Unpin + 'static,
+    {
+        let lua = &self.lua;
+        let batch = self.batch.clone();
+        lua.globals().set(
+            "get_next",
+            lua.create_async_function(move |lua, ()| {
+                let batch = batch.clone();
+                let input = input.clone();
+                async move {
+                    // Taken out for the duration of the await so no RefCell
+                    // borrow is held across it.
+                    let mut stream = input.borrow_mut().take().ok_or_else(|| {
+                        LuaError::runtime("get_next is already waiting for input")
+                    })?;
+                    let next = stream.next().await;
+                    *input.borrow_mut() = Some(stream);
+
+                    let Some(v) = next else {
+                        return Ok((LuaValue::Nil, false));
+                    };
+                    batch.start_document(&lua, &v);
+                    Ok((json_to_lua(&lua, v, &batch.alive.borrow())?, true))
+                }
+            })?,
+        )?;
+        self.install_input_helpers(None)
+    }
+
+    /// Turns the runner into a stream of the documents the script emits over
+    /// `input`. The script runs as a coroutine that is suspended after every
+    /// emit until the consumer asks for the next item, so nothing runs ahead
+    /// of what has been consumed.
+    pub fn into_stream<S>(self, input: S) -> EmitStream
+    where
+        S: Stream<Item = Value> + Unpin + 'static,
+    {
+        let queue = Rc::new(RefCell::new(VecDeque::new()));
+        self.batch.begin(None);
+        if let Err(err) = self.install_stream_globals(input, queue.clone()) {
+            return EmitStream {
+                call: None,
+                queue,
+                error: Some(self.convert_error(err)),
+                runner: Some(self),
+            };
+        }
+
+        let chunk = self.chunk.clone();
+        EmitStream {
+            call: Some(Box::pin(async move { chunk.call_async::<()>(()).await })),
+            queue,
+            error: None,
+            runner: Some(self),
+        }
+    }
+
+    fn install_stream_globals<S>(
+        &self,
+        input: S,
+        queue: Rc<RefCell<VecDeque<Value>>>,
+    ) -> LuaResult<()>
+    where
+        S: Stream<Item = Value> + Unpin + 'static,
+    {
+        self.install_globals()?;
+        self.install_async_input(Rc::new(RefCell::new(Some(input))))?;
+        let lua = &self.lua;
+
+        for (name, clone) in [("emit_clone", true), ("emit", false)] {
+            let queue = queue.clone();
+            let batch = self.batch.clone();
+            lua.globals().set(
+                name,
+                lua.create_async_function(move |lua, args: LuaMultiValue| {
+                    let queue = queue.clone();
+                    let batch = batch.clone();
+                    async move {
+                        for value in emit_values(args, clone)? {
+                            batch.record_emit(&lua, clone, &value)?;
+                            queue.borrow_mut().push_back(value);
+                        }
+                        YieldNow(false).await;
+                        Ok(())
+                    }
+                })?,
+            )?;
+        }
+        self.install_emit_each()
+    }
+}
+
+/// Sends `value`, emitted to `channel`, into the sink, failing with
+/// [`Error::Sink`] like an [`OutputSink`](crate::OutputSink) does.
+async fn send<K>(
+    sink: &RefCell<Option<K>>,
+    batch: &Batch,
+    channel: &str,
+    value: Value,
+) -> LuaResult<()>
+where
+    K: Sink<Value> + Unpin,
+    K::Error: std::error::Error + Send + Sync + 'static,
+{
+    batch.admit(channel)?;
+    // Taken out for the duration of the await so no RefCell borrow is held
+    // across it.
+    let mut target = sink
+        .borrow_mut()
+        .take()
+        .ok_or_else(|| LuaError::runtime("output sink is closed or busy"))?;
+    let sent = target.send(value).await;
+    *sink.borrow_mut() = Some(target);
+    sent.map_err(|err| {
+        batch.raise(Error::Sink {
+            channel: Some(channel.to_string()),
+            source: Box::new(sink_error(err)),
+        })
+    })
+}
+
+fn sink_error<E: std::error::Error + Send + Sync + 'static>(err: E) -> Error {
+    Error::Io(io::Error::other(err))
+}
+
+pub async fn run_async<S>(script: &str, input: S, options: RunOptions) -> Result<Vec<Value>>
+where
+    S: Stream<Item = Value> + Unpin + 'static,
+{
+    Runner::with_options(script, options)?
+        .run_batch_async(input)
+        .await
+}
+
+pub async fn run_async_into<S, K>(
+    script: &str,
+    input: S,
+    sink: K,
+    options: RunOptions,
+) -> Result<()>
+where
+    S: Stream<Item = Value> + Unpin + 'static,
+    K: Sink<Value> + Unpin + 'static,
+    K::Error: std::error::Error + Send + Sync + 'static,
+{
+    Runner::with_options(script, options)?
+        .run_batch_async_into(input, sink)
+        .await
+}
+
+pub fn run_stream<S>(script: &str, input: S, options: RunOptions) -> EmitStream
+where
+    S: Stream<Item = Value> + Unpin + 'static,
+{
+    match Runner::with_options(script, options) {
+        Ok(runner) => runner.into_stream(input),
+        Err(err) => EmitStream {
+            call: None,
+            queue: Rc::default(),
+            error: Some(err),
+            runner: None,
+        },
+    }
+}
+
+/// Stream of emitted documents returned by [`Runner::into_stream`]. Ends after
+/// the script finishes, or right after yielding the error that stopped it.
+pub struct EmitStream {
+    call: Option<LocalBoxFuture<'static, LuaResult<()>>>,
+    queue: Rc<RefCell<VecDeque<Value>>>,
+    error: Option<Error>,
+    runner: Option<Runner>,
+}
+
+impl Stream for EmitStream {
+    type Item = Result<Value>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        // Polling resumes the script, whose emits borrow the queue.
+        let idle = this.queue.borrow().is_empty();
+        if idle
+            && let Some(call) = this.call.as_mut()
+            && let Poll::Ready(result) = call.as_mut().poll(cx)
+        {
+            this.call = None;
+            if let Some(runner) = &this.runner {
+                runner.batch.end();
+                this.error = result.err().map(|err| runner.convert_error(err));
+            }
+        }
+
+        if let Some(v) = this.queue.borrow_mut().pop_front() {
+            return Poll::Ready(Some(Ok(v)));
+        }
+        if let Some(err) = this.error.take() {
+            return Poll::Ready(Some(Err(err)));
+        }
+        if this.call.is_some() {
+            Poll::Pending
+        } else {
+            Poll::Ready(None)
+        }
+    }
+}
+
+/// Suspends the calling coroutine once, handing control back to whoever is
+/// polling the script.
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use mlua::{Error as LuaError, Lua, Result as LuaResult, String as LuaString};
+
+/// Installs the `str` global, string helpers that understand UTF-8 where
+/// Lua's own `string` library only sees bytes:
+///
+/// - `str.split(s, sep, max)` splits on every `sep`, into at most `max`
+///   pieces when given.
+/// - `str.trim(s)` strips Unicode whitespace from both ends.
+/// - `str.starts_with(s, prefix)` and `str.ends_with(s, suffix)`.
+/// - `str.lower_utf8(s)` and `str.upper_utf8(s)` apply full Unicode case
+///   mapping.
+/// - `str.len_utf8(s)` counts characters rather than bytes.
+pub(crate) fn install_str(lua: &Lua) -> LuaResult<()> {
+    let str = lua.create_table()?;
+
+    str.set(
+        "split",
+        lua.create_function(
+            |lua, (s, sep, max): (LuaString, LuaString, Option<usize>)| {
+                let (s, sep) = (s.to_str()?, sep.to_str()?);
+                if sep.is_empty() {
+                    return Err(LuaError::runtime("separator must not be empty"));
+                }
+                let pieces: Vec<&str> = match max {
+                    Some(max) => s.splitn(max, &*sep).collect(),
+                    None => s.split(&*sep).collect(),
+                };
+                lua.create_sequence_from(pieces)
+            },
+        )?,
+    )?;
+    str.set(
+        "trim",
+        lua.create_function(|lua, s: LuaString| lua.create_string(s.to_str()?.trim()))?,
+    )?;
+    str.set(
+        "starts_with",
+        lua.create_function(|_, (s, prefix): (LuaString, LuaString)| {
+            Ok(s.as_bytes().starts_with(&prefix.as_bytes()))
+        })?,
+    )?;
+    str.set(
+        "ends_with",
+        lua.create_function(|_, (s, suffix): (LuaString, LuaString)| {
+            Ok(s.as_bytes().ends_with(&suffix.as_bytes()))
+        })?,
+    )?;
+    str.set(
+        "lower_utf8",
+        lua.create_function(|lua, s: LuaString| lua.create_string(s.to_str()?.to_lowercase()))?,
+    )?;
+    str.set(
+        "upper_utf8",
+        lua.create_function(|lua, s: LuaString| lua.create_string(s.to_str()?.to_uppercase()))?,
+    )?;
+    str.set(
+        "len_utf8",
+        lua.create_function(|_, s: LuaString| Ok(s.to_str()?.chars().count()))?,
+    )?;
+
+    lua.globals().set("str", str)
+}
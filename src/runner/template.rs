@@ -0,0 +1,74 @@
+use mlua::{Error as LuaError, Lua, Result as LuaResult, String as LuaString, Value as LuaValue};
+use serde_json::Value;
+
+use super::output_value;
+
+/// Installs `fmt(template, value)`, which fills the `{path}` placeholders of
+/// `template` from `value`, a handle or a table. Paths are field names and
+/// array indices, counted from 1 as in Lua, joined by dots, like
+/// `{items.1.name}`.
+/// `{path|default}` falls back to `default` when nothing is at `path`, and
+/// `{{` and `}}` stand for literal braces.
+///
+/// Strings are inserted as they are, anything else as compact JSON, just
+/// like `tostring` shows documents.
+pub(crate) fn install_fmt(lua: &Lua) -> LuaResult<()> {
+    lua.globals().set(
+        "fmt",
+        lua.create_function(|_, (template, value): (LuaString, LuaValue)| {
+            render(&template.to_str()?, &output_value(value, true)?)
+        })?,
+    )
+}
+
+fn render(template: &str, value: &Value) -> LuaResult<String> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        if rest[start..].starts_with("{{") {
+            out.push('{');
+            rest = &after[1..];
+            continue;
+        }
+        if rest[start..].starts_with("}}") {
+            out.push('}');
+            rest = &after[1..];
+            continue;
+        }
+        if rest[start..].starts_with('}') {
+            return Err(LuaError::runtime("unmatched '}' in template"));
+        }
+        let end = after
+            .find('}')
+            .ok_or_else(|| LuaError::runtime("unterminated placeholder in template"))?;
+        let placeholder = &after[..end];
+        let (path, default) = match placeholder.split_once('|') {
+            Some((path, default)) => (path, Some(default)),
+            None => (placeholder, None),
+        };
+        match lookup(value, path.trim()) {
+            Some(Value::String(s)) => out.push_str(s),
+            Some(found) => out.push_str(&found.to_string()),
+            None => match default {
+                Some(default) => out.push_str(default),
+                None => {
+                    return Err(LuaError::runtime(format!(
+                        "nothing at '{{{placeholder}}}' in template"
+                    )));
+                }
+            },
+        }
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(value, |node, segment| match node {
+        Value::Array(arr) => arr.get(segment.parse::<usize>().ok()?.checked_sub(1)?),
+        node => node.get(segment),
+    })
+}
@@ -0,0 +1,140 @@
+use std::rc::Rc;
+
+use chrono::format::StrftimeItems;
+use chrono::{
+    DateTime, Days, FixedOffset, Months, NaiveDate, NaiveDateTime, NaiveTime, SecondsFormat,
+    TimeDelta, Utc,
+};
+use mlua::{Error as LuaError, Lua, Result as LuaResult, String as LuaString};
+
+use crate::determinism::ClockSource;
+
+/// Installs the `time` global, for timestamps as epoch milliseconds:
+///
+/// - `time.parse(s, fmt)` reads RFC 3339, or the strftime format `fmt`; a
+///   format without an offset is taken as UTC, and one without a time of
+///   day as midnight.
+/// - `time.format(ms, fmt, tz)` writes RFC 3339, or `fmt`, in UTC or in the
+///   fixed offset `tz`, like `+02:00`.
+/// - `time.now_ms()` reads [`RunOptions::clock`](super::RunOptions::clock)
+///   when set, the system clock otherwise.
+/// - `time.add(ms, amount, unit)` adds `amount` of `ms`, `s`, `m`, `h`, `d`,
+///   `w`, `months` or `years`.
+pub(crate) fn install_time(lua: &Lua, clock: Option<Rc<ClockSource>>) -> LuaResult<()> {
+    let time = lua.create_table()?;
+
+    time.set(
+        "parse",
+        lua.create_function(|_, (s, fmt): (LuaString, Option<LuaString>)| {
+            let s = s.to_str()?;
+            let parsed = match &fmt {
+                None => DateTime::parse_from_rfc3339(&s).map(|t| t.timestamp_millis()),
+                Some(fmt) => {
+                    let fmt = fmt.to_str()?;
+                    DateTime::parse_from_str(&s, &fmt)
+                        .map(|t| t.timestamp_millis())
+                        .or_else(|_| {
+                            NaiveDateTime::parse_from_str(&s, &fmt)
+                                .map(|t| t.and_utc().timestamp_millis())
+                        })
+                        .or_else(|err| {
+                            NaiveDate::parse_from_str(&s, &fmt)
+                                .map(|d| d.and_time(NaiveTime::MIN).and_utc().timestamp_millis())
+                                .map_err(|_| err)
+                        })
+                }
+            };
+            parsed.map_err(|err| LuaError::runtime(format!("cannot parse time '{}': {err}", &*s)))
+        })?,
+    )?;
+
+    time.set(
+        "format",
+        lua.create_function(
+            |_, (ms, fmt, tz): (i64, Option<LuaString>, Option<LuaString>)| {
+                let offset = match &tz {
+                    Some(tz) => parse_offset(&tz.to_str()?)?,
+                    None => FixedOffset::east_opt(0).expect("zero offset is valid"),
+                };
+                let t = DateTime::<Utc>::from_timestamp_millis(ms)
+                    .ok_or_else(|| LuaError::runtime(format!("timestamp {ms} is out of range")))?
+                    .with_timezone(&offset);
+                let Some(fmt) = fmt else {
+                    return Ok(t.to_rfc3339_opts(SecondsFormat::Millis, true));
+                };
+                let fmt = fmt.to_str()?;
+                // Checked up front, since formatting panics on a bad format.
+                let items = StrftimeItems::new(&fmt).parse().map_err(|err| {
+                    LuaError::runtime(format!("invalid time format '{}': {err}", &*fmt))
+                })?;
+                Ok(t.format_with_items(items.iter()).to_string())
+            },
+        )?,
+    )?;
+
+    time.set(
+        "now_ms",
+        lua.create_function(move |_, ()| {
+            Ok(match &clock {
+                Some(clock) => (clock.now() * 1000.0) as i64,
+                None => Utc::now().timestamp_millis(),
+            })
+        })?,
+    )?;
+
+    time.set(
+        "add",
+        lua.create_function(|_, (ms, amount, unit): (i64, i64, LuaString)| {
+            let unit = unit.to_str()?;
+            let t = DateTime::<Utc>::from_timestamp_millis(ms)
+                .ok_or_else(|| LuaError::runtime(format!("timestamp {ms} is out of range")))?;
+            let added = match &*unit {
+                "ms" => TimeDelta::try_milliseconds(amount).and_then(|d| t.checked_add_signed(d)),
+                "s" => TimeDelta::try_seconds(amount).and_then(|d| t.checked_add_signed(d)),
+                "m" => TimeDelta::try_minutes(amount).and_then(|d| t.checked_add_signed(d)),
+                "h" => TimeDelta::try_hours(amount).and_then(|d| t.checked_add_signed(d)),
+                "d" => add_days(t, amount, 1),
+                "w" => add_days(t, amount, 7),
+                "months" => add_months(t, amount, 1),
+                "years" => add_months(t, amount, 12),
+                other => return Err(LuaError::runtime(format!("unknown time unit '{other}'"))),
+            };
+            added
+                .map(|t| t.timestamp_millis())
+                .ok_or_else(|| LuaError::runtime("resulting time is out of range"))
+        })?,
+    )?;
+
+    lua.globals().set("time", time)
+}
+
+/// Reads `Z`, `UTC` or an offset like `+02:00`.
+fn parse_offset(tz: &str) -> LuaResult<FixedOffset> {
+    if tz == "Z" || tz.eq_ignore_ascii_case("utc") {
+        return Ok(FixedOffset::east_opt(0).expect("zero offset is valid"));
+    }
+    tz.parse()
+        .map_err(|err| LuaError::runtime(format!("invalid time zone '{tz}': {err}")))
+}
+
+fn add_days(t: DateTime<Utc>, amount: i64, per: u64) -> Option<DateTime<Utc>> {
+    let days = Days::new(amount.unsigned_abs().checked_mul(per)?);
+    if amount < 0 {
+        t.checked_sub_days(days)
+    } else {
+        t.checked_add_days(days)
+    }
+}
+
+fn add_months(t: DateTime<Utc>, amount: i64, per: u32) -> Option<DateTime<Utc>> {
+    let months = Months::new(
+        u32::try_from(amount.unsigned_abs())
+            .ok()?
+            .checked_mul(per)?,
+    );
+    if amount < 0 {
+        t.checked_sub_months(months)
+    } else {
+        t.checked_add_months(months)
+    }
+}
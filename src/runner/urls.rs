@@ -0,0 +1,196 @@
+use mlua::{
+    Error as LuaError, Lua, MultiValue as LuaMultiValue, Result as LuaResult, String as LuaString,
+    Value as LuaValue,
+};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, percent_decode, utf8_percent_encode};
+use serde_json::{Map, Value};
+use url::{ParseError, Url};
+
+use super::output_value;
+use crate::value::json_to_table;
+
+/// Characters `url.encode` leaves alone, as JavaScript's `encodeURIComponent`
+/// does.
+const COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// Stands in as the base of relative URLs, whose scheme and host are then
+/// left out.
+const RELATIVE_BASE: &str = "relative:///";
+
+/// Installs the `url` global:
+///
+/// - `url.parse(s)` returns `{scheme, username, password, host, port, path,
+///   query, fragment}`, with the query decoded into an object whose repeated
+///   keys hold arrays. Relative URLs have no scheme, host or port. Returns
+///   nil and an error message for URLs that do not parse, and for text that
+///   is not a relative reference either, rather than raising.
+/// - `url.build(t)` puts such a table back together.
+/// - `url.encode(s)` and `url.decode(s)` percent-encode and decode.
+pub(crate) fn install_url(lua: &Lua) -> LuaResult<()> {
+    let url = lua.create_table()?;
+
+    url.set(
+        "parse",
+        lua.create_function(|lua, s: LuaString| {
+            let mut ret = LuaMultiValue::new();
+            match parse(&s.to_str()?) {
+                Ok(parsed) => ret.push_back(json_to_table(lua, &parsed)?),
+                Err(err) => {
+                    ret.push_back(LuaValue::Nil);
+                    ret.push_back(LuaValue::String(lua.create_string(err)?));
+                }
+            }
+            Ok(ret)
+        })?,
+    )?;
+    url.set(
+        "build",
+        lua.create_function(|_, t: LuaValue| match output_value(t, true)? {
+            Value::Object(parts) => Ok(build(&parts)),
+            _ => Err(LuaError::runtime("url.build expects a table")),
+        })?,
+    )?;
+    url.set(
+        "encode",
+        lua.create_function(|_, s: LuaString| {
+            Ok(utf8_percent_encode(&s.to_str()?, COMPONENT).to_string())
+        })?,
+    )?;
+    url.set(
+        "decode",
+        lua.create_function(|lua, s: LuaString| {
+            let decoded: Vec<u8> = percent_decode(&s.as_bytes()).collect();
+            lua.create_string(decoded)
+        })?,
+    )?;
+
+    lua.globals().set("url", url)
+}
+
+fn parse(s: &str) -> Result<Value, String> {
+    let (url, relative) = match Url::parse(s) {
+        Ok(url) => (url, false),
+        Err(ParseError::RelativeUrlWithoutBase) if is_relative_reference(s) => {
+            let base = Url::parse(RELATIVE_BASE).expect("base URL is valid");
+            (base.join(s).map_err(|err| err.to_string())?, true)
+        }
+        Err(ParseError::RelativeUrlWithoutBase) => {
+            return Err(format!("'{s}' is neither a URL nor a relative reference"));
+        }
+        Err(err) => return Err(err.to_string()),
+    };
+
+    let mut query = Map::new();
+    for (key, value) in url.query_pairs() {
+        let value = Value::String(value.into_owned());
+        match query.get_mut(key.as_ref()) {
+            None => {
+                query.insert(key.into_owned(), value);
+            }
+            Some(Value::Array(values)) => values.push(value),
+            Some(first) => *first = Value::Array(vec![first.take(), value]),
+        }
+    }
+
+    let mut parts = Map::new();
+    if !relative {
+        parts.insert("scheme".into(), url.scheme().into());
+        if !url.username().is_empty() {
+            parts.insert("username".into(), url.username().into());
+        }
+        if let Some(password) = url.password() {
+            parts.insert("password".into(), password.into());
+        }
+        if let Some(host) = url.host_str() {
+            parts.insert("host".into(), host.into());
+        }
+        if let Some(port) = url.port() {
+            parts.insert("port".into(), port.into());
+        }
+    }
+    parts.insert("path".into(), url.path().into());
+    if url.query().is_some() {
+        parts.insert("query".into(), Value::Object(query));
+    }
+    if let Some(fragment) = url.fragment() {
+        parts.insert("fragment".into(), fragment.into());
+    }
+    Ok(Value::Object(parts))
+}
+
+/// Whether `s` is made of what RFC 3986 allows in a URI reference, with
+/// every `%` starting an escape, and does not start like a scheme.
+fn is_relative_reference(s: &str) -> bool {
+    let bytes = s.as_bytes();
+    let allowed = |b: u8| b.is_ascii_alphanumeric() || b"-._~:/?#[]@!$&'()*+,;=%".contains(&b);
+    let escapes_valid = bytes.iter().enumerate().all(|(i, &b)| {
+        b != b'%'
+            || bytes
+                .get(i + 1..i + 3)
+                .is_some_and(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+    });
+    let first_segment = s.split(['/', '?', '#']).next().unwrap_or_default();
+    !s.is_empty()
+        && bytes.iter().all(|&b| allowed(b))
+        && escapes_valid
+        && !first_segment.contains(':')
+}
+
+fn build(parts: &Map<String, Value>) -> String {
+    let text = |key: &str| match parts.get(key) {
+        Some(Value::String(s)) => Some(s.clone()),
+        Some(Value::Null) | None => None,
+        Some(other) => Some(other.to_string()),
+    };
+
+    let mut url = String::new();
+    if let Some(scheme) = text("scheme") {
+        url.push_str(&scheme);
+        url.push(':');
+    }
+    if let Some(host) = text("host") {
+        url.push_str("//");
+        if let Some(username) = text("username") {
+            url.push_str(&username);
+            if let Some(password) = text("password") {
+                url.push(':');
+                url.push_str(&password);
+            }
+            url.push('@');
+        }
+        url.push_str(&host);
+        if let Some(port) = text("port") {
+            url.push(':');
+            url.push_str(&port);
+        }
+    }
+    if let Some(path) = text("path") {
+        url.push_str(&path);
+    }
+    if let Some(Value::Object(query)) = parts.get("query") {
+        let mut pairs = form_urlencoded::Serializer::new(String::new());
+        for (key, value) in query {
+            let values = match value {
+                Value::Array(values) => values.as_slice(),
+                value => std::slice::from_ref(value),
+            };
+            for value in values {
+                match value {
+                    Value::String(s) => pairs.append_pair(key, s),
+                    other => pairs.append_pair(key, &other.to_string()),
+                };
+            }
+        }
+        url.push('?');
+        url.push_str(&pairs.finish());
+    }
+    if let Some(fragment) = text("fragment") {
+        url.push('#');
+        url.push_str(&fragment);
+    }
+    url
+}
@@ -0,0 +1,121 @@
+-- Helpers preloaded as the `util` global. They go through `pairs`, so they
+-- work the same on plain tables and on document handles.
+
+local pairs, ipairs, type, select, setmetatable = pairs, ipairs, type, select, setmetatable
+local find, sub = string.find, string.sub
+
+local util = {}
+
+-- Arrays are walked in order, everything else in `pairs` order.
+local function iterate(t)
+    if type(t) == "table" and #t > 0 then
+        return ipairs(t)
+    end
+    return pairs(t)
+end
+
+function util.map(t, f)
+    local result = {}
+    for k, v in iterate(t) do
+        result[k] = f(v, k)
+    end
+    return result
+end
+
+-- Array elements that pass are packed into a new array; other keys are kept.
+function util.filter(t, pred)
+    local result = {}
+    for k, v in iterate(t) do
+        if pred(v, k) then
+            if type(k) == "number" then
+                result[#result + 1] = v
+            else
+                result[k] = v
+            end
+        end
+    end
+    return result
+end
+
+function util.reduce(t, f, acc)
+    for k, v in iterate(t) do
+        acc = f(acc, v, k)
+    end
+    return acc
+end
+
+function util.keys(t)
+    local result = {}
+    for k in iterate(t) do
+        result[#result + 1] = k
+    end
+    return result
+end
+
+function util.values(t)
+    local result = {}
+    for _, v in iterate(t) do
+        result[#result + 1] = v
+    end
+    return result
+end
+
+function util.contains(t, value)
+    for _, v in iterate(t) do
+        if v == value then
+            return true
+        end
+    end
+    return false
+end
+
+-- Shallow merge into a new table; later arguments win.
+function util.merge(...)
+    local result = {}
+    for i = 1, select("#", ...) do
+        local t = select(i, ...)
+        if t ~= nil then
+            for k, v in pairs(t) do
+                result[k] = v
+            end
+        end
+    end
+    return result
+end
+
+-- Copies into plain tables all the way down, detaching handles from their
+-- documents.
+function util.deep_copy(value)
+    local kind = type(value)
+    if kind ~= "table" and kind ~= "userdata" then
+        return value
+    end
+    local result = {}
+    for k, v in pairs(value) do
+        result[k] = util.deep_copy(v)
+    end
+    return result
+end
+
+-- Splits on a plain separator, or on runs of whitespace when none is given.
+function util.split(s, sep)
+    local result = {}
+    if sep == nil then
+        for word in s:gmatch("%S+") do
+            result[#result + 1] = word
+        end
+        return result
+    end
+    local start = 1
+    while true do
+        local i, j = find(s, sep, start, true)
+        if i == nil then
+            result[#result + 1] = sub(s, start)
+            return result
+        end
+        result[#result + 1] = sub(s, start, i - 1)
+        start = j + 1
+    end
+end
+
+return setmetatable(util, { __metatable = false })
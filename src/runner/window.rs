@@ -0,0 +1,108 @@
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use mlua::{Error as LuaError, Lua, Result as LuaResult, Value as LuaValue};
+use serde_json::Value;
+
+use super::Batch;
+use crate::value::json_to_lua;
+
+/// Copies of the documents most recently handed to the script, kept once the
+/// script asks for them with `window(n)`.
+#[derive(Default)]
+pub(super) struct Window {
+    size: usize,
+    /// The documents before the current one, most recent first.
+    history: VecDeque<Value>,
+    current: Option<Value>,
+    /// A document fetched by `peek`, which only becomes current once
+    /// `get_next` hands it over.
+    peeked: Option<Value>,
+}
+
+impl Window {
+    pub(super) fn reset(&mut self) {
+        *self = Window::default();
+    }
+
+    /// Remembers `doc` as the current document, or as the peeked one.
+    pub(super) fn read(&mut self, doc: &Value, peeking: bool) {
+        if self.size == 0 {
+            return;
+        }
+        if peeking {
+            self.peeked = Some(doc.clone());
+        } else {
+            self.advance(doc.clone());
+        }
+    }
+
+    /// Makes the peeked document the current one.
+    pub(super) fn start_peeked(&mut self) {
+        if let Some(doc) = self.peeked.take() {
+            self.advance(doc);
+        }
+    }
+
+    fn advance(&mut self, doc: Value) {
+        if let Some(previous) = self.current.replace(doc) {
+            self.history.push_front(previous);
+            self.history.truncate(self.size);
+        }
+    }
+
+    fn resize(&mut self, size: usize) {
+        self.size = size;
+        self.history.truncate(size);
+        if size == 0 {
+            self.current = None;
+            self.peeked = None;
+        }
+    }
+
+    /// The documents before the current one, most recent first, or `None`
+    /// when the script has no window.
+    pub(super) fn snapshot(&self) -> Option<Value> {
+        (self.size > 0).then(|| Value::Array(self.history.iter().cloned().collect()))
+    }
+}
+
+/// Installs `window(n)`, which keeps copies of the last `n` documents before
+/// the current one, and `prev(k)`, which returns a copy of the `k`th of them,
+/// 1 being the previous document, or nil when fewer have been read. The
+/// copies are taken as documents are read, so changing or emitting the
+/// current document leaves them as they were, and every `prev` call returns
+/// a fresh handle.
+///
+/// Documents read before `window` is first called are not remembered, and
+/// the window starts empty with every run.
+pub(super) fn install_window(lua: &Lua, batch: Rc<Batch>) -> LuaResult<()> {
+    let globals = lua.globals();
+
+    let state = batch.clone();
+    globals.set(
+        "window",
+        lua.create_function(move |_, size: usize| {
+            state.window.borrow_mut().resize(size);
+            Ok(())
+        })?,
+    )?;
+
+    globals.set(
+        "prev",
+        lua.create_function(move |lua, k: Option<usize>| {
+            let k = k.unwrap_or(1);
+            let window = batch.window.borrow();
+            if k == 0 || k > window.size {
+                return Err(LuaError::runtime(format!(
+                    "prev({k}) is outside the window of {} documents",
+                    window.size
+                )));
+            }
+            match window.history.get(k - 1) {
+                Some(doc) => json_to_lua(lua, doc.clone(), &batch.alive.borrow()),
+                None => Ok(LuaValue::Nil),
+            }
+        })?,
+    )
+}
@@ -0,0 +1,31 @@
+use std::rc::Rc;
+
+use mlua::{Error as LuaError, Lua, Result as LuaResult, String as LuaString, Table as LuaTable};
+
+use super::Batch;
+use crate::value::json_to_lua;
+use crate::xml::{XmlOptions, decode};
+
+/// Installs the `xml` global: `xml.decode(text, opts)` reads XML carried
+/// inside a document the way `--input-format xml` reads files, as
+/// `{ root = ... }`, or, with `opts.record` naming an element, as an array
+/// of every such element. `opts.keep_namespaces` keeps namespace prefixes.
+pub(crate) fn install_xml(lua: &Lua, batch: Rc<Batch>) -> LuaResult<()> {
+    let xml = lua.create_table()?;
+    xml.set(
+        "decode",
+        lua.create_function(move |lua, (text, opts): (LuaString, Option<LuaTable>)| {
+            let mut options = XmlOptions::default();
+            if let Some(opts) = opts {
+                options.record = opts.get("record")?;
+                options.keep_namespaces = opts
+                    .get::<Option<bool>>("keep_namespaces")?
+                    .unwrap_or(false);
+            }
+            let value = decode(&text.to_str()?, options)
+                .map_err(|err| LuaError::runtime(err.to_string()))?;
+            json_to_lua(lua, value, &batch.alive.borrow())
+        })?,
+    )?;
+    lua.globals().set("xml", xml)
+}
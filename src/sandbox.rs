@@ -0,0 +1,102 @@
+use std::fmt;
+use std::str::FromStr;
+
+use mlua::{Lua, LuaOptions, Result, StdLib};
+
+/// Replaces `load` and `loadstring` with versions refusing binary chunks,
+/// whatever mode the caller asks for.
+const TEXT_ONLY_LOAD: &str = r#"
+local load = load
+_G.load = function(chunk, name, _, env)
+    return load(chunk, name, "t", env)
+end
+_G.loadstring = function(source, name)
+    return load(source, name, "t")
+end
+"#;
+
+/// Which parts of the Lua standard library a script gets to see.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Sandbox {
+    /// Everything `Lua::new()` provides, including `io` and `os`.
+    #[default]
+    Full,
+    /// No `io`, `debug`, `package`/`require`, `dofile` or `loadfile`, and
+    /// `os` reduced to `time`, `clock` and `date`. `load` and `loadstring`
+    /// only take source text.
+    Standard,
+    /// Only `string`, `table`, `math` and `bit` on top of the base library,
+    /// minus `dofile` and `loadfile`, and with `load` and `loadstring`
+    /// taking source text only.
+    Pure,
+}
+
+impl Sandbox {
+    /// The `jit` library is loaded even where the profile hides it, since the
+    /// instruction hook needs it to switch the JIT off; [`Sandbox::restrict`]
+    /// removes it afterwards.
+    pub(crate) fn create_lua(self) -> Result<Lua> {
+        let libs = match self {
+            Sandbox::Full => return Ok(Lua::new()),
+            Sandbox::Standard => {
+                StdLib::TABLE
+                    | StdLib::STRING
+                    | StdLib::MATH
+                    | StdLib::BIT
+                    | StdLib::OS
+                    | StdLib::JIT
+            }
+            Sandbox::Pure => {
+                StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::BIT | StdLib::JIT
+            }
+        };
+        Lua::new_with(libs, LuaOptions::default())
+    }
+
+    /// Strips the globals the profile forbids. Runs after our own setup code,
+    /// which may rely on them, and before the user chunk is loaded.
+    ///
+    /// `load` and `loadstring` are pinned to text mode: LuaJIT does not verify
+    /// bytecode, so loading a crafted chunk could escape any sandbox.
+    pub(crate) fn restrict(self, lua: &Lua) -> Result<()> {
+        let script = match self {
+            Sandbox::Full => return Ok(()),
+            Sandbox::Standard => {
+                r#"
+                os = { time = os.time, clock = os.clock, date = os.date }
+                dofile, loadfile, jit = nil, nil, nil
+                "#
+            }
+            Sandbox::Pure => "dofile, loadfile, jit = nil, nil, nil",
+        };
+        lua.load(script).set_name("=[mlua_play sandbox]").exec()?;
+        lua.load(TEXT_ONLY_LOAD)
+            .set_name("=[mlua_play sandbox]")
+            .exec()
+    }
+}
+
+impl fmt::Display for Sandbox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Sandbox::Full => "full",
+            Sandbox::Standard => "standard",
+            Sandbox::Pure => "pure",
+        })
+    }
+}
+
+impl FromStr for Sandbox {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(Sandbox::Full),
+            "standard" => Ok(Sandbox::Standard),
+            "pure" => Ok(Sandbox::Pure),
+            _ => Err(format!(
+                "unknown sandbox profile '{s}' (expected full, standard or pure)"
+            )),
+        }
+    }
+}
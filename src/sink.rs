@@ -0,0 +1,188 @@
+use std::io::{self, Write};
+use std::sync::mpsc::Sender;
+
+use serde_json::Value;
+
+use crate::error::{Error, Result};
+
+/// Where emitted documents go when set as [`RunOptions::sink`](crate::RunOptions::sink),
+/// instead of being collected until the run ends.
+///
+/// Every document arrives with the channel it was emitted to, `out` for plain
+/// `emit`. The built-in sinks put all channels into the same stream.
+pub trait OutputSink {
+    /// Takes a document as soon as the script emits it. Failing aborts the
+    /// script with [`Error::Sink`].
+    fn emit(&mut self, channel: &str, value: Value) -> Result<()>;
+
+    /// Takes a document emitted with `emit_kv`, along with its key. Sinks
+    /// without a notion of keys get just the document by default.
+    fn emit_kv(&mut self, channel: &str, _key: Value, value: Value) -> Result<()> {
+        self.emit(channel, value)
+    }
+
+    /// Called once a run completes successfully, e.g. to flush buffers.
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<S: OutputSink + ?Sized> OutputSink for Box<S> {
+    fn emit(&mut self, channel: &str, value: Value) -> Result<()> {
+        (**self).emit(channel, value)
+    }
+
+    fn emit_kv(&mut self, channel: &str, key: Value, value: Value) -> Result<()> {
+        (**self).emit_kv(channel, key, value)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        (**self).finish()
+    }
+}
+
+impl OutputSink for Vec<Value> {
+    fn emit(&mut self, _channel: &str, value: Value) -> Result<()> {
+        self.push(value);
+        Ok(())
+    }
+}
+
+/// Fails once the receiving end is dropped.
+impl OutputSink for Sender<Value> {
+    fn emit(&mut self, _channel: &str, value: Value) -> Result<()> {
+        self.send(value).map_err(|_| {
+            Error::Io(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "output receiver was dropped",
+            ))
+        })
+    }
+}
+
+/// How [`JsonLinesSink::raw`] writes documents, like `jq -r`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RawOutput {
+    /// Strings as their text, unquoted and unescaped, and anything else as
+    /// JSON.
+    #[default]
+    Strings,
+    /// Strings as their text, failing on anything else.
+    StringsOnly,
+}
+
+/// Writes every document as a line of compact JSON, or pretty-printed when
+/// asked to. Documents emitted with `emit_kv` are written as
+/// `{"key": ..., "value": ...}`.
+pub struct JsonLinesSink<W: Write> {
+    writer: W,
+    line_buffered: bool,
+    /// What goes between pretty-printed documents, when pretty-printing.
+    pretty: Option<String>,
+    /// What ends every document.
+    record_separator: Vec<u8>,
+    raw: Option<RawOutput>,
+    written: bool,
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            line_buffered: false,
+            pretty: None,
+            record_separator: b"\n".to_vec(),
+            raw: None,
+            written: false,
+        }
+    }
+
+    /// Ends every document with `separator`, such as `b"\0"`, rather than a
+    /// newline, for readers splitting records at it; with
+    /// [`JsonLinesSink::pretty`], this alone is what keeps documents spanning
+    /// lines apart.
+    pub fn record_separator(mut self, separator: impl Into<Vec<u8>>) -> Self {
+        self.record_separator = separator.into();
+        self
+    }
+
+    /// Pretty-prints every document over as many lines as it takes, with
+    /// `separator` between documents, e.g. `"\n"` for a blank line, so the
+    /// output can still be split back into documents. `separator` is left
+    /// out when documents end in a [`JsonLinesSink::record_separator`] other
+    /// than a newline.
+    pub fn pretty(mut self, separator: impl Into<String>) -> Self {
+        self.pretty = Some(separator.into());
+        self
+    }
+
+    /// Writes documents that are strings as their text rather than as JSON,
+    /// for handing lines to tools that know nothing of JSON.
+    pub fn raw(mut self, raw: RawOutput) -> Self {
+        self.raw = Some(raw);
+        self
+    }
+
+    /// Flushes the writer after every document, so that a buffered writer
+    /// still hands each one over as soon as it is emitted.
+    pub fn line_buffered(mut self) -> Self {
+        self.line_buffered = true;
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> OutputSink for JsonLinesSink<W> {
+    fn emit(&mut self, _channel: &str, value: Value) -> Result<()> {
+        let text = match (&value, self.raw) {
+            (Value::String(text), Some(_)) => Some(text),
+            (_, Some(RawOutput::StringsOnly)) => {
+                let kind = match value {
+                    Value::Null => "null",
+                    Value::Bool(_) => "a boolean",
+                    Value::Number(_) => "a number",
+                    Value::Array(_) => "an array",
+                    Value::Object(_) => "an object",
+                    Value::String(_) => unreachable!("strings are written"),
+                };
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("raw output takes only strings, not {kind}"),
+                )));
+            }
+            _ => None,
+        };
+        if let (Some(separator), true) = (&self.pretty, self.written)
+            && self.record_separator == b"\n"
+        {
+            self.writer.write_all(separator.as_bytes())?;
+        }
+        match (text, &self.pretty) {
+            (Some(text), _) => self.writer.write_all(text.as_bytes()),
+            (None, Some(_)) => {
+                serde_json::to_writer_pretty(&mut self.writer, &value).map_err(io::Error::from)
+            }
+            (None, None) => {
+                serde_json::to_writer(&mut self.writer, &value).map_err(io::Error::from)
+            }
+        }?;
+        self.writer.write_all(&self.record_separator)?;
+        self.written = true;
+        if self.line_buffered {
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn emit_kv(&mut self, channel: &str, key: Value, value: Value) -> Result<()> {
+        self.emit(channel, serde_json::json!({ "key": key, "value": value }))
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
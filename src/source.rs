@@ -0,0 +1,350 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::mpsc::Receiver;
+
+use serde_json::Value;
+
+use crate::compression::Compression;
+use crate::error::Result;
+use crate::json_dialect::JsonDialect;
+
+/// Where [`Runner::run_source`](crate::Runner::run_source) reads documents
+/// from, pulling the next one whenever the script calls `get_next`.
+///
+/// Failing raises [`Error::InputError`](crate::Error::InputError) into the script, just like the input
+/// of any other run.
+pub trait InputSource {
+    /// The next document, or `None` once the input is exhausted.
+    fn next_doc(&mut self) -> Result<Option<Value>>;
+
+    /// Where the document last returned by `next_doc` came from, for
+    /// sources that know; the script sees it through `doc_meta()`.
+    fn position(&self) -> Option<SourcePosition> {
+        None
+    }
+
+    /// Passes over the next `n` documents, returning how many there were,
+    /// fewer than `n` only once the input is exhausted. Sources that can
+    /// tell where a document ends without parsing it skip it unparsed.
+    fn skip(&mut self, n: usize) -> Result<usize> {
+        for skipped in 0..n {
+            if self.next_doc()?.is_none() {
+                return Ok(skipped);
+            }
+        }
+        Ok(n)
+    }
+
+    /// How many bytes of its input the source has gone through, for sources
+    /// that keep count, e.g. to tell how far along a run is. Documents
+    /// returned and skipped count, and so may input read ahead.
+    fn bytes_read(&self) -> Option<u64> {
+        None
+    }
+}
+
+impl<S: InputSource + ?Sized> InputSource for Box<S> {
+    fn next_doc(&mut self) -> Result<Option<Value>> {
+        (**self).next_doc()
+    }
+
+    fn position(&self) -> Option<SourcePosition> {
+        (**self).position()
+    }
+
+    fn skip(&mut self, n: usize) -> Result<usize> {
+        (**self).skip(n)
+    }
+
+    fn bytes_read(&self) -> Option<u64> {
+        (**self).bytes_read()
+    }
+}
+
+/// A window of another source's documents: those after the first
+/// [`SlicedSource::skip`] of them, up to [`SlicedSource::limit`] of them.
+/// Nothing is read from the source until the first document is asked for,
+/// and nothing at all under a limit of 0.
+///
+/// Pass the number skipped as [`RunOptions::index_offset`](crate::RunOptions::index_offset)
+/// for `doc_index()` to count from the start of the source.
+pub struct SlicedSource<S> {
+    inner: S,
+    skip: usize,
+    limit: Option<usize>,
+}
+
+impl<S: InputSource> SlicedSource<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            skip: 0,
+            limit: None,
+        }
+    }
+
+    /// Passes over the first `n` documents, unparsed where the source
+    /// allows (see [`InputSource::skip`]).
+    pub fn skip(mut self, n: usize) -> Self {
+        self.skip = n;
+        self
+    }
+
+    /// Ends after `n` documents.
+    pub fn limit(mut self, n: usize) -> Self {
+        self.limit = Some(n);
+        self
+    }
+}
+
+impl<S: InputSource> InputSource for SlicedSource<S> {
+    fn next_doc(&mut self) -> Result<Option<Value>> {
+        if self.limit == Some(0) {
+            return Ok(None);
+        }
+        if self.skip > 0 {
+            let skip = std::mem::take(&mut self.skip);
+            if self.inner.skip(skip)? < skip {
+                self.limit = Some(0);
+                return Ok(None);
+            }
+        }
+        let next = self.inner.next_doc()?;
+        if let Some(limit) = &mut self.limit {
+            *limit -= 1;
+        }
+        Ok(next)
+    }
+
+    fn position(&self) -> Option<SourcePosition> {
+        self.inner.position()
+    }
+
+    fn bytes_read(&self) -> Option<u64> {
+        self.inner.bytes_read()
+    }
+}
+
+/// Every document of another source, read in one go into an array handed
+/// over as the only document, like `jq --slurp`; a source without any makes
+/// an empty array. The whole input is held in memory at once, so bound it
+/// with [`SlicedSource::limit`] when it may be large.
+pub struct SlurpedSource<S> {
+    inner: S,
+    done: bool,
+}
+
+impl<S: InputSource> SlurpedSource<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner, done: false }
+    }
+}
+
+impl<S: InputSource> InputSource for SlurpedSource<S> {
+    fn next_doc(&mut self) -> Result<Option<Value>> {
+        if self.done {
+            return Ok(None);
+        }
+        self.done = true;
+        let mut docs = Vec::new();
+        while let Some(doc) = self.inner.next_doc()? {
+            docs.push(doc);
+        }
+        Ok(Some(Value::Array(docs)))
+    }
+
+    fn bytes_read(&self) -> Option<u64> {
+        self.inner.bytes_read()
+    }
+}
+
+/// Where in its input a document was read.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourcePosition {
+    /// 1-based line the document was on.
+    pub line: usize,
+    /// Name of the input, usually a file path, when known.
+    pub source: Option<Rc<str>>,
+}
+
+/// Blocks until a document arrives, ending once every sender is dropped.
+impl InputSource for Receiver<Value> {
+    fn next_doc(&mut self) -> Result<Option<Value>> {
+        Ok(self.recv().ok())
+    }
+}
+
+/// Documents of an in-memory iterator.
+pub struct IterSource<I> {
+    iter: I,
+}
+
+impl<I: Iterator<Item = Value>> IterSource<I> {
+    pub fn new(iter: impl IntoIterator<IntoIter = I>) -> Self {
+        Self {
+            iter: iter.into_iter(),
+        }
+    }
+}
+
+impl<I: Iterator<Item = Value>> InputSource for IterSource<I> {
+    fn next_doc(&mut self) -> Result<Option<Value>> {
+        Ok(self.iter.next())
+    }
+}
+
+/// Newline-delimited JSON, parsed one line at a time as documents are asked
+/// for. Blank lines are skipped.
+pub struct JsonLinesSource<R: BufRead> {
+    reader: R,
+    line: usize,
+    buf: String,
+    source: Option<Rc<str>>,
+    dialect: JsonDialect,
+    /// What ends every record other than a newline.
+    separator: Option<Vec<u8>>,
+    bytes: Vec<u8>,
+    /// Bytes read so far, separators included.
+    offset: u64,
+}
+
+impl<R: BufRead> JsonLinesSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: 0,
+            buf: String::new(),
+            source: None,
+            dialect: JsonDialect::Strict,
+            separator: None,
+            bytes: Vec::new(),
+            offset: 0,
+        }
+    }
+
+    /// Splits the input into records at `separator`, such as `b"\0"`,
+    /// rather than at newlines, so that records may span lines, as
+    /// pretty-printed JSON does. Records can be of any length.
+    /// [`SourcePosition::line`] then counts records.
+    pub fn record_separator(mut self, separator: impl Into<Vec<u8>>) -> Self {
+        let separator = separator.into();
+        self.separator = (separator != b"\n" && !separator.is_empty()).then_some(separator);
+        self
+    }
+
+    /// Reads the next record into `buf`, without its separator, returning
+    /// false at the end of the input.
+    fn read_record(&mut self) -> Result<bool> {
+        self.buf.clear();
+        let Some(separator) = &self.separator else {
+            let n = self.reader.read_line(&mut self.buf)?;
+            self.offset += n as u64;
+            return Ok(n != 0);
+        };
+        self.bytes.clear();
+        let last = separator[separator.len() - 1];
+        loop {
+            let n = self.reader.read_until(last, &mut self.bytes)?;
+            if n == 0 {
+                break;
+            }
+            self.offset += n as u64;
+            if self.bytes.ends_with(separator) {
+                self.bytes.truncate(self.bytes.len() - separator.len());
+                break;
+            }
+        }
+        if self.bytes.is_empty() && self.reader.fill_buf()?.is_empty() {
+            // Either the end of the input, or an empty record right before
+            // it, which counts for nothing either way.
+            return Ok(false);
+        }
+        let record = std::str::from_utf8(&self.bytes).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "stream did not contain valid UTF-8",
+            )
+        })?;
+        self.buf.push_str(record);
+        Ok(true)
+    }
+
+    /// Parses every line by `dialect` rather than as strict JSON, skipping
+    /// lines holding only comments. Block comments cannot span lines, every
+    /// line being parsed on its own.
+    pub fn dialect(mut self, dialect: JsonDialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Names the input, e.g. with the path of the file being read, for
+    /// [`SourcePosition::source`].
+    pub fn with_source(mut self, source: impl Into<Rc<str>>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+}
+
+impl JsonLinesSource<Box<dyn BufRead>> {
+    /// Reads the file at `path`, named after it, decompressing it when its
+    /// extension says it is compressed (see [`Compression::from_extension`]).
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let reader = BufReader::new(File::open(path)?);
+        let reader: Box<dyn BufRead> = match Compression::from_extension(path) {
+            Some(compression) => compression.decoder(reader)?,
+            None => Box::new(reader),
+        };
+        Ok(Self::new(reader).with_source(path.display().to_string()))
+    }
+}
+
+impl<R: BufRead> InputSource for JsonLinesSource<R> {
+    fn next_doc(&mut self) -> Result<Option<Value>> {
+        loop {
+            if !self.read_record()? {
+                return Ok(None);
+            }
+            self.line += 1;
+            if let Some(doc) = self.dialect.parse(&self.buf, self.line)? {
+                return Ok(Some(doc));
+            }
+        }
+    }
+
+    /// Skips lines unparsed under [`JsonDialect::Strict`], where every line
+    /// that is not blank is a document, however malformed.
+    fn skip(&mut self, n: usize) -> Result<usize> {
+        if self.dialect != JsonDialect::Strict {
+            let mut skipped = 0;
+            while skipped < n && self.next_doc()?.is_some() {
+                skipped += 1;
+            }
+            return Ok(skipped);
+        }
+        let mut skipped = 0;
+        while skipped < n {
+            if !self.read_record()? {
+                break;
+            }
+            self.line += 1;
+            if !self.buf.trim().is_empty() {
+                skipped += 1;
+            }
+        }
+        Ok(skipped)
+    }
+
+    fn position(&self) -> Option<SourcePosition> {
+        Some(SourcePosition {
+            line: self.line,
+            source: self.source.clone(),
+        })
+    }
+
+    fn bytes_read(&self) -> Option<u64> {
+        Some(self.offset)
+    }
+}
@@ -0,0 +1,147 @@
+use std::io::{self, Read, Write};
+
+use serde_json::{Map, Number, Value};
+
+use crate::error::{Error, Result};
+use crate::sink::OutputSink;
+use crate::source::InputSource;
+use crate::value::escape_pointer;
+
+/// A TOML file as a single input document: tables become objects, arrays
+/// become arrays, and datetimes become their RFC 3339 strings, e.g.
+/// `"1979-05-27T07:32:00Z"`, or the date or time alone for local dates and
+/// times. `nan` and `inf` are an error, JSON having no such numbers.
+///
+/// The whole file is read when the document is first asked for.
+pub struct TomlSource<R: Read> {
+    reader: Option<R>,
+}
+
+impl<R: Read> TomlSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: Some(reader),
+        }
+    }
+}
+
+impl<R: Read> InputSource for TomlSource<R> {
+    fn next_doc(&mut self) -> Result<Option<Value>> {
+        let Some(mut reader) = self.reader.take() else {
+            return Ok(None);
+        };
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        let table = text
+            .parse::<toml::Table>()
+            .map_err(|source| Error::InvalidToml { source })?;
+        toml_to_json(toml::Value::Table(table)).map(Some)
+    }
+}
+
+fn invalid_data(message: String) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+fn toml_to_json(value: toml::Value) -> Result<Value> {
+    Ok(match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(n) => Value::from(n),
+        toml::Value::Float(f) => {
+            Value::Number(Number::from_f64(f).ok_or_else(|| {
+                invalid_data(format!("TOML float {f} has no JSON representation"))
+            })?)
+        }
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(datetime) => Value::String(datetime.to_string()),
+        toml::Value::Array(items) => {
+            Value::Array(items.into_iter().map(toml_to_json).collect::<Result<_>>()?)
+        }
+        toml::Value::Table(table) => {
+            let mut object = Map::new();
+            for (key, value) in table {
+                object.insert(key, toml_to_json(value)?);
+            }
+            Value::Object(object)
+        }
+    })
+}
+
+/// Writes the one document of a run as a TOML file. TOML has no notion of a
+/// stream of documents, so emitting a second one is an error, as is a
+/// document that is not an object or holds a null anywhere, TOML having no
+/// null. Documents emitted with `emit_kv` are written as
+/// `{key = ..., value = ...}`.
+pub struct TomlSink<W: Write> {
+    writer: W,
+    written: bool,
+}
+
+impl<W: Write> TomlSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            written: false,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Fails on the first value under `value` that TOML cannot hold, naming it
+/// by JSON pointer.
+fn check_representable(value: &Value, path: &mut String) -> Result<()> {
+    match value {
+        Value::Null => Err(invalid_data(format!(
+            "null at '{path}' has no TOML representation"
+        ))),
+        Value::Array(items) => items.iter().enumerate().try_for_each(|(i, item)| {
+            let len = path.len();
+            path.push_str(&format!("/{i}"));
+            check_representable(item, path)?;
+            path.truncate(len);
+            Ok(())
+        }),
+        Value::Object(object) => object.iter().try_for_each(|(key, item)| {
+            let len = path.len();
+            path.push('/');
+            path.push_str(&escape_pointer(key));
+            check_representable(item, path)?;
+            path.truncate(len);
+            Ok(())
+        }),
+        _ => Ok(()),
+    }
+}
+
+impl<W: Write> OutputSink for TomlSink<W> {
+    fn emit(&mut self, _channel: &str, value: Value) -> Result<()> {
+        if self.written {
+            return Err(invalid_data(
+                "TOML output holds a single document, but a second one was emitted".to_string(),
+            ));
+        }
+        if !value.is_object() {
+            return Err(invalid_data(
+                "TOML output needs the document to be an object".to_string(),
+            ));
+        }
+        check_representable(&value, &mut String::new())?;
+        let text = toml::to_string(&value)
+            .map_err(|err| invalid_data(format!("cannot write TOML: {err}")))?;
+        self.writer.write_all(text.as_bytes())?;
+        self.written = true;
+        Ok(())
+    }
+
+    fn emit_kv(&mut self, channel: &str, key: Value, value: Value) -> Result<()> {
+        self.emit(channel, serde_json::json!({ "key": key, "value": value }))
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
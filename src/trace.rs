@@ -0,0 +1,41 @@
+use std::cell::RefCell;
+
+/// Progress of a run, reported to [`RunOptions::trace`](crate::RunOptions::trace).
+#[derive(Clone, Copy, Debug)]
+pub enum TraceEvent<'a> {
+    /// The script is about to run over a new batch of input.
+    ScriptStarting { script: &'a str, source: &'a str },
+    /// `get_next` handed the script the document at `index`.
+    DocumentRead { index: usize },
+    /// The script emitted its `index`th document of the batch.
+    Emitted { index: usize },
+}
+
+pub type TraceFn = Box<dyn FnMut(TraceEvent<'_>)>;
+
+pub(crate) struct Tracer {
+    callback: RefCell<TraceFn>,
+    script: String,
+    source: String,
+}
+
+impl Tracer {
+    pub(crate) fn new(callback: TraceFn, script: &str, source: &str) -> Self {
+        Self {
+            callback: RefCell::new(callback),
+            script: script.to_string(),
+            source: source.to_string(),
+        }
+    }
+
+    pub(crate) fn script_starting(&self) {
+        self.send(TraceEvent::ScriptStarting {
+            script: &self.script,
+            source: &self.source,
+        });
+    }
+
+    pub(crate) fn send(&self, event: TraceEvent<'_>) {
+        (self.callback.borrow_mut())(event);
+    }
+}
@@ -0,0 +1,292 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use mlua::Error as LuaError;
+
+use crate::error::{Result, find_line};
+use crate::sandbox::Sandbox;
+
+/// Chunk name the script is compiled under, so locations can be found.
+const CHUNK_NAME: &str = "script";
+
+/// Why [`validate`] rejected a script.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScriptSyntaxError {
+    pub line: Option<u32>,
+    /// Lua does not report columns, so this is only set when the message
+    /// carries one.
+    pub column: Option<u32>,
+    pub message: String,
+}
+
+impl fmt::Display for ScriptSyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "{line}:{column}: {}", self.message),
+            (Some(line), None) => write!(f, "{line}: {}", self.message),
+            _ => f.write_str(&self.message),
+        }
+    }
+}
+
+impl std::error::Error for ScriptSyntaxError {}
+
+/// Checks that `script` compiles, without running any of it.
+pub fn validate(script: &str) -> std::result::Result<(), ScriptSyntaxError> {
+    let lua = Sandbox::Pure
+        .create_lua()
+        .map_err(|err| ScriptSyntaxError {
+            line: None,
+            column: None,
+            message: err.to_string(),
+        })?;
+    let Err(err) = lua
+        .load(script)
+        .set_name(format!("={CHUNK_NAME}"))
+        .into_function()
+    else {
+        return Ok(());
+    };
+
+    let message = match err {
+        LuaError::SyntaxError { message, .. } => message,
+        err => err.to_string(),
+    };
+    let line = find_line(&message, CHUNK_NAME);
+    let message = match line {
+        Some(line) => message
+            .split_once(&format!("{CHUNK_NAME}:{line}:"))
+            .map_or(message.as_str(), |(_, rest)| rest)
+            .trim()
+            .to_string(),
+        None => message,
+    };
+    Err(ScriptSyntaxError {
+        line,
+        column: None,
+        message,
+    })
+}
+
+/// A global a script reads that nothing defines, as [`undefined_globals`]
+/// finds.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UndefinedGlobal {
+    pub name: String,
+    /// Where it is first read.
+    pub line: u32,
+}
+
+const KEYWORDS: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if", "in",
+    "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+/// Names `script` reads as globals that neither it, Lua nor the runner
+/// defines, which tend to be typos, each with the line it is first read on.
+///
+/// This is a scan of the source, which runs none of it, and so only a best
+/// effort: scopes are not tracked, so a name declared `local` or assigned
+/// anywhere in the script counts as defined everywhere, and globals the
+/// script sets in ways other than by name, like through `_G`, are missed.
+/// `script` is expected to compile; see [`validate`].
+pub fn undefined_globals(script: &str) -> Result<Vec<UndefinedGlobal>> {
+    let tokens = tokenize(script);
+    let mut defined: HashSet<&str> = HashSet::new();
+    let mut reads = Vec::new();
+    let mut scan = Scan::Code;
+    for (i, token) in tokens.iter().enumerate() {
+        let prev = i.checked_sub(1).map(|i| tokens[i].text);
+        let next = tokens.get(i + 1).map(|token| token.text);
+        scan = match (scan, token.text) {
+            (_, "local" | "for") => Scan::Declaring,
+            (_, "function") => Scan::FunctionName,
+            (Scan::Declaring, "," | "<" | ">") => Scan::Declaring,
+            (Scan::Declaring, _)
+                if token.is_name() && matches!(prev, Some("local" | "for" | "," | "<")) =>
+            {
+                Scan::Declaring
+            }
+            (Scan::FunctionName, "(") => Scan::Parameters,
+            (Scan::FunctionName, "." | ":") => Scan::FunctionName,
+            (Scan::FunctionName, _)
+                if token.is_name() && matches!(prev, Some("function" | "." | ":")) =>
+            {
+                Scan::FunctionName
+            }
+            (Scan::Parameters, ")") => Scan::Code,
+            (Scan::Parameters, _) => Scan::Parameters,
+            _ => Scan::Code,
+        };
+        // Fields, methods and labels are no globals.
+        if !token.is_name() || matches!(prev, Some("." | ":" | "::" | "goto")) {
+            continue;
+        }
+        let defines = match scan {
+            Scan::Declaring | Scan::Parameters => true,
+            // `function name()` defines it, `function name.field()` reads
+            // it.
+            Scan::FunctionName => next == Some("("),
+            Scan::Code => next == Some("="),
+        };
+        if defines {
+            defined.insert(token.text);
+        } else {
+            reads.push(token);
+        }
+    }
+
+    let known: HashSet<String> = crate::runner::global_names()?.into_iter().collect();
+    let mut seen = HashSet::new();
+    Ok(reads
+        .into_iter()
+        .filter(|token| !defined.contains(token.text) && !known.contains(token.text))
+        .filter(|token| seen.insert(token.text))
+        .map(|token| UndefinedGlobal {
+            name: token.text.to_string(),
+            line: token.line,
+        })
+        .collect())
+}
+
+/// What the names being scanned are.
+#[derive(Clone, Copy)]
+enum Scan {
+    Code,
+    /// Declared by `local` or `for`.
+    Declaring,
+    /// The name of a function being defined.
+    FunctionName,
+    Parameters,
+}
+
+struct Token<'a> {
+    text: &'a str,
+    line: u32,
+}
+
+impl Token<'_> {
+    /// Whether this is a name, keywords aside.
+    fn is_name(&self) -> bool {
+        self.text
+            .starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && !KEYWORDS.contains(&self.text)
+    }
+}
+
+/// The names and punctuation of `script`, leaving out comments, strings and
+/// numbers, which hold no names.
+fn tokenize(script: &str) -> Vec<Token<'_>> {
+    let bytes = script.as_bytes();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let start_line = line;
+        match bytes[i] {
+            b'\n' => {
+                line += 1;
+                i += 1;
+            }
+            b if b.is_ascii_whitespace() => i += 1,
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                i += 2;
+                match long_bracket(bytes, i) {
+                    Some(level) => i = skip_long(bytes, i, level, &mut line),
+                    None => {
+                        while i < bytes.len() && bytes[i] != b'\n' {
+                            i += 1;
+                        }
+                    }
+                }
+            }
+            b'[' if long_bracket(bytes, i).is_some() => {
+                let level = long_bracket(bytes, i).expect("checked above");
+                i = skip_long(bytes, i, level, &mut line);
+            }
+            quote @ (b'"' | b'\'') => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote {
+                    match bytes[i] {
+                        b'\\' => {
+                            if bytes.get(i + 1) == Some(&b'\n') {
+                                line += 1;
+                            }
+                            i += 2;
+                        }
+                        b'\n' => {
+                            line += 1;
+                            i += 1;
+                        }
+                        _ => i += 1,
+                    }
+                }
+                i += 1;
+            }
+            b if b.is_ascii_digit()
+                || (b == b'.' && bytes.get(i + 1).is_some_and(u8::is_ascii_digit)) =>
+            {
+                // Hex digits, exponents and their signs included.
+                while i < bytes.len()
+                    && (bytes[i].is_ascii_alphanumeric()
+                        || bytes[i] == b'.'
+                        || (matches!(bytes[i], b'+' | b'-')
+                            && matches!(bytes[i - 1], b'e' | b'E' | b'p' | b'P')))
+                {
+                    i += 1;
+                }
+            }
+            b if b.is_ascii_alphabetic() || b == b'_' => {
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+                tokens.push(Token {
+                    text: &script[start..i],
+                    line: start_line,
+                });
+            }
+            _ => {
+                // Tells `.` and `:` apart from `..`, `...` and `::`, and `=`
+                // from `==`, `<=`, `>=` and `~=`.
+                let len = [b"...".as_slice(), b"..", b"::", b"==", b"<=", b">=", b"~="]
+                    .iter()
+                    .find(|op| bytes[i..].starts_with(op))
+                    .map_or(1, |op| op.len());
+                // Leaves whatever is not ASCII, which only strings and
+                // comments hold, whole.
+                let len = (len..=4)
+                    .find(|&len| script.is_char_boundary(i + len))
+                    .unwrap_or(len);
+                i += len;
+                tokens.push(Token {
+                    text: &script[start..i],
+                    line: start_line,
+                });
+            }
+        }
+    }
+    tokens
+}
+
+/// The level of the long bracket, like `[==[`, opening at `i`, if one does.
+fn long_bracket(bytes: &[u8], i: usize) -> Option<usize> {
+    if bytes.get(i) != Some(&b'[') {
+        return None;
+    }
+    let level = bytes[i + 1..].iter().take_while(|&&b| b == b'=').count();
+    (bytes.get(i + 1 + level) == Some(&b'[')).then_some(level)
+}
+
+/// Past the long string or comment opening at `i`, counting its lines.
+fn skip_long(bytes: &[u8], i: usize, level: usize, line: &mut u32) -> usize {
+    let close = [b"]".as_slice(), &b"=".repeat(level), b"]"].concat();
+    let mut i = i + level + 2;
+    while i < bytes.len() && !bytes[i..].starts_with(&close) {
+        if bytes[i] == b'\n' {
+            *line += 1;
+        }
+        i += 1;
+    }
+    i + close.len()
+}
@@ -0,0 +1,494 @@
+use std::cell::{Cell, Ref, RefCell, RefMut};
+use std::fmt;
+use std::rc::Rc;
+
+use mlua::{
+    Error as LuaError, FromLua, Function as LuaFunction, Lua, MetaMethod, Result,
+    String as LuaString, UserData, UserDataMethods, Value as LuaValue,
+};
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+
+#[derive(Clone)]
+pub struct SharedValue {
+    root: Rc<RefCell<Value>>,
+    path: Vec<PathElement>,
+    alive: Rc<Cell<bool>>,
+}
+
+#[derive(Clone)]
+pub(crate) enum PathElement {
+    Key(String),
+    Index(usize),
+}
+
+impl SharedValue {
+    pub fn new(root: Value) -> Self {
+        Self::with_liveness(root, Rc::new(Cell::new(true)))
+    }
+
+    /// Creates a root handle that becomes stale once `alive` is cleared, which
+    /// the runner does at the end of every batch.
+    pub(crate) fn with_liveness(root: Value, alive: Rc<Cell<bool>>) -> Self {
+        Self {
+            root: Rc::new(RefCell::new(root)),
+            path: Vec::new(),
+            alive,
+        }
+    }
+
+    fn check_alive(&self) -> Result<()> {
+        if self.alive.get() {
+            Ok(())
+        } else {
+            Err(LuaError::runtime(
+                "attempt to use a document handle from a previous batch",
+            ))
+        }
+    }
+
+    pub fn take(self) -> Result<Value> {
+        self.check_alive()?;
+        let mut node = self.root.take();
+        for elem in &self.path {
+            node = match elem {
+                PathElement::Key(k) => remove_by_key(node, k),
+                PathElement::Index(i) => remove_by_index(node, *i),
+            }
+            .ok_or_else(|| missing_path(elem))?;
+        }
+        Ok(node)
+    }
+
+    pub fn resolve(&self) -> Result<Ref<'_, Value>> {
+        self.check_alive()?;
+        let mut node = self.root.borrow();
+        for elem in &self.path {
+            node = match elem {
+                PathElement::Key(k) => Ref::filter_map(node, |n| n.get(k)),
+                PathElement::Index(i) => Ref::filter_map(node, |n| n.get(*i)),
+            }
+            .map_err(|_| missing_path(elem))?;
+        }
+        Ok(node)
+    }
+
+    pub fn resolve_mut(&self) -> Result<RefMut<'_, Value>> {
+        self.check_alive()?;
+        let mut node = self.root.borrow_mut();
+        for elem in &self.path {
+            node = match elem {
+                PathElement::Key(k) => RefMut::filter_map(node, |n| n.get_mut(k)),
+                PathElement::Index(i) => RefMut::filter_map(node, |n| n.get_mut(*i)),
+            }
+            .map_err(|_| missing_path(elem))?;
+        }
+        Ok(node)
+    }
+
+    /// A copy of the value the handle points at.
+    pub fn to_value(&self) -> Result<Value> {
+        Ok(self.resolve()?.clone())
+    }
+
+    pub(crate) fn subhandle(&self, elem: PathElement) -> Self {
+        let mut new_path = self.path.clone();
+        new_path.push(elem);
+        Self {
+            root: self.root.clone(),
+            path: new_path,
+            alive: self.alive.clone(),
+        }
+    }
+}
+
+/// Lets Rust functions registered into Lua take handles as arguments. Handles
+/// come through as they are, sharing their document, while a plain table is
+/// copied into a new document of its own, so changes made through that handle
+/// never reach the table. Handles convert back into Lua as userdata.
+impl FromLua for SharedValue {
+    fn from_lua(value: LuaValue, _: &Lua) -> Result<Self> {
+        match value {
+            LuaValue::UserData(data) if data.is::<SharedValue>() => {
+                Ok(data.borrow::<SharedValue>()?.clone())
+            }
+            LuaValue::Table(_) => Ok(SharedValue::new(lua_to_json(value)?)),
+            value => Err(LuaError::FromLuaConversionError {
+                from: value.type_name(),
+                to: "SharedValue".to_string(),
+                message: Some("expected a document handle or a table".to_string()),
+            }),
+        }
+    }
+}
+
+/// Serializes the value the handle points at, failing like
+/// [`SharedValue::resolve`] does.
+impl Serialize for SharedValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let value = self.resolve().map_err(serde::ser::Error::custom)?;
+        value.serialize(serializer)
+    }
+}
+
+/// Lends `doc` to `f` as a handle, e.g. to pass to a script, and puts it back,
+/// with whatever changes were made through the handle, once `f` returns.
+///
+/// The handle and any handles derived from it go stale when `f` returns, so
+/// one stashed away, like in a Lua global, errors when used afterwards.
+pub fn with_document<R>(doc: &mut Value, f: impl FnOnce(SharedValue) -> R) -> R {
+    let handle = SharedValue::with_liveness(std::mem::take(doc), Rc::new(Cell::new(true)));
+    let _lend = Lend {
+        doc,
+        root: handle.root.clone(),
+        alive: handle.alive.clone(),
+    };
+    f(handle)
+}
+
+/// Moves a document lent by [`with_document`] back, even if `f` panics.
+struct Lend<'a> {
+    doc: &'a mut Value,
+    root: Rc<RefCell<Value>>,
+    alive: Rc<Cell<bool>>,
+}
+
+impl Drop for Lend<'_> {
+    fn drop(&mut self) {
+        self.alive.set(false);
+        *self.doc = self.root.replace(Value::Null);
+    }
+}
+
+fn missing_path(elem: &PathElement) -> LuaError {
+    match elem {
+        PathElement::Key(k) => LuaError::runtime(format!("document no longer has key '{k}'")),
+        PathElement::Index(i) => {
+            LuaError::runtime(format!("document no longer has index {}", i + 1))
+        }
+    }
+}
+
+fn remove_by_key(value: Value, key: &str) -> Option<Value> {
+    if let Value::Object(mut map) = value {
+        map.remove(key)
+    } else {
+        None
+    }
+}
+
+fn remove_by_index(value: Value, index: usize) -> Option<Value> {
+    match value {
+        Value::Array(mut arr) if index < arr.len() => Some(arr.remove(index)),
+        _ => None,
+    }
+}
+
+impl UserData for SharedValue {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_meta_method(MetaMethod::Index, |lua, this, key: LuaValue| {
+            let val = this.resolve()?;
+            match key {
+                LuaValue::String(s) => {
+                    let k = s.to_str()?.to_string();
+                    if let Some(child) = val.get(&k) {
+                        Ok(json_subhandle_to_lua(
+                            lua,
+                            this.clone(),
+                            child,
+                            PathElement::Key(k),
+                        )?)
+                    } else {
+                        Ok(LuaValue::Nil)
+                    }
+                }
+                LuaValue::Integer(i) => {
+                    let idx = (i - 1) as usize;
+                    if let Some(child) = val.get(idx) {
+                        Ok(json_subhandle_to_lua(
+                            lua,
+                            this.clone(),
+                            child,
+                            PathElement::Index(idx),
+                        )?)
+                    } else {
+                        Ok(LuaValue::Nil)
+                    }
+                }
+                _ => Ok(LuaValue::Nil),
+            }
+        });
+
+        methods.add_meta_method_mut(
+            MetaMethod::NewIndex,
+            |_, this, (key, val): (LuaValue, LuaValue)| {
+                // Converted first, as `val` may be a handle into this same
+                // document.
+                let new_val = lua_to_json(val)?;
+                let mut node = this.resolve_mut()?;
+                match key {
+                    LuaValue::String(s) => {
+                        let key_str = s.to_str()?.to_string();
+                        node[key_str] = new_val;
+                    }
+                    LuaValue::Integer(i) => {
+                        let idx = (i - 1) as usize;
+                        // One past the end appends, as `arr[#arr + 1] = v` does
+                        // for tables.
+                        match &mut *node {
+                            Value::Array(arr) if idx < arr.len() => arr[idx] = new_val,
+                            Value::Array(arr) if idx == arr.len() => arr.push(new_val),
+                            _ => {}
+                        }
+                    }
+                    _ => {}
+                }
+                Ok(())
+            },
+        );
+
+        methods.add_meta_method(MetaMethod::Len, |_, this, ()| {
+            Ok(match &*this.resolve()? {
+                Value::Array(arr) => arr.len(),
+                Value::Object(obj) => obj.len(),
+                Value::String(s) => s.len(),
+                _ => 0,
+            })
+        });
+
+        methods.add_meta_method(MetaMethod::ToString, |_, this, ()| {
+            Ok(this.resolve()?.to_string())
+        });
+
+        methods.add_method("__pairs_impl", |lua, this, ()| {
+            let this = this.clone();
+            let val = this.resolve()?.clone();
+
+            match val {
+                Value::Object(obj) => make_iter(lua, obj, move |lua, (k, v)| {
+                    Ok((
+                        LuaValue::String(lua.create_string(&k)?),
+                        json_subhandle_to_lua(lua, this.clone(), &v, PathElement::Key(k))?,
+                    ))
+                }),
+                Value::Array(arr) => {
+                    make_iter(lua, arr.into_iter().enumerate(), move |lua, (i, v)| {
+                        Ok((
+                            LuaValue::Integer(i as i64 + 1),
+                            json_subhandle_to_lua(lua, this.clone(), &v, PathElement::Index(i))?,
+                        ))
+                    })
+                }
+                _ => make_iter(lua, std::iter::empty::<()>(), |_, _| {
+                    Ok((LuaValue::Nil, LuaValue::Nil))
+                }),
+            }
+        });
+    }
+}
+
+fn json_subhandle_to_lua(
+    lua: &Lua,
+    parent: SharedValue,
+    val: &Value,
+    elem: PathElement,
+) -> Result<LuaValue> {
+    Ok(match val {
+        Value::Null => LuaValue::Nil,
+        Value::Bool(b) => LuaValue::Boolean(*b),
+        Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                LuaValue::Integer(i)
+            } else {
+                LuaValue::Number(n.as_f64().unwrap())
+            }
+        }
+        Value::String(s) => LuaValue::String(lua.create_string(s)?),
+        Value::Array(_) | Value::Object(_) => {
+            LuaValue::UserData(lua.create_userdata(parent.subhandle(elem))?)
+        }
+    })
+}
+
+pub(crate) fn json_to_lua(lua: &Lua, val: Value, alive: &Rc<Cell<bool>>) -> Result<LuaValue> {
+    Ok(match val {
+        Value::Null => LuaValue::Nil,
+        Value::Bool(b) => LuaValue::Boolean(b),
+        Value::Number(n) => n
+            .as_i64()
+            .map(LuaValue::Integer)
+            .unwrap_or(LuaValue::Number(n.as_f64().unwrap())),
+        Value::String(s) => LuaValue::String(lua.create_string(s)?),
+        Value::Array(_) | Value::Object(_) => {
+            LuaValue::UserData(lua.create_userdata(SharedValue::with_liveness(val, alive.clone()))?)
+        }
+    })
+}
+
+/// A Lua value that has no JSON representation, found at the JSON pointer
+/// `path` inside the value being converted.
+#[derive(Debug)]
+pub(crate) struct ConversionError {
+    pub(crate) path: String,
+    pub(crate) message: String,
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cannot convert value at '{}': {}",
+            self.path, self.message
+        )
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+pub(crate) fn lua_to_json(val: LuaValue) -> Result<Value> {
+    lua_to_json_at(val, &mut String::new(), false)
+}
+
+/// Like [`lua_to_json`], but fails on values with no JSON representation,
+/// like functions, instead of turning them into null. `name` becomes the
+/// first segment of the path reported on failure.
+pub(crate) fn lua_to_json_strict(val: LuaValue, name: &str) -> Result<Value> {
+    lua_to_json_at(val, &mut format!("/{}", escape_pointer(name)), true)
+}
+
+fn lua_to_json_at(val: LuaValue, path: &mut String, strict: bool) -> Result<Value> {
+    Ok(match val {
+        LuaValue::Nil => Value::Null,
+        LuaValue::Boolean(b) => Value::Bool(b),
+        LuaValue::Integer(i) => Value::Number(i.into()),
+        LuaValue::Number(f) => serde_json::Number::from_f64(f)
+            .map(Value::Number)
+            .unwrap_or(Value::Null),
+        LuaValue::String(s) => Value::String(lua_string(&s, path)?),
+        LuaValue::Table(t) => {
+            let mut arr: Vec<Value> = Vec::new();
+            let mut map: serde_json::Map<String, Value> = serde_json::Map::new();
+            let mut is_array = true;
+
+            for pair in t.pairs::<LuaValue, LuaValue>() {
+                let (k, v) = pair?;
+                let parent_len = path.len();
+                push_path_segment(path, &k);
+                let value = lua_to_json_at(v, path, strict)?;
+                path.truncate(parent_len);
+                match k {
+                    LuaValue::Integer(i) if i > 0 => {
+                        let idx = (i - 1) as usize;
+                        if idx != arr.len() {
+                            is_array = false;
+                        }
+                        if is_array {
+                            arr.push(value);
+                        } else {
+                            map.insert(i.to_string(), value);
+                        }
+                    }
+                    LuaValue::String(s) => {
+                        is_array = false;
+                        map.insert(lua_string(&s, path)?, value);
+                    }
+                    _ => {
+                        is_array = false;
+                    }
+                }
+            }
+
+            if is_array {
+                Value::Array(arr)
+            } else {
+                if !arr.is_empty() {
+                    for (i, v) in arr.into_iter().enumerate() {
+                        map.insert((i + 1).to_string(), v);
+                    }
+                }
+                Value::Object(map)
+            }
+        }
+        val if val.is_null() => Value::Null,
+        // A handle stored inside a table, or into another document, is copied.
+        LuaValue::UserData(data) if data.is::<SharedValue>() => {
+            data.borrow::<SharedValue>()?.resolve()?.clone()
+        }
+        LuaValue::UserData(_) if strict => return Err(unconvertible(path, "userdata")),
+        other if strict => return Err(unconvertible(path, other.type_name())),
+        _ => Value::Null,
+    })
+}
+
+fn unconvertible(path: &str, type_name: &str) -> LuaError {
+    LuaError::external(ConversionError {
+        path: path.to_string(),
+        message: format!("{type_name} values have no JSON representation"),
+    })
+}
+
+/// Builds plain Lua values out of `val`, with no handle into a document, for
+/// data that outlives the batch it is loaded in.
+pub(crate) fn json_to_table(lua: &Lua, val: &Value) -> Result<LuaValue> {
+    Ok(match val {
+        Value::Null => LuaValue::Nil,
+        Value::Bool(b) => LuaValue::Boolean(*b),
+        Value::Number(n) => n
+            .as_i64()
+            .map(LuaValue::Integer)
+            .unwrap_or(LuaValue::Number(n.as_f64().unwrap())),
+        Value::String(s) => LuaValue::String(lua.create_string(s)?),
+        Value::Array(arr) => {
+            let table = lua.create_table_with_capacity(arr.len(), 0)?;
+            for (i, v) in arr.iter().enumerate() {
+                table.raw_set(i + 1, json_to_table(lua, v)?)?;
+            }
+            LuaValue::Table(table)
+        }
+        Value::Object(map) => {
+            let table = lua.create_table_with_capacity(0, map.len())?;
+            for (k, v) in map {
+                table.raw_set(k.as_str(), json_to_table(lua, v)?)?;
+            }
+            LuaValue::Table(table)
+        }
+    })
+}
+
+fn lua_string(s: &LuaString, path: &str) -> Result<String> {
+    s.to_str().map(|s| s.to_string()).map_err(|err| {
+        LuaError::external(ConversionError {
+            path: path.to_string(),
+            message: err.to_string(),
+        })
+    })
+}
+
+fn push_path_segment(path: &mut String, key: &LuaValue) {
+    path.push('/');
+    match key {
+        LuaValue::String(s) => path.push_str(&escape_pointer(&s.to_string_lossy())),
+        LuaValue::Integer(i) => path.push_str(&i.to_string()),
+        other => path.push_str(other.type_name()),
+    }
+}
+
+pub(crate) fn escape_pointer(segment: &str) -> String {
+    segment.replace('~', "~0").replace('/', "~1")
+}
+
+fn make_iter<I, F>(lua: &Lua, iter: I, mut f: F) -> Result<(LuaFunction, LuaValue, LuaValue)>
+where
+    I: IntoIterator + 'static,
+    F: FnMut(&Lua, I::Item) -> Result<(LuaValue, LuaValue)> + 'static,
+{
+    let mut it = iter.into_iter();
+    let iter_fn = lua.create_function_mut(move |lua, _: ()| {
+        if let Some(item) = it.next() {
+            f(lua, item)
+        } else {
+            Ok((LuaValue::Nil, LuaValue::Nil))
+        }
+    })?;
+    Ok((iter_fn, LuaValue::Nil, LuaValue::Nil))
+}
@@ -0,0 +1,239 @@
+use std::io::BufRead;
+
+use quick_xml::Reader;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::name::QName;
+use serde_json::{Map, Value};
+
+use crate::error::{Error, Result};
+use crate::source::InputSource;
+
+/// How XML is read into documents, for [`XmlSource`] and the `xml.decode`
+/// global.
+#[derive(Clone, Debug, Default)]
+pub struct XmlOptions {
+    /// The name of the elements every one of which is a document, wherever
+    /// they are; without it, the root element is the one document.
+    pub record: Option<String>,
+    /// Keeps namespace prefixes on names, and `xmlns` attributes, which are
+    /// otherwise dropped.
+    pub keep_namespaces: bool,
+}
+
+/// XML, turned into JSON by these conventions:
+///
+/// - An element becomes an object, with its attributes under `@` and their
+///   name, e.g. `"@id"`, its child elements under their name, and its text
+///   under `"#text"`. Child elements sharing a name become an array of them,
+///   in order.
+/// - An element with only text becomes that text as a string, and one with
+///   nothing at all null.
+/// - Text is trimmed, and in mixed content, the pieces of text between child
+///   elements are joined with single spaces. CDATA counts as text; comments
+///   and processing instructions are dropped.
+/// - All values are strings, XML having no other types.
+///
+/// The root element becomes a document of its own, `{ root = ... }`, unless
+/// [`XmlOptions::record`] names the elements to read as documents instead, in
+/// which case every such element becomes a document as above, without its
+/// name, and everything outside of them is skipped. Records are read as they
+/// come, so a large file never needs to be held in memory.
+pub struct XmlSource<R: BufRead> {
+    reader: Reader<R>,
+    options: XmlOptions,
+    buf: Vec<u8>,
+    done: bool,
+}
+
+/// An element being read.
+struct Node {
+    name: String,
+    fields: Map<String, Value>,
+    text: Vec<String>,
+}
+
+impl Node {
+    fn finish(mut self) -> Value {
+        let text = self.text.join(" ");
+        if self.fields.is_empty() {
+            return if text.is_empty() {
+                Value::Null
+            } else {
+                Value::String(text)
+            };
+        }
+        if !text.is_empty() {
+            self.fields.insert("#text".to_string(), Value::String(text));
+        }
+        Value::Object(self.fields)
+    }
+
+    fn add_child(&mut self, name: String, value: Value) {
+        match self.fields.get_mut(&name) {
+            // Element values are never arrays, so an array is one of repeated
+            // children.
+            Some(Value::Array(items)) => items.push(value),
+            Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+            None => {
+                self.fields.insert(name, value);
+            }
+        }
+    }
+}
+
+fn invalid_at<R>(reader: &Reader<R>, message: impl ToString) -> Error {
+    Error::InvalidXml {
+        offset: reader.buffer_position(),
+        message: message.to_string(),
+    }
+}
+
+impl<R: BufRead> XmlSource<R> {
+    pub fn new(reader: R, options: XmlOptions) -> Self {
+        Self {
+            reader: Reader::from_reader(reader),
+            options,
+            buf: Vec::new(),
+            done: false,
+        }
+    }
+
+    fn invalid(&self, message: impl ToString) -> Error {
+        invalid_at(&self.reader, message)
+    }
+
+    fn name(&self, name: QName) -> Result<String> {
+        let name = if self.options.keep_namespaces {
+            name.as_ref()
+        } else {
+            name.local_name().into_inner()
+        };
+        std::str::from_utf8(name)
+            .map(str::to_string)
+            .map_err(|err| self.invalid(err))
+    }
+
+    fn open(&self, start: &BytesStart) -> Result<Node> {
+        let mut node = Node {
+            name: self.name(start.name())?,
+            fields: Map::new(),
+            text: Vec::new(),
+        };
+        for attr in start.attributes() {
+            let attr = attr.map_err(|err| self.invalid(err))?;
+            let is_xmlns = attr.key.as_namespace_binding().is_some();
+            if is_xmlns && !self.options.keep_namespaces {
+                continue;
+            }
+            let value = attr.unescape_value().map_err(|err| self.invalid(err))?;
+            node.fields.insert(
+                format!("@{}", self.name(attr.key)?),
+                Value::String(value.into_owned()),
+            );
+        }
+        Ok(node)
+    }
+
+    fn is_record(&self, name: &str) -> bool {
+        self.options
+            .record
+            .as_deref()
+            .is_none_or(|record| record == name)
+    }
+}
+
+impl<R: BufRead> InputSource for XmlSource<R> {
+    fn next_doc(&mut self) -> Result<Option<Value>> {
+        if self.done {
+            return Ok(None);
+        }
+        // The elements being read, outermost first, once inside a document.
+        let mut stack: Vec<Node> = Vec::new();
+        loop {
+            self.buf.clear();
+            let event = self
+                .reader
+                .read_event_into(&mut self.buf)
+                .map_err(|err| invalid_at(&self.reader, err))?
+                .into_owned();
+            let finished = match event {
+                Event::Start(start) => {
+                    let node = self.open(&start)?;
+                    if stack.is_empty() && !self.is_record(&node.name) {
+                        continue;
+                    }
+                    stack.push(node);
+                    None
+                }
+                Event::Empty(start) => {
+                    let node = self.open(&start)?;
+                    if stack.is_empty() && !self.is_record(&node.name) {
+                        continue;
+                    }
+                    Some(node)
+                }
+                Event::End(_) => stack.pop(),
+                Event::Text(text) => {
+                    if let Some(node) = stack.last_mut() {
+                        let text = text.unescape().map_err(|err| self.invalid(err))?;
+                        let text = text.trim();
+                        if !text.is_empty() {
+                            node.text.push(text.to_string());
+                        }
+                    }
+                    continue;
+                }
+                Event::CData(data) => {
+                    if let Some(node) = stack.last_mut() {
+                        let data = data.into_inner();
+                        let text = std::str::from_utf8(&data).map_err(|err| self.invalid(err))?;
+                        let text = text.trim();
+                        if !text.is_empty() {
+                            node.text.push(text.to_string());
+                        }
+                    }
+                    continue;
+                }
+                Event::Eof => {
+                    self.done = true;
+                    if !stack.is_empty() {
+                        return Err(self.invalid("unexpected end of input inside an element"));
+                    }
+                    return Ok(None);
+                }
+                _ => continue,
+            };
+            let Some(node) = finished else {
+                continue;
+            };
+            let name = node.name.clone();
+            let value = node.finish();
+            match stack.last_mut() {
+                Some(parent) => parent.add_child(name, value),
+                None if self.options.record.is_some() => return Ok(Some(value)),
+                None => {
+                    self.done = true;
+                    let mut root = Map::new();
+                    root.insert(name, value);
+                    return Ok(Some(Value::Object(root)));
+                }
+            }
+        }
+    }
+}
+
+/// Reads every document of `text`: the root element as one document, or the
+/// [`XmlOptions::record`] elements as an array of them.
+pub(crate) fn decode(text: &str, options: XmlOptions) -> Result<Value> {
+    let by_record = options.record.is_some();
+    let mut source = XmlSource::new(text.as_bytes(), options);
+    let mut docs = Vec::new();
+    while let Some(doc) = source.next_doc()? {
+        docs.push(doc);
+    }
+    Ok(if by_record {
+        Value::Array(docs)
+    } else {
+        docs.pop().unwrap_or(Value::Null)
+    })
+}
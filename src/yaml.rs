@@ -0,0 +1,143 @@
+use std::io::{self, Read, Write};
+
+use serde::Deserialize;
+use serde::de::Error as _;
+use serde_json::{Map, Number, Value};
+use serde_yaml::Value as YamlValue;
+
+use crate::error::{Error, Result};
+use crate::sink::OutputSink;
+use crate::source::InputSource;
+
+/// A YAML stream, with every `---`-separated document in it becoming one
+/// input document.
+///
+/// Documents are read with serde_yaml, which resolves anchors, aliases and
+/// `<<` merge keys, and then turned into JSON, which loses some of YAML:
+///
+/// - Tags are dropped, leaving the value they tag.
+/// - Mapping keys that are numbers, booleans or null become their YAML
+///   spelling as strings; keys that are sequences or mappings are an error.
+/// - `.nan` and `.inf` are an error, JSON having no such numbers.
+///
+/// serde_yaml reads the whole stream before handing over the first
+/// document, so unlike [`JsonLinesSource`](crate::JsonLinesSource), this
+/// holds all of the input in memory.
+pub struct YamlSource<'a> {
+    documents: serde_yaml::Deserializer<'a>,
+    /// Documents read so far.
+    read: usize,
+}
+
+impl<'a> YamlSource<'a> {
+    pub fn new(reader: impl Read + 'a) -> Self {
+        Self {
+            documents: serde_yaml::Deserializer::from_reader(reader),
+            read: 0,
+        }
+    }
+}
+
+impl InputSource for YamlSource<'_> {
+    fn next_doc(&mut self) -> Result<Option<Value>> {
+        let Some(document) = self.documents.next() else {
+            return Ok(None);
+        };
+        self.read += 1;
+        let invalid = |source| Error::InvalidYaml {
+            document: self.read,
+            source,
+        };
+        let mut value = YamlValue::deserialize(document).map_err(invalid)?;
+        value.apply_merge().map_err(invalid)?;
+        yaml_to_json(value).map(Some).map_err(invalid)
+    }
+}
+
+fn yaml_to_json(value: YamlValue) -> serde_yaml::Result<Value> {
+    Ok(match value {
+        YamlValue::Null => Value::Null,
+        YamlValue::Bool(b) => Value::Bool(b),
+        YamlValue::Number(n) => {
+            if let Some(n) = n.as_i64() {
+                Value::from(n)
+            } else if let Some(n) = n.as_u64() {
+                Value::from(n)
+            } else {
+                let f = n.as_f64().unwrap_or(f64::NAN);
+                Value::Number(Number::from_f64(f).ok_or_else(|| {
+                    serde_yaml::Error::custom(format!("{n} has no JSON representation"))
+                })?)
+            }
+        }
+        YamlValue::String(s) => Value::String(s),
+        YamlValue::Sequence(items) => Value::Array(
+            items
+                .into_iter()
+                .map(yaml_to_json)
+                .collect::<serde_yaml::Result<_>>()?,
+        ),
+        YamlValue::Mapping(mapping) => {
+            let mut object = Map::new();
+            for (key, value) in mapping {
+                object.insert(yaml_key(key)?, yaml_to_json(value)?);
+            }
+            Value::Object(object)
+        }
+        YamlValue::Tagged(tagged) => yaml_to_json(tagged.value)?,
+    })
+}
+
+fn yaml_key(key: YamlValue) -> serde_yaml::Result<String> {
+    match key {
+        YamlValue::String(s) => Ok(s),
+        YamlValue::Null => Ok("null".to_string()),
+        YamlValue::Bool(b) => Ok(b.to_string()),
+        YamlValue::Number(n) => Ok(n.to_string()),
+        YamlValue::Tagged(tagged) => yaml_key(tagged.value),
+        YamlValue::Sequence(_) | YamlValue::Mapping(_) => Err(serde_yaml::Error::custom(
+            "mapping keys must be scalars to become JSON",
+        )),
+    }
+}
+
+/// Writes every document as a YAML document, `---` going between them.
+/// Documents emitted with `emit_kv` are written as `{key: ..., value: ...}`.
+pub struct YamlSink<W: Write> {
+    writer: W,
+    written: bool,
+}
+
+impl<W: Write> YamlSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            written: false,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> OutputSink for YamlSink<W> {
+    fn emit(&mut self, _channel: &str, value: Value) -> Result<()> {
+        if self.written {
+            self.writer.write_all(b"---\n")?;
+        }
+        serde_yaml::to_writer(&mut self.writer, &value)
+            .map_err(|err| Error::Io(io::Error::new(io::ErrorKind::InvalidData, err)))?;
+        self.written = true;
+        Ok(())
+    }
+
+    fn emit_kv(&mut self, channel: &str, key: Value, value: Value) -> Result<()> {
+        self.emit(channel, serde_json::json!({ "key": key, "value": value }))
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,45 @@
+use std::sync::mpsc;
+use std::thread;
+
+use mlua_play::{Error, RunOptions, run_channel};
+use serde_json::json;
+
+const DOUBLE: &str = r#"
+    local doc = get_next()
+    while doc ~= nil do
+        emit(doc * 2)
+        doc = get_next()
+    end
+"#;
+
+#[test]
+fn results_stream_out_before_the_input_ends() {
+    let (input, documents) = mpsc::channel();
+    let (results, output) = mpsc::channel();
+    let worker =
+        thread::spawn(move || run_channel(DOUBLE, documents, results, RunOptions::default()));
+    // Every document waits on the result of the one before, which could
+    // never arrive if results were held back until the input ended.
+    for n in 1..=3 {
+        input.send(json!(n)).unwrap();
+        assert_eq!(output.recv().unwrap(), json!(n * 2));
+    }
+    drop(input);
+    worker.join().unwrap().unwrap();
+    assert!(output.recv().is_err());
+}
+
+#[test]
+fn dropping_the_output_aborts_the_script() {
+    let (input, documents) = mpsc::channel();
+    let (results, output) = mpsc::channel();
+    drop(output);
+    input.send(json!(1)).unwrap();
+    let worker =
+        thread::spawn(move || run_channel(DOUBLE, documents, results, RunOptions::default()));
+    match worker.join().unwrap().unwrap_err() {
+        Error::Sink { channel, .. } => assert_eq!(channel.as_deref(), Some("out")),
+        err => panic!("{err:?}"),
+    }
+    drop(input);
+}
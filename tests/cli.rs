@@ -0,0 +1,1031 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+use serde_json::json;
+
+const ECHO: &str = r#"
+local doc = get_next()
+while doc ~= nil do
+    emit(doc)
+    doc = get_next()
+end
+"#;
+
+/// An empty directory of the test's own, under the system's temporary one.
+fn scratch(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mlua_play-{}-{test}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Writes `contents` to `name` in `dir`, returning its path as a string.
+fn file(dir: &Path, name: &str, contents: impl AsRef<[u8]>) -> String {
+    let path = dir.join(name);
+    fs::write(&path, contents).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+/// Runs the binary with `args`, feeding it `stdin`.
+fn mlua_play(args: &[&str], stdin: impl AsRef<[u8]>) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mlua_play"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Written from another thread so a child not reading it cannot block us.
+    let mut input = child.stdin.take().unwrap();
+    let stdin = stdin.as_ref().to_vec();
+    let writer = std::thread::spawn(move || {
+        let _ = input.write_all(&stdin);
+    });
+    let output = child.wait_with_output().unwrap();
+    writer.join().unwrap();
+    output
+}
+
+fn stdout(output: &Output) -> &str {
+    std::str::from_utf8(&output.stdout).unwrap()
+}
+
+fn stderr(output: &Output) -> &str {
+    std::str::from_utf8(&output.stderr).unwrap()
+}
+
+#[test]
+fn scripts_run_over_input_files_into_output_files() {
+    let dir = scratch("files");
+    let script = file(&dir, "echo.lua", ECHO);
+    let input = file(&dir, "in.ndjson", "{\"n\":1}\n{\"n\":2}\n");
+    let out = dir.join("out.ndjson");
+    let output = mlua_play(
+        &[
+            &script,
+            "--input",
+            &input,
+            "--output",
+            out.to_str().unwrap(),
+        ],
+        "",
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "");
+    assert_eq!(fs::read_to_string(&out).unwrap(), "{\"n\":1}\n{\"n\":2}\n");
+
+    // Without --output, documents go to stdout, and `-` reads the script
+    // from stdin.
+    let output = mlua_play(&["-", "--input", &input], ECHO);
+    assert_eq!(stdout(&output), "{\"n\":1}\n{\"n\":2}\n");
+}
+
+#[test]
+fn missing_files_are_named_in_the_error() {
+    let dir = scratch("missing");
+    let script = dir.join("nope.lua");
+    let output = mlua_play(&[script.to_str().unwrap()], "");
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains(&format!("cannot read script '{}'", script.display())),
+        "{}",
+        stderr(&output)
+    );
+
+    let script = file(&dir, "echo.lua", ECHO);
+    let input = dir.join("nope.ndjson");
+    let output = mlua_play(&[&script, "--input", input.to_str().unwrap()], "");
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).starts_with(&format!("mlua_play: in '{}'", input.display())),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn stdin_streams_through_to_stdout_like_a_buffered_run() {
+    let script = r#"
+        local doc = get_next()
+        while doc ~= nil do
+            if doc.n % 3 ~= 0 then emit({ n = doc.n * 2, tag = doc.tag }) end
+            doc = get_next()
+        end
+    "#;
+    let docs: Vec<_> = (0..3000)
+        .map(|n| json!({"n": n, "tag": format!("t{}", n % 7)}))
+        .collect();
+    let input: String = docs.iter().map(|doc| format!("{doc}\n")).collect();
+    let dir = scratch("stdin");
+    let path = file(&dir, "double.lua", script);
+    let output = mlua_play(&[&path], &input);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    let expected: String = mlua_play::run(script, docs)
+        .unwrap()
+        .iter()
+        .map(|doc| format!("{doc}\n"))
+        .collect();
+    assert_eq!(stdout(&output), expected);
+}
+
+#[test]
+fn malformed_lines_fail_the_run_or_are_skipped() {
+    let dir = scratch("malformed");
+    let script = file(&dir, "echo.lua", ECHO);
+    let input = "{\"n\":1}\n{oops\n{\"n\":3}\n";
+    let output = mlua_play(&[&script], input);
+    assert!(!output.status.success());
+    assert_eq!(stdout(&output), "{\"n\":1}\n");
+    assert!(
+        stderr(&output).contains("invalid JSON on line 2"),
+        "{}",
+        stderr(&output)
+    );
+
+    let output = mlua_play(&[&script, "--on-invalid-json", "skip"], input);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "{\"n\":1}\n{\"n\":3}\n");
+    assert!(
+        stderr(&output).contains("invalid JSON on line 2"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn eval_runs_a_one_liner_named_eval() {
+    let input = "{\"status\":200}\n{\"status\":404}\n{\"n\":3,\"status\":200}\n";
+    let filter = "while true do local d = get_next() if d == nil then break end \
+                  if d.status == 200 then emit(d) end end";
+    let output = mlua_play(&["-e", filter], input);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "{\"status\":200}\n{\"n\":3,\"status\":200}\n"
+    );
+
+    // Several pieces are joined by newlines.
+    let output = mlua_play(&["-e", "local n = 6", "-e", "emit(n * 7)"], "");
+    assert_eq!(stdout(&output), "42\n");
+
+    let output = mlua_play(&["-e", "error('boom')"], "");
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("(eval):1: boom"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn eval_under_map_mode_is_the_body_of_transform() {
+    let input = "{\"status\":200}\n{\"status\":404}\n";
+    let output = mlua_play(
+        &[
+            "--mode",
+            "map",
+            "-e",
+            "if doc.status == 200 then return doc end",
+        ],
+        input,
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "{\"status\":200}\n");
+}
+
+#[test]
+fn eval_conflicts_with_a_script_path() {
+    let output = mlua_play(&["script.lua", "-e", "emit(1)"], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        stderr(&output).contains("cannot be used with '--eval <CODE>'"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn pretty_and_compact_write_the_exact_bytes() {
+    let input = "{\"a\":{\"b\":[1,2]}}\n7\n";
+    let output = mlua_play(&["-e", ECHO, "--pretty"], input);
+    assert_eq!(
+        stdout(&output),
+        "{\n  \"a\": {\n    \"b\": [\n      1,\n      2\n    ]\n  }\n}\n\n7\n"
+    );
+    let output = mlua_play(&["-e", ECHO, "--pretty", "--separator=--\n"], input);
+    assert_eq!(
+        stdout(&output),
+        "{\n  \"a\": {\n    \"b\": [\n      1,\n      2\n    ]\n  }\n}\n--\n7\n"
+    );
+    let output = mlua_play(&["-e", ECHO, "--compact"], input);
+    assert_eq!(stdout(&output), "{\"a\":{\"b\":[1,2]}}\n7\n");
+    // A pipe is not a terminal.
+    let output = mlua_play(&["-e", ECHO, "--auto"], input);
+    assert_eq!(stdout(&output), "{\"a\":{\"b\":[1,2]}}\n7\n");
+
+    let output = mlua_play(&["-e", ECHO, "--pretty", "--compact"], input);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+const META: &str = r#"
+local doc = get_next()
+while doc ~= nil do
+    local meta = doc_meta()
+    emit({ doc.n, meta.source:match("[^/\\]+$"), meta.line, meta.index })
+    doc = get_next()
+end
+"#;
+
+#[test]
+fn inputs_are_read_in_order_as_one_stream() {
+    let dir = scratch("inputs");
+    let b = file(&dir, "b.ndjson", "{\"n\":1}\n");
+    let a = file(&dir, "a.ndjson", "{\"n\":2}\n{\"n\":3}\n");
+    file(&dir, "notes.txt", "not JSON");
+    fs::create_dir(dir.join("nested")).unwrap();
+    file(&dir.join("nested"), "c.ndjson", "{\"n\":4}\n");
+    let expected = "[2,\"a.ndjson\",1,1]\n[3,\"a.ndjson\",2,2]\n[1,\"b.ndjson\",1,3]\n";
+
+    let pattern = dir.join("*.ndjson");
+    let output = mlua_play(&["-e", META, "--input", pattern.to_str().unwrap()], "");
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), expected);
+
+    // A directory stands for the files directly inside it.
+    let output = mlua_play(&["-e", META, "--input", dir.to_str().unwrap()], "");
+    assert_eq!(stdout(&output), expected);
+
+    // Paths given one by one keep the order given.
+    let output = mlua_play(&["-e", META, "--input", &b, "--input", &a], "");
+    assert_eq!(
+        stdout(&output),
+        "[1,\"b.ndjson\",1,1]\n[2,\"a.ndjson\",1,2]\n[3,\"a.ndjson\",2,3]\n"
+    );
+}
+
+#[test]
+fn patterns_matching_nothing_are_an_error_unless_allowed() {
+    let dir = scratch("empty-glob");
+    let pattern = dir.join("part-*.ndjson");
+    let pattern = pattern.to_str().unwrap();
+    let output = mlua_play(&["-e", "emit(1)", "--input", pattern], "");
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("no input files match"),
+        "{}",
+        stderr(&output)
+    );
+
+    let output = mlua_play(&["-e", "emit(1)", "--input", pattern, "--allow-empty"], "");
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "1\n");
+}
+
+#[cfg(all(feature = "gzip", feature = "zstd"))]
+#[test]
+fn compressed_inputs_are_read_alongside_plain_ones() {
+    let dir = scratch("compressed");
+    let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gzip.write_all(b"{\"n\":1}\n{\"n\":2}\n").unwrap();
+    let gzip = gzip.finish().unwrap();
+    let zstd = zstd::encode_all(&b"{\"n\":3}\n"[..], 0).unwrap();
+    let gz = file(&dir, "a.ndjson.gz", &gzip);
+    let zst = file(&dir, "b.ndjson.zst", &zstd);
+    let plain = file(&dir, "c.ndjson", "{\"n\":4}\n");
+
+    let output = mlua_play(
+        &[
+            "-e", ECHO, "--input", &gz, "--input", &zst, "--input", &plain,
+        ],
+        "",
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "{\"n\":1}\n{\"n\":2}\n{\"n\":3}\n{\"n\":4}\n"
+    );
+
+    // Stdin is only decompressed when asked to.
+    let output = mlua_play(&["-e", ECHO, "--decompress", "auto"], &zstd);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "{\"n\":3}\n");
+    let output = mlua_play(&["-e", ECHO], &gzip);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn yaml_goes_in_and_out() {
+    let input = "name: web\nreplicas: 2\n---\nname: worker\nreplicas: 3\n";
+    let output = mlua_play(
+        &[
+            "-e",
+            ECHO,
+            "--input-format",
+            "yaml",
+            "--output-format",
+            "yaml",
+        ],
+        input,
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), input);
+}
+
+#[test]
+fn csv_options_shape_the_input_and_output() {
+    let output = mlua_play(
+        &[
+            "-e",
+            "local d = get_next() emit({ n = d.a + 1, p = { q = d.b } })",
+            "--input-format",
+            "csv",
+            "--csv-delimiter",
+            ";",
+            "--csv-types",
+            "--output-format",
+            "csv",
+            "--csv-flatten",
+        ],
+        "a;b\n1;\"x;y\"\n",
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "n;p.q\n2;\"x;y\"\n");
+}
+
+#[test]
+fn the_repl_reads_lua_from_stdin_over_an_input_file() {
+    let dir = scratch("repl");
+    let input = file(&dir, "in.ndjson", "{\"n\":4}\n");
+    let output = mlua_play(
+        &["repl", "--input", &input],
+        "function f(x)\n  return x * 10\nend\nerror('ignored')\nlocal doc = get_next()\nf(doc.n)\n",
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "40\n");
+    assert!(stderr(&output).contains("ignored"), "{}", stderr(&output));
+
+    let output = mlua_play(&["repl"], "1\n");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        stderr(&output).contains("pass --input"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn watch_runs_again_when_the_script_changes() {
+    use std::io::{BufRead, BufReader};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let dir = scratch("watch");
+    let script = file(&dir, "watched.lua", "emit('first')");
+    let input = file(&dir, "in.ndjson", "1\n");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mlua_play"))
+        .args([script.as_str(), "--input", &input, "--watch"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Lines from both streams.
+    let (sender, lines) = mpsc::channel();
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    let stderr = BufReader::new(child.stderr.take().unwrap());
+    for stream in [
+        Box::new(stdout) as Box<dyn BufRead + Send>,
+        Box::new(stderr),
+    ] {
+        let sender = sender.clone();
+        std::thread::spawn(move || {
+            for line in stream.lines() {
+                let _ = sender.send(line.unwrap());
+            }
+        });
+    }
+    // The two streams interleave as they please, so lines come in any
+    // order; those not waited for yet are kept for later.
+    let mut seen = Vec::<String>::new();
+    let mut wait_for = |wanted: &str| loop {
+        if let Some(i) = seen.iter().position(|line| line.contains(wanted)) {
+            seen.remove(i);
+            break;
+        }
+        let line = lines
+            .recv_timeout(Duration::from_secs(20))
+            .unwrap_or_else(|_| panic!("no line with '{wanted}' in {seen:?}"));
+        seen.push(line);
+    };
+
+    wait_for("\"first\"");
+    wait_for("[watch] run 1 done");
+    fs::write(&script, "emit('second')").unwrap();
+    wait_for("[watch] run 2: ");
+    wait_for("\"second\"");
+    // A broken script is reported, and watching carries on.
+    fs::write(&script, "emit(").unwrap();
+    wait_for("mlua_play: syntax error");
+    wait_for("[watch] run 3 done");
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn watch_needs_files_to_read_again() {
+    let dir = scratch("watch-stdin");
+    let script = file(&dir, "watched.lua", "emit(1)");
+    let output = mlua_play(&[&script, "--watch"], "1\n");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        stderr(&output).contains("pass --input"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn skip_and_limit_window_the_input_across_files() {
+    let dir = scratch("slice");
+    let a = file(&dir, "a.ndjson", "{\"n\":1}\n{oops\n");
+    let b = file(&dir, "b.ndjson", "{\"n\":3}\n{\"n\":4}\n{\"n\":5}\n");
+    let args = ["-e", META, "--input", &a, "--input", &b];
+    let output = mlua_play(&[&args[..], &["--skip", "2", "--limit", "2"]].concat(), "");
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "[3,\"b.ndjson\",1,3]\n[4,\"b.ndjson\",2,4]\n"
+    );
+
+    // With a limit of 0, the script still runs, over no documents.
+    let output = mlua_play(
+        &["-e", "emit(get_next() == nil)", "--limit", "0"],
+        "{oops\n",
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "true\n");
+}
+
+#[test]
+fn printed_text_goes_to_stderr() {
+    let body = "print('saw', doc.n) return doc";
+    let input = "{\"n\":1}\n{\"n\":2}\n";
+    for jobs in ["1", "2"] {
+        let output = mlua_play(&["--mode", "map", "-e", body, "--jobs", jobs], input);
+        assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+        assert_eq!(stdout(&output), "{\"n\":1}\n{\"n\":2}\n");
+        // Workers print as they go, so their lines can come in any order.
+        let mut printed: Vec<_> = stderr(&output).lines().collect();
+        printed.sort_unstable();
+        assert_eq!(printed, ["saw\t1", "saw\t2"]);
+    }
+}
+
+#[test]
+fn jobs_write_the_same_bytes_as_a_single_state() {
+    let input: String = (0..2000)
+        .map(|n| format!("{{\"n\":{n},\"s\":\"{}\"}}\n", "x".repeat(n % 13)))
+        .collect();
+    let body = "if doc.n % 5 == 0 then return nil end \
+                return { n = doc.n, len = #doc.s, i = doc_index() }, doc.n * 2";
+    let single = mlua_play(&["--mode", "map", "-e", body], &input);
+    assert_eq!(single.status.code(), Some(0), "{}", stderr(&single));
+    let parallel = mlua_play(&["--mode", "map", "-e", body, "--jobs", "4"], &input);
+    assert_eq!(parallel.status.code(), Some(0), "{}", stderr(&parallel));
+    assert_eq!(stdout(&parallel), stdout(&single));
+
+    let unordered = mlua_play(
+        &["--mode", "map", "-e", body, "--jobs", "4", "--unordered"],
+        &input,
+    );
+    let mut got: Vec<_> = stdout(&unordered).lines().collect();
+    let mut want: Vec<_> = stdout(&single).lines().collect();
+    got.sort_unstable();
+    want.sort_unstable();
+    assert_eq!(got, want);
+
+    let output = mlua_play(
+        &[
+            "--mode",
+            "map",
+            "-e",
+            "return doc_index()",
+            "--jobs",
+            "3",
+            "--skip",
+            "1",
+            "--limit",
+            "3",
+        ],
+        "1\n2\n3\n4\n5\n",
+    );
+    assert_eq!(stdout(&output), "2\n3\n4\n");
+
+    // Stats count what every worker did.
+    let output = mlua_play(
+        &[
+            "--mode",
+            "map",
+            "-e",
+            "return doc",
+            "--jobs",
+            "3",
+            "--stats",
+        ],
+        &input,
+    );
+    assert!(
+        stderr(&output).contains("documents read    2000\nemitted to out    2000\n"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn jobs_need_map_mode() {
+    let output = mlua_play(&["-e", ECHO, "--jobs", "2"], "1\n");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        stderr(&output).contains("pass --mode map"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn limits_stop_the_run_naming_the_limit_and_document() {
+    let output = mlua_play(&["-e", "while true do end", "--timeout", "100ms"], "");
+    assert_eq!(output.status.code(), Some(5));
+    assert_eq!(
+        stderr(&output),
+        "mlua_play: script timed out after 0.1s in (eval)\n"
+    );
+
+    let output = mlua_play(
+        &[
+            "-e",
+            "local t = {} for i = 1, 1e8 do t[i] = i end",
+            "--max-memory",
+            "1MB",
+        ],
+        "",
+    );
+    assert_eq!(output.status.code(), Some(5));
+    assert!(
+        stderr(&output).contains("Lua memory limit of 1048576 bytes exceeded"),
+        "{}",
+        stderr(&output)
+    );
+
+    let slow = "if doc == 2 then while true do end end return doc";
+    let output = mlua_play(
+        &["--mode", "map", "-e", slow, "--doc-max-instructions", "1e5"],
+        "1\n2\n3\n",
+    );
+    assert_eq!(output.status.code(), Some(5));
+    assert_eq!(stdout(&output), "1\n");
+    assert!(
+        stderr(&output).contains("instruction budget exceeded after")
+            && stderr(&output).contains("while processing document 2"),
+        "{}",
+        stderr(&output)
+    );
+
+    let output = mlua_play(
+        &[
+            "--mode",
+            "map",
+            "-e",
+            slow,
+            "--doc-timeout",
+            "100ms",
+            "--on-limit",
+            "skip-doc",
+        ],
+        "1\n2\n3\n",
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "1\n3\n");
+}
+
+#[test]
+fn sandboxes_take_away_os_execute() {
+    let probe = "emit(type(os.execute))";
+    assert_eq!(
+        stdout(&mlua_play(&["-e", probe, "--sandbox", "full"], "")),
+        "\"function\"\n"
+    );
+    assert_eq!(
+        stdout(&mlua_play(&["-e", probe, "--sandbox", "standard"], "")),
+        "\"nil\"\n"
+    );
+    assert_eq!(
+        stdout(&mlua_play(&["-e", probe, "--untrusted"], "")),
+        "\"nil\"\n"
+    );
+    assert_eq!(stdout(&mlua_play(&["-e", probe], "")), "\"function\"\n");
+
+    let output = mlua_play(&["-e", "os.execute('true')", "--sandbox", "standard"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).contains("attempt to call field 'execute' (a nil value)")
+            && stderr(&output).contains("stack traceback:"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn stats_json_counts_a_known_input() {
+    let body = r#"
+        metrics.incr("seen")
+        if doc == 3 then error("bad") end
+        if doc % 2 == 0 then emit_to("even", doc) end
+        return doc
+    "#;
+    let args = ["--mode", "map", "-e", body, "--on-error", "continue"];
+    let output = mlua_play(&[&args[..], &["--stats-json"]].concat(), "1\n2\n3\n4\n");
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    let stats: serde_json::Value =
+        serde_json::from_str(stderr(&output).lines().last().unwrap()).unwrap();
+    assert_eq!(stats["documents_read"], 4);
+    assert_eq!(stats["emitted"], json!({"out": 3, "even": 2}));
+    assert_eq!(stats["failures"], 1);
+    assert_eq!(
+        stats["metrics"]["seen"],
+        json!({"type": "counter", "value": 4})
+    );
+    assert!(stats["elapsed_seconds"].is_f64() && stats["peak_memory_bytes"].is_u64());
+
+    let output = mlua_play(&[&args[..], &["--stats"]].concat(), "1\n2\n3\n4\n");
+    assert!(
+        stderr(&output).contains("documents read    4\n")
+            && stderr(&output).contains("failures          1\n"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn exit_codes_tell_failures_apart() {
+    let code = |args: &[&str], stdin: &str| mlua_play(args, stdin).status.code();
+    assert_eq!(code(&["-e", ECHO], "1\n"), Some(0));
+    assert_eq!(code(&["-e", "error('boom')"], ""), Some(1));
+    assert_eq!(code(&["-e", ECHO, "--no-such-flag"], ""), Some(2));
+    assert_eq!(code(&["-e", "emit("], ""), Some(3));
+    assert_eq!(code(&["-e", ECHO], "1\n{x\n"), Some(4));
+    let skipping = ["-e", ECHO, "--on-invalid-json", "skip"];
+    assert_eq!(code(&skipping, "1\n{x\n"), Some(0));
+    assert_eq!(
+        code(
+            &[&skipping[..], &["--fail-on-input-errors"]].concat(),
+            "1\n{x\n"
+        ),
+        Some(4)
+    );
+    assert_eq!(
+        code(&["-e", "while true do end", "--timeout", "50ms"], ""),
+        Some(5)
+    );
+
+    let help = mlua_play(&["--help"], "");
+    assert!(
+        stdout(&help).contains("  3  the script has a syntax error"),
+        "{}",
+        stdout(&help)
+    );
+}
+
+#[test]
+fn output_files_are_only_replaced_once_the_run_succeeds() {
+    let dir = scratch("atomic");
+    let out = file(&dir, "out.ndjson", "old\n");
+    let output = mlua_play(&["-e", "emit(1) error('halfway')", "--output", &out], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(fs::read_to_string(&out).unwrap(), "old\n");
+    // No temporary file is left behind either.
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+    let output = mlua_play(&["-e", "emit(1)", "--output", &out], "");
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(fs::read_to_string(&out).unwrap(), "1\n");
+    let output = mlua_play(&["-e", "emit(2)", "--output", &out, "--append"], "");
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(fs::read_to_string(&out).unwrap(), "1\n2\n");
+}
+
+#[test]
+fn every_channel_gets_a_file_of_its_own() {
+    let dir = scratch("per-channel");
+    let channels = dir.join("channels");
+    let script = "emit(1) emit_to('errors', { m = 'x' }) emit_to('errors', 2)";
+    let output = mlua_play(
+        &[
+            "-e",
+            script,
+            "--output-per-channel",
+            channels.to_str().unwrap(),
+        ],
+        "",
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    let mut files: Vec<_> = fs::read_dir(&channels)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(files, ["errors.ndjson", "out.ndjson"]);
+    assert_eq!(
+        fs::read_to_string(channels.join("out.ndjson")).unwrap(),
+        "1\n"
+    );
+    assert_eq!(
+        fs::read_to_string(channels.join("errors.ndjson")).unwrap(),
+        "{\"m\":\"x\"}\n2\n"
+    );
+}
+
+#[test]
+fn a_closed_stdout_ends_the_run_quietly() {
+    use std::io::{BufRead, BufReader};
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mlua_play"))
+        .args(["-e", "for i = 1, 1e7 do emit(i) end"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut first = String::new();
+    BufReader::new(child.stdout.take().unwrap())
+        .read_line(&mut first)
+        .unwrap();
+    assert_eq!(first, "1\n");
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn nul_separated_pretty_records_round_trip() {
+    let input = "{\"a\":\n1}\0{\"b\":\"x\\ny\"}\0";
+    let output = mlua_play(
+        &[
+            "-e",
+            ECHO,
+            "--input-separator",
+            "nul",
+            "--output-separator",
+            "nul",
+            "--pretty",
+        ],
+        input,
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "{\n  \"a\": 1\n}\0{\n  \"b\": \"x\\ny\"\n}\0"
+    );
+
+    let output = mlua_play(
+        &[
+            "-e",
+            ECHO,
+            "--input-separator",
+            "nul",
+            "--output-separator",
+            "\\x1e",
+        ],
+        &output.stdout,
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "{\"a\":1}\x1e{\"b\":\"x\\ny\"}\x1e");
+}
+
+#[test]
+fn raw_output_writes_strings_unquoted() {
+    let script = "emit('plain \"q\"', { a = 1 }, 'tab\\there', 7)";
+    let output = mlua_play(&["-e", script, "-r"], "");
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "plain \"q\"\n{\"a\":1}\ntab\there\n7\n");
+
+    let output = mlua_play(&["-e", script, "--raw-output=strict"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "plain \"q\"\n");
+    assert!(
+        stderr(&output).contains("raw output takes only strings, not an object"),
+        "{}",
+        stderr(&output)
+    );
+
+    let output = mlua_play(&["-e", "emit('a', 'b', { x = 1 })", "--join-output"], "");
+    assert_eq!(stdout(&output), "ab{\"x\":1}");
+}
+
+#[test]
+fn arg_and_argjson_reach_the_script() {
+    let body = "if doc.v > args.min then return args.label .. doc.v end";
+    let input = "{\"v\":3}\n{\"v\":8}\n{\"v\":12}\n";
+    let output = mlua_play(
+        &[
+            "--mode",
+            "map",
+            "-e",
+            body,
+            "--argjson",
+            "min",
+            "5",
+            "--arg",
+            "label",
+            "big:",
+        ],
+        input,
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "\"big:8\"\n\"big:12\"\n");
+
+    // A later value replaces an earlier one, whichever flag gave it.
+    let output = mlua_play(
+        &[
+            "-e",
+            "emit(args.a, args.b[2].c)",
+            "--arg",
+            "a",
+            "1",
+            "--argjson",
+            "a",
+            "2",
+            "--argjson",
+            "b",
+            "[1, {\"c\": true}]",
+        ],
+        "",
+    );
+    assert_eq!(stdout(&output), "2\ntrue\n");
+
+    let output = mlua_play(&["-e", "emit(1)", "--argjson", "min", "{x"], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stdout(&output), "");
+    assert!(
+        stderr(&output).starts_with("mlua_play: --argjson min: invalid JSON"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn slurp_hands_the_whole_input_over_as_one_array() {
+    let median = r#"
+        local all = get_next()
+        local values = {}
+        for i = 1, #all do values[i] = all[i].v end
+        table.sort(values)
+        local mid = math.floor(#values / 2)
+        emit(#values % 2 == 1 and values[mid + 1] or (values[mid] + values[mid + 1]) / 2)
+        emit(get_next() == nil)
+    "#;
+    let input: String = [7, 1, 9, 3, 100, 4]
+        .iter()
+        .map(|v| format!("{{\"v\":{v}}}\n"))
+        .collect();
+    let output = mlua_play(&["-e", median, "--slurp"], &input);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "5.5\ntrue\n");
+    let output = mlua_play(&["-e", median, "--slurp", "--limit", "3"], &input);
+    assert_eq!(stdout(&output), "7\ntrue\n");
+
+    // In map mode, the array is the one document given to transform.
+    let output = mlua_play(&["--mode", "map", "-e", "return #doc", "--slurp"], &input);
+    assert_eq!(stdout(&output), "6\n");
+    let output = mlua_play(&["-e", ECHO, "--slurp"], "");
+    assert_eq!(stdout(&output), "[]\n");
+}
+
+#[test]
+fn json_seq_skips_corrupted_records_and_counts_them() {
+    let input = "\x1e{\"a\":1}\n\x1e{oops\n\x1e{\n \"b\": 2\n}\n";
+    let args = [
+        "-e",
+        ECHO,
+        "--input-format",
+        "json-seq",
+        "--output-format",
+        "json-seq",
+    ];
+    let output = mlua_play(&args, input);
+    assert_eq!(output.status.code(), Some(4));
+    assert_eq!(stdout(&output), "\x1e{\"a\":1}\n");
+
+    let skipping = [&args[..], &["--on-invalid-json", "skip"]].concat();
+    let output = mlua_play(&skipping, input);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "\x1e{\"a\":1}\n\x1e{\"b\":2}\n");
+    assert!(
+        stderr(&output).contains("invalid JSON text sequence record 2"),
+        "{}",
+        stderr(&output)
+    );
+
+    let output = mlua_play(
+        &[&skipping[..], &["--fail-on-input-errors"]].concat(),
+        input,
+    );
+    assert_eq!(output.status.code(), Some(4));
+    assert!(
+        stderr(&output).contains("skipped 1 invalid input record\n"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn skipped_invalid_input_counts_as_failures() {
+    let input = "1\n{x\n2\n[\n";
+    for jobs in ["1", "2"] {
+        let args = [
+            "--mode",
+            "map",
+            "-e",
+            "return doc",
+            "--jobs",
+            jobs,
+            "--on-invalid-json",
+            "skip",
+            "--stats-json",
+        ];
+        let output = mlua_play(&args, input);
+        assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+        let stats: serde_json::Value =
+            serde_json::from_str(stderr(&output).lines().last().unwrap()).unwrap();
+        assert_eq!(stats["documents_read"], 2);
+        assert_eq!(stats["failures"], 2);
+    }
+
+    let args = [
+        "-e",
+        ECHO,
+        "--on-invalid-json",
+        "skip",
+        "--fail-on-input-errors",
+    ];
+    let output = mlua_play(&args, input);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(
+        stderr(&output).contains("skipped 2 invalid input lines\n"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn progress_needs_a_terminal_unless_forced() {
+    let dir = scratch("progress");
+    let lines: String = (1..=1000).map(|n| format!("{n}\n")).collect();
+    let input = file(&dir, "in.ndjson", lines);
+    let drain = "local d = get_next() while d do d = get_next() end";
+    let output = mlua_play(&["-e", drain, "--input", &input, "--progress"], "");
+    assert_eq!(stderr(&output), "");
+
+    let output = mlua_play(&["-e", drain, "--input", &input, "--progress=force"], "");
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    // The last refresh comes once the whole file has been read.
+    let last = stderr(&output).trim_end().rsplit('\r').next().unwrap();
+    assert!(
+        last.contains("1000 documents") && last.contains("100.0%"),
+        "{last:?}"
+    );
+}
+
+#[test]
+fn check_reports_every_script_and_fails_if_any_does() {
+    let dir = scratch("check");
+    let good = file(&dir, "good.lua", "emit(get_next())\n");
+    let bad = file(&dir, "bad.lua", "local a = 1\nlocal b =\n");
+    let typo = file(&dir, "typo.lua", "local doc = get_next()\nemit(dco)\n");
+
+    let output = mlua_play(&["check", &good], "");
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), format!("{good}: OK\n"));
+
+    let output = mlua_play(&["check", &good, &bad, &typo], "");
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(
+        stdout(&output),
+        format!("{good}: OK\n{bad}:3: unexpected symbol near '<eof>'\n{typo}: OK\n")
+    );
+    assert!(
+        stderr(&output).contains("1 of 3 scripts failed the check"),
+        "{}",
+        stderr(&output)
+    );
+
+    // Undefined globals are warnings, which do not fail the check.
+    let output = mlua_play(&["check", &typo, "--undefined-globals"], "");
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        format!("{typo}: OK\n{typo}:2: warning: undefined global 'dco'\n")
+    );
+}
@@ -0,0 +1,184 @@
+use std::cell::Cell;
+
+use mlua_play::{ClockSource, RunOptions, run_with_options};
+use serde_json::{Value, json};
+
+const RANDOM: &str = r#"
+    local draws = {}
+    for i = 1, 5 do draws[i] = math.random(1, 1000000) end
+    draws[6] = math.random()
+    emit(draws)
+"#;
+
+fn seeded(script: &str, seed: u64) -> Vec<Value> {
+    let options = RunOptions {
+        random_seed: Some(seed),
+        ..RunOptions::default()
+    };
+    run_with_options(script, [], options).unwrap()
+}
+
+#[test]
+fn the_same_seed_draws_the_same_numbers() {
+    assert_eq!(seeded(RANDOM, 42), seeded(RANDOM, 42));
+    assert_ne!(seeded(RANDOM, 42), seeded(RANDOM, 43));
+}
+
+#[test]
+fn randomseed_restarts_the_sequence() {
+    let script = r#"
+        math.randomseed(7)
+        local a = math.random(1, 1000000)
+        math.randomseed(7)
+        emit(a == math.random(1, 1000000))
+    "#;
+    assert_eq!(seeded(script, 1), [true]);
+}
+
+#[test]
+fn a_fixed_clock_pins_the_time_functions() {
+    let options = RunOptions {
+        clock: Some(ClockSource::Fixed(1_700_000_000)),
+        ..RunOptions::default()
+    };
+    let script = r#"
+        emit(os.time(), os.date("!%Y-%m-%dT%H:%M:%S"), os.clock(), time.now_ms())
+    "#;
+    let outputs = run_with_options(script, [], options).unwrap();
+    assert_eq!(
+        outputs,
+        [
+            json!(1_700_000_000),
+            json!("2023-11-14T22:13:20"),
+            json!(0),
+            json!(1_700_000_000_000i64),
+        ]
+    );
+}
+
+#[test]
+fn a_custom_clock_is_asked_every_time() {
+    let ticks = Cell::new(100.0);
+    let options = RunOptions {
+        clock: Some(ClockSource::Custom(Box::new(move || {
+            let now = ticks.get();
+            ticks.set(now + 1.5);
+            now
+        }))),
+        ..RunOptions::default()
+    };
+    let outputs = run_with_options("emit(os.clock(), os.time())", [], options).unwrap();
+    assert_eq!(outputs, [json!(1.5), json!(103)]);
+}
+
+const IDS: &str = r#"
+    emit(uuid(), uuid(), ulid(), ulid(), uuid5("dns", "python.org"))
+"#;
+
+fn is_uuid_v4(id: &str) -> bool {
+    let groups: Vec<_> = id.split('-').map(str::len).collect();
+    groups == [8, 4, 4, 4, 12]
+        && id.as_bytes()[14] == b'4'
+        && matches!(id.as_bytes()[19], b'8' | b'9' | b'a' | b'b')
+        && id
+            .chars()
+            .all(|c| c == '-' || c.is_ascii_digit() || c.is_ascii_lowercase())
+}
+
+#[test]
+fn ids_are_well_formed_and_unique() {
+    let ids = run_with_options(IDS, [], RunOptions::default()).unwrap();
+    let ids: Vec<_> = ids.iter().map(|id| id.as_str().unwrap()).collect();
+    assert!(is_uuid_v4(ids[0]) && is_uuid_v4(ids[1]), "{ids:?}");
+    assert_ne!(ids[0], ids[1]);
+    for ulid in &ids[2..4] {
+        assert_eq!(ulid.len(), 26);
+        assert!(
+            ulid.chars()
+                .all(|c| c.is_ascii_digit() || (c.is_ascii_uppercase() && !"ILOU".contains(c))),
+            "{ulid}"
+        );
+    }
+    assert_ne!(ids[2], ids[3]);
+    assert_eq!(ids[4], "886313e1-3b8a-5372-9b90-0c9aee199e5d");
+}
+
+#[test]
+fn a_seed_and_a_fixed_clock_replay_the_same_ids() {
+    let replay = |seed| {
+        let options = RunOptions {
+            random_seed: Some(seed),
+            clock: Some(ClockSource::Fixed(1_700_000_000)),
+            ..RunOptions::default()
+        };
+        run_with_options(IDS, [], options).unwrap()
+    };
+    assert_eq!(replay(7), replay(7));
+    assert_ne!(replay(7)[0], replay(8)[0]);
+    // The first ten characters of a ULID are its timestamp.
+    assert_eq!(replay(7)[2].as_str().unwrap()[..10], *"01HF7YAT00");
+}
+
+const SAMPLE: &str = r#"
+    local r = reservoir(3)
+    local kept = 0
+    local doc = get_next()
+    while doc ~= nil do
+        r:add(doc)
+        doc.n = -1
+        if sample(0.5) then kept = kept + 1 end
+        doc = get_next()
+    end
+    emit(r:items(), kept)
+"#;
+
+fn sampled(seed: u64) -> Vec<Value> {
+    let options = RunOptions {
+        random_seed: Some(seed),
+        ..RunOptions::default()
+    };
+    let input = (1..=100).map(|n| json!({ "n": n }));
+    run_with_options(SAMPLE, input, options).unwrap()
+}
+
+#[test]
+fn samples_are_bounded_copies_replayed_by_a_seed() {
+    let outputs = sampled(11);
+    assert_eq!(outputs, sampled(11));
+    assert_ne!(outputs, sampled(12));
+    let items = outputs[0].as_array().unwrap();
+    assert_eq!(items.len(), 3);
+    // Changing a document after adding it leaves the sample as it was.
+    assert!(
+        items.iter().all(|item| item["n"].as_i64().unwrap() >= 1),
+        "{items:?}"
+    );
+    let kept = outputs[1].as_i64().unwrap();
+    assert!((20..=80).contains(&kept), "{kept}");
+
+    let outputs = seeded(
+        "local r = reservoir(5) r:add(1) r:add(2) emit(r:items())",
+        1,
+    );
+    assert_eq!(outputs, [json!([1, 2])]);
+    assert_eq!(
+        seeded("emit(sample(0), sample(1))", 1),
+        [json!(false), json!(true)]
+    );
+}
+
+#[test]
+fn reservoirs_need_a_positive_size() {
+    let err = run_with_options("reservoir(0)", [], RunOptions::default()).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("reservoir size must be positive, got 0"),
+        "{err}"
+    );
+}
+
+#[test]
+fn reservoirs_only_hold_what_was_added() {
+    let script = "local r = reservoir(2^53) r:add('only') emit(r:items())";
+    assert_eq!(seeded(script, 1), [json!(["only"])]);
+}
@@ -0,0 +1,180 @@
+use mlua_play::{
+    Error, ErrorPolicy, JsonLinesSource, OutputOrder, ParallelOptions, RunOptions, Runner,
+    YamlSource, run_fanout, run_map, run_parallel_with_options, run_pipeline,
+};
+use serde_json::json;
+
+fn named(name: &str) -> RunOptions {
+    RunOptions {
+        script_name: Some(name.to_string()),
+        ..RunOptions::default()
+    }
+}
+
+#[test]
+fn syntax_errors_name_the_script_and_line() {
+    let err = Runner::with_options("local x = 1\nlocal = 2", named("broken.lua"))
+        .err()
+        .unwrap();
+    match err {
+        Error::ScriptSyntax { script, line, .. } => {
+            assert_eq!(script, "broken.lua");
+            assert_eq!(line, Some(2));
+        }
+        err => panic!("{err:?}"),
+    }
+}
+
+#[test]
+fn runtime_errors_carry_the_line_document_and_traceback() {
+    let script = r#"
+        local function check(doc)
+            if doc.bad then error("bad document") end
+        end
+        while true do
+            local doc = get_next()
+            if doc == nil then break end
+            check(doc)
+        end
+    "#;
+    let err = Runner::with_options(script, named("check.lua"))
+        .unwrap()
+        .run_batch([json!({}), json!({"bad": true})])
+        .unwrap_err();
+    match &err {
+        Error::ScriptRuntime {
+            script,
+            line,
+            message,
+            traceback,
+            document,
+        } => {
+            assert_eq!(script, "check.lua");
+            assert_eq!(*line, Some(3));
+            assert!(message.contains("bad document"), "{message}");
+            assert!(traceback.as_ref().unwrap().contains("check"));
+            assert_eq!(*document, Some(1));
+        }
+        err => panic!("{err:?}"),
+    }
+    let shown = err.to_string();
+    assert!(
+        shown.starts_with("runtime error in check.lua at line 3 while processing document 2"),
+        "{shown}"
+    );
+}
+
+#[test]
+fn messages_count_documents_from_one_like_doc_index() {
+    let echo = "local doc = get_next() while doc ~= nil do emit(doc) doc = get_next() end";
+    let input = JsonLinesSource::new("1\n2\n{oops\n".as_bytes());
+    let err = Runner::new(echo).unwrap().run_source(input).unwrap_err();
+    assert!(matches!(err, Error::InputError { index: 2, .. }), "{err:?}");
+    assert!(
+        err.to_string()
+            .starts_with("failed to read input document 3:\n  invalid JSON on line 3"),
+        "{err}"
+    );
+
+    let yaml = "a: 1\n---\nb: [\n";
+    let err = Runner::new(echo)
+        .unwrap()
+        .run_source(YamlSource::new(yaml.as_bytes()))
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .starts_with("failed to read input document 2:\n  invalid YAML in document 2"),
+        "{err}"
+    );
+
+    let options = ParallelOptions {
+        workers: 2,
+        order: OutputOrder::Input,
+        ..ParallelOptions::default()
+    };
+    let fail_on_three = "function transform(doc) if doc == 3 then error('three') end end";
+    let input = (1..=4).map(|n| json!(n));
+    let err = run_parallel_with_options(fail_on_three, input, options).unwrap_err();
+    assert!(matches!(err, Error::Document { index: 2, .. }), "{err:?}");
+    assert!(
+        err.to_string().starts_with(
+            "document 3: runtime error in script at line 1 while processing document 3"
+        ),
+        "{err}"
+    );
+
+    let fail = "error('no')";
+    let err = run_pipeline(&[echo, fail], [json!(1)]).unwrap_err();
+    assert!(err.to_string().starts_with("pipeline stage 2: "), "{err}");
+    let err = run_fanout(&[echo, fail], [json!(1)]).unwrap_err();
+    assert!(err.to_string().starts_with("script 2: "), "{err}");
+}
+
+#[test]
+fn conversion_errors_name_the_path_of_the_value() {
+    let err = Runner::new(r#"emit({ a = { b = "\xff" } })"#)
+        .unwrap()
+        .run_batch([])
+        .unwrap_err();
+    match err {
+        Error::Conversion { path, .. } => assert_eq!(path, "/a/b"),
+        err => panic!("{err:?}"),
+    }
+}
+
+const FAIL_ON_ODD: &str = r#"
+    function transform(doc)
+        if doc.n % 2 == 1 then error("odd") end
+        return doc.n
+    end
+"#;
+
+fn collecting(max_failures: usize) -> RunOptions {
+    RunOptions {
+        on_error: ErrorPolicy::Collect,
+        max_failures,
+        ..RunOptions::default()
+    }
+}
+
+#[test]
+fn collected_failures_keep_the_document_and_error() {
+    let input = (0..5).map(|n| json!({ "n": n }));
+    let output = run_map(FAIL_ON_ODD, input, collecting(100)).unwrap();
+    assert_eq!(output.outputs, [0, 2, 4]);
+    let indices = output.failures.iter().map(|f| f.index).collect::<Vec<_>>();
+    assert_eq!(indices, [1, 3]);
+    assert_eq!(output.failures[0].document, json!({"n": 1}));
+    assert!(
+        matches!(output.failures[0].error, Error::ScriptRuntime { .. }),
+        "{:?}",
+        output.failures[0].error
+    );
+    assert_eq!(output.failures_dropped, 0);
+}
+
+#[test]
+fn failures_past_the_cap_are_only_counted() {
+    let input = (0..10).map(|n| json!({ "n": n }));
+    let mut runner = Runner::with_options(FAIL_ON_ODD, collecting(2)).unwrap();
+    let output = runner.run_map(input).unwrap();
+    assert_eq!(output.failures.len(), 2);
+    assert_eq!(output.failures_dropped, 3);
+    assert_eq!(runner.stats().failures, 5);
+}
+
+#[test]
+fn the_first_failure_aborts_by_default() {
+    let input = (0..5).map(|n| json!({ "n": n }));
+    let err = run_map(FAIL_ON_ODD, input, RunOptions::default()).unwrap_err();
+    assert!(
+        matches!(
+            err,
+            Error::ScriptRuntime {
+                document: Some(1),
+                ..
+            }
+        ),
+        "{err:?}"
+    );
+}
@@ -0,0 +1,389 @@
+use std::io::Cursor;
+
+use mlua_play::{
+    CsvColumns, CsvSink, CsvSource, Error, InputSource, JsonDialect, JsonDocumentSource,
+    JsonLinesSource, JsonSeqSink, JsonSeqSource, MsgpackSink, MsgpackSource, OutputSink, Runner,
+    TomlSink, TomlSource, XmlOptions, XmlSource, YamlSink, YamlSource,
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+const DOUBLE: &str = r#"
+    local doc = get_next()
+    while doc ~= nil do
+        doc.replicas = doc.replicas * 2
+        emit(doc)
+        doc = get_next()
+    end
+"#;
+
+/// Every document of `source`, drained directly.
+fn drain(mut source: impl InputSource) -> Vec<Value> {
+    let mut docs = Vec::new();
+    while let Some(doc) = source.next_doc().unwrap() {
+        docs.push(doc);
+    }
+    docs
+}
+
+/// Writes `docs` to `sink`, handing back the sink to read what it wrote.
+fn write_all<S: OutputSink>(mut sink: S, docs: Vec<Value>) -> S {
+    for doc in docs {
+        sink.emit("out", doc).unwrap();
+    }
+    sink.finish().unwrap();
+    sink
+}
+
+#[test]
+fn yaml_streams_are_read_and_written_a_document_at_a_time() {
+    let yaml = "\
+name: web
+replicas: 2
+base: &base { image: nginx }
+---
+name: worker
+replicas: 3
+ports: [80, 443]
+";
+    let mut runner = Runner::new(DOUBLE).unwrap();
+    let outputs = runner
+        .run_source(YamlSource::new(Cursor::new(yaml)))
+        .unwrap();
+    assert_eq!(
+        outputs,
+        [
+            json!({"name": "web", "replicas": 4, "base": {"image": "nginx"}}),
+            json!({"name": "worker", "replicas": 6, "ports": [80, 443]}),
+        ]
+    );
+
+    let written = write_all(YamlSink::new(Vec::new()), outputs.clone()).into_inner();
+    let written = String::from_utf8(written).unwrap();
+    assert_eq!(written.matches("---").count(), 1, "{written}");
+    let read_back: Vec<Value> = serde_yaml::Deserializer::from_str(&written)
+        .map(|doc| Value::deserialize(doc).unwrap())
+        .collect();
+    assert_eq!(read_back, outputs);
+}
+
+#[test]
+fn yaml_aliases_resolve_and_odd_keys_become_strings() {
+    let yaml = "defaults: &d { retries: 3 }\nservice: *d\n1: one\ntrue: yes\n";
+    let docs = drain(YamlSource::new(Cursor::new(yaml)));
+    assert_eq!(
+        docs,
+        [json!({
+            "defaults": {"retries": 3},
+            "service": {"retries": 3},
+            "1": "one",
+            "true": "yes",
+        })]
+    );
+}
+
+#[test]
+fn toml_files_are_a_single_document() {
+    let toml = r#"
+[package]
+name = "demo"
+released = 1979-05-27T07:32:00Z
+
+[package.metadata]
+tags = ["a", "b"]
+
+[[bin]]
+name = "first"
+
+[[bin]]
+name = "second"
+path = "src/second.rs"
+"#;
+    let script = r#"
+        local doc = get_next()
+        emit(doc.package.metadata.tags[2], #doc.bin, doc.bin[2].path, get_next())
+        emit(doc)
+    "#;
+    let mut runner = Runner::new(script).unwrap();
+    let outputs = runner.run_source(TomlSource::new(toml.as_bytes())).unwrap();
+    assert_eq!(
+        outputs,
+        [
+            json!("b"),
+            json!(2),
+            json!("src/second.rs"),
+            Value::Null,
+            json!({
+                "package": {
+                    "name": "demo",
+                    "released": "1979-05-27T07:32:00Z",
+                    "metadata": {"tags": ["a", "b"]},
+                },
+                "bin": [{"name": "first"}, {"name": "second", "path": "src/second.rs"}],
+            }),
+        ]
+    );
+}
+
+#[test]
+fn toml_output_takes_one_object_without_nulls() {
+    let sink = write_all(TomlSink::new(Vec::new()), vec![json!({"a": {"b": [1, 2]}})]);
+    let text = String::from_utf8(sink.into_inner()).unwrap();
+    assert_eq!(
+        toml::from_str::<Value>(&text).unwrap(),
+        json!({"a": {"b": [1, 2]}})
+    );
+
+    let mut sink = TomlSink::new(Vec::new());
+    let err = sink.emit("out", json!({"a": [1, null]})).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("null at '/a/1' has no TOML representation"),
+        "{err}"
+    );
+    let err = sink.emit("out", json!([1])).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("needs the document to be an object"),
+        "{err}"
+    );
+
+    let mut sink = TomlSink::new(Vec::new());
+    sink.emit("out", json!({"a": 1})).unwrap();
+    let err = sink.emit("out", json!({"a": 2})).unwrap_err();
+    assert!(
+        err.to_string().contains("a second one was emitted"),
+        "{err}"
+    );
+}
+
+#[test]
+fn csv_round_trips_quoted_fields() {
+    let csv = "name,quote,n\n\"Doe, Jane\",\"she said \"\"hi\"\"\",3\nBob,,4\n";
+    let docs = drain(CsvSource::new(csv.as_bytes()));
+    assert_eq!(
+        docs,
+        [
+            json!({"name": "Doe, Jane", "quote": "she said \"hi\"", "n": "3"}),
+            json!({"name": "Bob", "quote": "", "n": "4"}),
+        ]
+    );
+    let typed = drain(CsvSource::new(csv.as_bytes()).infer_types());
+    assert_eq!(typed[1], json!({"name": "Bob", "quote": null, "n": 4}));
+
+    let mut written = Vec::new();
+    let columns = ["name", "quote", "n"].map(String::from).to_vec();
+    write_all(
+        CsvSink::new(&mut written).columns(CsvColumns::Named(columns)),
+        docs,
+    );
+    assert_eq!(String::from_utf8(written).unwrap(), csv);
+}
+
+#[test]
+fn csv_output_needs_nested_values_flattened() {
+    let doc = json!({"id": 1, "user": {"name": "ann", "tags": ["x", "y"]}});
+    let mut sink = CsvSink::new(Vec::new());
+    let err = sink.emit("out", doc.clone()).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("column 'user' holds a nested value"),
+        "{err}"
+    );
+
+    let mut written = Vec::new();
+    write_all(CsvSink::new(&mut written).flatten("."), vec![doc]);
+    assert_eq!(
+        String::from_utf8(written).unwrap(),
+        "id,user.name,user.tags.0,user.tags.1\n1,ann,x,y\n"
+    );
+}
+
+#[test]
+fn csv_union_columns_cover_every_document() {
+    let mut written = Vec::new();
+    let docs = vec![json!({"b": 1}), json!({"a": true, "c": [] })];
+    write_all(CsvSink::new(&mut written).columns(CsvColumns::Union), docs);
+    assert_eq!(
+        String::from_utf8(written).unwrap(),
+        "a,b,c\n,1,\ntrue,,[]\n"
+    );
+}
+
+#[test]
+fn msgpack_round_trips_nested_documents() {
+    let docs = vec![
+        json!({"user": {"name": "ann", "roles": ["admin", {"scope": 3}]}, "score": -1.5}),
+        json!([1, null, true]),
+    ];
+    let written = write_all(MsgpackSink::new(Vec::new()), docs.clone()).into_inner();
+    assert_eq!(drain(MsgpackSource::new(written.as_slice())), docs);
+
+    let written = write_all(MsgpackSink::new(Vec::new()).length_prefixed(), docs.clone());
+    let written = written.into_inner();
+    // {"user": ...} is a map of two entries, after its four length bytes.
+    assert_eq!(written[4], 0x82);
+    let source = MsgpackSource::new(written.as_slice()).length_prefixed();
+    assert_eq!(drain(source), docs);
+}
+
+#[test]
+fn msgpack_binary_values_are_read_as_utf8_strings() {
+    // {"b": bin8 "hi"}, then {1: "x"}.
+    let bytes = [
+        0x81, 0xa1, b'b', 0xc4, 0x02, b'h', b'i', 0x81, 0x01, 0xa1, b'x',
+    ];
+    assert_eq!(
+        drain(MsgpackSource::new(&bytes[..])),
+        [json!({"b": "hi"}), json!({"1": "x"})]
+    );
+
+    let mut source = MsgpackSource::new(&[0x81, 0xa1, b'b', 0xc4, 0x01, 0xff][..]);
+    let err = source.next_doc().unwrap_err();
+    assert!(
+        err.to_string().contains("binary value is not UTF-8"),
+        "{err}"
+    );
+}
+
+#[cfg(feature = "cbor")]
+#[test]
+fn cbor_maps_byte_strings_tags_and_integer_keys() {
+    use mlua_play::{CborSink, CborSource};
+
+    // {"b": h'6869', 1: "x", "t": 1(1700000000)}
+    let bytes = [
+        0xa3, 0x61, b'b', 0x42, b'h', b'i', 0x01, 0x61, b'x', 0x61, b't', 0xc1, 0x1a, 0x65, 0x53,
+        0xf1, 0x00,
+    ];
+    let expected = json!({"b": "hi", "1": "x", "t": 1_700_000_000});
+    assert_eq!(
+        drain(CborSource::new(&bytes[..])),
+        std::slice::from_ref(&expected)
+    );
+
+    let mut strict = CborSource::new(&bytes[..]).reject_non_string_keys();
+    let err = strict.next_doc().unwrap_err();
+    assert!(err.to_string().contains("map key is not a string"), "{err}");
+
+    let docs = vec![expected, json!([1.5, null, {"nested": [true]}])];
+    let written = write_all(CborSink::new(Vec::new()), docs.clone()).into_inner();
+    assert_eq!(drain(CborSource::new(written.as_slice())), docs);
+}
+
+#[test]
+fn jsonc_configs_may_have_comments_and_trailing_commas() {
+    let config = r#"{
+    // Where to listen.
+    "listen": "0.0.0.0:8080", /* the usual */
+    "urls": ["http://a/*not a comment*/", "b",],
+}
+"#;
+    let expected = json!({"listen": "0.0.0.0:8080", "urls": ["http://a/*not a comment*/", "b"]});
+    for dialect in [JsonDialect::Jsonc, JsonDialect::Json5] {
+        let source = JsonDocumentSource::new(config.as_bytes(), dialect);
+        assert_eq!(drain(source), std::slice::from_ref(&expected));
+    }
+
+    let lines = "{\"a\": 1,} // first\n// nothing here\n[2, 3,]\n";
+    let source = JsonLinesSource::new(lines.as_bytes()).dialect(JsonDialect::Jsonc);
+    assert_eq!(drain(source), [json!({"a": 1}), json!([2, 3])]);
+}
+
+#[test]
+fn malformed_jsonc_and_json5_point_at_the_original_text() {
+    let config = "{\n  /* a\n     comment */ \"a\": 1,\n  \"b\" 2\n}\n";
+    let mut source = JsonDocumentSource::new(config.as_bytes(), JsonDialect::Jsonc);
+    match source.next_doc().unwrap_err() {
+        Error::InvalidJson { line, source } => {
+            assert_eq!((line, source.column()), (4, 7));
+        }
+        err => panic!("{err:?}"),
+    }
+
+    let mut source = JsonDocumentSource::new(config.as_bytes(), JsonDialect::Json5);
+    match source.next_doc().unwrap_err() {
+        Error::InvalidJson5 { line, column, .. } => assert_eq!((line, column), (4, 3)),
+        err => panic!("{err:?}"),
+    }
+    let unquoted = "{unquoted: 'single', hex: 0x10}";
+    let source = JsonDocumentSource::new(unquoted.as_bytes(), JsonDialect::Json5);
+    assert_eq!(drain(source), [json!({"unquoted": "single", "hex": 16})]);
+}
+
+#[test]
+fn xml_maps_attributes_repeated_children_and_mixed_content() {
+    let xml = r#"<?xml version="1.0"?>
+<order id="7" xmlns:x="urn:x">
+  <!-- dropped -->
+  <item sku="a">Apple</item>
+  <item sku="b"/>
+  <x:note>Leave <b>at</b> the door</x:note>
+  <empty/>
+</order>"#;
+    let docs = drain(XmlSource::new(xml.as_bytes(), XmlOptions::default()));
+    assert_eq!(
+        docs,
+        [json!({"order": {
+            "@id": "7",
+            "item": [{"@sku": "a", "#text": "Apple"}, {"@sku": "b"}],
+            "note": {"b": "at", "#text": "Leave the door"},
+            "empty": null,
+        }})]
+    );
+
+    let options = XmlOptions {
+        keep_namespaces: true,
+        ..XmlOptions::default()
+    };
+    let docs = drain(XmlSource::new(xml.as_bytes(), options));
+    assert_eq!(docs[0]["order"]["@xmlns:x"], "urn:x");
+    assert_eq!(docs[0]["order"]["x:note"]["#text"], "Leave the door");
+}
+
+#[test]
+fn xml_records_are_read_one_document_each() {
+    let xml =
+        "<feed><meta>skipped</meta><entry n=\"1\">a</entry><group><entry n=\"2\"/></group></feed>";
+    let options = XmlOptions {
+        record: Some("entry".to_string()),
+        ..XmlOptions::default()
+    };
+    assert_eq!(
+        drain(XmlSource::new(xml.as_bytes(), options)),
+        [json!({"@n": "1", "#text": "a"}), json!({"@n": "2"})]
+    );
+
+    let script = r#"
+        local doc = get_next()
+        emit(xml.decode(doc.body), xml.decode(doc.body, { record = "entry" }))
+    "#;
+    let mut runner = Runner::new(script).unwrap();
+    let outputs = runner
+        .run_batch([json!({"body": "<feed><entry>x</entry><entry>y</entry></feed>"})])
+        .unwrap();
+    assert_eq!(
+        outputs,
+        [json!({"feed": {"entry": ["x", "y"]}}), json!(["x", "y"])]
+    );
+}
+
+#[test]
+fn json_seq_carries_on_past_a_corrupted_record() {
+    let seq = "\x1e{\"a\":1}\n\x1e{oops\n\x1e\x1e{\n  \"b\": 2\n}\n\x1e3";
+    let mut source = JsonSeqSource::new(seq.as_bytes());
+    assert_eq!(source.next_doc().unwrap(), Some(json!({"a": 1})));
+    match source.next_doc().unwrap_err() {
+        Error::InvalidJsonSeq { record, .. } => assert_eq!(record, 2),
+        err => panic!("{err:?}"),
+    }
+    assert_eq!(source.next_doc().unwrap(), Some(json!({"b": 2})));
+    // A number without its final newline may have been cut short.
+    assert!(source.next_doc().is_err());
+    assert_eq!(source.next_doc().unwrap(), None);
+
+    let docs = vec![json!({"b": [1, 2]}), json!("x")];
+    let written = write_all(JsonSeqSink::new(Vec::new()).pretty(), docs.clone()).into_inner();
+    assert!(written.starts_with(b"\x1e{\n"));
+    assert_eq!(drain(JsonSeqSource::new(written.as_slice())), docs);
+}
@@ -0,0 +1,100 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use mlua_play::{Error, Hooks, Mode, RunOptions, Runner};
+use serde_json::json;
+
+const TRANSFORM: &str = r#"
+    function transform(doc)
+        if doc.fail then error("failed") end
+        return doc.n
+    end
+"#;
+
+fn with_hooks(hooks: Hooks) -> RunOptions {
+    RunOptions {
+        mode: Mode::Map,
+        hooks,
+        ..RunOptions::default()
+    }
+}
+
+#[test]
+fn document_hooks_surround_every_document() {
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    let hooks = Hooks {
+        on_document_start: Some(Box::new({
+            let calls = calls.clone();
+            move |index, doc| calls.borrow_mut().push(format!("start {index} {doc}"))
+        })),
+        on_document_end: Some(Box::new({
+            let calls = calls.clone();
+            move |index, result| {
+                let outcome = if result.is_ok() { "ok" } else { "failed" };
+                calls.borrow_mut().push(format!("end {index} {outcome}"))
+            }
+        })),
+        on_emit: Some(Box::new({
+            let calls = calls.clone();
+            move |index, value| {
+                calls.borrow_mut().push(format!("emit {index} {value}"));
+                Ok(())
+            }
+        })),
+    };
+    let mut runner = Runner::with_options(TRANSFORM, with_hooks(hooks)).unwrap();
+    runner
+        .run_batch([json!({"n": 1}), json!({"fail": true})])
+        .unwrap_err();
+    assert_eq!(
+        *calls.borrow(),
+        [
+            r#"start 0 {"n":1}"#,
+            "emit 0 1",
+            "end 0 ok",
+            r#"start 1 {"fail":true}"#,
+            "end 1 failed",
+        ]
+    );
+}
+
+#[test]
+fn an_emit_hook_can_veto_a_document() {
+    let hooks = Hooks {
+        on_emit: Some(Box::new(|_, value| match value.as_i64() {
+            Some(n) if n > 1 => Err(format!("{n} is too large")),
+            _ => Ok(()),
+        })),
+        ..Hooks::default()
+    };
+    let err = Runner::with_options(TRANSFORM, with_hooks(hooks))
+        .unwrap()
+        .run_batch([json!({"n": 1}), json!({"n": 2})])
+        .unwrap_err();
+    match err {
+        Error::Hook { hook, message } => {
+            assert_eq!(hook, "on_emit");
+            assert_eq!(message, "2 is too large");
+        }
+        err => panic!("{err:?}"),
+    }
+}
+
+#[test]
+fn a_panicking_hook_fails_the_run() {
+    let hooks = Hooks {
+        on_document_start: Some(Box::new(|_, _| panic!("hook blew up"))),
+        ..Hooks::default()
+    };
+    let err = Runner::with_options(TRANSFORM, with_hooks(hooks))
+        .unwrap()
+        .run_batch([json!({"n": 1})])
+        .unwrap_err();
+    match err {
+        Error::Hook { hook, message } => {
+            assert_eq!(hook, "on_document_start");
+            assert!(message.contains("hook blew up"), "{message}");
+        }
+        err => panic!("{err:?}"),
+    }
+}
@@ -0,0 +1,519 @@
+use std::cell::Cell;
+use std::io::Cursor;
+use std::rc::Rc;
+
+use mlua::Table as LuaTable;
+use mlua_play::{Error, Explode, JsonLinesSource, Mode, RunOptions, Runner, run_with_options};
+use serde_json::{Value, json};
+
+const ECHO: &str = r#"
+    local doc = get_next()
+    while doc ~= nil do
+        emit(doc)
+        doc = get_next()
+    end
+"#;
+
+#[test]
+fn named_inputs_are_read_by_name() {
+    let script = r#"
+        local user = get_next("users")
+        local event = get_next()
+        emit({ user = user.name, event = event.kind })
+        emit(read_all("users"))
+    "#;
+    let mut runner = Runner::new(script).unwrap().with_input(
+        "users",
+        [
+            json!({"name": "ada"}),
+            json!({"name": "bob"}),
+            json!({"name": "cy"}),
+        ],
+    );
+    let outputs = runner.run_batch([json!({"kind": "login"})]).unwrap();
+    assert_eq!(
+        outputs,
+        [
+            json!({"user": "ada", "event": "login"}),
+            json!([{"name": "bob"}, {"name": "cy"}]),
+        ]
+    );
+}
+
+#[test]
+fn named_inputs_carry_over_between_batches() {
+    let mut runner = Runner::new("emit(get_next('ids'))").unwrap();
+    runner.set_input("ids", [json!(1), json!(2)]);
+    assert_eq!(runner.run_batch([]).unwrap(), [1]);
+    assert_eq!(runner.run_batch([]).unwrap(), [2]);
+}
+
+#[test]
+fn unknown_inputs_are_script_errors() {
+    let err = Runner::new("get_next('missing')")
+        .unwrap()
+        .run_batch([])
+        .unwrap_err();
+    assert!(err.to_string().contains("unknown input 'missing'"), "{err}");
+}
+
+#[test]
+fn peek_looks_ahead_without_consuming() {
+    let script = r#"
+        local ahead = peek()
+        ahead.seen = true
+        local again = peek()
+        local doc = get_next()
+        emit({ same = rawequal(ahead, doc), again = again.n })
+        emit(doc)
+        emit(peek())
+        get_next()
+        emit({ done = peek() == nil })
+    "#;
+    let outputs = Runner::new(script)
+        .unwrap()
+        .run_batch([json!({"n": 1}), json!({"n": 2})])
+        .unwrap();
+    assert_eq!(
+        outputs,
+        [
+            json!({"same": true, "again": 1}),
+            json!({"n": 1, "seen": true}),
+            json!({"n": 2}),
+            json!({"done": true}),
+        ]
+    );
+}
+
+#[test]
+fn peeking_reads_one_document_ahead_at_most() {
+    let pulled = Rc::new(Cell::new(0));
+    let input = {
+        let pulled = pulled.clone();
+        (1..=3).map(move |n| {
+            pulled.set(pulled.get() + 1);
+            json!(n)
+        })
+    };
+    let mut runner = Runner::new("peek() peek() emit('peeked')").unwrap();
+    assert_eq!(runner.run_batch(input).unwrap(), ["peeked"]);
+    assert_eq!(pulled.get(), 1);
+    // A document counts as read once `get_next` hands it over.
+    assert_eq!(runner.stats().documents_read, 0);
+}
+
+#[test]
+fn named_inputs_can_be_peeked_too() {
+    let script = r#"
+        local ahead = peek("users")
+        ahead.seen = true
+        local merged = {}
+        while has_next("users") or has_next("events") do
+            local user, event = peek("users"), peek("events")
+            if event == nil or (user ~= nil and user.at <= event.at) then
+                table.insert(merged, get_next("users").at)
+            else
+                table.insert(merged, get_next("events").at)
+            end
+        end
+        emit(merged, { seen = ahead.seen, done = peek("users") == nil })
+    "#;
+    let mut runner = Runner::new(script)
+        .unwrap()
+        .with_input("users", [json!({"at": 1}), json!({"at": 4})])
+        .with_input("events", [json!({"at": 2}), json!({"at": 3})]);
+    let outputs = runner.run_batch([]).unwrap();
+    assert_eq!(
+        outputs,
+        [json!([1, 2, 3, 4]), json!({"seen": true, "done": true})]
+    );
+
+    // A peeked document stays in its input for the next batch.
+    let script = r#"
+        batches = (batches or 0) + 1
+        if batches == 1 then
+            emit(has_next("ids"), peek("ids"))
+        else
+            emit(get_next("ids"), has_next("ids"))
+        end
+    "#;
+    let mut runner = Runner::new(script).unwrap().with_input("ids", [json!(7)]);
+    assert_eq!(runner.run_batch([]).unwrap(), [json!(true), json!(7)]);
+    assert_eq!(runner.run_batch([]).unwrap(), [json!(7), json!(false)]);
+}
+
+#[test]
+fn get_batch_hands_out_documents_in_chunks() {
+    let script = r#"
+        while true do
+            local docs = get_batch(2)
+            if docs == nil then break end
+            local sum = 0
+            for _, doc in ipairs(docs) do sum = sum + doc.n end
+            emit({ size = #docs, sum = sum })
+        end
+    "#;
+    let input = (1..=5).map(|n| json!({ "n": n }));
+    let outputs = Runner::new(script).unwrap().run_batch(input).unwrap();
+    assert_eq!(
+        outputs,
+        [
+            json!({"size": 2, "sum": 3}),
+            json!({"size": 2, "sum": 7}),
+            json!({"size": 1, "sum": 5}),
+        ]
+    );
+}
+
+#[test]
+fn get_batch_starts_with_a_peeked_document() {
+    let script = r#"
+        peek()
+        local docs = get_batch(2)
+        emit(docs[1], docs[2], get_next())
+    "#;
+    let outputs = Runner::new(script)
+        .unwrap()
+        .run_batch([json!("a"), json!("b"), json!("c")])
+        .unwrap();
+    assert_eq!(outputs, ["a", "b", "c"]);
+}
+
+#[test]
+fn null_documents_are_told_apart_from_the_end_of_input() {
+    let script = r#"
+        local seen = 0
+        while has_next() do
+            emit({ doc = get_next() })
+            seen = seen + 1
+        end
+        emit({ seen = seen, ended = not has_next() })
+    "#;
+    let outputs = Runner::new(script)
+        .unwrap()
+        .run_batch([json!(1), json!(null), json!(2)])
+        .unwrap();
+    assert_eq!(
+        outputs,
+        [
+            json!({"doc": 1}),
+            json!([]),
+            json!({"doc": 2}),
+            json!({"seen": 3, "ended": true}),
+        ]
+    );
+}
+
+#[test]
+fn get_next_and_peek_return_the_document_alone() {
+    let script = r#"
+        emit(select('#', peek()), select('#', get_next()))
+        emit(get_next())
+        emit(peek("ids"))
+        emit(get_next("ids"))
+    "#;
+    let outputs = Runner::new(script)
+        .unwrap()
+        .with_input("ids", [json!(7)])
+        .run_batch([json!({"n": 1}), json!({"n": 2})])
+        .unwrap();
+    assert_eq!(
+        outputs,
+        [json!(1), json!(1), json!({"n": 2}), json!(7), json!(7)]
+    );
+}
+
+#[test]
+fn tables_from_the_same_state_are_read_as_documents() {
+    let mut runner = Runner::new("local doc = get_next() doc.n = doc.n * 2 emit(doc)").unwrap();
+    let table: LuaTable = runner
+        .lua()
+        .load("return { n = 21, tags = { 'a' } }")
+        .eval()
+        .unwrap();
+    let outputs = runner.run_tables([table]).unwrap();
+    assert_eq!(outputs, [json!({"n": 42, "tags": ["a"]})]);
+}
+
+#[test]
+fn tables_failing_to_convert_fail_the_run() {
+    let mut runner = Runner::new("emit(get_next())").unwrap();
+    let table: LuaTable = runner.lua().load(r#"return { "\xff" }"#).eval().unwrap();
+    let err = runner.run_tables([table]).unwrap_err();
+    match err {
+        Error::InputError { index, source } => {
+            assert_eq!(index, 0);
+            assert!(matches!(*source, Error::Conversion { .. }), "{source:?}");
+        }
+        err => panic!("{err:?}"),
+    }
+}
+
+fn exploding(explode: Explode) -> RunOptions {
+    RunOptions {
+        explode: Some(explode),
+        ..RunOptions::default()
+    }
+}
+
+#[test]
+fn envelopes_explode_into_their_items() {
+    let explode = Explode {
+        parent_key: Some("_parent".to_string()),
+        ..Explode::new("$.items[*]")
+    };
+    let input = [
+        json!({"batch_id": 1, "items": [{"n": 1}, {"n": 2}, {"n": 3}]}),
+        json!({"batch_id": 2, "items": [{"n": 4}, "plain"]}),
+        json!({"batch_id": 3}),
+    ];
+    let outputs = run_with_options(ECHO, input, exploding(explode)).unwrap();
+    assert_eq!(
+        outputs,
+        [
+            json!({"n": 1, "_parent": {"batch_id": 1}}),
+            json!({"n": 2, "_parent": {"batch_id": 1}}),
+            json!({"n": 3, "_parent": {"batch_id": 1}}),
+            json!({"n": 4, "_parent": {"batch_id": 2}}),
+            json!("plain"),
+        ]
+    );
+}
+
+#[test]
+fn unmatched_documents_can_be_kept() {
+    let explode = Explode {
+        keep_unmatched: true,
+        ..Explode::new("/items")
+    };
+    let input = [json!({"items": [1, 2]}), json!({"other": true})];
+    let outputs = run_with_options(ECHO, input, exploding(explode)).unwrap();
+    assert_eq!(outputs, [json!(1), json!(2), json!({"other": true})]);
+
+    let err = Runner::with_options(ECHO, exploding(Explode::new("$..items"))).err();
+    assert!(
+        matches!(
+            err,
+            Some(Error::InvalidOption {
+                option: "explode",
+                ..
+            })
+        ),
+        "{err:?}"
+    );
+}
+
+#[test]
+fn doc_index_counts_documents_handed_over() {
+    let script = r#"
+        emit(doc_index())
+        local doc = get_next()
+        while doc ~= nil do
+            emit(string.format("record %d: %s", doc_index(), doc.name))
+            doc = get_next()
+        end
+        emit(doc_index())
+    "#;
+    let input = [
+        json!({"name": "a"}),
+        json!({"name": "b"}),
+        json!({"name": "c"}),
+    ];
+    assert_eq!(
+        run_with_options(script, input, RunOptions::default()).unwrap(),
+        [
+            json!(0),
+            json!("record 1: a"),
+            json!("record 2: b"),
+            json!("record 3: c"),
+            json!(3),
+        ]
+    );
+}
+
+#[test]
+fn transform_sees_the_index_of_its_document() {
+    let options = RunOptions {
+        mode: Mode::Map,
+        ..RunOptions::default()
+    };
+    let script = "function transform(doc) return doc_index() end";
+    let outputs = run_with_options(script, [json!("a"), json!("b")], options).unwrap();
+    assert_eq!(outputs, [1, 2]);
+}
+
+#[test]
+fn doc_meta_says_where_a_document_was_read() {
+    let script = r#"
+        emit(doc_meta())
+        local doc = get_next()
+        while doc ~= nil do
+            emit(doc_meta())
+            doc = get_next()
+        end
+    "#;
+    let source = JsonLinesSource::new(Cursor::new("{}\n\n{}\n[]")).with_source("in.jsonl");
+    let outputs = Runner::new(script).unwrap().run_source(source).unwrap();
+    assert_eq!(
+        outputs,
+        [
+            json!({"index": 0}),
+            json!({"index": 1, "line": 1, "source": "in.jsonl"}),
+            json!({"index": 2, "line": 3, "source": "in.jsonl"}),
+            json!({"index": 3, "line": 4, "source": "in.jsonl"}),
+        ]
+    );
+
+    let outputs = run_with_options(script, [json!(1)], RunOptions::default()).unwrap();
+    assert_eq!(outputs, [json!({"index": 0}), json!({"index": 1})]);
+}
+
+#[test]
+fn prev_gives_a_moving_average() {
+    let script = r#"
+        window(2)
+        local doc = get_next()
+        while doc ~= nil do
+            local sum, count = doc.v, 1
+            for k = 1, 2 do
+                local before = prev(k)
+                if before ~= nil then
+                    sum, count = sum + before.v, count + 1
+                end
+            end
+            emit(sum / count)
+            doc = get_next()
+        end
+        emit(tostring(select(2, pcall(prev, 3))))
+    "#;
+    let input = [3, 6, 9, 12, 0].map(|v| json!({"v": v}));
+    let outputs = run_with_options(script, input, RunOptions::default()).unwrap();
+    assert_eq!(
+        outputs[..5],
+        [json!(3), json!(4.5), json!(6), json!(9), json!(7)]
+    );
+    let err = outputs[5].as_str().unwrap();
+    assert!(
+        err.contains("prev(3) is outside the window of 2 documents"),
+        "{err}"
+    );
+}
+
+#[test]
+fn the_window_keeps_copies_of_emitted_documents() {
+    let script = r#"
+        window(2)
+        emit(get_next())
+        get_next()
+        emit(prev(1), prev(2) == nil)
+    "#;
+    let outputs = run_with_options(script, [json!({"a": 1}), json!(2)], RunOptions::default());
+    assert_eq!(
+        outputs.unwrap(),
+        [json!({"a": 1}), json!({"a": 1}), json!(true)]
+    );
+}
+
+#[test]
+fn transform_gets_the_window_too() {
+    let options = RunOptions {
+        mode: Mode::Map,
+        ..RunOptions::default()
+    };
+    let script = r#"
+        window(2)
+        function transform(doc, window)
+            return { doc = doc, window = window }
+        end
+    "#;
+    let outputs = run_with_options(script, [json!(1), json!(2), json!(3)], options).unwrap();
+    assert_eq!(
+        outputs,
+        [
+            json!({"doc": 1, "window": []}),
+            json!({"doc": 2, "window": [1]}),
+            json!({"doc": 3, "window": [2, 1]}),
+        ]
+    );
+}
+
+/// Orders 1 to 6, one of them by a user missing from [`users`].
+fn orders() -> Vec<Value> {
+    ["u1", "u2", "u1", "u9", "u3", "u2"]
+        .iter()
+        .enumerate()
+        .map(|(i, user)| json!({"order": i + 1, "user": user}))
+        .collect()
+}
+
+fn users() -> Value {
+    json!([
+        {"id": "u1", "name": "ada"},
+        {"id": "u2", "name": "bob"},
+        {"id": "u3", "name": "cy"},
+    ])
+}
+
+/// Joins the `orders` input against the users read as the only document.
+fn join_orders(opts: &str) -> Value {
+    let script = format!(
+        "emit(join('orders', get_next(), {{ left_key = 'user', right_key = 'id', {opts} }}))"
+    );
+    let mut runner = Runner::new(&script).unwrap().with_input("orders", orders());
+    let mut outputs = runner.run_batch([users()]).unwrap();
+    assert_eq!(outputs.len(), 1);
+    outputs.remove(0)
+}
+
+#[test]
+fn join_matches_a_lookup_against_an_input() {
+    let inner = join_orders("");
+    assert_eq!(inner.as_array().unwrap().len(), 5);
+    assert_eq!(
+        inner[0],
+        json!({"left": {"order": 1, "user": "u1"}, "right": {"id": "u1", "name": "ada"}})
+    );
+    assert!(
+        inner
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|pair| pair["left"]["user"] != "u9")
+    );
+
+    let left = join_orders("type = 'left'");
+    assert_eq!(left.as_array().unwrap().len(), 6);
+    assert_eq!(
+        left[3],
+        json!({"left": {"order": 4, "user": "u9"}, "right": null})
+    );
+
+    assert_eq!(
+        join_orders("type = 'left', merge = 'shallow'"),
+        json!([
+            {"order": 1, "user": "u1", "id": "u1", "name": "ada"},
+            {"order": 2, "user": "u2", "id": "u2", "name": "bob"},
+            {"order": 3, "user": "u1", "id": "u1", "name": "ada"},
+            {"order": 4, "user": "u9"},
+            {"order": 5, "user": "u3", "id": "u3", "name": "cy"},
+            {"order": 6, "user": "u2", "id": "u2", "name": "bob"},
+        ])
+    );
+}
+
+#[test]
+fn join_conflicts_can_be_errors() {
+    let script = r#"
+        join({ { id = 1, v = "l" } }, { { id = 1, v = "r" } }, {
+            on = "id",
+            merge = "shallow",
+            conflict = "error",
+        })
+    "#;
+    let err = run_with_options(script, [], RunOptions::default()).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("field 'id' is on both sides of the join"),
+        "{err}"
+    );
+}
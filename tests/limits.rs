@@ -0,0 +1,254 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mlua_play::{
+    CancellationToken, DocumentLimitPolicy, Error, Failure, Limit, Mode, RunOptions, Runner,
+};
+use serde_json::json;
+
+const SPIN: &str = "while true do end";
+
+#[test]
+fn cancelling_from_another_thread_stops_the_script() {
+    let token = CancellationToken::new();
+    let canceller = {
+        let token = token.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            token.cancel();
+        })
+    };
+    let options = RunOptions {
+        cancellation: Some(token),
+        ..RunOptions::default()
+    };
+    let err = Runner::with_options(SPIN, options)
+        .unwrap()
+        .run_batch([])
+        .unwrap_err();
+    canceller.join().unwrap();
+    assert!(matches!(err, Error::Cancelled), "{err:?}");
+}
+
+#[test]
+fn a_token_cancelled_up_front_stops_the_script_right_away() {
+    let token = CancellationToken::new();
+    token.cancel();
+    let options = RunOptions {
+        cancellation: Some(token),
+        ..RunOptions::default()
+    };
+    let err = Runner::with_options(SPIN, options)
+        .unwrap()
+        .run_batch([])
+        .unwrap_err();
+    assert!(matches!(err, Error::Cancelled), "{err:?}");
+}
+
+#[test]
+fn an_instruction_budget_stops_a_runaway_script() {
+    let options = RunOptions {
+        max_instructions: Some(100_000),
+        ..RunOptions::default()
+    };
+    let err = Runner::with_options(SPIN, options)
+        .unwrap()
+        .run_batch([])
+        .unwrap_err();
+    match err {
+        Error::LimitExceeded {
+            which: Limit::Instructions { executed },
+            ..
+        } => assert!(executed >= 100_000, "{executed}"),
+        err => panic!("{err:?}"),
+    }
+}
+
+#[test]
+fn the_instruction_budget_starts_over_with_every_batch() {
+    let options = RunOptions {
+        max_instructions: Some(1_000_000),
+        ..RunOptions::default()
+    };
+    let mut runner = Runner::with_options("for i = 1, 100000 do end emit(true)", options).unwrap();
+    for _ in 0..20 {
+        assert_eq!(runner.run_batch([]).unwrap(), [true]);
+    }
+}
+
+#[test]
+fn a_timeout_stops_a_script_that_runs_too_long() {
+    let options = RunOptions {
+        timeout: Some(Duration::from_millis(50)),
+        ..RunOptions::default()
+    };
+    let started = Instant::now();
+    let err = Runner::with_options(SPIN, options)
+        .unwrap()
+        .run_batch([])
+        .unwrap_err();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert!(
+        matches!(
+            err,
+            Error::LimitExceeded {
+                which: Limit::Timeout { .. },
+                ..
+            }
+        ),
+        "{err:?}"
+    );
+}
+
+const SPIN_ON_NEGATIVE: &str = r#"
+    function transform(doc)
+        while doc.n < 0 do end
+        return doc.n
+    end
+"#;
+
+fn per_document_budget(policy: DocumentLimitPolicy) -> RunOptions {
+    RunOptions {
+        mode: Mode::Map,
+        per_document_max_instructions: Some(100_000),
+        on_document_limit: policy,
+        ..RunOptions::default()
+    }
+}
+
+#[test]
+fn a_document_over_its_budget_aborts_the_run_by_default() {
+    let options = per_document_budget(DocumentLimitPolicy::Abort);
+    let err = Runner::with_options(SPIN_ON_NEGATIVE, options)
+        .unwrap()
+        .run_batch([json!({"n": 1}), json!({"n": -1}), json!({"n": 2})])
+        .unwrap_err();
+    assert!(
+        matches!(
+            err,
+            Error::LimitExceeded {
+                which: Limit::DocumentInstructions { .. },
+                document: Some(1),
+                ..
+            }
+        ),
+        "{err:?}"
+    );
+}
+
+#[test]
+fn the_document_budget_starts_over_with_every_document() {
+    let options = RunOptions {
+        per_document_max_instructions: Some(100_000),
+        ..RunOptions::default()
+    };
+    let script = r#"
+        while true do
+            local doc = get_next()
+            if doc == nil then break end
+            for i = 1, 10000 do end
+            emit(doc.n)
+        end
+    "#;
+    let outputs = Runner::with_options(script, options)
+        .unwrap()
+        .run_batch((0..100).map(|n| json!({"n": n})))
+        .unwrap();
+    assert_eq!(outputs.len(), 100);
+}
+
+#[test]
+fn skipped_documents_are_recorded_as_failures() {
+    let options = per_document_budget(DocumentLimitPolicy::Skip);
+    let mut runner = Runner::with_options(SPIN_ON_NEGATIVE, options).unwrap();
+    let outputs = runner
+        .run_batch([json!({"n": 1}), json!({"n": -1}), json!({"n": 2})])
+        .unwrap();
+    assert_eq!(outputs, [1, 2]);
+    assert_eq!(runner.stats().failures, 1);
+
+    let failures = runner.take_failures();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].index, 1);
+    assert_eq!(failures[0].document, json!({"n": -1}));
+    assert!(
+        matches!(
+            failures[0].error,
+            Error::LimitExceeded {
+                which: Limit::DocumentInstructions { .. },
+                ..
+            }
+        ),
+        "{:?}",
+        failures[0].error
+    );
+}
+
+#[test]
+fn a_slow_document_is_skipped_under_its_own_timeout() {
+    let options = RunOptions {
+        mode: Mode::Map,
+        per_document_timeout: Some(Duration::from_millis(20)),
+        on_document_limit: DocumentLimitPolicy::Skip,
+        ..RunOptions::default()
+    };
+    let mut runner = Runner::with_options(SPIN_ON_NEGATIVE, options).unwrap();
+    let outputs = runner
+        .run_batch([json!({"n": -1}), json!({"n": 1})])
+        .unwrap();
+    assert_eq!(outputs, [1]);
+    let failures = runner.take_failures();
+    assert!(
+        matches!(
+            failures[..],
+            [Failure {
+                index: 0,
+                error: Error::LimitExceeded {
+                    which: Limit::DocumentTimeout { .. },
+                    ..
+                },
+                ..
+            }]
+        ),
+        "{failures:?}"
+    );
+}
+
+#[test]
+fn running_out_of_lua_memory_is_a_limit_error() {
+    let options = RunOptions {
+        max_lua_memory: Some(8 * 1024 * 1024),
+        ..RunOptions::default()
+    };
+    let script = r#"
+        local t = {}
+        for i = 1, 1e8 do t[i] = string.rep("x", 64) .. i end
+    "#;
+    let err = Runner::with_options(script, options)
+        .unwrap()
+        .run_batch([])
+        .unwrap_err();
+    assert!(
+        matches!(
+            err,
+            Error::LimitExceeded {
+                which: Limit::Memory { limit: 8_388_608 },
+                ..
+            }
+        ),
+        "{err:?}"
+    );
+}
+
+#[test]
+fn scripts_within_the_memory_limit_run() {
+    let options = RunOptions {
+        max_lua_memory: Some(8 * 1024 * 1024),
+        ..RunOptions::default()
+    };
+    let outputs = Runner::with_options("emit(#string.rep('x', 1000))", options)
+        .unwrap()
+        .run_batch([])
+        .unwrap();
+    assert_eq!(outputs, [1000]);
+}
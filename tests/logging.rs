@@ -0,0 +1,97 @@
+use std::sync::Mutex;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use mlua_play::{RunOptions, Runner};
+use serde_json::json;
+
+/// What a record logged by a script looks like once captured.
+#[derive(Debug, PartialEq)]
+struct Captured {
+    level: Level,
+    target: String,
+    message: String,
+    script: String,
+    document: Option<u64>,
+}
+
+static RECORDS: Mutex<Vec<Captured>> = Mutex::new(Vec::new());
+
+struct Capture;
+
+impl Log for Capture {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let kv = record.key_values();
+        RECORDS.lock().unwrap().push(Captured {
+            level: record.level(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+            script: kv.get("script".into()).unwrap().to_string(),
+            document: kv.get("document".into()).and_then(|doc| doc.to_u64()),
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+/// Takes what `script` logged, leaving what other tests log alone.
+fn logged_by(script: &str) -> Vec<Captured> {
+    let mut records = RECORDS.lock().unwrap();
+    let (mine, others) = records.drain(..).partition(|r| r.script == script);
+    *records = others;
+    mine
+}
+
+fn run_named(name: &str, script: &str, input: Vec<serde_json::Value>) -> mlua_play::Result<()> {
+    static LOGGER: Capture = Capture;
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(LevelFilter::Trace);
+    let options = RunOptions {
+        script_name: Some(name.to_string()),
+        ..RunOptions::default()
+    };
+    Runner::with_options(script, options)?.run_batch(input)?;
+    Ok(())
+}
+
+#[test]
+fn script_records_reach_the_log_crate() {
+    let script = r#"
+        log("info", "starting")
+        local doc = get_next()
+        log.warn(doc)
+    "#;
+    run_named("levels.lua", script, vec![json!({"a": 1})]).unwrap();
+    assert_eq!(
+        logged_by("levels.lua"),
+        [
+            Captured {
+                level: Level::Info,
+                target: "mlua_play::script".to_string(),
+                message: "starting".to_string(),
+                script: "levels.lua".to_string(),
+                document: None,
+            },
+            Captured {
+                level: Level::Warn,
+                target: "mlua_play::script".to_string(),
+                message: r#"{"a":1}"#.to_string(),
+                script: "levels.lua".to_string(),
+                document: Some(0),
+            },
+        ]
+    );
+}
+
+#[test]
+fn unknown_levels_are_script_errors() {
+    let err = run_named("bad-level.lua", r#"log("loud", "x")"#, vec![]).unwrap_err();
+    assert!(
+        err.to_string().contains("invalid log level 'loud'"),
+        "{err}"
+    );
+    assert!(logged_by("bad-level.lua").is_empty());
+}
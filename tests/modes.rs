@@ -0,0 +1,131 @@
+use mlua_play::{Error, Mode, RunOptions, Runner, run_map, run_with_options};
+use serde_json::json;
+
+fn mode(mode: Mode) -> RunOptions {
+    RunOptions {
+        mode,
+        ..RunOptions::default()
+    }
+}
+
+#[test]
+fn transform_filters_and_expands_documents() {
+    let script = r#"
+        function transform(doc)
+            if doc.n == 2 then return nil end
+            if doc.n == 3 then return doc.n, doc.n * 10 end
+            doc.seen = true
+            return doc
+        end
+    "#;
+    let input = (1..=3).map(|n| json!({ "n": n }));
+    let outputs = run_with_options(script, input, mode(Mode::Map)).unwrap();
+    assert_eq!(
+        outputs,
+        [json!({"n": 1, "seen": true}), json!(3), json!(30)]
+    );
+}
+
+#[test]
+fn run_map_works_whatever_the_mode() {
+    let script = "function transform(doc) return doc * 2 end";
+    let output = run_map(script, [json!(1), json!(2)], RunOptions::default()).unwrap();
+    assert_eq!(output.outputs, [2, 4]);
+    assert!(output.failures.is_empty());
+}
+
+#[test]
+fn map_mode_needs_a_transform_function() {
+    let err = run_with_options("x = 1", [json!(1)], mode(Mode::Map)).unwrap_err();
+    match err {
+        Error::ScriptRuntime { message, .. } => {
+            assert!(message.contains("'transform'"), "{message}")
+        }
+        err => panic!("{err:?}"),
+    }
+}
+
+#[test]
+fn transform_is_defined_once_per_batch() {
+    let script = r#"
+        calls = 0
+        function transform(doc)
+            calls = calls + 1
+            return calls
+        end
+    "#;
+    let mut runner = Runner::with_options(script, mode(Mode::Map)).unwrap();
+    let outputs = runner.run_batch([json!(1), json!(2), json!(3)]).unwrap();
+    assert_eq!(outputs, [1, 2, 3]);
+}
+
+#[test]
+fn aggregate_threads_state_through_every_document() {
+    let script = r#"
+        function init() return { count = 0, total = 0 } end
+        function accumulate(state, doc)
+            return { count = state.count + 1, total = state.total + doc.n }
+        end
+        function finalize(state) return state end
+    "#;
+    let input = (1..=4).map(|n| json!({ "n": n }));
+    let outputs = run_with_options(script, input, mode(Mode::Aggregate)).unwrap();
+    assert_eq!(outputs, [json!({"count": 4, "total": 10})]);
+}
+
+#[test]
+fn accumulate_may_update_the_state_in_place() {
+    let script = r#"
+        function init() return { seen = {} } end
+        function accumulate(state, doc) table.insert(state.seen, doc) end
+        function finalize(state) return #state.seen, state.seen[1] end
+    "#;
+    let outputs =
+        run_with_options(script, [json!("a"), json!("b")], mode(Mode::Aggregate)).unwrap();
+    assert_eq!(outputs, [json!(2), json!("a")]);
+}
+
+#[test]
+fn aggregate_emits_nothing_for_nil_and_emitted_documents_first() {
+    let script = r#"
+        function init() return { sum = 0 } end
+        function accumulate(state, doc)
+            state.sum = state.sum + doc
+            if doc % 2 == 0 then emit({ even = doc }) end
+            return nil
+        end
+        function finalize(state)
+            emit("finalizing")
+            if state.sum == 0 then return nil end
+            return nil, state.sum
+        end
+    "#;
+    let outputs = run_with_options(
+        script,
+        [json!(1), json!(2), json!(4)],
+        mode(Mode::Aggregate),
+    )
+    .unwrap();
+    assert_eq!(
+        outputs,
+        [
+            json!({"even": 2}),
+            json!({"even": 4}),
+            json!("finalizing"),
+            json!(7),
+        ]
+    );
+
+    let outputs = run_with_options(script, [json!(0)], mode(Mode::Aggregate)).unwrap();
+    assert_eq!(outputs, [json!({"even": 0}), json!("finalizing")]);
+}
+
+#[test]
+fn aggregate_needs_all_three_functions() {
+    let script = r#"
+        function init() return 0 end
+        function accumulate(state, doc) return state + doc end
+    "#;
+    let err = run_with_options(script, [json!(1)], mode(Mode::Aggregate)).unwrap_err();
+    assert!(err.to_string().contains("'finalize'"), "{err}");
+}
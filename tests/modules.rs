@@ -0,0 +1,68 @@
+use mlua_play::{Error, RunOptions, Runner, Sandbox};
+use serde_json::json;
+
+const STRINGS: &str = r#"
+    loads = (loads or 0) + 1
+    local M = {}
+    function M.shout(s) return s:upper() .. "!" end
+    return M
+"#;
+
+#[test]
+fn registered_modules_are_required_once() {
+    let script = r#"
+        local a = require("strings")
+        local b = require("strings")
+        emit(a.shout("hi"), rawequal(a, b), loads)
+    "#;
+    let mut runner = Runner::new(script)
+        .unwrap()
+        .with_module("strings", STRINGS)
+        .unwrap();
+    assert_eq!(
+        runner.run_batch([]).unwrap(),
+        [json!("HI!"), json!(true), json!(1)]
+    );
+}
+
+#[test]
+fn modules_work_under_a_sandbox_without_require() {
+    let options = RunOptions {
+        sandbox: Sandbox::Pure,
+        ..RunOptions::default()
+    };
+    let mut runner = Runner::with_options("emit(require('strings').shout('ok'))", options)
+        .unwrap()
+        .with_module("strings", STRINGS)
+        .unwrap();
+    assert_eq!(runner.run_batch([]).unwrap(), ["OK!"]);
+}
+
+#[test]
+fn unknown_modules_list_the_available_ones() {
+    let mut runner = Runner::new("require('os_helpers')")
+        .unwrap()
+        .with_module("strings", STRINGS)
+        .unwrap()
+        .with_module("dates", "return {}")
+        .unwrap();
+    let err = runner.run_batch([]).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("module 'os_helpers' not found (available: dates, strings)"),
+        "{err}"
+    );
+}
+
+#[test]
+fn modules_failing_to_compile_name_themselves() {
+    let err = Runner::new("")
+        .unwrap()
+        .with_module("broken", "return {")
+        .err()
+        .unwrap();
+    match err {
+        Error::ScriptSyntax { script, .. } => assert_eq!(script, "broken"),
+        err => panic!("{err:?}"),
+    }
+}
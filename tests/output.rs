@@ -0,0 +1,297 @@
+use std::cell::{Cell, RefCell};
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::sync::mpsc;
+
+use mlua_play::{
+    Error, JsonLinesSink, Limit, OutputSink, RawOutput, RunOptions, Runner, run_kv,
+    run_with_options, run_with_output,
+};
+use serde_json::{Value, json};
+
+#[test]
+fn documents_go_to_the_channel_they_are_emitted_to() {
+    let script = r#"
+        emit(1)
+        emit_to("errors", { message = "bad" })
+        emit_to("out", 2)
+        emit_to("errors", { message = "worse" })
+    "#;
+    let output = run_with_output(script, [], RunOptions::default()).unwrap();
+    assert_eq!(output.outputs, [1, 2]);
+    assert_eq!(
+        output.channels["errors"],
+        [json!({"message": "bad"}), json!({"message": "worse"})]
+    );
+    assert_eq!(output.stats.emitted_by_channel["errors"], 2);
+}
+
+#[test]
+fn registered_channels_show_up_even_when_empty() {
+    let options = RunOptions {
+        channels: vec!["errors".to_string()],
+        ..RunOptions::default()
+    };
+    let output = run_with_output("emit(1)", [], options).unwrap();
+    assert!(output.channels["errors"].is_empty());
+}
+
+#[test]
+fn strict_channels_reject_unknown_names() {
+    let options = RunOptions {
+        channels: vec!["errors".to_string()],
+        strict_channels: true,
+        ..RunOptions::default()
+    };
+    let mut runner = Runner::with_options(r#"emit_to("typo", 1)"#, options).unwrap();
+    let err = runner.run_batch([]).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("unknown output channel 'typo' (registered: out, errors)"),
+        "{err}"
+    );
+}
+
+/// Records what reaches it, readable after the runner takes it over.
+#[derive(Clone, Default)]
+struct Recorder {
+    emitted: Rc<RefCell<Vec<(String, Value)>>>,
+    finished: Rc<Cell<bool>>,
+}
+
+impl OutputSink for Recorder {
+    fn emit(&mut self, channel: &str, value: Value) -> mlua_play::Result<()> {
+        self.emitted.borrow_mut().push((channel.to_string(), value));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> mlua_play::Result<()> {
+        self.finished.set(true);
+        Ok(())
+    }
+}
+
+struct Failing;
+
+impl OutputSink for Failing {
+    fn emit(&mut self, _channel: &str, _value: Value) -> mlua_play::Result<()> {
+        Err(Error::Io(io::Error::other("disk full")))
+    }
+}
+
+#[test]
+fn a_sink_takes_every_document_as_it_is_emitted() {
+    let recorder = Recorder::default();
+    let options = RunOptions {
+        sink: Some(Box::new(recorder.clone())),
+        ..RunOptions::default()
+    };
+    let script = r#"
+        emit(1)
+        emit_to("audit", { seen = 1 })
+    "#;
+    let outputs = run_with_options(script, [], options).unwrap();
+    assert!(outputs.is_empty());
+    assert_eq!(
+        *recorder.emitted.borrow(),
+        [
+            ("out".to_string(), json!(1)),
+            ("audit".to_string(), json!({"seen": 1})),
+        ]
+    );
+    assert!(recorder.finished.get());
+}
+
+#[test]
+fn a_failing_sink_stops_the_script() {
+    let options = RunOptions {
+        sink: Some(Box::new(Failing)),
+        ..RunOptions::default()
+    };
+    let err =
+        run_with_options("emit_to('audit', 1) error('not reached')", [], options).unwrap_err();
+    match err {
+        Error::Sink { channel, source } => {
+            assert_eq!(channel.as_deref(), Some("audit"));
+            assert_eq!(source.to_string(), "disk full");
+        }
+        err => panic!("{err:?}"),
+    }
+}
+
+/// A writer whose bytes stay readable after the runner takes it over.
+#[derive(Clone, Default)]
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn json_lines_are_written_one_per_document() {
+    let buf = SharedBuf::default();
+    let mut runner = Runner::new("emit({ a = 1 }, 'two')").unwrap();
+    runner.set_sink(JsonLinesSink::new(buf.clone()));
+    assert!(runner.run_batch([]).unwrap().is_empty());
+    assert_eq!(
+        String::from_utf8(buf.0.take()).unwrap(),
+        "{\"a\":1}\n\"two\"\n"
+    );
+}
+
+#[test]
+fn json_lines_can_be_raw_or_pretty() {
+    let mut raw = JsonLinesSink::new(Vec::new()).raw(RawOutput::Strings);
+    raw.emit("out", json!("plain")).unwrap();
+    raw.emit("out", json!([1])).unwrap();
+    assert_eq!(raw.into_inner(), b"plain\n[1]\n");
+
+    let mut strict = JsonLinesSink::new(Vec::new()).raw(RawOutput::StringsOnly);
+    let err = strict.emit("out", json!(1)).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "raw output takes only strings, not a number"
+    );
+
+    let mut pretty = JsonLinesSink::new(Vec::new())
+        .pretty("\n")
+        .record_separator("\0");
+    pretty.emit("out", json!({"a": 1})).unwrap();
+    pretty.emit("out", json!(2)).unwrap();
+    // The record separator alone keeps the documents apart.
+    assert_eq!(pretty.into_inner(), b"{\n  \"a\": 1\n}\x002\0");
+
+    let mut pretty = JsonLinesSink::new(Vec::new()).pretty("\n");
+    pretty.emit("out", json!([1])).unwrap();
+    pretty.emit("out", json!(2)).unwrap();
+    assert_eq!(pretty.into_inner(), b"[\n  1\n]\n\n2\n");
+}
+
+#[test]
+fn a_sender_fails_once_the_receiver_is_gone() {
+    let (mut sender, receiver) = mpsc::channel();
+    sender.emit("out", json!(1)).unwrap();
+    assert_eq!(receiver.recv().unwrap(), 1);
+    drop(receiver);
+    let err = sender.emit("out", json!(2)).unwrap_err();
+    assert_eq!(err.to_string(), "output receiver was dropped");
+}
+
+#[test]
+fn emit_takes_any_number_of_values() {
+    let outputs = run_with_options("emit(1, 'two', { 3 })", [], RunOptions::default()).unwrap();
+    assert_eq!(outputs, [json!(1), json!("two"), json!([3])]);
+
+    let err = run_with_options("emit()", [], RunOptions::default()).unwrap_err();
+    assert!(
+        err.to_string().contains("emit expects at least one value"),
+        "{err}"
+    );
+}
+
+#[test]
+fn emit_many_matches_emitting_in_a_loop() {
+    let looped = r#"
+        local items = { 1, { a = 2 }, "three" }
+        for _, v in ipairs(items) do emit(v) end
+    "#;
+    let many = r#"emit_many({ 1, { a = 2 }, "three" })"#;
+    let expected = run_with_options(looped, [], RunOptions::default()).unwrap();
+    assert_eq!(expected, [json!(1), json!({"a": 2}), json!("three")]);
+    assert_eq!(
+        run_with_options(many, [], RunOptions::default()).unwrap(),
+        expected
+    );
+
+    let input = [json!({"items": [1, {"a": 2}, "three"]})];
+    let from_doc = "emit_many(get_next().items)";
+    assert_eq!(
+        run_with_options(from_doc, input, RunOptions::default()).unwrap(),
+        expected
+    );
+
+    let err = run_with_options("emit_many({ a = 1 })", [], RunOptions::default()).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("emit_many expects a sequence or an array"),
+        "{err}"
+    );
+}
+
+#[test]
+fn keyed_emits_keep_their_keys_in_order() {
+    let script = r#"
+        emit(1)
+        emit_kv("user-1", { n = 2 })
+        emit_to("other", 3)
+        emit_kv({ shard = 4 }, { 4 })
+        emit(5)
+    "#;
+    let outputs = run_kv(script, [], RunOptions::default()).unwrap();
+    assert_eq!(
+        outputs,
+        [
+            (None, json!(1)),
+            (Some(json!("user-1")), json!({"n": 2})),
+            (Some(json!({"shard": 4})), json!([4])),
+            (None, json!(5)),
+        ]
+    );
+}
+
+#[test]
+fn keyed_emits_reach_sinks_with_their_keys() {
+    let buf = SharedBuf::default();
+    let mut runner = Runner::new("emit_kv('k', 3) emit(4)").unwrap();
+    runner.set_sink(JsonLinesSink::new(buf.clone()));
+    runner.run_batch([]).unwrap();
+    assert_eq!(
+        String::from_utf8(buf.0.take()).unwrap(),
+        "{\"key\":\"k\",\"value\":3}\n4\n"
+    );
+
+    // Sinks without a notion of keys get just the document.
+    let mut sink = Vec::new();
+    sink.emit_kv("out", json!("k"), json!(3)).unwrap();
+    assert_eq!(sink, [3]);
+}
+
+#[test]
+fn emitting_past_max_emits_keeps_what_came_before() {
+    let options = RunOptions {
+        max_emits: Some(3),
+        ..RunOptions::default()
+    };
+    let err = run_with_options("for i = 1, 1e9 do emit(i) end", [], options).unwrap_err();
+    match err {
+        Error::OutputLimitExceeded { which, outputs } => {
+            assert_eq!(which, Limit::Emits { limit: 3 });
+            assert_eq!(outputs, [1, 2, 3]);
+        }
+        err => panic!("{err:?}"),
+    }
+}
+
+#[test]
+fn emitting_past_max_output_bytes_keeps_what_came_before() {
+    let options = RunOptions {
+        max_output_bytes: Some(25),
+        ..RunOptions::default()
+    };
+    let script = "while true do emit('abcdefgh') end";
+    let err = run_with_options(script, [], options).unwrap_err();
+    match err {
+        Error::OutputLimitExceeded { which, outputs } => {
+            assert_eq!(which, Limit::OutputBytes { limit: 25 });
+            assert_eq!(outputs, ["abcdefgh", "abcdefgh"]);
+        }
+        err => panic!("{err:?}"),
+    }
+}
@@ -0,0 +1,141 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+use mlua_play::{
+    Error, InputSource, IterSource, OutputOrder, OutputSink, ParallelOptions, Result, run_parallel,
+    run_parallel_into, run_parallel_with_options,
+};
+use serde_json::{Value, json};
+
+const DOUBLE: &str = "function transform(doc) return doc.n * 2 end";
+
+fn numbers(count: i64) -> Vec<Value> {
+    (0..count).map(|n| json!({ "n": n })).collect()
+}
+
+#[test]
+fn outputs_come_back_in_input_order() {
+    let outputs = run_parallel(DOUBLE, numbers(200), 4).unwrap();
+    let expected = (0..200).map(|n| json!(n * 2)).collect::<Vec<_>>();
+    assert_eq!(outputs, expected);
+}
+
+#[test]
+fn arrival_order_returns_every_output() {
+    let options = ParallelOptions {
+        workers: 4,
+        order: OutputOrder::Arrival,
+        ..ParallelOptions::default()
+    };
+    let mut outputs = run_parallel_with_options(DOUBLE, numbers(200), options).unwrap();
+    outputs.sort_by_key(|n| n.as_i64());
+    let expected = (0..200).map(|n| json!(n * 2)).collect::<Vec<_>>();
+    assert_eq!(outputs, expected);
+}
+
+#[test]
+fn the_script_is_loaded_once_per_worker() {
+    let script = r#"
+        loads = (loads or 0) + 1
+        function transform(doc) return loads end
+    "#;
+    let outputs = run_parallel(script, numbers(50), 3).unwrap();
+    assert!(outputs.iter().all(|loads| loads == 1), "{outputs:?}");
+}
+
+#[test]
+fn the_first_failing_document_is_reported() {
+    let script = r#"
+        function transform(doc)
+            if doc.n % 10 == 7 then error("bad document") end
+            return doc.n
+        end
+    "#;
+    let err = run_parallel(script, numbers(100), 4).unwrap_err();
+    match err {
+        Error::Document { index, source } => {
+            assert_eq!(index, 7);
+            assert!(matches!(*source, Error::ScriptRuntime { .. }), "{source:?}");
+        }
+        err => panic!("{err:?}"),
+    }
+}
+
+#[test]
+fn workers_see_the_index_of_their_document() {
+    let script = "function transform(doc) return doc_index() end";
+    let outputs = run_parallel(script, numbers(30), 3).unwrap();
+    let expected = (1..=30).map(|n| json!(n)).collect::<Vec<_>>();
+    assert_eq!(outputs, expected);
+}
+
+#[test]
+fn outputs_stream_into_a_sink_with_the_stats_added_up() {
+    let options = ParallelOptions {
+        workers: 4,
+        ..ParallelOptions::default()
+    };
+    let mut sink = Vec::new();
+    let stats =
+        run_parallel_into(DOUBLE, IterSource::new(numbers(40)), options, &mut sink).unwrap();
+    assert_eq!(sink.len(), 40);
+    assert_eq!(sink[39], 78);
+    assert_eq!(stats.documents_read, 40);
+    assert_eq!(stats.emitted, 40);
+}
+
+/// Hands out `docs`, taking a while over every one after the first, and
+/// counts how many it was asked for.
+struct SlowSource {
+    docs: Vec<Value>,
+    asked: Rc<Cell<usize>>,
+}
+
+impl InputSource for SlowSource {
+    fn next_doc(&mut self) -> Result<Option<Value>> {
+        let asked = self.asked.get();
+        self.asked.set(asked + 1);
+        if asked > 0 {
+            thread::sleep(Duration::from_millis(300));
+        }
+        Ok(self.docs.get(asked).cloned())
+    }
+}
+
+/// Notes how many documents the source had been asked for by the time each
+/// output came in.
+struct AskedBy {
+    asked: Rc<Cell<usize>>,
+    seen: Vec<(Value, usize)>,
+}
+
+impl OutputSink for AskedBy {
+    fn emit(&mut self, _channel: &str, value: Value) -> Result<()> {
+        self.seen.push((value, self.asked.get()));
+        Ok(())
+    }
+}
+
+#[test]
+fn outputs_are_sent_as_soon_as_their_document_is_done() {
+    let asked = Rc::new(Cell::new(0));
+    let source = SlowSource {
+        docs: numbers(3),
+        asked: asked.clone(),
+    };
+    let mut sink = AskedBy {
+        asked,
+        seen: Vec::new(),
+    };
+    let options = ParallelOptions {
+        workers: 1,
+        ..ParallelOptions::default()
+    };
+    run_parallel_into(DOUBLE, source, options, &mut sink).unwrap();
+    // The worker is done with every document long before the next one is
+    // read, so its outputs are written as soon as the next one is handed
+    // out rather than once the worker gets to it.
+    assert_eq!(sink.seen, [(json!(0), 2), (json!(2), 3), (json!(4), 4)]);
+}
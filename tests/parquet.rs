@@ -0,0 +1,128 @@
+#![cfg(feature = "parquet")]
+
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use arrow::array::{
+    ArrayRef, Decimal128Array, Int64Array, ListBuilder, RecordBatch, StringArray, StringBuilder,
+    TimestampMillisecondArray,
+};
+use mlua_play::{InputSource, ParquetOptions, ParquetSource, Runner, TimestampFormat};
+use parquet::arrow::ArrowWriter;
+use serde_json::{Value, json};
+
+/// A Parquet file of two rows, written under the system's temporary
+/// directory for the test named `test`.
+fn orders(test: &str) -> PathBuf {
+    let mut tags = ListBuilder::new(StringBuilder::new());
+    tags.values().append_value("new");
+    tags.values().append_value("gift");
+    tags.append(true);
+    tags.append(false);
+    let columns: Vec<(&str, ArrayRef)> = vec![
+        ("id", Arc::new(Int64Array::from(vec![1, 2]))),
+        ("name", Arc::new(StringArray::from(vec![Some("ann"), None]))),
+        (
+            "at",
+            Arc::new(
+                TimestampMillisecondArray::from(vec![1_714_564_800_000, 0]).with_timezone("UTC"),
+            ),
+        ),
+        ("tags", Arc::new(tags.finish())),
+        (
+            "price",
+            Arc::new(
+                Decimal128Array::from(vec![1234, 5])
+                    .with_precision_and_scale(10, 2)
+                    .unwrap(),
+            ),
+        ),
+    ];
+    let batch = RecordBatch::try_from_iter(columns).unwrap();
+    let dir = std::env::temp_dir().join(format!("mlua_play-{}-{test}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("orders.parquet");
+    let mut writer =
+        ArrowWriter::try_new(File::create(&path).unwrap(), batch.schema(), None).unwrap();
+    writer.write(&batch).unwrap();
+    writer.close().unwrap();
+    path
+}
+
+fn read(path: &PathBuf, options: &ParquetOptions) -> Vec<Value> {
+    let mut source = ParquetSource::new(File::open(path).unwrap(), options).unwrap();
+    let mut rows = Vec::new();
+    while let Some(row) = source.next_doc().unwrap() {
+        rows.push(row);
+    }
+    rows
+}
+
+#[test]
+fn rows_become_documents_keyed_by_column() {
+    let path = orders("rows");
+    assert_eq!(
+        read(&path, &ParquetOptions::default()),
+        [
+            json!({
+                "id": 1,
+                "name": "ann",
+                "at": "2024-05-01T12:00:00Z",
+                "tags": ["new", "gift"],
+                "price": "12.34",
+            }),
+            json!({
+                "id": 2,
+                "name": null,
+                "at": "1970-01-01T00:00:00Z",
+                "tags": null,
+                "price": "0.05",
+            }),
+        ]
+    );
+
+    let script = r#"
+        local row = get_next()
+        while row ~= nil do
+            emit({ id = row.id, tagged = row.tags ~= nil and #row.tags or 0 })
+            row = get_next()
+        end
+    "#;
+    let source =
+        ParquetSource::new(File::open(&path).unwrap(), &ParquetOptions::default()).unwrap();
+    let outputs = Runner::new(script).unwrap().run_source(source).unwrap();
+    assert_eq!(
+        outputs,
+        [json!({"id": 1, "tagged": 2}), json!({"id": 2, "tagged": 0})]
+    );
+}
+
+#[test]
+fn columns_are_projected_and_timestamps_may_be_millis() {
+    let path = orders("projected");
+    let options = ParquetOptions {
+        columns: Some(vec!["at".to_string(), "id".to_string()]),
+        timestamps: TimestampFormat::EpochMillis,
+        batch_size: 1,
+    };
+    assert_eq!(
+        read(&path, &options),
+        [
+            json!({"id": 1, "at": 1_714_564_800_000i64}),
+            json!({"id": 2, "at": 0})
+        ]
+    );
+
+    let options = ParquetOptions {
+        columns: Some(vec!["missing".to_string()]),
+        ..ParquetOptions::default()
+    };
+    let err = ParquetSource::new(File::open(&path).unwrap(), &options)
+        .err()
+        .unwrap();
+    assert!(
+        err.to_string().contains("no column named 'missing'"),
+        "{err}"
+    );
+}
@@ -0,0 +1,166 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use mlua_play::{Error, run_fanout, run_fanout_isolated, run_pipeline};
+use serde_json::{Value, json};
+
+const DOUBLE: &str = r#"
+    while true do
+        local doc = get_next()
+        if doc == nil then break end
+        emit(doc * 2)
+    end
+"#;
+
+const EVENS: &str = r#"
+    while true do
+        local doc = get_next()
+        if doc == nil then break end
+        if doc % 4 == 0 then emit(doc) end
+    end
+"#;
+
+#[test]
+fn every_stage_reads_what_the_one_before_emits() {
+    let input = (1..=4).map(|n| json!(n));
+    let outputs = run_pipeline(&[DOUBLE, EVENS, DOUBLE], input).unwrap();
+    assert_eq!(outputs, [8, 16]);
+}
+
+#[test]
+fn no_stages_pass_the_input_through() {
+    let outputs = run_pipeline(&[], [json!(1), json!(2)]).unwrap();
+    assert_eq!(outputs, [1, 2]);
+}
+
+#[test]
+fn documents_flow_through_the_whole_chain_one_at_a_time() {
+    let read = Rc::new(Cell::new(0));
+    let input = {
+        let read = read.clone();
+        (1..=3).map(move |n| {
+            read.set(read.get() + 1);
+            json!(n)
+        })
+    };
+    let script = r#"
+        local first = get_next()
+        emit(first)
+    "#;
+    let outputs = run_pipeline(&[DOUBLE, script], input).unwrap();
+    assert_eq!(outputs, [2]);
+    assert_eq!(read.get(), 1);
+}
+
+#[test]
+fn failures_name_the_stage() {
+    let failing = r#"
+        local doc = get_next()
+        error("stage failed")
+    "#;
+    let err = run_pipeline(&[DOUBLE, failing, DOUBLE], [json!(1)]).unwrap_err();
+    assert!(matches!(err, Error::Stage { index: 1, .. }), "{err:?}");
+
+    let err = run_pipeline(&[DOUBLE, "syntax error here"], Vec::<Value>::new()).unwrap_err();
+    assert!(matches!(err, Error::Stage { index: 1, .. }), "{err:?}");
+}
+
+const SUM: &str = r#"
+    local total = 0
+    while true do
+        local doc = get_next()
+        if doc == nil then break end
+        total = total + doc
+    end
+    emit(total)
+"#;
+
+const FAIL_ON_TWO: &str = r#"
+    while true do
+        local doc = get_next()
+        if doc == nil then break end
+        if doc == 2 then error("two") end
+        emit(doc)
+    end
+"#;
+
+#[test]
+fn fan_out_runs_every_script_over_one_pass() {
+    let read = Rc::new(Cell::new(0));
+    let input = {
+        let read = read.clone();
+        (1..=3).map(move |n| {
+            read.set(read.get() + 1);
+            json!(n)
+        })
+    };
+    let outputs = run_fanout(&[DOUBLE, SUM], input).unwrap();
+    assert_eq!(
+        outputs,
+        [vec![json!(2), json!(4), json!(6)], vec![json!(6)]]
+    );
+    assert_eq!(read.get(), 3);
+}
+
+#[test]
+fn fan_out_fails_with_the_failing_script() {
+    let input = (1..=3).map(|n| json!(n));
+    let err = run_fanout(&[SUM, FAIL_ON_TWO], input).unwrap_err();
+    assert!(matches!(err, Error::Script { index: 1, .. }), "{err:?}");
+}
+
+#[test]
+fn isolated_fan_out_lets_the_other_scripts_finish() {
+    let input = (1..=3).map(|n| json!(n));
+    let results = run_fanout_isolated(&[FAIL_ON_TWO, SUM], input);
+    assert!(
+        matches!(results[0], Err(Error::ScriptRuntime { .. })),
+        "{:?}",
+        results[0]
+    );
+    assert_eq!(results[1].as_ref().unwrap(), &[json!(6)]);
+}
+
+#[test]
+fn fan_out_scripts_can_read_from_their_own_coroutines() {
+    let reader = r#"
+        local docs = coroutine.wrap(function()
+            while true do
+                local doc = get_next()
+                if doc == nil then return end
+                coroutine.yield(doc)
+            end
+        end)
+        for doc in docs do
+            emit(doc * 10)
+        end
+    "#;
+    let input = (1..=3).map(|n| json!(n));
+    let outputs = run_fanout(&[reader, DOUBLE], input).unwrap();
+    assert_eq!(
+        outputs,
+        [
+            vec![json!(10), json!(20), json!(30)],
+            vec![json!(2), json!(4), json!(6)]
+        ]
+    );
+}
+
+#[test]
+fn fan_out_scripts_cannot_wait_for_input_inside_a_c_function() {
+    let sorter = r#"
+        table.sort({ 2, 1 }, function(a, b)
+            get_next()
+            return a < b
+        end)
+    "#;
+    let input = (1..=3).map(|n| json!(n));
+    let results = run_fanout_isolated(&[sorter], input);
+    match &results[0] {
+        Err(Error::ScriptRuntime { message, .. }) => assert!(
+            message.contains("get_next cannot wait for the next document inside a C function"),
+            "{message}"
+        ),
+        result => panic!("{result:?}"),
+    }
+}
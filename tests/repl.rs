@@ -0,0 +1,74 @@
+use mlua_play::{Evaluated, IterSource, RunOptions, Session};
+use serde_json::json;
+
+fn session() -> Session {
+    let input = IterSource::new([json!({"n": 1}), json!({"n": 2})]);
+    Session::new(RunOptions::default(), input).unwrap()
+}
+
+fn done(values: &[&str]) -> Evaluated {
+    Evaluated::Done(values.iter().map(|value| value.to_string()).collect())
+}
+
+#[test]
+fn incomplete_chunks_ask_for_more_lines() {
+    let mut session = session();
+    assert_eq!(
+        session.eval("function double(x)").unwrap(),
+        Evaluated::Incomplete
+    );
+    assert_eq!(
+        session.eval("for i = 1, 3 do").unwrap(),
+        Evaluated::Incomplete
+    );
+    assert_eq!(
+        session
+            .eval("function double(x)\n  return x * 2\nend")
+            .unwrap(),
+        done(&[])
+    );
+    assert_eq!(session.eval("double(21)").unwrap(), done(&["42"]));
+    // A chunk that cannot be finished is an error rather than incomplete.
+    assert!(session.eval("local = 1").is_err());
+}
+
+#[test]
+fn variables_and_the_input_carry_over_between_chunks() {
+    let mut session = session();
+    assert_eq!(session.eval("local doc = get_next()").unwrap(), done(&[]));
+    assert_eq!(session.eval("doc").unwrap(), done(&["{\"n\":1}"]));
+    assert_eq!(session.last_document(), Some(json!({"n": 1})));
+    assert_eq!(session.eval("total = doc.n").unwrap(), done(&[]));
+
+    // A failing chunk leaves the session as it was.
+    let err = session.eval("error('oops')").unwrap_err();
+    assert!(err.to_string().contains("oops"), "{err}");
+    assert_eq!(
+        session.eval("total, 'x', nil").unwrap(),
+        done(&["1", "x", "nil"])
+    );
+
+    assert_eq!(
+        session.eval("emit(get_next()) print('next')").unwrap(),
+        done(&[])
+    );
+    assert_eq!(session.take_output(), [json!({"n": 2})]);
+    assert_eq!(session.take_log(), ["next"]);
+    assert_eq!(session.last_document(), Some(json!({"n": 2})));
+}
+
+#[test]
+fn reset_forgets_variables_and_load_runs_a_whole_file() {
+    let mut session = session();
+    session.eval("local kept = 5").unwrap();
+    session.reset().unwrap();
+    assert_eq!(session.eval("kept").unwrap(), done(&["nil"]));
+    // Globals every script has are still there.
+    assert_eq!(session.eval("type(get_next)").unwrap(), done(&["function"]));
+
+    let file = "helper = function(x) return x + 1 end\nreturn helper(1)";
+    assert_eq!(session.load(file, "helpers.lua").unwrap(), ["2"]);
+    assert_eq!(session.eval("helper(9)").unwrap(), done(&["10"]));
+    let err = session.load("return (", "broken.lua").unwrap_err();
+    assert!(err.to_string().contains("broken.lua"), "{err}");
+}
@@ -0,0 +1,377 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use mlua_play::{Error, Metric, MetricSummary, RunOptions, Runner, TraceEvent, run_with_output};
+use serde_json::json;
+
+const SUM: &str = r#"
+    total = total or 0
+    while true do
+        local doc = get_next()
+        if doc == nil then break end
+        total = total + doc.n
+    end
+    emit(total)
+"#;
+
+#[test]
+fn globals_survive_across_batches() {
+    let mut runner = Runner::new(SUM).unwrap();
+    let first = runner
+        .run_batch([json!({"n": 1}), json!({"n": 2})])
+        .unwrap();
+    let second = runner.run_batch([json!({"n": 3})]).unwrap();
+    assert_eq!(first, [json!(3)]);
+    assert_eq!(second, [json!(6)]);
+}
+
+#[test]
+fn handles_kept_from_an_earlier_batch_are_stale() {
+    let mut runner = Runner::new(
+        r#"
+        if kept then
+            emit(kept.n)
+        else
+            kept = get_next()
+        end
+        "#,
+    )
+    .unwrap();
+    runner.run_batch([json!({"n": 1})]).unwrap();
+    let err = runner.run_batch([]).unwrap_err();
+    assert!(matches!(err, Error::ScriptRuntime { .. }), "{err:?}");
+}
+
+#[test]
+fn the_trace_callback_follows_the_run() {
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let options = RunOptions {
+        script_name: Some("sum.lua".to_string()),
+        trace: Some(Box::new({
+            let events = events.clone();
+            move |event| {
+                events.borrow_mut().push(match event {
+                    TraceEvent::ScriptStarting { script, .. } => format!("start {script}"),
+                    TraceEvent::DocumentRead { index } => format!("read {index}"),
+                    TraceEvent::Emitted { index } => format!("emit {index}"),
+                })
+            }
+        })),
+        ..RunOptions::default()
+    };
+    Runner::with_options(SUM, options)
+        .unwrap()
+        .run_batch([json!({"n": 1}), json!({"n": 2})])
+        .unwrap();
+    assert_eq!(
+        *events.borrow(),
+        ["start sum.lua", "read 0", "read 1", "emit 0"]
+    );
+}
+
+/// A writer whose bytes stay readable after it is handed over.
+#[derive(Clone, Default)]
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn printed_lines_are_captured() {
+    let script = r#"
+        print("a", 1, true)
+        io.write("b", 2)
+        io.write("c\n")
+        local doc = get_next()
+        print(doc)
+        io.write("unterminated")
+    "#;
+    let output = run_with_output(script, [json!({"k": "v"})], RunOptions::default()).unwrap();
+    assert_eq!(
+        output.log,
+        ["a\t1\ttrue", "b2c", r#"{"k":"v"}"#, "unterminated"]
+    );
+}
+
+#[test]
+fn printed_text_is_forwarded_as_it_comes() {
+    let forwarded = SharedBuf::default();
+    let options = RunOptions {
+        print_to: Some(Box::new(forwarded.clone())),
+        ..RunOptions::default()
+    };
+    let mut runner = Runner::with_options("print('hello')", options).unwrap();
+    runner.run_batch([]).unwrap();
+    assert_eq!(*forwarded.0.borrow(), b"hello\n");
+    // Forwarded lines are not kept as well.
+    assert!(runner.take_log().is_empty());
+}
+
+#[test]
+fn stats_count_what_the_batch_did() {
+    let script = r#"
+        while true do
+            local doc = get_next()
+            if doc == nil then break end
+            emit(doc)
+            emit_clone(doc)
+        end
+    "#;
+    let options = RunOptions {
+        max_instructions: Some(1_000_000_000),
+        ..RunOptions::default()
+    };
+    let mut runner = Runner::with_options(script, options).unwrap();
+    runner.run_batch([json!(1), json!(2), json!(3)]).unwrap();
+    let stats = runner.stats();
+    assert_eq!(stats.documents_read, 3);
+    assert_eq!(stats.emitted, 3);
+    assert_eq!(stats.emitted_clones, 3);
+    assert_eq!(stats.emitted_by_channel["out"], 6);
+    assert!(stats.peak_memory > 0);
+    assert!(stats.instructions.is_some());
+}
+
+#[test]
+fn stats_are_kept_for_a_failed_batch() {
+    let script = r#"
+        get_next()
+        emit(1)
+        error("stop")
+    "#;
+    let mut runner = Runner::new(script).unwrap();
+    runner.run_batch([json!(1), json!(2)]).unwrap_err();
+    let stats = runner.stats();
+    assert_eq!(stats.documents_read, 1);
+    assert_eq!(stats.emitted, 1);
+    assert_eq!(stats.instructions, None);
+}
+
+#[test]
+fn persisted_globals_carry_over_to_a_new_runner() {
+    let persist = || RunOptions {
+        persist_globals: vec!["total".to_string(), "seen".to_string()],
+        ..RunOptions::default()
+    };
+    let script = r#"
+        seen = seen or {}
+        table.insert(seen, "batch")
+    "#;
+    let first = run_with_output(&format!("{SUM}{script}"), [json!({"n": 2})], persist()).unwrap();
+    let state = first.state.unwrap();
+    assert_eq!(state, json!({"total": 2, "seen": ["batch"]}));
+
+    let options = RunOptions {
+        restore_globals: Some(state),
+        ..persist()
+    };
+    let second = run_with_output(&format!("{SUM}{script}"), [json!({"n": 3})], options).unwrap();
+    assert_eq!(second.outputs, [5]);
+    assert_eq!(
+        second.state.unwrap(),
+        json!({"total": 5, "seen": ["batch", "batch"]})
+    );
+}
+
+#[test]
+fn globals_without_a_json_form_cannot_be_persisted() {
+    let options = RunOptions {
+        persist_globals: vec!["handler".to_string()],
+        ..RunOptions::default()
+    };
+    let mut runner = Runner::with_options("handler = { on = print }", options).unwrap();
+    runner.run_batch([]).unwrap();
+    match runner.snapshot_globals().unwrap_err() {
+        Error::Conversion { path, .. } => assert_eq!(path, "/handler/on"),
+        err => panic!("{err:?}"),
+    }
+}
+
+#[test]
+fn scripts_see_their_parameters() {
+    let script = r#"
+        local fields = {}
+        for _, field in ipairs(args.fields) do table.insert(fields, field) end
+        emit({ threshold = args.threshold, fields = fields, argv = ARGV })
+    "#;
+    let mut runner = Runner::new(script)
+        .unwrap()
+        .with_args(&json!({"threshold": 5, "fields": ["a", "b"]}))
+        .unwrap()
+        .with_argv(["x", "y"])
+        .unwrap();
+    assert_eq!(
+        runner.run_batch([]).unwrap(),
+        [json!({"threshold": 5, "fields": ["a", "b"], "argv": ["x", "y"]})]
+    );
+}
+
+#[test]
+fn args_are_read_only() {
+    for script in ["args.extra = 1", "args.nested.n = 2"] {
+        let options = RunOptions {
+            args: Some(json!({"nested": {"n": 1}})),
+            ..RunOptions::default()
+        };
+        let err = Runner::with_options(script, options)
+            .unwrap()
+            .run_batch([])
+            .unwrap_err();
+        assert!(err.to_string().contains("args is read-only"), "{err}");
+    }
+}
+
+#[test]
+fn run_iter_only_runs_as_far_as_is_read() {
+    let script = r#"
+        while true do
+            local doc = get_next()
+            if doc == nil then break end
+            emit(doc)
+        end
+    "#;
+    let mut runner = Runner::new(script).unwrap();
+    let first = runner
+        .run_iter((0..).map(|n| json!(n)))
+        .unwrap()
+        .take(2)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(first, [0, 1]);
+    assert_eq!(runner.stats().documents_read, 2);
+
+    let again = runner
+        .run_iter([json!("x")])
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(again, ["x"]);
+}
+
+#[test]
+fn run_iter_ends_with_the_error_that_stopped_the_script() {
+    let mut runner = Runner::new("emit(1) error('boom') emit(2)").unwrap();
+    let items = runner.run_iter([]).unwrap().collect::<Vec<_>>();
+    assert!(
+        matches!(&items[..], [Ok(_), Err(Error::ScriptRuntime { .. })]),
+        "{items:?}"
+    );
+}
+
+#[test]
+fn run_iter_matches_run_batch_for_scripts_with_coroutines() {
+    let scripts = [
+        // Emitting from a coroutine of the script's own.
+        r#"
+            emit(1)
+            local co = coroutine.wrap(function() emit(2) end)
+            co()
+            emit(3)
+        "#,
+        // A generator, whose own yields must not be mistaken for emits.
+        r#"
+            local gen = coroutine.wrap(function()
+                for i = 1, 2 do
+                    emit(i)
+                    coroutine.yield(i * 10)
+                end
+            end)
+            emit(gen())
+            emit(gen())
+        "#,
+        // One that yields values of its own back to the script.
+        r#"
+            local co = coroutine.create(function(a)
+                local b = coroutine.yield(a + 1)
+                emit(b)
+                return b * 2
+            end)
+            local _, first = coroutine.resume(co, 1)
+            emit(first)
+            local _, second = coroutine.resume(co, 10)
+            emit(second)
+        "#,
+        // From a C function calling back into Lua.
+        r#"
+            local list = { 3, 1, 2 }
+            table.sort(list, function(a, b)
+                emit(a)
+                return a < b
+            end)
+            emit(list)
+        "#,
+    ];
+    for script in scripts {
+        let batch = Runner::new(script).unwrap().run_batch([]).unwrap();
+        let iter = Runner::new(script)
+            .unwrap()
+            .run_iter([])
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(iter, batch, "{script}");
+    }
+}
+
+#[test]
+fn metrics_are_recorded_into_the_stats() {
+    let script = r#"
+        local doc = get_next()
+        while doc ~= nil do
+            metrics.incr("docs")
+            if doc.price == nil then
+                metrics.incr("price_missing", 2)
+            else
+                metrics.observe("price", doc.price)
+            end
+            metrics.gauge("last_id", doc.id)
+            doc = get_next()
+        end
+    "#;
+    let input = [
+        json!({"id": 1, "price": 5}),
+        json!({"id": 2}),
+        json!({"id": 3, "price": 1.5}),
+        json!({"id": 4, "price": 10}),
+    ];
+    let output = run_with_output(script, input, RunOptions::default()).unwrap();
+    let metrics = output.stats.metrics;
+    assert_eq!(metrics["docs"], Metric::Counter(4));
+    assert_eq!(metrics["price_missing"], Metric::Counter(2));
+    assert_eq!(metrics["last_id"], Metric::Gauge(4.0));
+    assert_eq!(
+        metrics["price"],
+        Metric::Summary(MetricSummary {
+            count: 3,
+            sum: 16.5,
+            min: 1.5,
+            max: 10.0,
+        })
+    );
+}
+
+#[test]
+fn metric_names_keep_their_kind() {
+    let mut runner = Runner::new("metrics.incr('n') metrics.observe('n', 1)").unwrap();
+    let err = runner.run_batch([]).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("metric 'n' is a counter, not a summary"),
+        "{err}"
+    );
+    let mut runner = Runner::new("metrics.incr('')").unwrap();
+    let err = runner.run_batch([]).unwrap_err();
+    assert!(
+        err.to_string().contains("metric name must not be empty"),
+        "{err}"
+    );
+}
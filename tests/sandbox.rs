@@ -0,0 +1,102 @@
+use mlua_play::{RunOptions, Runner, Sandbox};
+use serde_json::{Value, json};
+
+const PROBE: &str = r#"
+    emit({
+        io = io ~= nil,
+        os = os ~= nil,
+        os_execute = os ~= nil and os.execute ~= nil,
+        os_time = os ~= nil and os.time ~= nil,
+        require = require ~= nil,
+        dofile = dofile ~= nil,
+        debug = debug ~= nil,
+        string = string ~= nil,
+    })
+"#;
+
+fn probe(sandbox: Sandbox) -> Value {
+    let options = RunOptions {
+        sandbox,
+        ..RunOptions::default()
+    };
+    let mut outputs = Runner::with_options(PROBE, options)
+        .unwrap()
+        .run_batch([])
+        .unwrap();
+    outputs.remove(0)
+}
+
+/// `debug` is left out even here, as `Lua::new` leaves it out.
+#[test]
+fn full_keeps_the_whole_standard_library() {
+    assert_eq!(
+        probe(Sandbox::Full),
+        json!({
+            "io": true, "os": true, "os_execute": true, "os_time": true,
+            "require": true, "dofile": true, "debug": false, "string": true,
+        })
+    );
+}
+
+#[test]
+fn standard_leaves_out_files_processes_and_modules() {
+    assert_eq!(
+        probe(Sandbox::Standard),
+        json!({
+            "io": false, "os": true, "os_execute": false, "os_time": true,
+            "require": false, "dofile": false, "debug": false, "string": true,
+        })
+    );
+}
+
+#[test]
+fn pure_leaves_only_computation() {
+    assert_eq!(
+        probe(Sandbox::Pure),
+        json!({
+            "io": false, "os": false, "os_execute": false, "os_time": false,
+            "require": false, "dofile": false, "debug": false, "string": true,
+        })
+    );
+}
+
+#[test]
+fn restricted_profiles_only_load_source_text() {
+    let script = r#"
+        local bytecode = string.dump(function() return 1 end)
+        local from_load, load_err = load(bytecode)
+        local from_loadstring, loadstring_err = loadstring(bytecode, "chunk")
+        emit({
+            text = load("return 1 + 1")(),
+            with_env = load("return x", "chunk", "b", { x = 3 })(),
+            loadstring = loadstring("return 4")(),
+            load = from_load == nil and load_err ~= nil,
+            loadstring_binary = from_loadstring == nil and loadstring_err ~= nil,
+        })
+    "#;
+    for sandbox in [Sandbox::Standard, Sandbox::Pure] {
+        let options = RunOptions {
+            sandbox,
+            ..RunOptions::default()
+        };
+        let outputs = Runner::with_options(script, options)
+            .unwrap()
+            .run_batch([])
+            .unwrap();
+        assert_eq!(
+            outputs,
+            [json!({
+                "text": 2, "with_env": 3, "loadstring": 4,
+                "load": true, "loadstring_binary": true,
+            })],
+            "{sandbox}"
+        );
+    }
+}
+
+#[test]
+fn profiles_parse_from_their_names() {
+    assert_eq!("standard".parse(), Ok(Sandbox::Standard));
+    assert_eq!(Sandbox::Pure.to_string(), "pure");
+    assert!("none".parse::<Sandbox>().is_err());
+}
@@ -0,0 +1,107 @@
+use mlua_play::{Error, RunOptions, run, run_with_options, run_with_output};
+use serde_json::{Value, json};
+
+fn schema() -> Value {
+    json!({
+        "type": "object",
+        "required": ["id", "name"],
+        "properties": {
+            "id": {"type": "integer"},
+            "tags": {"type": "array", "items": {"type": "string"}},
+        },
+    })
+}
+
+#[test]
+fn scripts_validate_values_against_a_schema() {
+    let script = r#"
+        local schema = get_next()
+        local doc = get_next()
+        while doc ~= nil do
+            local ok, errors = validate(doc, schema)
+            emit({ ok = ok, errors = errors })
+            doc = get_next()
+        end
+        emit((validate({ id = 1, name = "text" }, json.encode(schema))))
+    "#;
+    let input = [
+        schema(),
+        json!({"id": 1, "name": "a"}),
+        json!({"id": "x", "tags": ["ok", 2]}),
+    ];
+    let outputs = run(script, input).unwrap();
+    assert_eq!(outputs[0], json!({"ok": true}));
+    assert_eq!(outputs[1]["ok"], false);
+    let mut pointers: Vec<_> = outputs[1]["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| {
+            assert!(error["message"].is_string(), "{error}");
+            error["pointer"].as_str().unwrap()
+        })
+        .collect();
+    pointers.sort();
+    assert_eq!(pointers, ["", "/id", "/tags/1"]);
+    assert_eq!(outputs[2], true);
+}
+
+#[test]
+fn input_failing_the_schema_fails_the_run() {
+    let options = RunOptions {
+        input_schema: Some(schema()),
+        ..RunOptions::default()
+    };
+    let input = [json!({"id": 1, "name": "a"}), json!({"id": 2})];
+    let err = run_with_options("while get_next() do end", input, options).unwrap_err();
+    match err {
+        Error::InputError { index, source } => {
+            assert_eq!(index, 1);
+            match *source {
+                Error::SchemaViolation { errors } => {
+                    assert_eq!(errors.len(), 1);
+                    assert!(errors[0].contains("\"name\""), "{errors:?}");
+                }
+                err => panic!("{err:?}"),
+            }
+        }
+        err => panic!("{err:?}"),
+    }
+}
+
+#[test]
+fn input_failing_the_schema_can_go_to_a_channel() {
+    let options = RunOptions {
+        input_schema: Some(schema()),
+        invalid_input_channel: Some("invalid".to_string()),
+        ..RunOptions::default()
+    };
+    let input = [
+        json!({"id": 1, "name": "a"}),
+        json!({"id": 2}),
+        json!({"id": 3, "name": "c"}),
+    ];
+    let script = "local doc = get_next() while doc do emit(doc.id) doc = get_next() end";
+    let output = run_with_output(script, input, options).unwrap();
+    assert_eq!(output.outputs, [1, 3]);
+    assert_eq!(output.channels["invalid"], [json!({"id": 2})]);
+}
+
+#[test]
+fn invalid_schemas_are_rejected_up_front() {
+    let options = RunOptions {
+        input_schema: Some(json!({"type": 12})),
+        ..RunOptions::default()
+    };
+    let err = run_with_options("", [], options).unwrap_err();
+    assert!(
+        matches!(
+            err,
+            Error::InvalidOption {
+                option: "input_schema",
+                ..
+            }
+        ),
+        "{err:?}"
+    );
+}
@@ -0,0 +1,173 @@
+use std::io::Cursor;
+use std::sync::mpsc;
+use std::thread;
+
+use mlua_play::{
+    Error, InputSource, IterSource, JsonLinesSink, JsonLinesSource, OutputSink, RunOptions, Runner,
+    SlicedSource, SlurpedSource,
+};
+use serde_json::{Value, json};
+
+const ECHO: &str = r#"
+    local doc = get_next()
+    while doc ~= nil do
+        emit(doc)
+        doc = get_next()
+    end
+"#;
+
+/// Every document of `source`, drained directly.
+fn drain(mut source: impl InputSource) -> Vec<Value> {
+    let mut docs = Vec::new();
+    while let Some(doc) = source.next_doc().unwrap() {
+        docs.push(doc);
+    }
+    docs
+}
+
+#[test]
+fn json_lines_skip_blank_lines_and_need_no_final_newline() {
+    let source = JsonLinesSource::new(Cursor::new("1\n\n   \n{\"a\": 2}\n3"));
+    assert_eq!(drain(source), [json!(1), json!({"a": 2}), json!(3)]);
+}
+
+#[test]
+fn a_malformed_line_fails_the_run_with_its_line_number() {
+    let source = JsonLinesSource::new(Cursor::new("1\n\n{oops\n4\n"));
+    let mut runner = Runner::new(ECHO).unwrap();
+    match runner.run_source(source).unwrap_err() {
+        Error::InputError { index, source } => {
+            assert_eq!(index, 1);
+            match *source {
+                Error::InvalidJson { line, .. } => assert_eq!(line, 3),
+                err => panic!("{err:?}"),
+            }
+        }
+        err => panic!("{err:?}"),
+    }
+}
+
+#[test]
+fn any_source_feeds_get_next() {
+    let mut runner = Runner::new(ECHO).unwrap();
+    let outputs = runner
+        .run_source(IterSource::new([json!(1), json!(2)]))
+        .unwrap();
+    assert_eq!(outputs, [1, 2]);
+
+    let (sender, receiver) = mpsc::channel();
+    let producer = thread::spawn(move || {
+        for n in 0..3 {
+            sender.send(json!(n)).unwrap();
+        }
+    });
+    assert_eq!(runner.run_source(receiver).unwrap(), [0, 1, 2]);
+    producer.join().unwrap();
+}
+
+#[cfg(all(feature = "gzip", feature = "zstd"))]
+#[test]
+fn compressed_json_lines_are_read_through_a_decoder() {
+    use std::io::{BufReader, Write};
+
+    use mlua_play::Compression;
+
+    let text = "{\"n\":1}\n{\"n\":2}\n";
+    let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gzip.write_all(text.as_bytes()).unwrap();
+    let gzip = gzip.finish().unwrap();
+    let zstd = zstd::encode_all(text.as_bytes(), 0).unwrap();
+
+    for (bytes, compression) in [(gzip, Compression::Gzip), (zstd, Compression::Zstd)] {
+        let mut reader = BufReader::new(Cursor::new(bytes));
+        assert_eq!(Compression::sniff(&mut reader).unwrap(), Some(compression));
+        let source = JsonLinesSource::new(compression.decoder(reader).unwrap());
+        assert_eq!(drain(source), [json!({"n": 1}), json!({"n": 2})]);
+    }
+    let mut plain = Cursor::new(text);
+    assert_eq!(Compression::sniff(&mut plain).unwrap(), None);
+    assert_eq!(
+        Compression::from_extension("part-1.ndjson.gz".as_ref()),
+        Some(Compression::Gzip)
+    );
+}
+
+#[test]
+fn sources_can_be_sliced_without_parsing_what_is_skipped() {
+    let lines = || JsonLinesSource::new(Cursor::new("1\n{oops\n3\n4\n5\n"));
+    assert_eq!(drain(SlicedSource::new(lines()).skip(2).limit(2)), [3, 4]);
+    assert_eq!(
+        drain(SlicedSource::new(lines()).skip(9)),
+        Vec::<Value>::new()
+    );
+    assert_eq!(
+        drain(SlicedSource::new(lines()).limit(0)),
+        Vec::<Value>::new()
+    );
+
+    let options = RunOptions {
+        index_offset: 2,
+        ..RunOptions::default()
+    };
+    let script =
+        "local doc = get_next() while doc ~= nil do emit(doc_index()) doc = get_next() end";
+    let mut runner = Runner::with_options(script, options).unwrap();
+    let outputs = runner
+        .run_source(SlicedSource::new(lines()).skip(2))
+        .unwrap();
+    assert_eq!(outputs, [3, 4, 5]);
+}
+
+#[test]
+fn pretty_records_round_trip_through_a_separator() {
+    let docs = vec![
+        json!({"text": "line one\nline two", "nested": {"list": [1, 2]}}),
+        json!("x".repeat(200_000)),
+        json!([]),
+    ];
+    for separator in [&b"\0"[..], b"--8<--"] {
+        let mut sink = JsonLinesSink::new(Vec::new())
+            .pretty("\n")
+            .record_separator(separator);
+        for doc in &docs {
+            sink.emit("out", doc.clone()).unwrap();
+        }
+        let written = sink.into_inner();
+        assert!(written.windows(2).any(|pair| pair == b"{\n"));
+        let source = JsonLinesSource::new(Cursor::new(written)).record_separator(separator);
+        assert_eq!(drain(source), docs);
+    }
+}
+
+#[test]
+fn sources_can_be_slurped_into_one_array() {
+    let lines = || JsonLinesSource::new(Cursor::new("1\n2\n3\n4\n5\n"));
+    assert_eq!(
+        drain(SlurpedSource::new(SlicedSource::new(lines()).limit(3))),
+        [json!([1, 2, 3])]
+    );
+    assert_eq!(drain(SlurpedSource::new(IterSource::new([]))), [json!([])]);
+}
+
+#[test]
+fn json_lines_count_the_bytes_behind_every_document() {
+    let text = "{\"a\":1}\n\n[1,2,3]\n\"tail\"";
+    let mut source = JsonLinesSource::new(Cursor::new(text));
+    assert_eq!(source.bytes_read(), Some(0));
+    let mut offsets = Vec::new();
+    while source.next_doc().unwrap().is_some() {
+        offsets.push(source.bytes_read().unwrap());
+    }
+    // Blank lines count along with the document after them.
+    assert_eq!(offsets, [8, 17, 23]);
+    assert_eq!(offsets.last(), Some(&(text.len() as u64)));
+
+    let mut sliced = SlicedSource::new(JsonLinesSource::new(Cursor::new(text))).skip(2);
+    sliced.next_doc().unwrap();
+    assert_eq!(sliced.bytes_read(), Some(23));
+    let mut separated =
+        JsonLinesSource::new(Cursor::new("1\0{\"b\":\n2}\0")).record_separator(b"\0");
+    separated.next_doc().unwrap();
+    assert_eq!(separated.bytes_read(), Some(2));
+    assert_eq!(IterSource::new([json!(1)]).bytes_read(), None);
+}
@@ -0,0 +1,130 @@
+#![cfg(feature = "async")]
+
+use std::time::Duration;
+
+use futures::channel::mpsc;
+use futures::{SinkExt, StreamExt};
+use mlua_play::{Error, RunOptions, run_async, run_async_into, run_stream};
+use serde_json::{Value, json};
+
+const DOUBLE: &str = r#"
+    while true do
+        local doc = get_next()
+        if doc == nil then break end
+        emit({ n = doc.n * 2 })
+    end
+"#;
+
+#[tokio::test]
+async fn outputs_arrive_while_input_is_still_coming() {
+    let (mut input, documents) = mpsc::unbounded::<Value>();
+    let (sink, mut outputs) = mpsc::unbounded::<Value>();
+    let run = run_async_into(DOUBLE, documents, sink, RunOptions::default());
+    let feed = async move {
+        let mut seen = Vec::new();
+        for n in 1..=3 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            input.send(json!({ "n": n })).await.unwrap();
+            // The next document is only sent once this one's output is out.
+            seen.push(outputs.next().await.unwrap());
+        }
+        drop(input);
+        seen
+    };
+
+    let (ran, seen) =
+        tokio::time::timeout(Duration::from_secs(5), async { tokio::join!(run, feed) })
+            .await
+            .expect("outputs are not held back until the input ends");
+    ran.unwrap();
+    assert_eq!(seen, [json!({"n": 2}), json!({"n": 4}), json!({"n": 6})]);
+}
+
+#[tokio::test]
+async fn collects_outputs_without_a_sink() {
+    let (mut input, documents) = mpsc::unbounded::<Value>();
+    input.send(json!({ "n": 1 })).await.unwrap();
+    input.send(json!({ "n": 2 })).await.unwrap();
+    drop(input);
+
+    let outputs = run_async(DOUBLE, documents, RunOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(outputs, [json!({"n": 2}), json!({"n": 4})]);
+}
+
+#[tokio::test]
+async fn every_emit_goes_through_the_sink() {
+    let script = r#"
+        emit(1)
+        emit_to("other", 2)
+        emit_kv("key", 3)
+        emit_many({ 4, 5 })
+    "#;
+    let (sink, outputs) = mpsc::unbounded::<Value>();
+    run_async_into(
+        script,
+        futures::stream::empty(),
+        sink,
+        RunOptions::default(),
+    )
+    .await
+    .unwrap();
+    let outputs = outputs.collect::<Vec<_>>().await;
+    assert_eq!(outputs, [1, 2, 3, 4, 5]);
+}
+
+#[tokio::test]
+async fn a_closed_sink_is_a_sink_error() {
+    let (sink, outputs) = mpsc::channel::<Value>(1);
+    drop(outputs);
+    let err = run_async_into(
+        "emit(1)",
+        futures::stream::empty(),
+        sink,
+        RunOptions::default(),
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, Error::Sink { .. }), "{err:?}");
+}
+
+#[tokio::test]
+async fn a_stream_of_documents_becomes_a_stream_of_outputs() {
+    let input = futures::stream::iter([json!({ "n": 1 }), json!({ "n": 2 })]);
+    let outputs = run_stream(DOUBLE, input, RunOptions::default())
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(outputs, [json!({"n": 2}), json!({"n": 4})]);
+}
+
+#[tokio::test]
+async fn the_script_runs_no_further_than_what_is_consumed() {
+    let input = futures::stream::repeat(json!({ "n": 1 }));
+    let outputs = run_stream(DOUBLE, input, RunOptions::default())
+        .take(3)
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(outputs.len(), 3);
+}
+
+#[tokio::test]
+async fn the_stream_ends_with_the_error_that_stopped_the_script() {
+    let script = r#"
+        emit(1)
+        error("boom")
+    "#;
+    let outputs = run_stream(script, futures::stream::empty(), RunOptions::default())
+        .collect::<Vec<_>>()
+        .await;
+    assert!(
+        matches!(
+            &outputs[..],
+            [Ok(Value::Number(_)), Err(Error::ScriptRuntime { .. })]
+        ),
+        "{outputs:?}"
+    );
+}
@@ -0,0 +1,31 @@
+use mlua_play::{undefined_globals, validate};
+
+#[test]
+fn valid_scripts_pass_without_running() {
+    assert_eq!(validate("while true do end"), Ok(()));
+    assert_eq!(validate("os.exit(1)"), Ok(()));
+}
+
+#[test]
+fn syntax_errors_give_the_line_and_message() {
+    let err = validate("local a = 1\nlocal b = \nreturn").unwrap_err();
+    assert_eq!(err.line, Some(3));
+    assert!(err.message.contains("unexpected symbol"), "{}", err.message);
+    assert!(err.to_string().starts_with("3: "), "{err}");
+}
+
+#[test]
+fn undefined_globals_are_found_without_running_the_script() {
+    let script = "\
+local doc = get_next()
+helper = function(x) return x end
+emit(helper(dco), string.upper(doc.name), json.encode(doc))
+os.exit(prnt)
+";
+    let found = undefined_globals(script).unwrap();
+    let found: Vec<_> = found
+        .iter()
+        .map(|global| (global.name.as_str(), global.line))
+        .collect();
+    assert_eq!(found, [("dco", 3), ("prnt", 4)]);
+}
@@ -0,0 +1,84 @@
+use mlua::{AnyUserData, Lua};
+use mlua_play::{SharedValue, with_document};
+use serde_json::json;
+
+#[test]
+fn a_lent_document_comes_back_changed() {
+    let lua = Lua::new();
+    let mut doc = json!({"n": 1, "tags": ["a"]});
+    with_document(&mut doc, |handle| {
+        lua.globals().set("doc", handle).unwrap();
+        lua.load("doc.n = doc.n + 1 doc.tags[1] = 'b' kept = doc.tags")
+            .exec()
+            .unwrap();
+    });
+    assert_eq!(doc, json!({"n": 2, "tags": ["b"]}));
+
+    let err = lua.load("return kept[1]").exec().unwrap_err();
+    assert!(err.to_string().contains("from a previous batch"), "{err}");
+}
+
+#[test]
+fn a_lent_document_comes_back_even_on_panic() {
+    let mut doc = json!({"n": 1});
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        with_document(&mut doc, |handle| {
+            *handle.resolve_mut().unwrap() = json!({"n": 2});
+            panic!("script host failed");
+        })
+    }));
+    assert!(panicked.is_err());
+    assert_eq!(doc, json!({"n": 2}));
+}
+
+/// The handle `path` evaluates to, inside a document set as `doc`.
+fn handle_at(lua: &Lua, root: &SharedValue, path: &str) -> SharedValue {
+    lua.globals().set("doc", root.clone()).unwrap();
+    let data: AnyUserData = lua.load(format!("return {path}")).eval().unwrap();
+    data.borrow::<SharedValue>().unwrap().clone()
+}
+
+#[test]
+fn handles_serialize_the_part_they_point_at() {
+    let lua = Lua::new();
+    let root = SharedValue::new(json!({"a": {"b": [1, {"c": true}]}, "z": 0}));
+    let nested = handle_at(&lua, &root, "doc.a.b");
+    assert_eq!(serde_json::to_string(&nested).unwrap(), r#"[1,{"c":true}]"#);
+    assert_eq!(nested.to_value().unwrap(), json!([1, {"c": true}]));
+
+    lua.load("doc.a = 1").exec().unwrap();
+    let err = serde_json::to_string(&nested).unwrap_err();
+    assert!(err.to_string().contains("'b'"), "{err}");
+}
+
+#[test]
+fn registered_functions_take_and_return_handles() {
+    let lua = Lua::new();
+    let mark = lua
+        .create_function(|_, (doc, key): (SharedValue, String)| {
+            doc.resolve_mut()?[key] = json!(true);
+            Ok(doc)
+        })
+        .unwrap();
+    lua.globals().set("mark", mark).unwrap();
+    let root = SharedValue::new(json!({"id": 1}));
+    lua.globals().set("doc", root.clone()).unwrap();
+    let script = r#"
+        local same = mark(doc, "seen")
+        local plain = { id = 2 }
+        local copy = mark(plain, "seen")
+        return same.id, copy.seen, plain.seen
+    "#;
+    let (id, copied, plain): (i64, bool, Option<bool>) = lua.load(script).eval().unwrap();
+    assert_eq!(id, 1);
+    assert!(copied);
+    assert_eq!(plain, None);
+    assert_eq!(root.to_value().unwrap(), json!({"id": 1, "seen": true}));
+
+    let err = lua.load("mark(1, 'seen')").exec().unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("expected a document handle or a table"),
+        "{err}"
+    );
+}